@@ -9,6 +9,8 @@ use crate::webhooks::UpgradeState;
 use super::{Result, ResultExt, ErrorKind};
 use super::{AuditWebhook};
 use crate::helm::direct::UpgradeData;
+use crate::kube::RolloutFailure;
+use crate::secret::SecretAction;
 
 /// Payload that gets sent via audit webhook
 #[derive(Serialize, Clone)]
@@ -58,6 +60,9 @@ pub struct AuditDeploymentPayload {
     manifests_revision: String,
     service: String,
     version: String,
+    /// Categorised reason for a failed rollout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure: Option<RolloutFailure>,
 }
 
 #[derive(Serialize, Clone)]
@@ -74,6 +79,7 @@ impl AuditDeploymentPayload {
         let manifests_revision = whc["SHIPCAT_AUDIT_REVISION"].clone();
         Self {
             id: format!("{}-{}-{}-{}", manifests_revision, region, service, version),
+            failure: ud.failure.clone(),
             manifests_revision, region, service, version,
         }
     }
@@ -102,6 +108,35 @@ impl AuditType for AuditReconciliationPayload {
     }
 }
 
+#[derive(Serialize, Clone)]
+pub struct AuditSecretPayload {
+    id: String,
+    region: String,
+    /// Eg Git SHA
+    manifests_revision: String,
+    service: String,
+    /// Vault path of the secret, never its value
+    path: String,
+    action: SecretAction,
+}
+
+impl AuditSecretPayload {
+    pub fn new(whc: &BTreeMap<String, String>, action: SecretAction, service: &str, path: &str, r: &str) -> Self {
+        let manifests_revision = whc["SHIPCAT_AUDIT_REVISION"].clone();
+        let (region, service, path) = (r.to_string(), service.to_string(), path.to_string());
+        Self {
+            id: format!("{}-{}-{}", manifests_revision, region, path),
+            manifests_revision, region, service, path, action,
+        }
+    }
+}
+
+impl AuditType for AuditSecretPayload {
+    fn get_domain_type(&self) -> String {
+        "secret".into()
+    }
+}
+
 pub fn audit_deployment(us: &UpgradeState, ud: &UpgradeData, audcfg: &AuditWebhook, whc: BTreeMap<String, String>) -> Result<()> {
     let ae = AuditEvent::new(&whc, &us, AuditDeploymentPayload::new(&whc, &ud));
     audit(ae, &audcfg)
@@ -112,6 +147,11 @@ pub fn audit_reconciliation(us: &UpgradeState, region: &str, audcfg: &AuditWebho
     audit(ae, &audcfg)
 }
 
+pub fn audit_secret(us: &UpgradeState, action: SecretAction, service: &str, path: &str, region: &str, audcfg: &AuditWebhook, whc: BTreeMap<String, String>) -> Result<()> {
+    let ae = AuditEvent::new(&whc, &us, AuditSecretPayload::new(&whc, action, service, path, region));
+    audit(ae, &audcfg)
+}
+
 fn audit<T: Serialize + Clone + AuditType>(ae: AuditEvent<T>, audcfg: &AuditWebhook) -> Result<()> {
     let endpoint = &audcfg.url;
     debug!("event status: {}, url: {:?}", serde_json::to_string(&ae.status)?, endpoint);
@@ -1,35 +1,37 @@
 use super::{Config, Region};
-use super::helm::{self, UpgradeMode};
+use super::helm::{self, UpgradeMode, ReconcileOpts};
 use super::{Result, Manifest};
+use crate::report::{Report, ReportTarget, ServiceReport, ServiceStatus};
 use crate::webhooks;
 
 /// Helm upgrade the region (reconcile)
 ///
 /// Upgrades multiple services at a time using rolling upgrade in a threadpool.
 /// Ignores upgrade failures.
-pub fn helm_reconcile(conf: &Config, region: &Region, n_workers: usize) -> Result<()> {
+/// See `ReconcileOpts` for ordering, waves and the failure budget.
+pub fn helm_reconcile(conf: &Config, region: &Region, opts: &ReconcileOpts) -> Result<()> {
     if let Err(e) = webhooks::ensure_requirements(&region) {
         warn!("Could not ensure webhook requirements: {}", e);
     }
-    mass_helm(conf, region, UpgradeMode::UpgradeInstallWait, n_workers)
+    mass_helm(conf, region, UpgradeMode::UpgradeInstallWait, opts)
 }
 
 /// Helm diff the region
 ///
 /// Returns the diffs only from all services across a region.
 /// Farms out the work to a thread pool.
-pub fn helm_diff(conf: &Config, region: &Region, n_workers: usize) -> Result<()> {
-    mass_helm(conf, region, UpgradeMode::DiffOnly, n_workers)
+pub fn helm_diff(conf: &Config, region: &Region, n_workers: usize, report: Option<ReportTarget>) -> Result<()> {
+    mass_helm(conf, region, UpgradeMode::DiffOnly, &ReconcileOpts { workers: n_workers, report, ..Default::default() })
 }
 
 // Find all active services in a region and helm::parallel::upgrade them
-fn mass_helm(conf: &Config, region: &Region, umode: UpgradeMode, n_workers: usize) -> Result<()> {
+fn mass_helm(conf: &Config, region: &Region, umode: UpgradeMode, opts: &ReconcileOpts) -> Result<()> {
     let mut svcs = vec![];
     for svc in Manifest::available(&region.name)? {
         debug!("Scanning service {:?}", svc);
         svcs.push(Manifest::base(&svc, conf, region)?);
     }
-    helm::parallel::reconcile(svcs, conf, region, umode, n_workers)
+    helm::parallel::reconcile(svcs, conf, region, umode, opts)
 }
 
 
@@ -37,14 +39,16 @@ fn mass_helm(conf: &Config, region: &Region, umode: UpgradeMode, n_workers: usiz
 ///
 /// Temporary helper that shells out to kubectl apply in parallel.
 /// This will go away with catapult.
-pub fn mass_crd(conf: &Config, reg: &Region, n_workers: usize) -> Result<()> {
-    crd_reconcile(Manifest::available(&reg.name)?, conf, reg, n_workers)
+pub fn mass_crd(conf: &Config, reg: &Region, n_workers: usize, report: Option<ReportTarget>) -> Result<()> {
+    crd_reconcile(Manifest::available(&reg.name)?, conf, reg, n_workers, report)
 }
 
 use super::kube;
-fn crd_reconcile(svcs: Vec<String>, config: &Config, region: &Region, n_workers: usize) -> Result<()> {
+fn crd_reconcile(svcs: Vec<String>, config: &Config, region: &Region, n_workers: usize, report: Option<ReportTarget>) -> Result<()> {
     use threadpool::ThreadPool;
     use std::sync::mpsc::channel;
+    use std::time::Instant;
+    let start = Instant::now();
 
     // Reconcile CRDs (definition itself)
     use shipcat_definitions::gen_all_crds;
@@ -77,18 +81,27 @@ fn crd_reconcile(svcs: Vec<String>, config: &Config, region: &Region, n_workers:
         let tx = tx.clone(); // tx channel reused in each thread
         pool.execute(move || {
             debug!("Running CRD reconcile for {}", svc);
+            let started = Instant::now();
             let res = crd_reconcile_worker(&svc, &conf, &reg);
-            tx.send(res).expect("channel will be there waiting for the pool");
+            tx.send((svc, res, started.elapsed())).expect("channel will be there waiting for the pool");
         });
     }
     // wait for threads collect errors
-    let res = rx.iter().take(n_jobs).map(|r| {
-        match r {
-            Ok(_) => {},
-            Err(ref e) => warn!("error: {}", e),
-        }
+    let mut services = vec![];
+    let res = rx.iter().take(n_jobs).map(|(svc, r, took)| {
+        let rep = match r {
+            Ok(_) => ServiceReport::new(&svc, ServiceStatus::Reconciled, "apply"),
+            Err(ref e) => {
+                warn!("error: {}", e);
+                ServiceReport::new(&svc, ServiceStatus::Failed, "apply").error(e)
+            },
+        };
+        services.push(rep.duration(took));
         r
     }).filter_map(Result::err).collect::<Vec<_>>();
+    if let Some(target) = report {
+        target.write(&Report::new("crd reconcile", &region.name, start.elapsed(), services));
+    }
     // propagate first non-ignorable error if exists
     if let Some(e) = res.into_iter().next() {
         // no errors ignoreable atm
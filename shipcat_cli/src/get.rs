@@ -156,17 +156,14 @@ pub fn apistatus(conf: &Config, reg: &Region) -> Result<()> {
             let mut params = APIServiceParams {
                 uris: k.uris.unwrap_or("".into()),
                 hosts: k.hosts.unwrap_or("".into()),
-                internal: k.internal,
-                publiclyAccessible: mf.publiclyAccessible,
+                internal: k.internal.unwrap_or(false),
+                publiclyAccessible: mf.publiclyAccessible.unwrap_or(false),
                 websockets: false,
             };
             if let Some(g) = mf.gate {
-                // `manifest.verify` ensures that if there is a gate conf,
-                // `gate.public` must be equal to `publiclyAccessible`.
-                // That means that the following line does not alter the value
-                // of `params.publiclyAccessible` but will be useful during the
-                // migration of manifest configuration (ie deprecate
-                // `publiclyAccessible` in favour of `gate.public`).
+                // `publiclyAccessible` is deprecated in favour of `gate.public`
+                // (see `shipcat migrate`), and `manifest.verify` ensures they
+                // never disagree when both are set, so `gate.public` wins.
                 params.publiclyAccessible = g.public;
                 params.websockets = g.websockets;
             }
@@ -179,8 +176,8 @@ pub fn apistatus(conf: &Config, reg: &Region) -> Result<()> {
         services.insert(name, APIServiceParams {
             uris: api.uris.unwrap_or("".into()),
             hosts: api.hosts.unwrap_or("".into()),
-            internal: api.internal,
-            publiclyAccessible: api.publiclyAccessible,
+            internal: api.internal.unwrap_or(false),
+            publiclyAccessible: api.publiclyAccessible.unwrap_or(false),
             // TODO [DIP-499]: `extra_apis` do not support `gate` confs
             websockets: false,
         });
@@ -1,5 +1,6 @@
 use petgraph::graph::{DiGraph, NodeIndex};
-use petgraph::dot;
+use petgraph::{algo, dot};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fmt::{self, Debug};
 
 use super::{Manifest, Region, Config};
@@ -166,3 +167,66 @@ pub fn reverse(service: &str, conf: &Config, reg: &Region) -> Result<Vec<String>
     println!("{}", out);
     Ok(res)
 }
+
+/// Group services into waves that only depend on services in earlier waves
+///
+/// Dependencies on services outside `svcs` are ignored.
+/// Fails with the path of a dependency cycle if there is one.
+pub fn waves(svcs: &[Manifest]) -> Result<Vec<Vec<String>>> {
+    let mut graph : CatGraph = DiGraph::<_, _>::new();
+    let mut idx = HashMap::new();
+    for mf in svcs {
+        idx.insert(mf.name.clone(), graph.add_node(ManifestNode::new(mf)));
+    }
+    for mf in svcs {
+        for dep in &mf.dependencies {
+            if let Some(&depidx) = idx.get(&dep.name) {
+                graph.update_edge(idx[&mf.name], depidx, DepEdge::new(&dep));
+            }
+        }
+    }
+
+    let order = match algo::toposort(&graph, None) {
+        Ok(o) => o,
+        Err(cycle) => bail!("Dependency cycle: {}", cycle_path(&graph, cycle.node_id()).join(" -> ")),
+    };
+    // dependencies come after their dependents, so walk backwards
+    let mut level : HashMap<NodeIndex, usize> = HashMap::new();
+    for &n in order.iter().rev() {
+        let l = graph.neighbors(n).map(|d| level[&d] + 1).max().unwrap_or(0);
+        level.insert(n, l);
+    }
+    let mut waves : BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for mf in svcs {
+        waves.entry(level[&idx[&mf.name]]).or_insert_with(Vec::new).push(mf.name.clone());
+    }
+    Ok(waves.into_iter().map(|(_, w)| w).collect())
+}
+
+/// Names along the shortest cycle through a node, ending where it started
+fn cycle_path(graph: &CatGraph, start: NodeIndex) -> Vec<String> {
+    let name = |n: NodeIndex| graph[n].name.clone();
+    let mut parent = HashMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some(n) = queue.pop_front() {
+        for m in graph.neighbors(n) {
+            if m == start {
+                let mut path = vec![name(n)];
+                let mut cur = n;
+                while let Some(&p) = parent.get(&cur) {
+                    path.push(name(p));
+                    cur = p;
+                }
+                path.reverse();
+                path.push(name(start));
+                return path;
+            }
+            if !parent.contains_key(&m) {
+                parent.insert(m, n);
+                queue.push_back(m);
+            }
+        }
+    }
+    vec![name(start)]
+}
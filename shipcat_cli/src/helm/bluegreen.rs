@@ -0,0 +1,126 @@
+use std::{thread, time};
+
+use crate::webhooks::{self, UpgradeState};
+use super::kube::{self, Colour, FailureReason, RolloutFailure};
+use super::direct::UpgradeData;
+use super::{Manifest, Region, Result};
+
+/// Move the traffic of a service that is new to blue/green onto a colour
+///
+/// Needed before its first blue/green upgrade, as that scales the main deployment down.
+/// The blue colour gets the running spec, and the `Service` switches to it once it is ready.
+/// A noop for services that already switched, or that have nothing running.
+pub fn adopt(mf: &Manifest) -> Result<Option<RolloutFailure>> {
+    if kube::colour_state(mf)?.active.is_some() {
+        return Ok(None);
+    }
+    let serving = kube::rollout_progress(mf)?.into_iter()
+        .find(|r| r.kind == "Deployment" && r.name == mf.name)
+        .map(|r| r.desired)
+        .unwrap_or(0);
+    if serving == 0 {
+        return Ok(None);
+    }
+    info!("Moving {} to blue/green deployments", mf.name);
+    bring_up(mf, Colour::Blue, serving)
+}
+
+/// Bring up a full new colour of a blue/green service and switch its traffic over
+///
+/// Runs after the release was upgraded, so the zero replica main deployment holds the new spec.
+/// The old colour keeps running for rollbacks, and is only retired once the new one took over.
+/// A new colour that fails to come up never receives traffic,
+/// and is left for debugging until the next upgrade replaces it.
+pub fn switch(mf: &Manifest) -> Result<Option<RolloutFailure>> {
+    let next = kube::colour_state(mf)?.active.map(Colour::other).unwrap_or(Colour::Blue);
+    let failure = bring_up(mf, next, mf.replicaCount.unwrap_or(1))?;
+    if failure.is_none() {
+        if let Err(e) = retire(mf) {
+            warn!("Failed to scale down the previous colour of {}: {}", mf.name, e);
+        }
+    }
+    Ok(failure)
+}
+
+fn bring_up(mf: &Manifest, colour: Colour, replicas: u32) -> Result<Option<RolloutFailure>> {
+    kube::create_colour(mf, colour, replicas)?;
+    if let Some(failure) = await_colour(mf, colour)? {
+        error!("Not switching {} to {}: {}", mf.name, colour, failure);
+        return Ok(Some(failure));
+    }
+    kube::switch_colour(mf, colour)?;
+    Ok(None)
+}
+
+/// Wait for every replica of a colour to become ready
+fn await_colour(mf: &Manifest, colour: Colour) -> Result<Option<RolloutFailure>> {
+    let start = time::Instant::now();
+    loop {
+        let (status, pods) = kube::colour_status(mf, colour)?;
+        for p in &pods {
+            if let Some((reason, message)) = kube::pod_failure(p) {
+                return Ok(Some(RolloutFailure { reason, object: format!("pod {}", p.metadata.name), message }));
+            }
+        }
+        if status.rolledOut {
+            return Ok(None);
+        }
+        let waited = start.elapsed().as_secs();
+        if waited >= u64::from(status.waitTime) {
+            return Ok(Some(RolloutFailure {
+                reason: FailureReason::Timeout,
+                object: format!("{} {}", status.kind, status.name),
+                message: format!("{}/{} replicas ready after {}s", status.ready, status.desired, waited),
+            }));
+        }
+        info!("{}: {}/{} replicas ready ({}s of {}s)", status.name, status.ready, status.desired, waited, status.waitTime);
+        // poll at 1/10th of the estimated wait time, but at least every 10s
+        thread::sleep(time::Duration::from_secs(u64::from(std::cmp::min(std::cmp::max(status.waitTime / 10, 1), 10))));
+    }
+}
+
+/// Scale down the colour a blue/green service switched away from, once its rollback window passed
+pub fn retire(mf: &Manifest) -> Result<()> {
+    let conf = mf.blueGreen.clone().unwrap_or_default();
+    let state = kube::colour_state(mf)?;
+    match state.previous {
+        Some(prev) if Some(prev) != state.active && !state.within(conf.rollback_window()) => {
+            kube::scale_colour(mf, prev, 0)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Switch a blue/green service straight back to the colour it ran before its last switch
+///
+/// Only possible within the rollback window, while the previous colour is still scaled up.
+/// The release itself is left alone, so upgrading to a fixed version brings up a fresh colour.
+pub fn rollback(reg: &Region, ud: &UpgradeData, mf: &Manifest) -> Result<()> {
+    let conf = mf.blueGreen.clone().unwrap_or_default();
+    let state = kube::colour_state(mf)?;
+    let previous = match state.previous {
+        Some(c) => c,
+        None => bail!("{} has not switched colours yet - there is nothing to switch back to", mf.name),
+    };
+    if !state.within(conf.rollback_window()) {
+        bail!("{} switched colours more than {}s ago - {} may be scaled down", mf.name,
+            conf.rollback_window(), kube::colour_name(mf, previous));
+    }
+    let (status, _) = kube::colour_status(mf, previous)?;
+    if status.desired == 0 || !status.rolledOut {
+        bail!("{} is not ready to take traffic back ({}/{} replicas ready)", status.name, status.ready, status.desired);
+    }
+    webhooks::upgrade_rollback_event(UpgradeState::RollingBack, &ud, &reg);
+    match kube::switch_colour(mf, previous) {
+        Err(e) => {
+            error!("{}", e);
+            webhooks::upgrade_rollback_event(UpgradeState::RollbackFailed, &ud, &reg);
+            Err(e)
+        },
+        Ok(_) => {
+            info!("Switched {} back to {}", mf.name, previous);
+            webhooks::upgrade_rollback_event(UpgradeState::RolledBack, &ud, &reg);
+            Ok(())
+        }
+    }
+}
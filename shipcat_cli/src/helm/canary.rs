@@ -0,0 +1,107 @@
+use std::{thread, time};
+
+use crate::structs::Canary;
+use crate::kubeapi::Pod;
+use crate::webhooks::{self, UpgradeState};
+use super::kube::{self, FailureReason, RolloutFailure};
+use super::direct::{self, UpgradeData};
+use super::{Manifest, Region, Result, ErrorKind};
+
+/// Bake a canary of the new version before a full upgrade
+///
+/// Runs the manifest's rendered main deployment as a separate canary deployment,
+/// waits for it to become ready, and watches it for the bake period.
+/// The canary is always removed again afterwards.
+///
+/// Fails with the categorised reason when the canary misbehaved, in which case
+/// the main deployment was never touched.
+pub fn bake(ud: &mut UpgradeData, mf: &Manifest, reg: &Region) -> Result<()> {
+    let conf = mf.canary.clone().unwrap_or_default();
+    let total = match mf.autoScaling {
+        Some(ref hpa) => hpa.minReplicas,
+        None => mf.replicaCount.unwrap_or(1),
+    };
+    let objs = direct::rendered(mf, ud.helm)?;
+    kube::create_canary(mf, &objs, conf.replicas(total))?;
+    webhooks::canary_event(UpgradeState::CanaryStarted, ud, reg);
+
+    let res = watch(mf, &conf);
+    if let Err(e) = kube::delete_canary(mf) {
+        warn!("Failed to remove the canary of {}: {}", mf.name, e);
+    }
+    match res {
+        Ok(None) => {
+            info!("Canary of {} baked for {}s, promoting", mf.name, conf.bake_time());
+            webhooks::canary_event(UpgradeState::CanaryPromoted, ud, reg);
+            Ok(())
+        },
+        Ok(Some(failure)) => {
+            error!("Aborting canary of {}: {}", mf.name, failure);
+            ud.failure = Some(failure.clone());
+            webhooks::canary_event(UpgradeState::CanaryAborted, ud, reg);
+            Err(ErrorKind::CanaryAborted(mf.name.clone(), failure.to_string()).into())
+        },
+        Err(e) => {
+            webhooks::canary_event(UpgradeState::CanaryAborted, ud, reg);
+            Err(e)
+        }
+    }
+}
+
+/// Wait for the canary to become ready, then watch it for the bake period
+fn watch(mf: &Manifest, conf: &Canary) -> Result<Option<RolloutFailure>> {
+    // poll at 1/10th of the bake time, but at least every 10s
+    let poll = time::Duration::from_secs(u64::from(std::cmp::min(std::cmp::max(conf.bake_time() / 10, 1), 10)));
+    let start = time::Instant::now();
+    loop {
+        let (status, pods) = kube::canary_status(mf)?;
+        if let Some(failure) = misbehaving(&pods, conf) {
+            return Ok(Some(failure));
+        }
+        if status.rolledOut {
+            break;
+        }
+        let waited = start.elapsed().as_secs();
+        if waited >= u64::from(status.waitTime) {
+            return Ok(Some(RolloutFailure {
+                reason: FailureReason::Timeout,
+                object: format!("{} {}", status.kind, status.name),
+                message: format!("{}/{} replicas ready after {}s", status.ready, status.desired, waited),
+            }));
+        }
+        info!("{}: {}/{} canary replicas ready ({}s of {}s)", status.name, status.ready, status.desired, waited, status.waitTime);
+        thread::sleep(poll);
+    }
+
+    info!("Baking canary of {} for {}s", mf.name, conf.bake_time());
+    let baking = time::Instant::now();
+    while baking.elapsed().as_secs() < u64::from(conf.bake_time()) {
+        thread::sleep(poll);
+        let (_, pods) = kube::canary_status(mf)?;
+        if let Some(failure) = misbehaving(&pods, conf) {
+            return Ok(Some(failure));
+        }
+    }
+    Ok(None)
+}
+
+/// Canary pods failing to start, or restarting more than allowed
+///
+/// Crashes only count through `maxRestarts`, not the limits of normal rollouts.
+fn misbehaving(pods: &[Pod], conf: &Canary) -> Option<RolloutFailure> {
+    for p in pods {
+        if let Some((reason, message)) = kube::pod_start_failure(p) {
+            return Some(RolloutFailure { reason, object: format!("pod {}", p.metadata.name), message });
+        }
+    }
+    let restarts : u32 = pods.iter().map(|p| p.restarts()).sum();
+    if restarts > conf.max_restarts() {
+        let worst = pods.iter().max_by_key(|p| p.restarts()).unwrap(); // restarts > 0 => non-empty
+        return Some(RolloutFailure {
+            reason: FailureReason::CrashLoop,
+            object: format!("pod {}", worst.metadata.name),
+            message: format!("{} canary restarts (allowed {})", restarts, conf.max_restarts()),
+        });
+    }
+    None
+}
@@ -5,12 +5,15 @@ use std::fs::File;
 use std::io::Write;
 
 use serde_yaml;
+use serde_json::Value;
 use crate::webhooks::{self, UpgradeState};
-use super::kube;
+use super::kube::{self, RolloutFailure};
+use crate::native;
 use super::Metadata;
-use super::{Manifest, Config, Region};
+use super::{Manifest, Config, Region, HelmVersion};
 use super::{Result, ResultExt, ErrorKind};
 use super::helpers::{self, hout, hexec};
+use super::{canary, bluegreen};
 
 /// The different modes we allow `helm upgrade` to run in
 #[derive(PartialEq, Clone, Debug)]
@@ -29,6 +32,8 @@ pub enum UpgradeMode {
     UpgradeInstall,
     /// Upgrade or install, but always wait (reconcile)
     UpgradeInstallWait,
+    /// Bake a canary of the new version before upgrading and waiting
+    Canary,
 
     // new modes
     /// Upgrade and Wait, or Install and don't wait (on first install)
@@ -50,6 +55,7 @@ impl fmt::Display for UpgradeMode {
             &UpgradeMode::UpgradeInstall => write!(f, "install"),
             &UpgradeMode::UpgradeWaitMaybeRollback => write!(f, "upgrade"),
             &UpgradeMode::UpgradeInstallWait => write!(f, "reconcile"),
+            &UpgradeMode::Canary => write!(f, "canary upgrade"),
             &UpgradeMode::Apply => write!(f, "apply"),
         }
     }
@@ -65,6 +71,7 @@ impl UpgradeMode {
             &UpgradeMode::UpgradeInstall => "installed",
             &UpgradeMode::UpgradeWaitMaybeRollback => "upgraded",
             &UpgradeMode::UpgradeInstallWait => "reconciled",
+            &UpgradeMode::Canary => "upgraded (after canary)",
             &UpgradeMode::Apply => "applied",
         }.into()
     }
@@ -79,16 +86,23 @@ impl UpgradeMode {
 /// TODO: deprecate
 pub fn rollback(reg: &Region, ud: &UpgradeData, mf: &Manifest) -> Result<()> {
     assert!(ud.namespace.len() > 0);
-    let rollbackvec = vec![
-        format!("--tiller-namespace={}", ud.namespace),
-        "rollback".into(),
-        ud.name.clone(),
-        "0".into(), // magic helm number for previous
-    ];
-    info!("helm {}", rollbackvec.join(" "));
-
     webhooks::upgrade_rollback_event(UpgradeState::RollingBack, &ud, &reg);
-    match hexec(rollbackvec) {
+    let res = if ud.chart == native::NATIVE_CHART {
+        info!("Rolling back the deployments of {}", ud.name);
+        kube::rollout_undo(mf)
+    } else {
+        helpers::helm_version(reg).and_then(|hv| {
+            let mut rollbackvec = helpers::namespace_args(hv, &ud.namespace);
+            rollbackvec.extend_from_slice(&[
+                "rollback".into(),
+                ud.name.clone(),
+                "0".into(), // magic helm number for previous
+            ]);
+            info!("helm {}", rollbackvec.join(" "));
+            hexec(rollbackvec)
+        })
+    };
+    match res {
         Err(e) => {
             error!("{}", e);
             webhooks::upgrade_rollback_event(UpgradeState::RollbackFailed, &ud, &reg);
@@ -107,6 +121,10 @@ pub fn rollback(reg: &Region, ud: &UpgradeData, mf: &Manifest) -> Result<()> {
 pub fn rollback_wrapper(svc: &str, conf: &Config, region: &Region) -> Result<()> {
     let base = Manifest::base(svc, &conf, region)?;
     let ud = UpgradeData::from_rollback(&base);
+    if base.blueGreen.is_some() {
+        // instant switch back to the previous colour
+        return bluegreen::rollback(&region, &ud, &base);
+    }
     rollback(&region, &ud, &base)
 }
 
@@ -117,6 +135,8 @@ pub struct UpgradeData {
     pub name: String,
     /// Chart the service is using
     pub chart: String,
+    /// Helm version of the region
+    pub helm: HelmVersion,
     /// Validated version string
     pub version: String,
     /// Validated region requested for installation
@@ -133,23 +153,24 @@ pub struct UpgradeData {
     pub values: String,
     /// Metadata used in slack notifications
     pub metadata: Option<Metadata>,
+    /// Categorised reason when the rollout failed
+    pub failure: Option<RolloutFailure>,
+    /// Version running before the upgrade, when known
+    pub running: Option<String>,
 }
 
 impl UpgradeData {
     /// Prepare an upgrade data from values and manifest data
     ///
     /// Manifest must have had a version set or inferred as appropriate.
-    /// (DiffOnly can sneak by early due to it not being technically needed)
     ///
     /// Performs basic sanity checks, and populates canonical values that are reused a lot.
-    pub fn new(mf: &Manifest, hfile: &str, mode: UpgradeMode, exists: bool) ->  Result<Option<UpgradeData>> {
+    /// For DiffOnly, this is only returned when there is a diff, and must not be upgraded.
+    pub fn new(mf: &Manifest, hfile: &str, mode: UpgradeMode, exists: bool, helm: HelmVersion) ->  Result<Option<UpgradeData>> {
         let helmdiff = if !exists {
             "".into() // can't diff against what's not there!
         } else {
-            let hdiff = diff(mf, hfile, DiffMode::Upgrade)?;
-            if mode == UpgradeMode::DiffOnly {
-                return Ok(None)
-            }
+            let hdiff = diff(mf, hfile, helm, DiffMode::Upgrade)?;
             if hdiff.is_empty() && mode != UpgradeMode::UpgradeRecreateWait {
                 debug!("Not upgrading {} - empty diff", mf.name);
                 return Ok(None)
@@ -170,7 +191,9 @@ impl UpgradeData {
             region: mf.region.clone(),
             values: hfile.into(),
             namespace: mf.namespace.clone(),
-            mode, version
+            failure: None,
+            running: None,
+            mode, version, helm
         }))
     }
 
@@ -203,9 +226,21 @@ impl UpgradeData {
 }
 
 pub fn upgrade(data: &UpgradeData) -> Result<()> {
+    if data.chart == native::NATIVE_CHART {
+        // values holds the rendered objects, which get created when missing
+        info!("Applying the objects of {} from {}", data.name, data.values);
+        let objs = native::read(&data.values)?;
+        kube::apply_objects(&objs, &data.namespace, &data.name).chain_err(||
+            ErrorKind::HelmUpgradeFailure(data.name.clone())
+        )?;
+        if data.mode == UpgradeMode::UpgradeRecreateWait {
+            restart(data)?;
+        }
+        return Ok(());
+    }
     // upgrade it using the same command
-    let mut upgradevec = vec![
-        format!("--tiller-namespace={}", data.namespace),
+    let mut upgradevec = helpers::namespace_args(data.helm, &data.namespace);
+    upgradevec.extend_from_slice(&[
         "upgrade".into(),
         data.name.clone(),
         format!("charts/{}", data.chart),
@@ -213,18 +248,21 @@ pub fn upgrade(data: &UpgradeData) -> Result<()> {
         data.values.clone(),
         "--set".into(),
         format!("version={}", data.version),
-    ];
+    ]);
 
     // TODO: dedupe
     match data.mode {
-        UpgradeMode::UpgradeWaitMaybeRollback | UpgradeMode::UpgradeWait | UpgradeMode::UpgradeNoWait => {
+        UpgradeMode::UpgradeWaitMaybeRollback | UpgradeMode::UpgradeWait | UpgradeMode::UpgradeNoWait | UpgradeMode::Canary => {
             upgradevec.extend_from_slice(&[
             ]);
         },
         UpgradeMode::UpgradeRecreateWait => {
-            upgradevec.extend_from_slice(&[
-                "--recreate-pods".into(),
-            ]);
+            // Helm 3 dropped the flag, so its pods get restarted after the upgrade
+            if data.helm == HelmVersion::V2 {
+                upgradevec.extend_from_slice(&[
+                    "--recreate-pods".into(),
+                ]);
+            }
         },
         UpgradeMode::UpgradeInstall => {
             upgradevec.extend_from_slice(&[
@@ -246,6 +284,18 @@ pub fn upgrade(data: &UpgradeData) -> Result<()> {
     info!("helm {}", upgradevec.join(" "));
     hexec(upgradevec).chain_err(||
         ErrorKind::HelmUpgradeFailure(data.name.clone())
+    )?;
+    if data.mode == UpgradeMode::UpgradeRecreateWait && data.helm == HelmVersion::V3 {
+        restart(data)?;
+    }
+    Ok(())
+}
+
+/// Recreate the pods of an upgraded release where helm's `--recreate-pods` is unavailable
+fn restart(data: &UpgradeData) -> Result<()> {
+    info!("Restarting the deployments of {}", data.name);
+    kube::rollout_restart(&data.name, &data.namespace).chain_err(||
+        ErrorKind::HelmUpgradeFailure(data.name.clone())
     )
 }
 
@@ -266,11 +316,14 @@ impl fmt::Display for DiffMode {
 /// helm diff against current running release
 ///
 /// Shells out to helm diff, then obfuscates secrets
-fn diff(mf: &Manifest, hfile: &str, dmode: DiffMode) -> Result<String> {
+fn diff(mf: &Manifest, hfile: &str, hv: HelmVersion, dmode: DiffMode) -> Result<String> {
+    if native::is_native(mf) {
+        return native_diff(mf, hfile);
+    }
     let ver = mf.version.clone().unwrap(); // must be set outside
     let namespace = mf.namespace.clone();
-    let diffvec = vec![
-        format!("--tiller-namespace={}", namespace),
+    let mut diffvec = helpers::namespace_args(hv, &namespace);
+    diffvec.extend_from_slice(&[
         "diff".into(),
         dmode.to_string(),
         "--no-color".into(),
@@ -280,7 +333,7 @@ fn diff(mf: &Manifest, hfile: &str, dmode: DiffMode) -> Result<String> {
         "-f".into(),
         hfile.into(),
         format!("--version={}", ver),
-    ];
+    ]);
     info!("helm {}", diffvec.join(" "));
     let (hdiffunobfusc, hdifferr, _) = hout(diffvec.clone())?;
     let helmdiff = helpers::obfuscate_secrets(
@@ -288,8 +341,12 @@ fn diff(mf: &Manifest, hfile: &str, dmode: DiffMode) -> Result<String> {
         mf.get_secrets()
     );
     if !hdifferr.is_empty() {
-        if hdifferr.starts_with(&format!("Error: \"{}\" has no deployed releases", mf.name)) {
-            let cmd = format!("helm --tiller-namespace={} del --purge {}", namespace, mf.name);
+        // helm 3 does not say why, so ask it for the release state
+        let failed_install = hdifferr.starts_with(&format!("Error: \"{}\" has no deployed releases", mf.name)) ||
+            (hv == HelmVersion::V3 && helpers::release(&mf.name, &namespace, hv)?
+                .map(|r| r.is_failed_install()).unwrap_or(false));
+        if failed_install {
+            let cmd = helpers::purge_command(hv, &namespace, &mf.name);
             let reason = "to let you be able to retry the install/reconcile";
             error!("Previous installs of {} failed, you need to run: \n\t{}\n{}",
                 mf.name, cmd, reason
@@ -316,6 +373,56 @@ fn diff(mf: &Manifest, hfile: &str, dmode: DiffMode) -> Result<String> {
     Ok(smalldiff)
 }
 
+/// Diff of natively rendered objects against what was last applied
+fn native_diff(mf: &Manifest, file: &str) -> Result<String> {
+    info!("Diffing the objects of {} from {}", mf.name, file);
+    let objs = native::read(file)?;
+    let kdiff = helpers::obfuscate_secrets(kube::diff_objects(&objs, &mf.namespace, &mf.name)?, mf.get_secrets());
+    let smalldiff = helpers::diff_format(kdiff.clone());
+    if !kdiff.is_empty() {
+        debug!("{}", kdiff); // full diff for logs
+        println!("{}", smalldiff);
+    } else {
+        info!("{} is up to date", mf.name);
+    }
+    Ok(smalldiff)
+}
+
+/// Write the file an upgrade is made from, and return its name
+///
+/// Helm values for charts, and the rendered objects for `chart: native`.
+pub fn upgrade_file(mf: &Manifest) -> Result<String> {
+    if native::is_native(mf) {
+        let file = format!("{}.native.gen.yml", mf.name);
+        let mut f = File::create(Path::new(".").join(&file))?;
+        writeln!(f, "{}", native::template(mf)?)?;
+        return Ok(file);
+    }
+    let hfile = format!("{}.helm.gen.yml", mf.name);
+    values(mf, Some(hfile.clone()))?;
+    Ok(hfile)
+}
+
+/// Version of a service that is running now
+///
+/// Asks helm for the release values, or kube for native services.
+pub fn running_version(mf: &Manifest, hv: HelmVersion) -> Result<String> {
+    if native::is_native(mf) {
+        return kube::running_version(&mf.name, &mf.namespace);
+    }
+    helpers::infer_fallback_version(&mf.name, &mf.namespace, hv)
+}
+
+/// Helm version to upgrade a service with
+///
+/// Native services never call helm, so they do not need one detected.
+pub fn helm_version(mf: &Manifest, region: &Region) -> Result<HelmVersion> {
+    if native::is_native(mf) {
+        return Ok(HelmVersion::default());
+    }
+    helpers::helm_version(region)
+}
+
 /// Create helm values file for a service
 ///
 /// Requires a completed manifest (with inlined configs)
@@ -353,60 +460,93 @@ pub fn template(svc: &str, region: &Region, conf: &Config, ver: Option<String>,
         region.versioningScheme.verify(&v)?;
     }
 
-    let hfile = format!("{}.helm.gen.yml", svc);
+    let tpl = if native::is_native(&mf) {
+        native::template(&mf)?
+    } else {
+        helm_template(&mf, helpers::helm_version(region)?)?
+    };
+    if let Some(o) = output {
+        let pth = Path::new(".").join(o);
+        info!("Writing helm template for {} to {}", svc, pth.display());
+        let mut f = File::create(&pth)?;
+        writeln!(f, "{}", tpl)?;
+        debug!("Wrote helm template for {} to {}: \n{}", svc, pth.display(), tpl);
+    } else {
+        println!("{}", tpl);
+    }
+    Ok(tpl)
+}
+
+/// helm template of a completed manifest through its chart
+fn helm_template(mf: &Manifest, hv: HelmVersion) -> Result<String> {
+    let hfile = format!("{}.helm.gen.yml", mf.name);
     values(&mf, Some(hfile.clone()))?;
 
     // helm template with correct params
-    let tplvec = vec![
-        "template".into(),
-        format!("charts/{}", mf.chart.unwrap()),
+    let mut tplvec = vec!["template".into()];
+    if hv == HelmVersion::V3 {
+        tplvec.push(mf.name.clone()); // helm 3 takes the release name first
+    }
+    tplvec.extend_from_slice(&[
+        format!("charts/{}", mf.chart.clone().unwrap()),
         "-f".into(),
         hfile.clone(),
-    ];
-    // NB: this call does NOT need --tiller-namespace (offline call)
+    ]);
+    // NB: this call does NOT need a namespace (offline call)
     let (tpl, tplerr, success) = hout(tplvec.clone())?;
     if !success {
         warn!("{} stderr: {}", tplvec.join(" "), tplerr);
         bail!("helm template failed");
     }
-    if let Some(o) = output {
-        let pth = Path::new(".").join(o);
-        info!("Writing helm template for {} to {}", svc, pth.display());
-        let mut f = File::create(&pth)?;
-        writeln!(f, "{}", tpl)?;
-        debug!("Wrote helm template for {} to {}: \n{}", svc, pth.display(), tpl);
-    } else {
-        println!("{}", tpl);
-    }
     fs::remove_file(hfile)?;
     Ok(tpl)
 }
 
+/// Kube objects of a completed manifest, as `template` renders them
+///
+/// Natively rendered services skip helm entirely.
+pub fn rendered(mf: &Manifest, hv: HelmVersion) -> Result<Vec<Value>> {
+    if native::is_native(mf) {
+        let mut objs = vec![];
+        for o in native::render(mf)? {
+            objs.push(serde_json::to_value(o)?);
+        }
+        Ok(objs)
+    } else {
+        native::parse(&helm_template(mf, hv)?)
+    }
+}
+
 /// Helm history wrapper
 ///
-/// Analogue to `helm history {service}` uses the right tiller namespace
+/// Analogue to `helm history {service}` using the right namespace
+/// and the same output for both helm versions.
 pub fn history(svc: &str, conf: &Config, region: &Region) -> Result<()> {
     let mf = Manifest::base(svc, &conf, region)?;
-    let histvec = vec![
-        format!("--tiller-namespace={}", mf.namespace),
-        "history".into(),
-        svc.into(),
-    ];
-    debug!("helm {}", histvec.join(" "));
-    hexec(histvec)?;
+    if native::is_native(&mf) {
+        bail!("{} uses chart: native and has no helm history - see kubectl rollout history", svc);
+    }
+    let revs = helpers::history(svc, &mf.namespace, helpers::helm_version(region)?)?;
+    println!("{:<10}{:<32}{:<12}{:<20}{}", "REVISION", "UPDATED", "STATUS", "CHART", "DESCRIPTION");
+    for r in revs {
+        println!("{:<10}{:<32}{:<12}{:<20}{}", r.revision, r.updated, r.status, r.chart, r.description);
+    }
     Ok(())
 }
 
 /// Helm status wrapper
 ///
-/// Analogue to `helm status {service}` uses the right tiller namespace
+/// Analogue to `helm status {service}` uses the right namespace
 pub fn status(svc: &str, conf: &Config, region: &Region) -> Result<()> {
     let mf = Manifest::base(svc, &conf, region)?;
-    let histvec = vec![
-        format!("--tiller-namespace={}", mf.namespace),
+    if native::is_native(&mf) {
+        bail!("{} uses chart: native and has no helm status - see kubectl rollout history", svc);
+    }
+    let mut histvec = helpers::namespace_args(helpers::helm_version(region)?, &mf.namespace);
+    histvec.extend_from_slice(&[
         "status".into(),
         svc.into(),
-    ];
+    ]);
     debug!("helm {}", histvec.join(" "));
     hexec(histvec)?;
     Ok(())
@@ -450,6 +590,9 @@ pub fn upgrade_wrapper(svc: &str, mode: UpgradeMode, region: &Region, conf: &Con
     }
 
     let mut mf = Manifest::base(svc, conf, region)?.complete(region)?;
+    if mf.blueGreen.is_some() && (mode == UpgradeMode::UpgradeNoWait || mode == UpgradeMode::Canary) {
+        bail!("{} is deployed blue/green, which always waits for the new colour and cannot be combined with a canary", svc);
+    }
 
     // Ensure we have a version - or are able to infer one
     if ver.is_some() {
@@ -466,20 +609,47 @@ pub fn upgrade_wrapper(svc: &str, mode: UpgradeMode, region: &Region, conf: &Con
     // Other modes can infer in a pinch
 
     // ..but if they already exist on kube, don't block on that..
+    let hv = helm_version(&mf, region)?;
     if mf.version.is_none() {
-        mf.version = Some(helpers::infer_fallback_version(&svc, &mf.namespace)?);
+        mf.version = Some(running_version(&mf, hv)?);
     };
     // sanity verify what we changed (no-shoehorning in illegal versions in rolling envs)
     region.versioningScheme.verify(&mf.version.clone().unwrap())?;
 
-    // Template values file
-    let hfile = format!("{}.helm.gen.yml", &svc);
-    values(&mf, Some(hfile.clone()))?;
+    // Template values file (or objects)
+    let hfile = upgrade_file(&mf)?;
 
     // Sanity step that gives canonical upgrade data
-    let upgrade_opt = UpgradeData::new(&mf, &hfile, mode, exists)?;
-    if let Some(ref udata) = upgrade_opt {
+    let mut upgrade_opt = UpgradeData::new(&mf, &hfile, mode, exists, hv)?;
+    if mode == UpgradeMode::DiffOnly {
+        let _ = fs::remove_file(&hfile);
+        return Ok(upgrade_opt);
+    }
+    if let Some(ref mut udata) = upgrade_opt {
         webhooks::upgrade_event(UpgradeState::Pending, &udata, &region);
+        if udata.mode == UpgradeMode::Canary {
+            // the full upgrade below only happens when the canary bakes cleanly
+            if let Err(e) = canary::bake(udata, &mf, &region) {
+                let _ = fs::remove_file(&hfile);
+                return Err(e);
+            }
+        }
+        if mf.blueGreen.is_some() {
+            // the upgrade scales the main deployment down, so its traffic has to move first
+            match bluegreen::adopt(&mf) {
+                Ok(None) => {},
+                Ok(Some(failure)) => {
+                    udata.failure = Some(failure.clone());
+                    webhooks::upgrade_event(UpgradeState::Failed, &udata, &region);
+                    let _ = fs::remove_file(&hfile);
+                    return Err(failure.into_error(&mf));
+                },
+                Err(e) => {
+                    let _ = fs::remove_file(&hfile);
+                    return Err(e);
+                }
+            }
+        }
         match upgrade(&udata) {
             Err(e) => {
                 // if it failed here, rollback in job : TODO: FIX kube-deploy-X jobs
@@ -491,19 +661,23 @@ pub fn upgrade_wrapper(svc: &str, mode: UpgradeMode, region: &Region, conf: &Con
             },
             Ok(_) => {
                 // after helm upgrade / kubectl apply, check rollout status in a loop:
-                if udata.mode == UpgradeMode::UpgradeNoWait || kube::await_rollout_status(&mf)? {
-                    info!("successfully rolled out {}", &udata.name);
-                    webhooks::upgrade_event(UpgradeState::Completed, &udata, &region);
+                if udata.mode != UpgradeMode::UpgradeNoWait {
+                    udata.failure = kube::await_rollout_status(&mf)?;
+                    if udata.failure.is_none() && mf.blueGreen.is_some() {
+                        udata.failure = bluegreen::switch(&mf)?;
+                    }
                 }
-                else {
+                if let Some(failure) = udata.failure.clone() {
                     let _ = kube::debug_rollout_status(&mf);
                     let _ = kube::debug(&mf);
-                    warn!("failed to roll out {}", &udata.name);
+                    warn!("failed to roll out {}: {}", &udata.name, failure);
                     webhooks::upgrade_event(UpgradeState::Failed, &udata, &region);
                     // if it failed here, rollback in job : TODO: FIX kube-deploy-X jobs
                     handle_upgrade_rollbacks(&region, &udata, &mf)?; // for now leave it in..
-                    return Err(ErrorKind::UpgradeTimeout(mf.name.clone(), mf.estimate_wait_time()).into());
+                    return Err(failure.into_error(&mf));
                 }
+                info!("successfully rolled out {}", &udata.name);
+                webhooks::upgrade_event(UpgradeState::Completed, &udata, &region);
             }
         };
     }
@@ -1,7 +1,8 @@
 use serde_yaml;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use regex::Regex;
-use super::{Result};
+use super::{Result, Region, HelmVersion};
 
 
 pub fn diff_format(diff: String) -> String {
@@ -64,6 +65,62 @@ struct HelmVals {
     version: String,
 }
 
+/// A revision from `helm history {service}`
+///
+/// Both helm versions use the same keys, but Helm 2 shouts the status.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct HelmRevision {
+    pub revision: u32,
+    pub updated: String,
+    pub status: String,
+    pub chart: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// A release from `helm list`
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct HelmRelease {
+    pub name: String,
+    pub revision: u32,
+    pub status: String,
+    pub chart: String,
+}
+
+impl HelmRelease {
+    /// Whether the only revision of the release failed to install
+    ///
+    /// Such releases cannot be upgraded or diffed until they are removed.
+    pub fn is_failed_install(&self) -> bool {
+        self.revision == 1 && self.status == "failed"
+    }
+}
+
+/// Helm 2 wraps releases with capitalised keys
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct V2Releases {
+    #[serde(default)]
+    releases: Vec<V2Release>,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct V2Release {
+    name: String,
+    revision: u32,
+    status: String,
+    chart: String,
+}
+
+/// Helm 3 lists releases with the revision as a string
+#[derive(Deserialize)]
+struct V3Release {
+    name: String,
+    revision: String,
+    status: String,
+    chart: String,
+}
+
 pub fn hexec(args: Vec<String>) -> Result<()> {
     use std::process::Command;
     debug!("helm {}", args.join(" "));
@@ -82,14 +139,83 @@ pub fn hout(args: Vec<String>) -> Result<(String, String, bool)> {
     Ok((out, err, s.status.success()))
 }
 
-pub fn infer_fallback_version(service: &str, ns: &str) -> Result<String> {
+/// Helm major version detected from the client, 0 until asked
+static DETECTED_VERSION: AtomicUsize = AtomicUsize::new(0);
+
+/// Helm version of a region
+///
+/// Uses the region's `helmVersion`, and asks the helm client when unset.
+pub fn helm_version(reg: &Region) -> Result<HelmVersion> {
+    match reg.helmVersion {
+        Some(hv) => Ok(hv),
+        None => detect_version(),
+    }
+}
+
+/// Ask the helm client for its version
+///
+/// Only asks for the client version, as Helm 2 needs a tiller to report its own.
+/// The answer is remembered for the rest of the run.
+pub fn detect_version() -> Result<HelmVersion> {
+    match DETECTED_VERSION.load(Ordering::SeqCst) {
+        2 => return Ok(HelmVersion::V2),
+        3 => return Ok(HelmVersion::V3),
+        _ => {}
+    }
+    let (out, err, _) = hout(vec!["version".into(), "--short".into(), "--client".into()])?;
+    let hv = match parse_version(&out) {
+        Some(hv) => hv,
+        None => bail!("Could not detect helm version from '{}' {}", out.trim(), err.trim()),
+    };
+    debug!("Detected helm {:?}", hv);
+    DETECTED_VERSION.store(if hv == HelmVersion::V3 { 3 } else { 2 }, Ordering::SeqCst);
+    Ok(hv)
+}
+
+/// Parse `helm version --short --client`
+///
+/// Helm 2 prints `Client: v2.14.3+g0e7f3b6` and Helm 3 prints `v3.0.2+g19e47ee`.
+fn parse_version(out: &str) -> Option<HelmVersion> {
+    let ver_re = Regex::new(r"(^|\s)v(?P<major>\d+)\.").unwrap();
+    let caps = ver_re.captures(out.trim())?;
+    match &caps["major"] {
+        "2" => Some(HelmVersion::V2),
+        "3" => Some(HelmVersion::V3),
+        _ => None,
+    }
+}
+
+/// Arguments that point helm at the releases of a namespace
+///
+/// Helm 2 finds releases through the tiller in the namespace,
+/// while Helm 3 stores them in the namespace directly.
+pub fn namespace_args(hv: HelmVersion, ns: &str) -> Vec<String> {
+    match hv {
+        HelmVersion::V2 => vec![format!("--tiller-namespace={}", ns)],
+        HelmVersion::V3 => vec![format!("--namespace={}", ns)],
+    }
+}
+
+/// Command that removes a release entirely
+pub fn purge_command(hv: HelmVersion, ns: &str, service: &str) -> String {
+    match hv {
+        HelmVersion::V2 => format!("helm --tiller-namespace={} del --purge {}", ns, service),
+        HelmVersion::V3 => format!("helm --namespace={} uninstall {}", ns, service),
+    }
+}
+
+pub fn infer_fallback_version(service: &str, ns: &str, hv: HelmVersion) -> Result<String> {
     // fetch current version from helm
-    let imgvec = vec![
-        format!("--tiller-namespace={}", ns),
+    let mut imgvec = namespace_args(hv, ns);
+    imgvec.extend_from_slice(&[
         "get".into(),
         "values".into(),
         service.into(),
-    ];
+    ]);
+    if hv == HelmVersion::V3 {
+        // Helm 3 prefixes its default output with a header
+        imgvec.push("--output=json".into());
+    }
     debug!("helm {}", imgvec.join(" "));
     match hout(imgvec.clone()) {
         // got a result from helm + rc was 0:
@@ -99,20 +225,124 @@ pub fn infer_fallback_version(service: &str, ns: &str) -> Result<String> {
             }
             // if we got this far, release was found
             // it should work to parse the HelmVals subset of the values:
-            let values : HelmVals = serde_yaml::from_str(&vout.to_owned())?;
+            let values : HelmVals = match hv {
+                HelmVersion::V2 => serde_yaml::from_str(&vout.to_owned())?,
+                HelmVersion::V3 => serde_json::from_str(&vout)?,
+            };
             Ok(values.version)
         },
         _ => {
             // nothing from helm
-            bail!("Service {} not found in in {} tiller", service, ns);
+            match hv {
+                HelmVersion::V2 => bail!("Service {} not found in in {} tiller", service, ns),
+                HelmVersion::V3 => bail!("Service {} not found in helm releases of {}", service, ns),
+            }
         }
     }
 }
 
+/// Revisions of a release, oldest first
+pub fn history(service: &str, ns: &str, hv: HelmVersion) -> Result<Vec<HelmRevision>> {
+    let mut histvec = namespace_args(hv, ns);
+    histvec.extend_from_slice(&[
+        "history".into(),
+        service.into(),
+        "--output=json".into(),
+    ]);
+    let (out, err, success) = hout(histvec.clone())?;
+    if !success {
+        bail!("{} failed: {}", histvec.join(" "), err.trim());
+    }
+    parse_history(&out)
+}
+
+fn parse_history(out: &str) -> Result<Vec<HelmRevision>> {
+    let mut revs : Vec<HelmRevision> = serde_json::from_str(out)?;
+    for r in &mut revs {
+        r.status = r.status.to_lowercase();
+    }
+    Ok(revs)
+}
+
+/// The latest revision of a release, if it exists in any state
+pub fn release(service: &str, ns: &str, hv: HelmVersion) -> Result<Option<HelmRelease>> {
+    let mut listvec = namespace_args(hv, ns);
+    listvec.extend_from_slice(&[
+        "list".into(),
+        "--all".into(),
+        format!("--filter=^{}$", service),
+        "--output=json".into(),
+    ]);
+    let (out, err, success) = hout(listvec.clone())?;
+    if !success {
+        bail!("{} failed: {}", listvec.join(" "), err.trim());
+    }
+    Ok(parse_releases(&out, hv)?.into_iter().find(|r| r.name == service))
+}
+
+fn parse_releases(out: &str, hv: HelmVersion) -> Result<Vec<HelmRelease>> {
+    if out.trim().is_empty() {
+        return Ok(vec![]); // helm 2 prints nothing without releases
+    }
+    let res = match hv {
+        HelmVersion::V2 => {
+            let rels : V2Releases = serde_json::from_str(out)?;
+            rels.releases.into_iter().map(|r| HelmRelease {
+                name: r.name, revision: r.revision, status: r.status.to_lowercase(), chart: r.chart,
+            }).collect()
+        },
+        HelmVersion::V3 => {
+            let rels : Vec<V3Release> = serde_json::from_str(out)?;
+            let mut res = vec![];
+            for r in rels {
+                let revision = r.revision.parse()
+                    .map_err(|_| format!("Invalid revision {} of {}", r.revision, r.name))?;
+                res.push(HelmRelease { name: r.name, revision, status: r.status.to_lowercase(), chart: r.chart });
+            }
+            res
+        }
+    };
+    Ok(res)
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::{infer_version_change, diff_is_version_only};
+    use super::{parse_version, parse_history, parse_releases, namespace_args, HelmVersion};
+
+    #[test]
+    fn helm_versions() {
+        assert_eq!(parse_version("Client: v2.14.3+g0e7f3b6\n"), Some(HelmVersion::V2));
+        assert_eq!(parse_version("v3.0.2+g19e47ee\n"), Some(HelmVersion::V3));
+        assert_eq!(parse_version("Error: unknown flag: --short"), None);
+
+        assert_eq!(namespace_args(HelmVersion::V2, "dev"), vec!["--tiller-namespace=dev"]);
+        assert_eq!(namespace_args(HelmVersion::V3, "dev"), vec!["--namespace=dev"]);
+    }
+
+    #[test]
+    fn helm_releases() {
+        let v2 = r#"{"Next":"","Releases":[{"Name":"fake-ask","Revision":4,"Updated":"Mon Dec  2 11:04:13 2019",
+            "Status":"DEPLOYED","Chart":"base-0.1.0","AppVersion":"","Namespace":"dev"}]}"#;
+        let rels = parse_releases(v2, HelmVersion::V2).unwrap();
+        assert_eq!(rels[0].revision, 4);
+        assert_eq!(rels[0].status, "deployed");
+        assert!(parse_releases("", HelmVersion::V2).unwrap().is_empty());
+
+        let v3 = r#"[{"name":"fake-ask","namespace":"dev","revision":"1","updated":"2019-12-02 11:04:13.1 +0000 UTC",
+            "status":"failed","chart":"base-0.1.0","app_version":""}]"#;
+        let rels = parse_releases(v3, HelmVersion::V3).unwrap();
+        assert_eq!(rels[0].revision, 1);
+        assert!(rels[0].is_failed_install());
+
+        let hist = r#"[{"revision":1,"updated":"2019-12-02T11:04:13Z","status":"SUPERSEDED","chart":"base-0.1.0","description":"Install complete"},
+            {"revision":2,"updated":"2019-12-03T09:00:00Z","status":"deployed","chart":"base-0.1.0","app_version":"","description":"Upgrade complete"}]"#;
+        let revs = parse_history(hist).unwrap();
+        assert_eq!(revs.len(), 2);
+        assert_eq!(revs[0].status, "superseded");
+        assert_eq!(revs[1].description, "Upgrade complete");
+    }
 
     #[test]
     fn version_change_test() {
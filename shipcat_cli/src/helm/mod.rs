@@ -1,7 +1,7 @@
 /// Allow normal error handling from structs
 pub use super::{Result, ResultExt, ErrorKind, Error};
 /// Verify trait gets the Config
-pub use super::{Config, Region, VersionScheme, AuditWebhook};
+pub use super::{Config, Region, VersionScheme, AuditWebhook, HelmVersion};
 /// Need basic manifest handling
 pub use super::Manifest;
 
@@ -22,9 +22,16 @@ pub mod direct;
 // Re-exports for main
 pub use self::direct::{history, template, values, status};
 
+/// Canary upgrades (used by direct upgrades)
+pub mod canary;
+
+/// Blue/green colour switching (used by direct and parallel upgrades)
+pub mod bluegreen;
+
 /// Helm related helpers
 pub mod helpers;
 // Commonly used helper
 pub use self::helpers::infer_fallback_version;
 
 pub use self::direct::{UpgradeMode, UpgradeData};
+pub use self::parallel::ReconcileOpts;
@@ -1,16 +1,98 @@
 use threadpool::ThreadPool;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::channel;
-use std::fs;
+use std::{fs, thread, time};
 
 use super::{Config, Manifest, Region};
 use super::{UpgradeMode, UpgradeData};
-use super::direct;
-use super::helpers;
+use super::{direct, bluegreen};
 use super::kube;
+use crate::graph;
+use crate::report::{self, Report, ReportTarget, ServiceReport, ServiceStatus};
 use crate::webhooks::{self, UpgradeState};
 use super::{Result, Error, ErrorKind};
 
 
+/// How a mass helm operation schedules its services
+#[derive(Clone, Debug)]
+pub struct ReconcileOpts {
+    /// Number of services handled at the same time
+    pub workers: usize,
+    /// Reconcile in waves of the dependency graph
+    pub ordered: bool,
+    /// Stop starting new upgrades after this many failures
+    pub max_failures: Option<usize>,
+    /// Most services started in the same wave
+    pub wave_size: Option<usize>,
+    /// Seconds to wait between waves
+    pub pause: u64,
+    /// Where to write a report of every service's outcome
+    pub report: Option<ReportTarget>,
+}
+
+impl Default for ReconcileOpts {
+    fn default() -> Self {
+        ReconcileOpts {
+            workers: 8,
+            ordered: false,
+            max_failures: None,
+            wave_size: None,
+            pause: 0,
+            report: None,
+        }
+    }
+}
+
+impl ReconcileOpts {
+    fn over_budget(&self, failures: usize) -> bool {
+        self.max_failures.map(|max| failures >= max).unwrap_or(false)
+    }
+
+    /// Time to wait before starting the wave at `index`
+    ///
+    /// Nothing before the first wave, or once over budget as that wave will not start anything.
+    fn pause_before(&self, index: usize, failures: usize) -> Option<time::Duration> {
+        if index == 0 || self.pause == 0 || self.over_budget(failures) {
+            return None;
+        }
+        Some(time::Duration::from_secs(self.pause))
+    }
+
+    /// Names of the services to start together, in order
+    ///
+    /// Dependency graph levels when `ordered` (or a single wave on cycles),
+    /// each split into chunks of at most `wave_size`.
+    fn waves(&self, svcs: &[Manifest]) -> Vec<Vec<String>> {
+        let all = || vec![svcs.iter().map(|mf| mf.name.clone()).collect::<Vec<_>>()];
+        let waves = if self.ordered {
+            graph::waves(svcs).unwrap_or_else(|e| {
+                warn!("{} - reconciling without ordering", e);
+                all()
+            })
+        } else {
+            all()
+        };
+        match self.wave_size {
+            Some(n) if n > 0 => waves.iter().flat_map(|w| w.chunks(n).map(|c| c.to_vec())).collect(),
+            _ => waves,
+        }
+    }
+}
+
+/// What a mass helm operation did with a service
+pub enum Outcome {
+    /// Upgraded or installed, or differs when only diffing
+    Reconciled(UpgradeData),
+    /// Up to date
+    Unchanged,
+    /// Failed, or skipped because a dependency failed
+    Failed(Error),
+    /// Never started because the failure budget ran out
+    NotStarted,
+}
+
 /// Stable threaded mass helm operation
 ///
 /// Reads secrets first, dumps all the helm values files
@@ -18,55 +100,193 @@ use super::{Result, Error, ErrorKind};
 /// The helm operations does --wait for upgrades, but this parallelises the wait
 /// and catches any errors.
 /// All operations run to completion and the first error is returned at end if any.
-pub fn reconcile(svcs: Vec<Manifest>, conf: &Config, region: &Region, umode: UpgradeMode, n_workers: usize) -> Result<()> {
+///
+/// Services are started in waves. When `ordered`, the waves follow the dependency graph,
+/// so that every service waits for its dependencies to roll out,
+/// and services whose dependencies failed are skipped.
+/// Waves are further split up by `wave_size`, optionally pausing in between.
+///
+/// Once `max_failures` services failed, no new upgrades are started,
+/// and the run ends with a report of what did and did not happen.
+///
+/// The outcome of every service is written to `report` when set.
+pub fn reconcile(svcs: Vec<Manifest>, conf: &Config, region: &Region, umode: UpgradeMode, opts: &ReconcileOpts) -> Result<()> {
+    if opts.max_failures == Some(0) {
+        bail!("The failure budget must allow at least one failure");
+    }
     let n_jobs = svcs.len();
-    let pool = ThreadPool::new(n_workers);
-    info!("Starting {} parallel helm jobs using {} workers", n_jobs, n_workers);
+    let start = time::Instant::now();
+    let versions : BTreeMap<String, Option<String>> = svcs.iter().map(|mf| (mf.name.clone(), mf.version.clone())).collect();
+    let waves = opts.waves(&svcs);
+    let deps : BTreeMap<String, Vec<String>> = svcs.iter()
+        .map(|mf| (mf.name.clone(), mf.dependencies.iter().map(|d| d.name.clone()).collect()))
+        .collect();
+    let mut pending : BTreeMap<String, Manifest> = svcs.into_iter().map(|mf| (mf.name.clone(), mf)).collect();
+
+    let pool = ThreadPool::new(opts.workers);
+    info!("Starting {} parallel helm jobs using {} workers", n_jobs, opts.workers);
     webhooks::reconcile_event(UpgradeState::Pending, &region);
 
-    let (tx, rx) = channel();
-    for mf in svcs {
-        // satisfying thread safety
-        let mode = umode.clone();
-        let reg = region.clone();
-        let config = conf.clone();
-
-        let tx = tx.clone(); // tx channel reused in each thread
-        pool.execute(move || {
-            info!("Running {} for {}", mode, mf.name);
-            let res = reconcile_worker(mf, mode, config, reg);
-            tx.send(res).expect("channel will be there waiting for the pool");
-        });
-    }
-
-    // wait for threads collect errors
-    let res = rx.iter().take(n_jobs).map(|r| {
-        match &r {
-            &Ok(Some(ref ud)) => debug!("{} {}", ud.mode, ud.name),
-            &Ok(None) => {},
-            &Err(ref e) => warn!("{} error: {}", umode, e),
+    // counted inside the workers so queued jobs see failures straight away
+    let failures = Arc::new(AtomicUsize::new(0));
+    let mut failed : BTreeSet<String> = BTreeSet::new();
+    let mut results : Vec<(String, Outcome, time::Duration)> = vec![];
+    for (i, wave) in waves.iter().enumerate() {
+        if let Some(pause) = opts.pause_before(i, failures.load(Ordering::SeqCst)) {
+            info!("Pausing {}s before the next wave", pause.as_secs());
+            thread::sleep(pause);
+        }
+        if waves.len() > 1 {
+            info!("Reconciling wave {}/{}: {}", i + 1, waves.len(), wave.join(", "));
         }
-        r
-    }).filter_map(Result::err).collect::<Vec<_>>();
+        let (tx, rx) = channel();
+        let mut n_wave = 0;
+        for svc in wave {
+            let mf = pending.remove(svc).expect("waves cover every service once");
+            if opts.over_budget(failures.load(Ordering::SeqCst)) {
+                results.push((svc.clone(), Outcome::NotStarted, time::Duration::default()));
+                continue;
+            }
+            if let Some(dep) = deps[svc].iter().find(|d| failed.contains(*d)) {
+                warn!("Skipping {} as its dependency {} did not roll out", svc, dep);
+                failed.insert(svc.clone());
+                let e = ErrorKind::DependencyFailed(svc.clone(), dep.clone()).into();
+                results.push((svc.clone(), Outcome::Failed(e), time::Duration::default()));
+                continue;
+            }
+            // satisfying thread safety
+            let mode = umode.clone();
+            let reg = region.clone();
+            let config = conf.clone();
+            let opts = opts.clone();
+            let failures = failures.clone();
+
+            let tx = tx.clone(); // tx channel reused in each thread
+            n_wave += 1;
+            pool.execute(move || {
+                let name = mf.name.clone();
+                let started = time::Instant::now();
+                let outcome = if opts.over_budget(failures.load(Ordering::SeqCst)) {
+                    Outcome::NotStarted
+                } else {
+                    info!("Running {} for {}", mode, mf.name);
+                    match reconcile_worker(mf, mode, config, reg) {
+                        Ok(Some(ud)) => Outcome::Reconciled(ud),
+                        Ok(None) => Outcome::Unchanged,
+                        Err(e) => {
+                            if !is_ignorable(&e) {
+                                failures.fetch_add(1, Ordering::SeqCst);
+                            }
+                            Outcome::Failed(e)
+                        }
+                    }
+                };
+                tx.send((name, outcome, started.elapsed())).expect("channel will be there waiting for the pool");
+            });
+        }
+
+        // wait for the wave to finish and collect errors
+        for (svc, o, took) in rx.iter().take(n_wave) {
+            match &o {
+                Outcome::Reconciled(ud) => debug!("{} {}", ud.mode, ud.name),
+                Outcome::Failed(e) => {
+                    warn!("{} error: {}", umode, e);
+                    if !is_ignorable(e) {
+                        failed.insert(svc.clone());
+                    }
+                },
+                Outcome::Unchanged | Outcome::NotStarted => {},
+            }
+            results.push((svc, o, took));
+        }
+    }
+
+    if let Some(target) = &opts.report {
+        let services = results.iter()
+            .map(|(svc, o, took)| service_report(svc, o, *took, &umode, &versions[svc]))
+            .collect();
+        let operation = if umode == UpgradeMode::DiffOnly { "helm diff" } else { "helm reconcile" };
+        target.write(&Report::new(operation, &region.name, start.elapsed(), services));
+    }
+
+    let n_failed = failures.load(Ordering::SeqCst);
+    if opts.over_budget(n_failed) {
+        partial_report(&results);
+        webhooks::reconcile_event(UpgradeState::Failed, &region);
+        let n_skipped = results.iter().filter(|(_, o, _)| match o { Outcome::NotStarted => true, _ => false }).count();
+        bail!(ErrorKind::FailureBudgetExceeded(n_failed, n_skipped));
+    }
 
     // propagate first non-ignorable error if exists
-    for e in res {
-        match e {
-            Error(ErrorKind::MissingRollingVersion(svc),_) => {
+    for (_, o, _) in results {
+        match o {
+            Outcome::Failed(Error(ErrorKind::MissingRollingVersion(svc),_)) => {
                 // This only happens in rolling envs because version is mandatory in other envs
                 warn!("'{}' missing version for {} - please add or install", svc, region.name);
             },
             // remaining cases not ignorable
-            _ => {
+            Outcome::Failed(e) => {
                 webhooks::reconcile_event(UpgradeState::Failed, &region);
                 return Err(e)
             },
+            _ => {},
         }
     }
     webhooks::reconcile_event(UpgradeState::Completed, &region);
     Ok(())
 }
 
+/// Errors that do not fail a reconcile
+fn is_ignorable(e: &Error) -> bool {
+    match e {
+        Error(ErrorKind::MissingRollingVersion(_), _) => true,
+        _ => false,
+    }
+}
+
+/// Report entry for the outcome of one service
+fn service_report(svc: &str, o: &Outcome, took: time::Duration, umode: &UpgradeMode, version: &Option<String>) -> ServiceReport {
+    let rep = match o {
+        Outcome::Reconciled(ud) => {
+            let status = if ud.mode == UpgradeMode::DiffOnly { ServiceStatus::Diffed } else { ServiceStatus::Reconciled };
+            let mut rep = ServiceReport::new(svc, status, &ud.mode.to_string());
+            rep.oldVersion = ud.running.clone();
+            rep.newVersion = Some(ud.version.clone());
+            rep.diff = Some(report::diff_summary(&ud.diff));
+            rep.waitTime = Some(ud.waittime);
+            rep
+        },
+        Outcome::Unchanged => ServiceReport::new(svc, ServiceStatus::Unchanged, &umode.to_string()),
+        Outcome::NotStarted => ServiceReport::new(svc, ServiceStatus::NotStarted, &umode.to_string()),
+        Outcome::Failed(e) => {
+            let status = if is_ignorable(e) { ServiceStatus::Ignored } else { ServiceStatus::Failed };
+            ServiceReport::new(svc, status, &umode.to_string()).error(e)
+        },
+    };
+    let mut rep = rep.duration(took);
+    if rep.newVersion.is_none() {
+        rep.newVersion = version.clone();
+    }
+    rep
+}
+
+/// Log what a reconcile stopped early did and did not get to
+fn partial_report(results: &[(String, Outcome, time::Duration)]) {
+    let (mut reconciled, mut unchanged, mut not_started) = (vec![], vec![], vec![]);
+    warn!("Reconcile stopped after too many failures");
+    for (svc, o, _) in results {
+        match o {
+            Outcome::Reconciled(_) => reconciled.push(svc.as_str()),
+            Outcome::Unchanged => unchanged.push(svc.as_str()),
+            Outcome::NotStarted => not_started.push(svc.as_str()),
+            Outcome::Failed(e) => warn!("Failed {}: {}", svc, e),
+        }
+    }
+    info!("Reconciled ({}): {}", reconciled.len(), reconciled.join(", "));
+    info!("Unchanged ({}): {}", unchanged.len(), unchanged.join(", "));
+    warn!("Not started ({}): {}", not_started.len(), not_started.join(", "));
+}
+
 
 /// Parallel reconcile worker that reports information sequentially
 ///
@@ -78,7 +298,8 @@ fn reconcile_worker(mut mf: Manifest, mode: UpgradeMode, _conf: Config, region:
 
     // get version running now (to limit race condition with deploys)
     // this query also lets us detect if we have to install or simply upgrade
-    let (exists, fallback) = match helpers::infer_fallback_version(&svc, &region.namespace) {
+    let hv = direct::helm_version(&mf, &region)?;
+    let (exists, fallback) = match direct::running_version(&mf, hv) {
         Ok(running_ver) => (true, running_ver),
         Err(e) => {
             if let Some(v) = mf.version.clone() {
@@ -94,6 +315,7 @@ fn reconcile_worker(mut mf: Manifest, mode: UpgradeMode, _conf: Config, region:
         }
     };
     debug!("reconcile worker {} - exists?{} fallback:{}", mf.name, exists, fallback);
+    let running = if exists { Some(fallback.clone()) } else { None };
 
     // only override version if not in manifests
     if mf.version.is_none() {
@@ -103,14 +325,30 @@ fn reconcile_worker(mut mf: Manifest, mode: UpgradeMode, _conf: Config, region:
     region.versioningScheme.verify(&mf.version.clone().unwrap())?;
 
 
-    // Template values file
-    let hfile = format!("{}.helm.gen.yml", &svc);
-    direct::values(&mf, Some(hfile.clone()))?;
+    // Template values file (or objects)
+    let hfile = direct::upgrade_file(&mf)?;
 
-    let upgrade_opt = UpgradeData::new(&mf, &hfile, mode, exists)?;
-    if let Some(ref udata) = upgrade_opt {
+    let mut upgrade_opt = UpgradeData::new(&mf, &hfile, mode, exists, hv)?;
+    if let Some(ref mut udata) = upgrade_opt {
+        udata.running = running;
+    }
+    if mode == UpgradeMode::DiffOnly {
+        let _ = fs::remove_file(&hfile);
+        return Ok(upgrade_opt);
+    }
+    if let Some(ref mut udata) = upgrade_opt {
         webhooks::upgrade_event(UpgradeState::Pending, &udata, &region);
 
+        if mf.blueGreen.is_some() && exists {
+            // the upgrade scales the main deployment down, so its traffic has to move first
+            if let Some(failure) = bluegreen::adopt(&mf)? {
+                error!("Moving {} to blue/green failed: {}", mf.name, failure);
+                udata.failure = Some(failure.clone());
+                webhooks::upgrade_event(UpgradeState::Failed, &udata, &region);
+                return Err(failure.into_error(&mf));
+            }
+        }
+
         // upgrade in given mode, potentially rolling back a failure
         match direct::upgrade(&udata) {
             Err(e) => {
@@ -121,20 +359,82 @@ fn reconcile_worker(mut mf: Manifest, mode: UpgradeMode, _conf: Config, region:
             }
             Ok(_)  => {
                 // after helm upgrade / kubectl apply, check rollout status in a loop:
-                if kube::await_rollout_status(&mf)? {
-                    info!("successfully rolled out {}", &udata.name);
-                    // notify about the result directly as they happen
-                    webhooks::upgrade_event(UpgradeState::Completed, &udata, &region);
-                } else {
-                    error!("Rollout of {} timed out", mf.name);
+                udata.failure = kube::await_rollout_status(&mf)?;
+                if udata.failure.is_none() && mf.blueGreen.is_some() {
+                    udata.failure = bluegreen::switch(&mf)?;
+                }
+                if let Some(failure) = udata.failure.clone() {
+                    error!("Rollout of {} failed: {}", mf.name, failure);
                     kube::debug(&mf)?;
                     webhooks::upgrade_event(UpgradeState::Failed, &udata, &region);
                     // need set this as a reconcile level error
-                    return Err(ErrorKind::UpgradeTimeout(mf.name.clone(), mf.estimate_wait_time()).into());
+                    return Err(failure.into_error(&mf));
                 }
+                info!("successfully rolled out {}", &udata.name);
+                // notify about the result directly as they happen
+                webhooks::upgrade_event(UpgradeState::Completed, &udata, &region);
             }
         }
     }
     let _ = fs::remove_file(&hfile); // try to remove temporary file
     Ok(upgrade_opt)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{ReconcileOpts, Manifest};
+    use crate::structs::Dependency;
+
+    fn service(name: &str, deps: &[&str]) -> Manifest {
+        let mut mf = Manifest::default();
+        mf.name = name.into();
+        mf.dependencies = deps.iter().map(|d| Dependency { name: d.to_string(), ..Default::default() }).collect();
+        mf
+    }
+
+    #[test]
+    fn failure_budget() {
+        let opts = ReconcileOpts::default();
+        assert!(!opts.over_budget(100)); // unlimited by default
+        let opts = ReconcileOpts { max_failures: Some(2), ..Default::default() };
+        assert!(!opts.over_budget(0));
+        assert!(!opts.over_budget(1));
+        assert!(opts.over_budget(2));
+        assert!(opts.over_budget(3));
+    }
+
+    #[test]
+    fn wave_chunking() {
+        let svcs = vec![service("a", &[]), service("b", &["a"]), service("c", &[]), service("d", &["b"])];
+        let opts = ReconcileOpts::default();
+        assert_eq!(opts.waves(&svcs), vec![vec!["a", "b", "c", "d"]]);
+
+        let opts = ReconcileOpts { wave_size: Some(3), ..Default::default() };
+        assert_eq!(opts.waves(&svcs), vec![vec!["a", "b", "c"], vec!["d"]]);
+
+        // dependency levels are chunked individually
+        let opts = ReconcileOpts { ordered: true, wave_size: Some(1), ..Default::default() };
+        assert_eq!(opts.waves(&svcs), vec![vec!["a"], vec!["c"], vec!["b"], vec!["d"]]);
+
+        // zero is treated as unlimited
+        let opts = ReconcileOpts { wave_size: Some(0), ..Default::default() };
+        assert_eq!(opts.waves(&svcs).len(), 1);
+
+        // cycles fall back to a single unordered wave
+        let cyclic = vec![service("a", &["b"]), service("b", &["a"])];
+        let opts = ReconcileOpts { ordered: true, ..Default::default() };
+        assert_eq!(opts.waves(&cyclic), vec![vec!["a", "b"]]);
+    }
+
+    #[test]
+    fn wave_pauses() {
+        let opts = ReconcileOpts::default();
+        assert_eq!(opts.pause_before(1, 0), None);
+
+        let opts = ReconcileOpts { pause: 30, max_failures: Some(1), ..Default::default() };
+        assert_eq!(opts.pause_before(0, 0), None); // never before the first wave
+        assert_eq!(opts.pause_before(1, 0), Some(Duration::from_secs(30)));
+        assert_eq!(opts.pause_before(2, 1), None); // nothing left to start
+    }
+}
@@ -1,7 +1,9 @@
-use super::{Result, Manifest};
-use regex::Regex;
-use serde_yaml;
+use std::fmt;
+
+use super::{Result, Error, ErrorKind, Manifest};
+use super::kubeapi::{self, Client, KubeConfig, Event, Object, ObjectList, Pod};
 use chrono::{Utc, DateTime};
+use serde_json::{json, Value};
 
 fn kexec(args: Vec<String>) -> Result<()> {
     use std::process::Command;
@@ -12,135 +14,397 @@ fn kexec(args: Vec<String>) -> Result<()> {
     }
     Ok(())
 }
-fn kout(args: Vec<String>) -> Result<String> {
-    use std::process::Command;
-    debug!("kubectl {}", args.join(" "));
-    let s = Command::new("kubectl").args(&args).output()?;
-    let out : String = String::from_utf8_lossy(&s.stdout).into();
-    let err : String = String::from_utf8_lossy(&s.stderr).into();
-    if !err.is_empty() {
-        warn!("kubectl {} stderr: {}", args.join(" "), err.trim());
-    }
-    // kubectl keeps returning opening and closing apostrophes - strip them:
-    if out.len() > 2 && out.starts_with('\'') {
-        let res = out.split('\'').collect::<Vec<_>>()[1];
-        return Ok(res.trim().into());
-    }
-    Ok(out)
-}
 
 /// CLI way to resolve kube context
 ///
 /// Should only be used from main.
 pub fn current_context() -> Result<String> {
-    let mut res = kout(vec!["config".into(), "current-context".into()]).map_err(|e| {
-        error!("Failed to Get kubectl config current-context. Is kubectl installed?");
+    let kc = KubeConfig::read().map_err(|e| {
+        error!("Failed to read the current context from your kubeconfig");
         e
     })?;
-    let len = res.len();
-    if res.ends_with('\n') {
-        res.truncate(len - 1);
+    Ok(kc.current_context.trim().into())
+}
+
+/// Annotation that deployments and their replicasets share for every revision
+const REVISION_ANNOTATION: &str = "deployment.kubernetes.io/revision";
+/// Pod template annotation that `kubectl rollout restart` sets to roll a deployment
+const RESTARTED_AT_ANNOTATION: &str = "kubectl.kubernetes.io/restartedAt";
+
+/// Restarts of a new pod before we consider it crashing
+const RESTART_LIMIT: u32 = 3;
+
+/// Why a rollout was aborted
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum FailureReason {
+    /// Containers keep exiting
+    CrashLoop,
+    /// Containers get killed by a failing liveness probe
+    FailedProbe,
+    /// The image cannot be pulled
+    ImagePull,
+    /// Containers cannot be created (typically missing secrets or config maps)
+    ContainerConfig,
+    /// Not ready within the estimated wait time
+    Timeout,
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            FailureReason::CrashLoop => "crash loop",
+            FailureReason::FailedProbe => "failed liveness probe",
+            FailureReason::ImagePull => "image pull failure",
+            FailureReason::ContainerConfig => "container config error",
+            FailureReason::Timeout => "timeout",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// A categorised rollout failure
+#[derive(Serialize, Debug, Clone)]
+pub struct RolloutFailure {
+    /// Category of the failure
+    pub reason: FailureReason,
+    /// Pod or workload that failed
+    pub object: String,
+    /// Message from kube explaining the failure
+    pub message: String,
+}
+
+impl fmt::Display for RolloutFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in {}", self.reason, self.object)?;
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl RolloutFailure {
+    /// The error an upgrade fails with
+    pub fn into_error(self, mf: &Manifest) -> Error {
+        match self.reason {
+            FailureReason::Timeout => ErrorKind::UpgradeTimeout(mf.name.clone(), mf.estimate_wait_time()).into(),
+            _ => ErrorKind::UpgradeRolloutFailure(mf.name.clone(), self.to_string()).into(),
+        }
+    }
+}
+
+/// Categorise a pod that will not become ready without intervention
+///
+/// Returns the reason along with the container message.
+pub fn pod_failure(pod: &Pod) -> Option<(FailureReason, String)> {
+    if let Some(failure) = pod_start_failure(pod) {
+        return Some(failure);
+    }
+    for c in &pod.status.containerStatuses {
+        match &c.state.waiting {
+            Some(w) if w.reason == "CrashLoopBackOff" => {
+                let msg = if w.message.is_empty() { w.reason.clone() } else { w.message.clone() };
+                return Some((FailureReason::CrashLoop, format!("{}: {}", c.name, msg)));
+            }
+            _ => {}
+        }
+        if c.restartCount >= RESTART_LIMIT {
+            return Some((FailureReason::CrashLoop, format!("{}: restarted {} times", c.name, c.restartCount)));
+        }
+    }
+    None
+}
+
+/// Categorise a pod whose containers cannot even start
+///
+/// Unlike `pod_failure`, crashing containers are left for the caller to judge.
+pub fn pod_start_failure(pod: &Pod) -> Option<(FailureReason, String)> {
+    for c in &pod.status.containerStatuses {
+        if let Some(w) = &c.state.waiting {
+            let reason = match w.reason.as_str() {
+                "ErrImagePull" | "ImagePullBackOff" | "InvalidImageName" | "ErrImageNeverPull" => FailureReason::ImagePull,
+                "CreateContainerConfigError" | "CreateContainerError" => FailureReason::ContainerConfig,
+                _ => continue,
+            };
+            let msg = if w.message.is_empty() { w.reason.clone() } else { w.message.clone() };
+            return Some((reason, format!("{}: {}", c.name, msg)));
+        }
     }
+    None
+}
+
+/// Rollout progress of a Deployment or StatefulSet in a release
+#[derive(Debug, Clone)]
+pub struct RolloutStatus {
+    /// Kind of workload
+    pub kind: String,
+    /// Name of the workload
+    pub name: String,
+    /// Updated replicas that are available
+    pub ready: u32,
+    /// Replicas asked for
+    pub desired: u32,
+    /// Whether the latest spec is fully rolled out
+    pub rolledOut: bool,
+    /// Estimated seconds the rollout should take
+    pub waitTime: u32,
+    /// Latest revision; the deployment revision or the statefulset update revision
+    pub revision: String,
+}
+
+impl RolloutStatus {
+    fn new(kind: &str, name: &str, mf: &Manifest) -> RolloutStatus {
+        // workers roll out their own replica counts
+        let waitTime = match mf.workers.iter().find(|w| w.name == name) {
+            Some(w) => mf.estimate_worker_wait_time(w),
+            None => mf.estimate_wait_time(),
+        };
+        RolloutStatus {
+            kind: kind.into(),
+            name: name.into(),
+            ready: 0,
+            desired: 0,
+            rolledOut: false,
+            revision: "".into(),
+            waitTime,
+        }
+    }
+
+    fn from_deployment(d: &kubeapi::Deployment, mf: &Manifest) -> RolloutStatus {
+        let mut rs = RolloutStatus::new("Deployment", &d.metadata.name, mf);
+        rs.ready = std::cmp::min(d.status.updatedReplicas, d.status.availableReplicas);
+        rs.desired = d.spec.replicas.unwrap_or(1);
+        rs.rolledOut = d.rolled_out();
+        rs.revision = d.metadata.annotations.get(REVISION_ANNOTATION).cloned().unwrap_or_default();
+        rs
+    }
+
+    fn from_statefulset(ss: &kubeapi::StatefulSet, mf: &Manifest) -> RolloutStatus {
+        let mut rs = RolloutStatus::new("StatefulSet", &ss.metadata.name, mf);
+        rs.ready = ss.status.readyReplicas;
+        rs.desired = ss.spec.replicas.unwrap_or(1);
+        rs.rolledOut = ss.rolled_out();
+        rs.revision = ss.status.updateRevision.clone();
+        rs
+    }
+}
+
+/// Rollout progress of every Deployment and StatefulSet in a service's release
+///
+/// Workloads are found through the `release` label the charts set.
+/// The main deployment and the workers are always included,
+/// and reported as not rolled out if they do not exist (yet).
+fn rollout_status(client: &Client, mf: &Manifest) -> Result<Vec<RolloutStatus>> {
+    let selector = kubeapi::encode(&format!("release={}", mf.name));
+    let dpth = format!("/apis/apps/v1/namespaces/{}/deployments?labelSelector={}", mf.namespace, selector);
+    let spth = format!("/apis/apps/v1/namespaces/{}/statefulsets?labelSelector={}", mf.namespace, selector);
+    let mut res = vec![];
+    for d in client.get::<ObjectList<kubeapi::Deployment>>(&dpth)?.items {
+        res.push(RolloutStatus::from_deployment(&d, mf));
+    }
+    for ss in client.get::<ObjectList<kubeapi::StatefulSet>>(&spth)?.items {
+        res.push(RolloutStatus::from_statefulset(&ss, mf));
+    }
+    // deployments we know about, but might not exist yet
+    let expected = vec![&mf.name].into_iter().chain(mf.workers.iter().map(|w| &w.name));
+    for name in expected {
+        if res.iter().any(|r| r.kind == "Deployment" && &r.name == name) {
+            continue;
+        }
+        let pth = format!("/apis/apps/v1/namespaces/{}/deployments/{}", mf.namespace, name);
+        match client.get_opt::<kubeapi::Deployment>(&pth)? {
+            Some(d) => res.push(RolloutStatus::from_deployment(&d, mf)),
+            None => {
+                debug!("Deployment {} not found in {}", name, mf.namespace);
+                res.push(RolloutStatus::new("Deployment", name, mf))
+            }
+        }
+    }
+    res.sort_by(|a, b| (&a.kind, &a.name).cmp(&(&b.kind, &b.name)));
+    debug!("{} rollout status: {:?}", mf.name, res);
     Ok(res)
 }
 
-fn rollout_status(mf: &Manifest) -> Result<bool> {
-    // TODO: handle more than one deployment
-    // Even if this were called 10 times with 1/10th of waiting time, we still can't wait:
-    // - we'd still need to check other deploys...
-    // - we'd have to deal with `kubectl rollout status` not having a timeout flag..
-    let statusvec = vec![
-        "rollout".into(),
-        "status".into(),
-        format!("deployment/{}", mf.name.clone()), // always one deployment with same name
-        format!("-n={}", mf.namespace),
-        "--watch=false".into(), // always just print current status
-    ];
-    let rollres = kout(statusvec)?;
-    debug!("{}", rollres);
-    if rollres.contains("successfully rolled out") {
-        Ok(true)
-    } else {
-        // TODO: check if any of the new pods have restarts in them
-        // will avoid waiting for the full time
-        Ok(false)
+/// Pods belonging to the latest revision of the workloads still rolling out
+///
+/// For deployments these are the pods of the newest ReplicaSet.
+fn new_pods(client: &Client, mf: &Manifest, statuses: &[RolloutStatus]) -> Result<Vec<Pod>> {
+    let selector = kubeapi::encode(&format!("release={}", mf.name));
+    let pending = statuses.iter().filter(|r| !r.rolledOut && !r.revision.is_empty()).collect::<Vec<_>>();
+    if pending.is_empty() {
+        return Ok(vec![])
+    }
+    let rspth = format!("/apis/apps/v1/namespaces/{}/replicasets?labelSelector={}", mf.namespace, selector);
+    let sets = client.get::<ObjectList<kubeapi::ReplicaSet>>(&rspth)?.items.into_iter()
+        .filter(|rs| pending.iter().any(|r| {
+            r.kind == "Deployment"
+                && rs.metadata.ownerReferences.iter().any(|o| o.kind == "Deployment" && o.name == r.name)
+                && rs.metadata.annotations.get(REVISION_ANNOTATION) == Some(&r.revision)
+        }))
+        .map(|rs| rs.metadata.name)
+        .collect::<Vec<_>>();
+    let podpth = format!("/api/v1/namespaces/{}/pods?labelSelector={}", mf.namespace, selector);
+    let pods = client.get::<ObjectList<Pod>>(&podpth)?.items.into_iter()
+        .filter(|p| p.metadata.ownerReferences.iter().any(|o| match o.kind.as_str() {
+            "ReplicaSet" => sets.contains(&o.name),
+            "StatefulSet" => pending.iter().any(|r| {
+                r.kind == "StatefulSet" && r.name == o.name
+                    && p.metadata.labels.get("controller-revision-hash") == Some(&r.revision)
+            }),
+            _ => false,
+        }))
+        .collect::<Vec<_>>();
+    trace!("New pods: {:?}", pods.iter().map(|p| &p.metadata.name).collect::<Vec<_>>());
+    Ok(pods)
+}
+
+/// First new pod that is failing in a way waiting will not fix
+fn find_rollout_failure(client: &Client, mf: &Manifest, statuses: &[RolloutStatus]) -> Result<Option<RolloutFailure>> {
+    for pod in new_pods(client, mf, statuses)? {
+        if let Some((mut reason, message)) = pod_failure(&pod) {
+            // restarts caused by the liveness probe are reported as events
+            if reason == FailureReason::CrashLoop && pod_events(client, mf, &pod.metadata.name)?
+                .iter().any(|e| e.reason == "Unhealthy" && e.message.starts_with("Liveness"))
+            {
+                reason = FailureReason::FailedProbe;
+            }
+            return Ok(Some(RolloutFailure { reason, object: format!("pod {}", pod.metadata.name), message }))
+        }
     }
+    Ok(None)
+}
+
+/// Rollout progress of every Deployment and StatefulSet in a service's release
+pub fn rollout_progress(mf: &Manifest) -> Result<Vec<RolloutStatus>> {
+    let client = Client::from_kubeconfig()?;
+    rollout_status(&client, mf)
+}
+
+/// First new pod in a service's release that is failing in a way waiting will not fix
+pub fn rollout_failure(mf: &Manifest) -> Result<Option<RolloutFailure>> {
+    let client = Client::from_kubeconfig()?;
+    let statuses = rollout_status(&client, mf)?;
+    find_rollout_failure(&client, mf, &statuses)
 }
 
 /// A replacement for helm upgrade's --wait and --timeout
-pub fn await_rollout_status(mf: &Manifest) -> Result<bool> {
+///
+/// Waits for every workload in the release, and fails as soon as one of them
+/// has not rolled out within its own estimated wait time,
+/// or as soon as a new pod is failing in a way that waiting will not fix.
+///
+/// Returns the categorised failure, or `None` when everything rolled out.
+pub fn await_rollout_status(mf: &Manifest) -> Result<Option<RolloutFailure>> {
     use std::{thread, time};
-    // Check for rollout progress
-    let waittime = mf.estimate_wait_time();
-    let sec = time::Duration::from_millis(1000);
+    let client = Client::from_kubeconfig()?;
+    let start = time::Instant::now();
     // if this is called immediately after apply/upgrade, resources might not exist yet
-    match rollout_status(&mf) {
-        Ok(true) => return Ok(true), // can also insta-succeed on "noops"
-        Ok(false) => debug!("Ignoring rollout failure right after upgrade"),
-        Err(e) => warn!("Ignoring rollout failure right after upgrade: {}", e),
+    let mut statuses = match rollout_status(&client, &mf) {
+        Ok(ref xs) if xs.iter().all(|r| r.rolledOut) => return Ok(None), // can also insta-succeed on "noops"
+        Ok(xs) => {
+            debug!("Ignoring rollout failure right after upgrade");
+            xs
+        }
+        Err(e) => {
+            warn!("Ignoring rollout failure right after upgrade: {}", e);
+            vec![RolloutStatus::new("Deployment", &mf.name, mf)]
+        }
     };
-    info!("Waiting {}s for deployment {} to rollout (not ready yet)", waittime, mf.name);
-    for i in 1..10 {
-        trace!("poll iteration {}", i);
-        let mut waited = 0;
-        // sleep until 1/10th of estimated upgrade time and poll for status
-        while waited < waittime/10 {
-            waited += 1;
-            trace!("sleep 1s (waited {})", waited);
-            thread::sleep(sec);
+    for r in statuses.iter().filter(|r| !r.rolledOut) {
+        info!("Waiting {}s for {} {} to rollout (not ready yet)", r.waitTime, r.kind, r.name);
+    }
+    loop {
+        // poll at 1/10th of the shortest estimated upgrade time
+        let pending = statuses.iter().filter(|r| !r.rolledOut);
+        let poll = pending.map(|r| r.waitTime / 10).min().unwrap_or(1);
+        trace!("sleep {}s", poll);
+        thread::sleep(time::Duration::from_secs(u64::from(std::cmp::max(poll, 1))));
+
+        let waited = start.elapsed().as_secs();
+        statuses = rollout_status(&client, &mf)?;
+        for r in statuses.iter().filter(|r| !r.rolledOut) {
+            info!("{} {}: {}/{} replicas ready ({}s of {}s)", r.kind, r.name, r.ready, r.desired, waited, r.waitTime);
         }
-        if rollout_status(&mf)? {
-            return Ok(true)
+        if statuses.iter().all(|r| r.rolledOut) {
+            return Ok(None)
+        }
+        if let Some(failure) = find_rollout_failure(&client, &mf, &statuses)? {
+            error!("Aborting rollout of {}: {}", mf.name, failure);
+            return Ok(Some(failure))
+        }
+        let laggard = statuses.iter().find(|r| !r.rolledOut && waited >= u64::from(r.waitTime));
+        if let Some(r) = laggard {
+            let failure = RolloutFailure {
+                reason: FailureReason::Timeout,
+                object: format!("{} {}", r.kind, r.name),
+                message: format!("{}/{} replicas ready after {}s", r.ready, r.desired, waited),
+            };
+            error!("{} did not roll out within {}s", failure.object, r.waitTime);
+            return Ok(Some(failure))
         }
     }
-    Ok(false) // timeout
 }
 
-fn get_pods(mf: &Manifest) -> Result<String> {
-    //kubectl get pods -l=app=$* -o jsonpath='{.items[*].metadata.name}'
-    let podargs = vec![
-        "get".into(),
-        "pods".into(),
-        format!("-l=app={}", mf.name),
-        format!("-n={}", mf.namespace),
-        "-o".into(),
-        "jsonpath='{.items[*].metadata.name}'".into(),
-    ];
-    // TODO: filter out ones not running conditionally - exec wont work with this
-    let podsres = kout(podargs)?;
-    debug!("Active pods: {:?}", podsres);
-    Ok(podsres)
+/// Pods of a service, sorted by name
+fn get_pods(client: &Client, mf: &Manifest) -> Result<Vec<Pod>> {
+    let pth = format!("/api/v1/namespaces/{}/pods?labelSelector={}",
+        mf.namespace, kubeapi::encode(&format!("app={}", mf.name))
+    );
+    let mut pods = client.get::<ObjectList<Pod>>(&pth)?.items;
+    pods.sort_by(|a, b| a.metadata.name.cmp(&b.metadata.name));
+    debug!("Active pods: {:?}", pods.iter().map(|p| &p.metadata.name).collect::<Vec<_>>());
+    Ok(pods)
+}
+
+/// Pod statuses as a table like `kubectl get pods`
+fn pod_table(pods: &[Pod]) -> String {
+    let mut rows = vec![format!("{:<50}{:<8}{:<28}{}", "NAME", "READY", "STATUS", "RESTARTS")];
+    for p in pods {
+        let (ready, total) = p.ready();
+        rows.push(format!("{:<50}{:<8}{:<28}{}",
+            p.metadata.name, format!("{}/{}", ready, total), p.reason(), p.restarts()
+        ));
+    }
+    rows.join("\n")
 }
 
 /// Return non-running or partially ready pods
-fn get_broken_pods(mf: &Manifest) -> Result<(String, Vec<String>)> {
-    let podargs = vec![
-        "get".into(),
-        "pods".into(),
-        format!("-l=app={}", mf.name),
-        format!("-n={}", mf.namespace),
-        "--no-headers".into(),
-    ];
-    let podres = kout(podargs)?;
+fn get_broken_pods(client: &Client, mf: &Manifest) -> Result<(String, Vec<String>)> {
+    let pods = get_pods(client, mf)?;
     let mut bpods = vec![];
-    let status_re = Regex::new(r" (?P<ready>\d+)/(?P<total>\d+) ").unwrap();
-    for l in podres.lines() {
-        if !l.contains("Running") {
-            if let Some(p) = l.split(' ').next() {
-                warn!("Found pod not running: {}", p);
-                bpods.push(p.into());
-            }
+    for p in &pods {
+        if let Some((reason, msg)) = pod_failure(p) {
+            warn!("Found pod with {}: {} ({})", reason, p.metadata.name, msg);
+            bpods.push(p.metadata.name.clone());
         }
-        else if let Some(caps) = status_re.captures(l) {
-            if caps["ready"] != caps["total"] {
-                if let Some(p) = l.split(' ').next() {
-                    warn!("Found pod with less than necessary containers healthy: {}", p);
-                    bpods.push(p.into());
-                }
-            }
+        else if p.status.phase != "Running" {
+            warn!("Found pod not running: {}", p.metadata.name);
+            bpods.push(p.metadata.name.clone());
+        }
+        else if !p.is_healthy() {
+            warn!("Found pod with less than necessary containers healthy: {}", p.metadata.name);
+            bpods.push(p.metadata.name.clone());
         }
     }
-    Ok((podres, bpods))
+    Ok((pod_table(&pods), bpods))
+}
+
+/// Events as a table like the end of `kubectl describe`
+fn event_table(events: &[Event]) -> String {
+    let mut rows = vec![format!("{:<10}{:<24}{:<8}{}", "TYPE", "REASON", "COUNT", "MESSAGE")];
+    for e in events {
+        rows.push(format!("{:<10}{:<24}{:<8}{}", e.eventType, e.reason, e.count, e.message.trim()));
+    }
+    rows.join("\n")
+}
+
+/// Events involving a pod
+fn pod_events(client: &Client, mf: &Manifest, pod: &str) -> Result<Vec<Event>> {
+    let evpth = format!("/api/v1/namespaces/{}/events?fieldSelector={}",
+        mf.namespace, kubeapi::encode(&format!("involvedObject.name={}", pod))
+    );
+    Ok(client.get::<ObjectList<Event>>(&evpth)?.items)
 }
 
 /// Debug helper when upgrades fail
@@ -148,7 +412,8 @@ fn get_broken_pods(mf: &Manifest) -> Result<(String, Vec<String>)> {
 /// Prints log excerpts and events for broken pods.
 /// Typically enough to figure out why upgrades broke.
 pub fn debug(mf: &Manifest) -> Result<()> {
-    let (podres, pods) = get_broken_pods(&mf)?;
+    let client = Client::from_kubeconfig()?;
+    let (podres, pods) = get_broken_pods(&client, &mf)?;
     if pods.is_empty() {
         info!("No broken pods found");
         info!("Pod statuses:\n{}", podres);
@@ -157,14 +422,10 @@ pub fn debug(mf: &Manifest) -> Result<()> {
     }
     for pod in pods.clone() {
         warn!("Debugging non-running pod {}", pod);
-        let logvec = vec![
-            "logs".into(),
-            pod.clone(),
-            mf.name.clone(),
-            format!("-n={}", mf.namespace),
-            "--tail=30".into(),
-        ];
-        match kout(logvec) {
+        let logpth = format!("/api/v1/namespaces/{}/pods/{}/log?container={}&tailLines=30",
+            mf.namespace, pod, mf.name
+        );
+        match client.text(&logpth) {
             Ok(l) => {
                 if l == "" {
                     warn!("No logs for pod {} found", pod);
@@ -181,74 +442,28 @@ pub fn debug(mf: &Manifest) -> Result<()> {
 
     for pod in pods {
         warn!("Describing events for pod {}", pod);
-        let descvec = vec![
-            "describe".into(),
-            "pod".into(),
-            pod.clone(),
-            format!("-n={}", mf.namespace),
-        ];
-
-        match kout(descvec) {
-            Ok(mut o) => {
-                if let Some(idx) = o.find("Events:\n") {
-                    println!("{}", o.split_off(idx))
-                }
-                else {
-                    // Not printing in this case, tons of secrets in here
+        match pod_events(&client, mf, &pod) {
+            Ok(evs) => {
+                if evs.is_empty() {
                     warn!("Unable to find events for pod {}", pod);
+                } else {
+                    println!("{}", event_table(&evs))
                 }
             },
             Err(e) => {
-                warn!("Failed to describe {}: {}", pod, e)
+                warn!("Failed to get events for {}: {}", pod, e)
             }
         }
     }
     // ignore errors from here atm - it's mostly here as a best effort helper
-    let _ = debug_active_replicasets(mf);
+    let _ = debug_active_replicasets(&client, mf);
     Ok(())
 }
 
 
-// Parsed replica status from a deployment description
-#[derive(Clone, Debug)]
-struct ReplicaStatus {
-    /// Name of ReplicaSet
-    pub name: String,
-    /// Total replicas expected
-    pub total: u32,
-    /// Total replicas running
-    pub running: u32,
-}
-
-// limited parsed struct from kube ReplicaSet yaml
-#[derive(Deserialize)]
-struct ReplicaSetVal {
-    // rollout status of the RS
-    status: ReplicaInfoVal,
-    // metadata for timestamps
-    metadata: ReplicaMetadataVal,
-}
-#[derive(Deserialize, Debug)]
-struct ReplicaMetadataVal {
-    creationTimestamp: DateTime<Utc>, // e.g. 2018-05-17T10:08:37Z
-}
-#[derive(Deserialize, Debug)]
-struct ReplicaInfoVal {
-    // `observedGeneration` <- not unique across replicasets so useless
-    // Currently available replicas (ready for minReadySeconds) from this generation
-    #[serde(default)] // 0 if missing
-    availableReplicas: u32,
-    /// Currently ready replilas from this generation (weaker than above)
-    #[serde(default)] // 0 if missing
-    readyReplicas: u32,
-    /// Most recently oberved number of replicas
-    replicas: u32,
-}
-
-
 /// Simplified ReplicaSet struct
 ///
-/// Created by combining deployment description with appropriate replicaset yamls
+/// The replica counts of a ReplicaSet owned by a service's deployment
 #[derive(Debug)]
 pub struct ReplicaSet {
     /// Name of replicaset
@@ -261,57 +476,27 @@ pub struct ReplicaSet {
     pub created: DateTime<Utc>,
 }
 
-// Finds affected replicatsets, and checks the state of them.
-fn find_active_replicasets(mf: &Manifest) -> Result<Vec<ReplicaSet>> {
-    // find relevant replicasets
-    // NB: not returned via `k get deploy {name} -oyaml` - have to scrape describe..
-    let descvec = vec![
-        "describe".into(),
-        "deploy".into(),
-        mf.name.clone(),
-        format!("-n={}", mf.namespace),
-    ];
-    // Finding the affected replicasets:
-    let rs_re = Regex::new(r"(Old|New)ReplicaSets?:\s+(?P<rs>\S+)\s+\((?P<running>\d+)/(?P<total>\d+)").unwrap();
-    let deployres = kout(descvec)?;
-    let mut sets = vec![];
-    for l in deployres.lines() {
-        if let Some(caps) = rs_re.captures(l) {
-            sets.push(ReplicaStatus {
-                name: caps["rs"].to_string(),
-                running: caps["running"].parse()?,
-                total: caps["total"].parse()?,
-            });
-        }
-    }
-    // Query each of the affected replicasets for info using yaml api:
-    let mut completesets = vec![];
-    for rs in sets {
-        // Find total counts from each replicaset
-        let getvec = vec![
-            "get".into(),
-            "rs".into(),
-            rs.name.clone(),
-            format!("-n={}", mf.namespace),
-            "-oyaml".into(),
-        ];
-        let getres = kout(getvec)?;
-        let rv : ReplicaSetVal = serde_yaml::from_str(&getres)?;
-        let ri = rv.status;
-        let res = ReplicaSet {
-            name: rs.name,
-            available: ri.availableReplicas,
-            total: ri.replicas,
-            created: rv.metadata.creationTimestamp
-        };
-        completesets.push(res);
-    }
-    Ok(completesets)
+// Finds replicasets of the deployment that still have replicas
+fn find_active_replicasets(client: &Client, mf: &Manifest) -> Result<Vec<ReplicaSet>> {
+    let pth = format!("/apis/apps/v1/namespaces/{}/replicasets?labelSelector={}",
+        mf.namespace, kubeapi::encode(&format!("app={}", mf.name))
+    );
+    let sets = client.get::<ObjectList<kubeapi::ReplicaSet>>(&pth)?.items.into_iter()
+        .filter(|rs| rs.metadata.ownerReferences.iter().any(|o| o.kind == "Deployment" && o.name == mf.name))
+        .filter(|rs| rs.status.replicas > 0)
+        .map(|rs| ReplicaSet {
+            name: rs.metadata.name,
+            available: rs.status.availableReplicas,
+            total: rs.status.replicas,
+            created: rs.metadata.creationTimestamp.unwrap_or_else(Utc::now),
+        })
+        .collect();
+    Ok(sets)
 }
 
 // Debug status of active replicasets post-upgrade helpful info
-fn debug_active_replicasets(mf: &Manifest) -> Result<()> {
-    let sets = find_active_replicasets(mf)?;
+fn debug_active_replicasets(client: &Client, mf: &Manifest) -> Result<()> {
+    let sets = find_active_replicasets(client, mf)?;
     if sets.len() > 1 {
         warn!("ReplicaSets: {:?}", sets);
     }
@@ -333,7 +518,8 @@ fn debug_active_replicasets(mf: &Manifest) -> Result<()> {
 
 /// Print upgrade status of current replicaset rollout
 pub fn debug_rollout_status(mf: &Manifest) -> Result<()> {
-    let mut sets = find_active_replicasets(mf)?;
+    let client = Client::from_kubeconfig()?;
+    let mut sets = find_active_replicasets(&client, mf)?;
     if sets.len() == 2 {
         sets.sort_unstable_by(|x,y| x.created.timestamp().cmp(&y.created.timestamp()));
         let old = sets.first().unwrap();
@@ -347,16 +533,534 @@ pub fn debug_rollout_status(mf: &Manifest) -> Result<()> {
 }
 
 
+/// Name of the Deployment running a service's canary
+pub fn canary_name(mf: &Manifest) -> String {
+    format!("{}-canary", mf.name)
+}
+
+/// Canary Deployment from the main Deployment of the manifest's rendered objects
+///
+/// Same pod spec as the new version would roll out, but with a `track: canary` label
+/// and its own `release` label, so neither helm nor the main Deployment's selector claim its pods.
+/// The service's `Service` selects on `app` only, and sends a share of traffic to it.
+pub fn canary_deployment(objs: &[Value], mf: &Manifest, replicas: u32) -> Result<Value> {
+    let main = match objs.iter().find(|o| o["kind"] == "Deployment" && o["metadata"]["name"] == mf.name.as_str()) {
+        Some(d) => d,
+        None => bail!("{} renders no deployment to canary", mf.name),
+    };
+    let mut canary = json!({
+        "apiVersion": "apps/v1",
+        "kind": "Deployment",
+        "metadata": {
+            "name": canary_name(mf),
+            "namespace": mf.namespace,
+            "labels": main["metadata"]["labels"].clone(),
+        },
+        "spec": main["spec"].clone(),
+    });
+    let release = canary_name(mf);
+    canary_labels(&mut canary["metadata"]["labels"], &release);
+    let spec = &mut canary["spec"];
+    spec["replicas"] = json!(replicas);
+    canary_labels(&mut spec["selector"]["matchLabels"], &release);
+    canary_labels(&mut spec["template"]["metadata"]["labels"], &release);
+    Ok(canary)
+}
+
+/// Mark labels as belonging to a canary rather than to the release it was rendered for
+fn canary_labels(labels: &mut Value, release: &str) {
+    if labels.get("release").is_some() {
+        labels["release"] = json!(release);
+    }
+    labels["track"] = json!("canary");
+}
+
+/// Start a canary of the manifest's version next to the main Deployment
+///
+/// Built from the manifest's rendered objects, so changes besides the version are baked too.
+/// Replaces any canary left behind by an earlier attempt.
+pub fn create_canary(mf: &Manifest, objs: &[Value], replicas: u32) -> Result<()> {
+    let client = Client::from_kubeconfig()?;
+    let coll = format!("/apis/apps/v1/namespaces/{}/deployments", mf.namespace);
+    let canary = canary_deployment(objs, mf, replicas)?;
+    delete_canary(mf)?;
+    info!("Creating deployment {} with {} replicas of {}", canary_name(mf), replicas, mf.version.clone().unwrap_or_default());
+    client.create(&coll, &canary)
+}
+
+/// Remove a service's canary Deployment and its pods, if any
+pub fn delete_canary(mf: &Manifest) -> Result<()> {
+    let client = Client::from_kubeconfig()?;
+    let pth = format!("/apis/apps/v1/namespaces/{}/deployments/{}", mf.namespace, canary_name(mf));
+    if client.get_opt::<Object>(&pth)?.is_some() {
+        info!("Removing deployment {}", canary_name(mf));
+        client.delete(&format!("{}?propagationPolicy=Background", pth))?;
+    }
+    Ok(())
+}
+
+/// Rollout progress of a deployment outside the release, and the pods matching a selector
+fn workload_status(mf: &Manifest, name: &str, selector: &str) -> Result<(RolloutStatus, Vec<Pod>)> {
+    let client = Client::from_kubeconfig()?;
+    let pth = format!("/apis/apps/v1/namespaces/{}/deployments/{}", mf.namespace, name);
+    let status = match client.get_opt::<kubeapi::Deployment>(&pth)? {
+        Some(d) => RolloutStatus::from_deployment(&d, mf),
+        None => RolloutStatus::new("Deployment", name, mf),
+    };
+    let podpth = format!("/api/v1/namespaces/{}/pods?labelSelector={}",
+        mf.namespace, kubeapi::encode(selector)
+    );
+    let pods = client.get::<ObjectList<Pod>>(&podpth)?.items;
+    Ok((status, pods))
+}
+
+/// Rollout progress and pods of a service's canary
+pub fn canary_status(mf: &Manifest) -> Result<(RolloutStatus, Vec<Pod>)> {
+    workload_status(mf, &canary_name(mf), &format!("app={},track=canary", mf.name))
+}
+
+/// Annotation on the `Service` of a blue/green service naming the colour it switched away from
+const PREVIOUS_COLOUR_ANNOTATION: &str = "shipcat.babylontech.co.uk/previous-colour";
+
+/// Annotation on the `Service` of a blue/green service recording when it last switched
+const SWITCHED_AT_ANNOTATION: &str = "shipcat.babylontech.co.uk/switched-at";
+
+/// One of the two deployments of a blue/green service
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Colour {
+    Blue,
+    Green,
+}
+
+impl Colour {
+    /// The colour that is not this one
+    pub fn other(self) -> Colour {
+        match self {
+            Colour::Blue => Colour::Green,
+            Colour::Green => Colour::Blue,
+        }
+    }
+
+    fn parse(s: &str) -> Option<Colour> {
+        match s {
+            "blue" => Some(Colour::Blue),
+            "green" => Some(Colour::Green),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Colour {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Colour::Blue => write!(f, "blue"),
+            Colour::Green => write!(f, "green"),
+        }
+    }
+}
+
+/// Colours of a blue/green service as recorded on its `Service`
+#[derive(Debug, Clone, Default)]
+pub struct ColourState {
+    /// Colour the `Service` selects, if it ever switched
+    pub active: Option<Colour>,
+    /// Colour it last switched away from
+    pub previous: Option<Colour>,
+    /// When it last switched
+    pub switchedAt: Option<DateTime<Utc>>,
+}
+
+impl ColourState {
+    /// Whether the last switch happened less than `window` seconds ago
+    pub fn within(&self, window: u32) -> bool {
+        match self.switchedAt {
+            Some(t) => Utc::now().signed_duration_since(t).num_seconds() < i64::from(window),
+            None => false,
+        }
+    }
+}
+
+/// Name of the Deployment running one colour of a blue/green service
+pub fn colour_name(mf: &Manifest, colour: Colour) -> String {
+    format!("{}-{}", mf.name, colour)
+}
+
+/// Colour Deployment from the service's main Deployment
+///
+/// Same pod spec, but with a `colour` label on the pods for the `Service` to select.
+/// The `release` label is left off the Deployment itself,
+/// so rollout checks of the release do not wait for colours.
+pub fn colour_deployment(main: &Value, mf: &Manifest, colour: Colour, replicas: u32) -> Result<Value> {
+    let mut deploy = json!({
+        "apiVersion": "apps/v1",
+        "kind": "Deployment",
+        "metadata": {
+            "name": colour_name(mf, colour),
+            "namespace": mf.namespace,
+            "labels": main["metadata"]["labels"].clone(),
+        },
+        "spec": main["spec"].clone(),
+    });
+    if let Some(labels) = deploy["metadata"]["labels"].as_object_mut() {
+        labels.remove("release");
+    }
+    deploy["metadata"]["labels"]["colour"] = json!(colour.to_string());
+    let spec = &mut deploy["spec"];
+    if spec["template"]["spec"]["containers"].as_array().map(|cs| cs.is_empty()).unwrap_or(true) {
+        bail!("Deployment {} has no containers", mf.name);
+    }
+    spec["replicas"] = json!(replicas);
+    spec["selector"]["matchLabels"]["colour"] = json!(colour.to_string());
+    spec["template"]["metadata"]["labels"]["colour"] = json!(colour.to_string());
+    Ok(deploy)
+}
+
+/// Run one colour of a blue/green service with the spec of its main Deployment
+///
+/// Replaces whatever that colour ran before.
+pub fn create_colour(mf: &Manifest, colour: Colour, replicas: u32) -> Result<()> {
+    let client = Client::from_kubeconfig()?;
+    let coll = format!("/apis/apps/v1/namespaces/{}/deployments", mf.namespace);
+    let main : Value = match client.get_opt(&format!("{}/{}", coll, mf.name))? {
+        Some(d) => d,
+        None => bail!("Cannot create the {} colour of {} without its deployment", colour, mf.name),
+    };
+    let deploy = colour_deployment(&main, mf, colour, replicas)?;
+    let pth = format!("{}/{}", coll, colour_name(mf, colour));
+    if client.get_opt::<Object>(&pth)?.is_some() {
+        info!("Removing deployment {}", colour_name(mf, colour));
+        client.delete(&format!("{}?propagationPolicy=Background", pth))?;
+    }
+    info!("Creating deployment {} with {} replicas", colour_name(mf, colour), replicas);
+    client.create(&coll, &deploy)
+}
+
+/// Scale one colour of a blue/green service, if it exists
+pub fn scale_colour(mf: &Manifest, colour: Colour, replicas: u32) -> Result<()> {
+    let client = Client::from_kubeconfig()?;
+    let pth = format!("/apis/apps/v1/namespaces/{}/deployments/{}", mf.namespace, colour_name(mf, colour));
+    let mut deploy : Value = match client.get_opt(&pth)? {
+        Some(d) => d,
+        None => return Ok(()),
+    };
+    if deploy["spec"]["replicas"] == json!(replicas) {
+        return Ok(());
+    }
+    info!("Scaling deployment {} to {} replicas", colour_name(mf, colour), replicas);
+    deploy["spec"]["replicas"] = json!(replicas);
+    client.replace(&pth, &deploy)
+}
+
+/// Rollout progress and pods of one colour of a blue/green service
+pub fn colour_status(mf: &Manifest, colour: Colour) -> Result<(RolloutStatus, Vec<Pod>)> {
+    workload_status(mf, &colour_name(mf, colour), &format!("app={},colour={}", mf.name, colour))
+}
+
+/// Colours of a blue/green service
+///
+/// Empty when the service has no `Service` yet.
+pub fn colour_state(mf: &Manifest) -> Result<ColourState> {
+    let client = Client::from_kubeconfig()?;
+    let pth = format!("/api/v1/namespaces/{}/services/{}", mf.namespace, mf.name);
+    let svc : Value = match client.get_opt(&pth)? {
+        Some(s) => s,
+        None => return Ok(ColourState::default()),
+    };
+    let annotations = &svc["metadata"]["annotations"];
+    Ok(ColourState {
+        active: svc["spec"]["selector"]["colour"].as_str().and_then(Colour::parse),
+        previous: annotations[PREVIOUS_COLOUR_ANNOTATION].as_str().and_then(Colour::parse),
+        switchedAt: annotations[SWITCHED_AT_ANNOTATION].as_str()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.with_timezone(&Utc)),
+    })
+}
+
+/// Send all traffic of a blue/green service to one colour
+///
+/// Records the colour it switched away from, and when, for rollbacks.
+pub fn switch_colour(mf: &Manifest, colour: Colour) -> Result<()> {
+    let client = Client::from_kubeconfig()?;
+    let pth = format!("/api/v1/namespaces/{}/services/{}", mf.namespace, mf.name);
+    let mut svc : Value = match client.get_opt(&pth)? {
+        Some(s) => s,
+        None => bail!("Service {} not found in {}", mf.name, mf.namespace),
+    };
+    let previous = svc["spec"]["selector"]["colour"].as_str().map(String::from);
+    svc["spec"]["selector"]["colour"] = json!(colour.to_string());
+    match previous {
+        Some(p) => svc["metadata"]["annotations"][PREVIOUS_COLOUR_ANNOTATION] = json!(p),
+        None => {
+            if let Some(a) = svc["metadata"]["annotations"].as_object_mut() {
+                a.remove(PREVIOUS_COLOUR_ANNOTATION);
+            }
+        }
+    }
+    svc["metadata"]["annotations"][SWITCHED_AT_ANNOTATION] = json!(Utc::now().to_rfc3339());
+    info!("Switching service {} to {}", mf.name, colour);
+    client.replace(&pth, &svc)
+}
+
+/// Image version of a service's running deployment
+///
+/// For services without a helm release to ask.
+pub fn running_version(svc: &str, ns: &str) -> Result<String> {
+    let client = Client::from_kubeconfig()?;
+    let pth = format!("/apis/apps/v1/namespaces/{}/deployments/{}", ns, svc);
+    let image = match client.get_opt::<kubeapi::Deployment>(&pth)? {
+        Some(d) => d.spec.template.spec.containers.first().map(|c| c.image.clone()).unwrap_or_default(),
+        None => bail!("Service {} not found in {}", svc, ns),
+    };
+    match image.rfind(':') {
+        Some(i) => Ok(image[i+1..].to_string()),
+        None => bail!("Service {} in {} runs an untagged image {}", svc, ns, image),
+    }
+}
+
+/// Annotation holding the object as shipcat last applied it
+///
+/// Fields in it that are no longer rendered get removed on the next apply.
+const LAST_APPLIED_ANNOTATION: &str = "shipcat.babylontech.co.uk/last-applied";
+
+/// Kinds the native renderer creates, as api version, kind and resource name
+const NATIVE_KINDS: &[(&str, &str, &str)] = &[
+    ("v1", "ServiceAccount", "serviceaccounts"),
+    ("rbac.authorization.k8s.io/v1", "Role", "roles"),
+    ("rbac.authorization.k8s.io/v1", "RoleBinding", "rolebindings"),
+    ("v1", "ConfigMap", "configmaps"),
+    ("v1", "Secret", "secrets"),
+    ("apps/v1", "Deployment", "deployments"),
+    ("v1", "Service", "services"),
+    ("autoscaling/v2beta1", "HorizontalPodAutoscaler", "horizontalpodautoscalers"),
+    ("policy/v1beta1", "PodDisruptionBudget", "poddisruptionbudgets"),
+    ("batch/v1beta1", "CronJob", "cronjobs"),
+];
+
+/// Collection path of a resource in a namespace
+fn collection(api_version: &str, resource: &str, ns: &str) -> String {
+    let prefix = if api_version.contains('/') { "apis" } else { "api" }; // core group
+    format!("/{}/{}/namespaces/{}/{}", prefix, api_version, ns, resource)
+}
+
+/// Collection path of a rendered object
+fn object_collection(obj: &Value, ns: &str) -> Result<String> {
+    let kind = obj["kind"].as_str().unwrap_or("");
+    match NATIVE_KINDS.iter().find(|(_, k, _)| *k == kind) {
+        Some((_, _, resource)) => Ok(collection(obj["apiVersion"].as_str().unwrap_or(""), resource, ns)),
+        None => bail!("Cannot apply {} {} - not a kind shipcat renders", kind, obj["metadata"]["name"]),
+    }
+}
+
+/// The object as shipcat last applied it, null if it never did
+fn last_applied(live: &Value) -> Value {
+    live["metadata"]["annotations"][LAST_APPLIED_ANNOTATION].as_str()
+        .and_then(|s| serde_json::from_str(s).ok())
+        .unwrap_or(Value::Null)
+}
+
+/// Objects of a release that shipcat applied before, but that are no longer rendered
+///
+/// Found through the `release` label every object is rendered with.
+/// Objects without the last applied annotation (like blue/green colours) are never included.
+fn pruned(client: &Client, objs: &[Value], ns: &str, release: &str) -> Result<Vec<(String, Value)>> {
+    let selector = kubeapi::encode(&format!("release={}", release));
+    let mut res = vec![];
+    for (api_version, kind, resource) in NATIVE_KINDS {
+        let coll = collection(api_version, resource, ns);
+        let pth = format!("{}?labelSelector={}", coll, selector);
+        for o in client.get::<ObjectList<Value>>(&pth)?.items {
+            let rendered = objs.iter().any(|r| r["kind"] == *kind && r["metadata"]["name"] == o["metadata"]["name"]);
+            if !rendered && !last_applied(&o).is_null() {
+                res.push((coll.clone(), o));
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Apply the rendered objects of a release, like `kubectl apply --prune`
+///
+/// Missing objects are created. Existing ones are merge patched with what changed
+/// since shipcat last applied them, so fields set by kube or other tools are kept.
+/// Objects of the release that are no longer rendered (removed workers or cron jobs) are deleted.
+pub fn apply_objects(objs: &[Value], ns: &str, release: &str) -> Result<()> {
+    let client = Client::from_kubeconfig()?;
+    for obj in objs {
+        let coll = object_collection(obj, ns)?;
+        let name = obj["metadata"]["name"].as_str().unwrap_or("");
+        let pth = format!("{}/{}", coll, name);
+        let mut desired = obj.clone();
+        desired["metadata"]["annotations"][LAST_APPLIED_ANNOTATION] = json!(serde_json::to_string(obj)?);
+        match client.get_opt::<Value>(&pth)? {
+            Some(live) => {
+                debug!("Patching {} {}", obj["kind"].as_str().unwrap_or(""), name);
+                client.merge_patch(&pth, &merge_patch(&last_applied(&live), desired))?;
+            },
+            None => {
+                debug!("Creating {} {}", obj["kind"].as_str().unwrap_or(""), name);
+                client.create(&coll, &desired)?;
+            }
+        }
+    }
+    for (coll, o) in pruned(&client, objs, ns, release)? {
+        let name = o["metadata"]["name"].as_str().unwrap_or("");
+        info!("Deleting {} {} as {} no longer has it", o["kind"].as_str().unwrap_or(&coll), name, release);
+        client.delete(&format!("{}/{}", coll, name))?;
+    }
+    Ok(())
+}
+
+/// Diff the rendered objects of a release against what shipcat last applied
+///
+/// Printed like `helm diff`, including the objects an apply would delete.
+pub fn diff_objects(objs: &[Value], ns: &str, release: &str) -> Result<String> {
+    let client = Client::from_kubeconfig()?;
+    let header = |o: &Value| format!("{}, {}, {} ({})", ns,
+        o["metadata"]["name"].as_str().unwrap_or(""),
+        o["kind"].as_str().unwrap_or(""),
+        o["apiVersion"].as_str().unwrap_or(""));
+    let mut res = vec![];
+    for obj in objs {
+        let coll = object_collection(obj, ns)?;
+        let pth = format!("{}/{}", coll, obj["metadata"]["name"].as_str().unwrap_or(""));
+        let new = serde_yaml::to_string(obj)?;
+        match client.get_opt::<Value>(&pth)? {
+            Some(live) => {
+                let old = serde_yaml::to_string(&last_applied(&live))?;
+                if old != new {
+                    res.push(format!("{} has changed:", header(obj)));
+                    res.extend(line_diff(&old, &new));
+                }
+            },
+            None => {
+                res.push(format!("{} has been added:", header(obj)));
+                res.extend(new.lines().map(|l| format!("+ {}", l)));
+            }
+        }
+    }
+    for (_, o) in pruned(&client, objs, ns, release)? {
+        let old = last_applied(&o);
+        res.push(format!("{} has been removed:", header(&old)));
+        res.extend(serde_yaml::to_string(&old)?.lines().map(|l| format!("- {}", l)));
+    }
+    Ok(res.join("\n"))
+}
+
+/// Lines removed from `old` and added in `new`, with unchanged lines as context
+fn line_diff(old: &str, new: &str) -> Vec<String> {
+    let a = old.lines().collect::<Vec<_>>();
+    let b = new.lines().collect::<Vec<_>>();
+    // length of the longest common subsequence of the lines from i and j onwards
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                std::cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut res = vec![];
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            res.push(format!("  {}", a[i]));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            res.push(format!("- {}", a[i]));
+            i += 1;
+        } else {
+            res.push(format!("+ {}", b[j]));
+            j += 1;
+        }
+    }
+    res
+}
+
+/// Restart the pods of every deployment in a release
+///
+/// Stamps the pod templates like `kubectl rollout restart` does, so each deployment rolls.
+pub fn rollout_restart(release: &str, ns: &str) -> Result<()> {
+    let client = Client::from_kubeconfig()?;
+    let selector = kubeapi::encode(&format!("release={}", release));
+    let pth = format!("/apis/apps/v1/namespaces/{}/deployments?labelSelector={}", ns, selector);
+    let mut patch = json!({});
+    patch["spec"]["template"]["metadata"]["annotations"][RESTARTED_AT_ANNOTATION] = json!(Utc::now().to_rfc3339());
+    for d in client.get::<ObjectList<kubeapi::Deployment>>(&pth)?.items {
+        debug!("Restarting deployment {} in {}", d.metadata.name, ns);
+        let dpth = format!("/apis/apps/v1/namespaces/{}/deployments/{}", ns, d.metadata.name);
+        client.merge_patch(&dpth, &patch)?;
+    }
+    Ok(())
+}
+
+/// Roll every deployment of a service back to its previous revision, like `kubectl rollout undo`
+///
+/// Undoes the main deployment and all workers, even if some of them fail.
+pub fn rollout_undo(mf: &Manifest) -> Result<()> {
+    let client = Client::from_kubeconfig()?;
+    let names = vec![&mf.name].into_iter().chain(mf.workers.iter().map(|w| &w.name));
+    let mut failed = vec![];
+    for name in names {
+        if let Err(e) = undo_deployment(&client, mf, name) {
+            warn!("Failed to undo deployment {}: {}", name, e);
+            failed.push(name.clone());
+        }
+    }
+    if !failed.is_empty() {
+        bail!("Failed to roll back {} of {}", failed.join(", "), mf.name);
+    }
+    Ok(())
+}
+
+/// Put the pod template of a deployment's previous ReplicaSet back
+fn undo_deployment(client: &Client, mf: &Manifest, name: &str) -> Result<()> {
+    let revision = |o: &Value| o["metadata"]["annotations"][REVISION_ANNOTATION].as_str()
+        .and_then(|r| r.parse::<u64>().ok())
+        .unwrap_or(0);
+    let pth = format!("/apis/apps/v1/namespaces/{}/deployments/{}", mf.namespace, name);
+    let deploy = match client.get_opt::<Value>(&pth)? {
+        Some(d) => d,
+        None => bail!("Deployment {} not found in {}", name, mf.namespace),
+    };
+    let current = revision(&deploy);
+    let selector = kubeapi::encode(&format!("release={}", mf.name));
+    let rspth = format!("/apis/apps/v1/namespaces/{}/replicasets?labelSelector={}", mf.namespace, selector);
+    let previous = client.get::<ObjectList<Value>>(&rspth)?.items.into_iter()
+        .filter(|rs| rs["metadata"]["ownerReferences"].as_array()
+            .map(|os| os.iter().any(|o| o["kind"] == "Deployment" && o["name"] == name))
+            .unwrap_or(false))
+        .filter(|rs| revision(rs) < current)
+        .max_by_key(revision);
+    let rs = match previous {
+        Some(rs) => rs,
+        None => bail!("Deployment {} has no previous revision to roll back to", name),
+    };
+    let mut template = rs["spec"]["template"].clone();
+    if let Some(labels) = template["metadata"]["labels"].as_object_mut() {
+        labels.remove("pod-template-hash"); // set by the deployment controller
+    }
+    info!("Rolling back deployment {} to revision {}", name, revision(&rs));
+    let patch = json!({ "spec": { "template": merge_patch(&deploy["spec"]["template"], template) } });
+    client.merge_patch(&pth, &patch)
+}
+
 /// Shell into all pods associated with a service
 ///
 /// Optionally specify the arbitrary pod index from kubectl get pods
 pub fn shell(mf: &Manifest, desiredpod: Option<usize>, cmd: Option<Vec<&str>>) -> Result<()> {
     // TODO: kubectl auth can-i create pods/exec
-    let podsres = get_pods(&mf)?;
-    let pods = podsres.split(' ').collect::<Vec<_>>();
+    let client = Client::from_kubeconfig()?;
+    let pods = get_pods(&client, &mf)?.into_iter()
+        .filter(|p| p.status.phase == "Running") // exec needs a running pod
+        .map(|p| p.metadata.name)
+        .collect::<Vec<_>>();
     let pnr = desiredpod.unwrap_or(0);
     if let Some(p) = pods.get(pnr) {
         debug!("Shelling into {}", p);
+        // interactive exec streams over SPDY, so this is left to kubectl
         //kubectl exec -it $pod sh
         let mut execargs = vec![
             "exec".into(),
@@ -423,74 +1127,101 @@ pub fn port_forward(mf: &Manifest) -> Result<()> {
 
 use shipcat_definitions::Crd;
 use serde::Serialize;
+
+/// API version the custom resource definitions themselves are served under
+const CRD_API_VERSION: &str = "apiextensions.k8s.io/v1beta1";
+
+/// Collection path of a custom resource, or of a definition itself
+///
+/// The plural resource name is read from the installed definition of the kind.
+fn crd_collection(client: &Client, api_version: &str, kind: &str, ns: &str) -> Result<String> {
+    if kind == "CustomResourceDefinition" {
+        return Ok(format!("/apis/{}/customresourcedefinitions", api_version)); // cluster scoped
+    }
+    let group = api_version.split('/').next().unwrap_or(api_version);
+    let pth = format!("/apis/{}/customresourcedefinitions", CRD_API_VERSION);
+    let defs = client.get::<ObjectList<kubeapi::CustomResourceDefinition>>(&pth)?;
+    match defs.items.into_iter().find(|d| d.spec.group == group && d.spec.names.kind == kind) {
+        Some(d) => Ok(format!("/apis/{}/namespaces/{}/{}", api_version, ns, d.spec.names.plural)),
+        None => bail!("No custom resource definition for {} in {} is installed", kind, group),
+    }
+}
+
+/// JSON merge patch that turns `current` into `desired`
+///
+/// Keys that are gone from `desired` are nulled so they get removed.
+fn merge_patch(current: &Value, desired: Value) -> Value {
+    match (current, desired) {
+        (Value::Object(cur), Value::Object(mut des)) => {
+            for (k, v) in des.iter_mut() {
+                if let Some(c) = cur.get(k) {
+                    *v = merge_patch(c, std::mem::replace(v, Value::Null));
+                }
+            }
+            for k in cur.keys() {
+                if !des.contains_key(k) {
+                    des.insert(k.clone(), Value::Null);
+                }
+            }
+            Value::Object(des)
+        }
+        (_, des) => des,
+    }
+}
+
 /// Apply the CRD for any struct that can be turned into a CRD
 ///
 /// CRDs itself, Manifest and Config typically.
+/// Creates the object, or merge patches its spec (and annotations) when it exists,
+/// leaving everything else on the object alone.
 pub fn apply_crd<T: Into<Crd<T>> + Serialize>(name: &str, data: T, ns: &str) -> Result<()> {
-    use std::path::Path;
-    use std::fs::{self, File};
-    use std::io::Write;
     // Use trait constraint to convert it to a CRD
     let crd : Crd<T> = data.into();
-
-    // Write it to a temporary file:
-    let crdfile = format!("{}.crd.gen.yml", name);
-    let pth = Path::new(".").join(&crdfile);
-    debug!("Writing {} CRD for {} to {}", crd.kind, name, pth.display());
-    let mut f = File::create(&pth)?;
-    let encoded = serde_yaml::to_string(&crd)?;
-    writeln!(f, "{}", encoded)?;
-    debug!("Wrote {} CRD for {} to {}: \n{}", crd.kind, name, pth.display(), encoded);
-
-    // Apply it using kubectl apply
-    debug!("Applying {} CRD for {}", crd.kind, name);
-    let applyargs = vec![
-        format!("-n={}", ns),
-        "apply".into(),
-        "-f".into(),
-        crdfile.clone(),
-    ];
-    debug!("applying {} : {:?}", name, applyargs);
-    kexec(applyargs)?;
-    let _ = fs::remove_file(&crdfile); // try to remove temporary file
-    Ok(())
+    let client = Client::from_kubeconfig()?;
+    let coll = crd_collection(&client, &crd.apiVersion, &crd.kind, ns)?;
+    let pth = format!("{}/{}", coll, crd.metadata.name);
+    match client.get_opt::<Value>(&pth)? {
+        Some(existing) => {
+            debug!("Patching {} CRD for {}", crd.kind, name);
+            let patch = json!({
+                "metadata": { "annotations": crd.metadata.annotations },
+                "spec": merge_patch(&existing["spec"], serde_json::to_value(&crd.spec)?),
+            });
+            client.merge_patch(&pth, &patch)
+        },
+        None => {
+            debug!("Creating {} CRD for {}", crd.kind, name);
+            client.create(&coll, &crd)
+        }
+    }
 }
+
 /// Find all ManifestCrds in a given namespace
 ///
 /// Allows us to purge manifests that are not in Manifest::available()
-fn find_all_manifest_crds(ns: &str) -> Result<Vec<String>> {
-     let getargs = vec![
-        "get".into(),
-        format!("-n={}", ns),
-        "shipcatmanifests".into(),
-        "-ojsonpath='{.items[*].metadata.name}'".into(),
-    ];
-    let out = kout(getargs)?;
-    if out == "''" { // stupid kubectl
-        return Ok(vec![])
-    }
-    Ok(out.split(' ').map(String::from).collect())
+fn find_all_manifest_crds(client: &Client, ns: &str) -> Result<Vec<String>> {
+    let pth = crd_collection(client, MANIFEST_API_VERSION, MANIFEST_KIND, ns)?;
+    let res = client.get::<ObjectList<Object>>(&pth)?;
+    Ok(res.items.into_iter().map(|o| o.metadata.name).collect())
 }
 
+const MANIFEST_API_VERSION: &str = "babylontech.co.uk/v1";
+const MANIFEST_KIND: &str = "ShipcatManifest";
+
 use std::collections::HashSet;
 pub fn remove_redundant_manifests(ns: &str, svcs: &[String]) -> Result<Vec<String>> {
+    let client = Client::from_kubeconfig()?;
     let requested: HashSet<_> = svcs.iter().cloned().collect();
-    let found: HashSet<_> = find_all_manifest_crds(ns)?.iter().cloned().collect();
+    let found: HashSet<_> = find_all_manifest_crds(&client, ns)?.iter().cloned().collect();
     debug!("Found manifests: {:?}", found);
 
     let excess : HashSet<_> = found.difference(&requested).collect();
     info!("Will remove excess manifests: {:?}", excess);
-     let mut delargs = vec![
-        "delete".into(),
-        format!("-n={}", ns),
-        "shipcatmanifests".into(),
-    ];
+    let coll = crd_collection(&client, MANIFEST_API_VERSION, MANIFEST_KIND, ns)?;
     for x in &excess {
-        delargs.push(x.to_string());
+        client.delete(&format!("{}/{}", coll, x))?;
     }
-    if !excess.is_empty() {
-        let _res = kexec(delargs)?;
-    } else {
+    if excess.is_empty() {
         debug!("No excess manifests found");
     }
     let exvec = excess.into_iter().cloned().collect();
@@ -500,7 +1231,7 @@ pub fn remove_redundant_manifests(ns: &str, svcs: &[String]) -> Result<Vec<Strin
 #[cfg(test)]
 mod tests {
     use dirs;
-    use super::current_context;
+    use super::{current_context, line_diff};
 
     #[test]
     fn validate_ctx() {
@@ -512,4 +1243,12 @@ mod tests {
             assert_ne!(ctx, "");
         }
     }
+
+    #[test]
+    fn line_diffs() {
+        let diff = line_diff("a\nb\nc\nd", "a\nc\nd\ne");
+        assert_eq!(diff, vec!["  a", "- b", "  c", "  d", "+ e"]);
+        let diff = line_diff("replicas: 1", "replicas: 2");
+        assert_eq!(diff, vec!["- replicas: 1", "+ replicas: 2"]);
+    }
 }
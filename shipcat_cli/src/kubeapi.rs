@@ -0,0 +1,447 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+
+use chrono::{DateTime, Utc};
+use kubernetes::config;
+use reqwest::{self, header, Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{Result, ResultExt};
+
+// ----------------------------------------------------------------------------
+// kubeconfig
+
+/// The current context of a kubeconfig
+///
+/// Everything else about reaching the api server is left to the kubernetes crate.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct KubeConfig {
+    #[serde(default)]
+    pub current_context: String,
+}
+
+impl KubeConfig {
+    /// Read the current context kubectl would use
+    ///
+    /// The first file in `KUBECONFIG` that sets one, or `~/.kube/config`.
+    pub fn read() -> Result<KubeConfig> {
+        let pths = match env::var_os("KUBECONFIG") {
+            Some(ref v) if !v.is_empty() => env::split_paths(v).collect::<Vec<_>>(),
+            _ => vec![dirs::home_dir().ok_or("Could not find a home directory")?.join(".kube").join("config")],
+        };
+        for pth in &pths {
+            if !pth.is_file() {
+                continue;
+            }
+            let data = fs::read_to_string(pth).chain_err(|| format!("Failed to read kubeconfig {}", pth.display()))?;
+            let kc : KubeConfig = serde_yaml::from_str(&data)?;
+            if !kc.current_context.is_empty() {
+                return Ok(kc);
+            }
+        }
+        bail!("No current-context set in {}", pths.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", "))
+    }
+}
+
+// ----------------------------------------------------------------------------
+// client
+
+/// Error body returned by the api server
+#[derive(Deserialize)]
+struct Status {
+    #[serde(default)]
+    message: String,
+}
+
+/// A small typed client for the kube api
+///
+/// Uses the http client and api server the kubernetes crate sets up,
+/// but reports statuses rather than failing on them, so missing objects can be handled.
+pub struct Client {
+    base: String,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// Client for an api server with auth already set up on the http client
+    pub fn new(base: &str, http: reqwest::Client) -> Client {
+        Client { base: base.trim_end_matches('/').into(), http }
+    }
+
+    /// Client for the current context of the kubeconfig
+    pub fn from_kubeconfig() -> Result<Client> {
+        let cfg = config::load_kube_config()
+            .map_err(|e| format!("Failed to load kubeconfig: {}", e))?;
+        Ok(Client::new(&cfg.base_path, cfg.client))
+    }
+
+    fn call(&self, method: Method, path: &str, body: Option<(&str, Vec<u8>)>) -> Result<(StatusCode, String)> {
+        let url = format!("{}{}", self.base, path);
+        debug!("{} {}", method, url);
+        let mut req = self.http.request(method, url.as_str());
+        if let Some((ctype, b)) = body {
+            req = req.header(header::CONTENT_TYPE, ctype).body(b);
+        }
+        let mut res = req.send()?;
+        Ok((res.status(), res.text()?))
+    }
+
+    fn call_ok(&self, method: Method, path: &str, body: Option<(&str, Vec<u8>)>) -> Result<String> {
+        let (status, text) = self.call(method.clone(), path, body)?;
+        if !status.is_success() {
+            let msg = serde_json::from_str::<Status>(&text).map(|s| s.message).unwrap_or(text);
+            bail!("{} {} returned {}: {}", method, path, status.as_u16(), msg.trim());
+        }
+        Ok(text)
+    }
+
+    /// Get an object, or None when it does not exist
+    pub fn get_opt<T: DeserializeOwned>(&self, path: &str) -> Result<Option<T>> {
+        let (status, text) = self.call(Method::GET, path, None)?;
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            let msg = serde_json::from_str::<Status>(&text).map(|s| s.message).unwrap_or(text);
+            bail!("GET {} returned {}: {}", path, status.as_u16(), msg.trim());
+        }
+        Ok(Some(serde_json::from_str(&text)?))
+    }
+
+    /// Get an object or a list of objects
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        Ok(serde_json::from_str(&self.call_ok(Method::GET, path, None)?)?)
+    }
+
+    /// Get a plain text response like logs
+    pub fn text(&self, path: &str) -> Result<String> {
+        self.call_ok(Method::GET, path, None)
+    }
+
+    /// Create an object in a collection
+    pub fn create<T: Serialize>(&self, path: &str, obj: &T) -> Result<()> {
+        self.call_ok(Method::POST, path, Some(("application/json", serde_json::to_vec(obj)?)))?;
+        Ok(())
+    }
+
+    /// Replace an existing object (it must carry the current resourceVersion)
+    pub fn replace<T: Serialize>(&self, path: &str, obj: &T) -> Result<()> {
+        self.call_ok(Method::PUT, path, Some(("application/json", serde_json::to_vec(obj)?)))?;
+        Ok(())
+    }
+
+    /// Merge a JSON merge patch into an existing object
+    pub fn merge_patch<T: Serialize>(&self, path: &str, patch: &T) -> Result<()> {
+        self.call_ok(Method::PATCH, path, Some(("application/merge-patch+json", serde_json::to_vec(patch)?)))?;
+        Ok(())
+    }
+
+    /// Delete an object
+    pub fn delete(&self, path: &str) -> Result<()> {
+        self.call_ok(Method::DELETE, path, None)?;
+        Ok(())
+    }
+}
+
+/// Url encode a query parameter value like a label selector
+pub fn encode(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+// ----------------------------------------------------------------------------
+// resources
+//
+// Only the fields shipcat reads are included.
+
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct ObjectMeta {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(default)]
+    pub resourceVersion: String,
+    #[serde(default)]
+    pub generation: i64,
+    pub creationTimestamp: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub ownerReferences: Vec<OwnerReference>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct OwnerReference {
+    pub kind: String,
+    pub name: String,
+}
+
+/// Any object when only the metadata matters
+#[derive(Deserialize, Clone, Debug)]
+pub struct Object {
+    pub metadata: ObjectMeta,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ObjectList<T> {
+    pub items: Vec<T>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Pod {
+    pub metadata: ObjectMeta,
+    #[serde(default)]
+    pub status: PodStatus,
+}
+
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct PodStatus {
+    #[serde(default)]
+    pub phase: String,
+    #[serde(default)]
+    pub containerStatuses: Vec<ContainerStatus>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ContainerStatus {
+    pub name: String,
+    #[serde(default)]
+    pub ready: bool,
+    #[serde(default)]
+    pub restartCount: u32,
+    #[serde(default)]
+    pub state: ContainerState,
+}
+
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct ContainerState {
+    pub waiting: Option<ContainerStateReason>,
+    pub terminated: Option<ContainerStateReason>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ContainerStateReason {
+    #[serde(default)]
+    pub reason: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+impl Pod {
+    /// Ready and total containers
+    pub fn ready(&self) -> (usize, usize) {
+        let cs = &self.status.containerStatuses;
+        (cs.iter().filter(|c| c.ready).count(), cs.len())
+    }
+
+    /// Running with every container ready
+    pub fn is_healthy(&self) -> bool {
+        let (ready, total) = self.ready();
+        self.status.phase == "Running" && ready == total
+    }
+
+    /// Status like the one `kubectl get pods` shows
+    pub fn reason(&self) -> String {
+        for c in &self.status.containerStatuses {
+            let state = c.state.waiting.as_ref().or_else(|| c.state.terminated.as_ref());
+            if let Some(s) = state {
+                if !s.reason.is_empty() {
+                    return s.reason.clone();
+                }
+            }
+        }
+        self.status.phase.clone()
+    }
+
+    /// Restarts across all containers
+    pub fn restarts(&self) -> u32 {
+        self.status.containerStatuses.iter().map(|c| c.restartCount).sum()
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Deployment {
+    pub metadata: ObjectMeta,
+    pub spec: DeploymentSpec,
+    #[serde(default)]
+    pub status: DeploymentStatus,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct DeploymentSpec {
+    pub replicas: Option<u32>,
+    pub template: PodTemplateSpec,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PodTemplateSpec {
+    pub spec: PodSpec,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PodSpec {
+    pub containers: Vec<Container>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Container {
+    pub name: String,
+    #[serde(default)]
+    pub image: String,
+}
+
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct DeploymentStatus {
+    #[serde(default)]
+    pub observedGeneration: i64,
+    #[serde(default)]
+    pub replicas: u32,
+    #[serde(default)]
+    pub updatedReplicas: u32,
+    #[serde(default)]
+    pub readyReplicas: u32,
+    #[serde(default)]
+    pub availableReplicas: u32,
+}
+
+impl Deployment {
+    /// Whether the latest spec is fully rolled out
+    ///
+    /// Same conditions as `kubectl rollout status`.
+    pub fn rolled_out(&self) -> bool {
+        let s = &self.status;
+        let desired = self.spec.replicas.unwrap_or(1);
+        s.observedGeneration >= self.metadata.generation
+            && s.updatedReplicas >= desired
+            && s.replicas <= s.updatedReplicas
+            && s.availableReplicas >= s.updatedReplicas
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct StatefulSet {
+    pub metadata: ObjectMeta,
+    pub spec: StatefulSetSpec,
+    #[serde(default)]
+    pub status: StatefulSetStatus,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct StatefulSetSpec {
+    pub replicas: Option<u32>,
+}
+
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct StatefulSetStatus {
+    #[serde(default)]
+    pub observedGeneration: i64,
+    #[serde(default)]
+    pub replicas: u32,
+    #[serde(default)]
+    pub readyReplicas: u32,
+    #[serde(default)]
+    pub updatedReplicas: u32,
+    #[serde(default)]
+    pub currentRevision: String,
+    #[serde(default)]
+    pub updateRevision: String,
+}
+
+impl StatefulSet {
+    /// Whether the latest spec is fully rolled out
+    ///
+    /// Same conditions as `kubectl rollout status` for the `RollingUpdate` strategy.
+    pub fn rolled_out(&self) -> bool {
+        let s = &self.status;
+        let desired = self.spec.replicas.unwrap_or(1);
+        s.observedGeneration >= self.metadata.generation
+            && s.readyReplicas >= desired
+            && s.updateRevision == s.currentRevision
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ReplicaSet {
+    pub metadata: ObjectMeta,
+    #[serde(default)]
+    pub status: ReplicaSetStatus,
+}
+
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct ReplicaSetStatus {
+    #[serde(default)]
+    pub replicas: u32,
+    #[serde(default)]
+    pub readyReplicas: u32,
+    #[serde(default)]
+    pub availableReplicas: u32,
+}
+
+/// An installed custom resource definition
+#[derive(Deserialize, Clone, Debug)]
+pub struct CustomResourceDefinition {
+    pub metadata: ObjectMeta,
+    pub spec: CustomResourceDefinitionSpec,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CustomResourceDefinitionSpec {
+    pub group: String,
+    pub names: CustomResourceDefinitionNames,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CustomResourceDefinitionNames {
+    pub plural: String,
+    pub kind: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Event {
+    #[serde(rename = "type", default)]
+    pub eventType: String,
+    #[serde(default)]
+    pub reason: String,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub count: u32,
+    pub lastTimestamp: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Deployment, StatefulSet};
+
+    #[test]
+    fn deployment_rollout() {
+        let mut d : Deployment = serde_json::from_str(r#"{
+            "metadata": {"name": "fake-ask", "generation": 4},
+            "spec": {"replicas": 2, "template": {"spec": {"containers": [{"name": "fake-ask", "image": "fake-ask:1.0.0"}]}}},
+            "status": {"observedGeneration": 4, "replicas": 3, "updatedReplicas": 2, "availableReplicas": 2}
+        }"#).unwrap();
+        assert!(!d.rolled_out()); // old pod still terminating
+        d.status.replicas = 2;
+        assert!(d.rolled_out());
+        d.metadata.generation = 5;
+        assert!(!d.rolled_out()); // new spec not observed yet
+    }
+
+    #[test]
+    fn statefulset_rollout() {
+        let mut ss : StatefulSet = serde_json::from_str(r#"{
+            "metadata": {"name": "fake-db", "generation": 2},
+            "spec": {"replicas": 3},
+            "status": {"observedGeneration": 2, "replicas": 3, "readyReplicas": 3,
+                "currentRevision": "fake-db-1", "updateRevision": "fake-db-2"}
+        }"#).unwrap();
+        assert!(!ss.rolled_out()); // pods still on the old revision
+        ss.status.currentRevision = "fake-db-2".into();
+        assert!(ss.rolled_out());
+        ss.status.readyReplicas = 2;
+        assert!(!ss.rolled_out());
+    }
+}
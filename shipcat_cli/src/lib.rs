@@ -63,6 +63,22 @@ error_chain! {
             description("upgrade timed out")
             display("{} upgrade timed out waiting {}s for deployment(s) to come online", &svc, secs)
         }
+        UpgradeRolloutFailure(svc: String, reason: String) {
+            description("upgrade rollout failed")
+            display("{} upgrade aborted: {}", &svc, &reason)
+        }
+        CanaryAborted(svc: String, reason: String) {
+            description("canary aborted")
+            display("{} canary aborted: {}", &svc, &reason)
+        }
+        DependencyFailed(svc: String, dep: String) {
+            description("dependency failed to roll out")
+            display("{} was not reconciled as its dependency {} failed", &svc, &dep)
+        }
+        FailureBudgetExceeded(failures: usize, skipped: usize) {
+            description("too many failures in reconcile")
+            display("reconcile stopped after {} failures with {} services not started", failures, skipped)
+        }
         SlackSendFailure(hook: String) {
             description("slack message send failed")
             display("Failed to send the slack message to '{}' ", &hook)
@@ -73,7 +89,7 @@ error_chain! {
 pub use shipcat_definitions::{Manifest, ConfigType};
 pub use shipcat_definitions::structs;
 pub use shipcat_definitions::config::{self, Config, Team};
-pub use shipcat_definitions::region::{Region, VersionScheme, KongConfig, Webhook, AuditWebhook};
+pub use shipcat_definitions::region::{Region, VersionScheme, KongConfig, Webhook, AuditWebhook, HelmVersion};
 //pub use shipcat_definitions::Product;
 
 /// Convenience listers
@@ -86,6 +102,8 @@ pub mod grafana;
 pub mod audit;
 /// Cluster level operations
 pub mod cluster;
+/// Machine readable reports of cluster level operations
+pub mod report;
 
 /// Validation methods of manifests post merge
 pub mod validate;
@@ -95,6 +113,8 @@ pub mod gdpr;
 
 /// A small CLI kubernetes interface
 pub mod kube;
+/// A typed kube api client built from kubeconfig
+pub mod kubeapi;
 
 /// A small CLI helm interface
 pub mod helm;
@@ -117,6 +137,18 @@ pub mod webhooks;
 /// Simple printers
 pub mod show;
 
+/// JSON schema generation for manifests and config
+pub mod schema;
+
+/// Manifest migrations for deprecated fields
+pub mod migrate;
+
+/// Secret audits across regions
+pub mod secret;
+
+/// Kube objects rendered natively for `chart: native`
+pub mod native;
+
 /// Smart initialiser with safety
 ///
 /// Tricks the library into reading from your manifest location.
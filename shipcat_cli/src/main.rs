@@ -83,6 +83,10 @@ fn main() {
                     .help("Do not wait for service timeout"))
                 .arg(Arg::with_name("auto-rollback")
                     .long("auto-rollback"))
+                .arg(Arg::with_name("canary")
+                    .long("canary")
+                    .conflicts_with_all(&["no-wait", "auto-rollback"])
+                    .help("Bake a canary of the new version before upgrading"))
                 .arg(Arg::with_name("dryrun")
                     .long("dry-run")
                     .help("Show the diff only"))))
@@ -145,6 +149,56 @@ fn main() {
                     .multiple(true)
                     .help("Regions to validate all enabled services for"))
                 .about("Verify existence of secrets for entire regions"))
+            .subcommand(SubCommand::with_name("audit")
+                .arg(Arg::with_name("regions")
+                    .long("regions")
+                    .takes_value(true)
+                    .use_delimiter(true)
+                    .help("Comma separated regions to audit (defaults to all)"))
+                .arg(Arg::with_name("json")
+                    .long("json")
+                    .help("Print findings as json instead of a table"))
+                .about("Find unused, missing and inconsistent secrets across regions"))
+            .subcommand(SubCommand::with_name("get")
+                .arg(Arg::with_name("service")
+                    .required(true)
+                    .help("Service whose vault folder to use"))
+                .arg(Arg::with_name("key")
+                    .required(true)
+                    .help("Name of the secret"))
+                .arg(Arg::with_name("force")
+                    .long("force")
+                    .help("Allow secrets the manifest does not reference as IN_VAULT"))
+                .about("Print a secret of a service"))
+            .subcommand(SubCommand::with_name("set")
+                .arg(Arg::with_name("service")
+                    .required(true)
+                    .help("Service whose vault folder to use"))
+                .arg(Arg::with_name("key")
+                    .required(true)
+                    .help("Name of the secret"))
+                .arg(Arg::with_name("value")
+                    .help("Value of the secret (read from stdin if missing)"))
+                .arg(Arg::with_name("force")
+                    .long("force")
+                    .help("Allow secrets the manifest does not reference as IN_VAULT"))
+                .about("Write a secret of a service"))
+            .subcommand(SubCommand::with_name("list")
+                .arg(Arg::with_name("service")
+                    .required(true)
+                    .help("Service whose vault folder to list"))
+                .about("List the secrets of a service"))
+            .subcommand(SubCommand::with_name("rm")
+                .arg(Arg::with_name("service")
+                    .required(true)
+                    .help("Service whose vault folder to use"))
+                .arg(Arg::with_name("key")
+                    .required(true)
+                    .help("Name of the secret"))
+                .arg(Arg::with_name("force")
+                    .long("force")
+                    .help("Allow deleting secrets the manifest still references as IN_VAULT"))
+                .about("Delete a secret of a service"))
             .about("Secret interaction"))
 
         .subcommand(SubCommand::with_name("gdpr")
@@ -209,6 +263,17 @@ fn main() {
                     .long("num-jobs")
                     .takes_value(true)
                     .help("Number of worker threads used"))
+                .arg(Arg::with_name("report")
+                    .long("report")
+                    .takes_value(true)
+                    .possible_values(&["json", "junit"])
+                    .global(true)
+                    .help("Write a report of every service's outcome"))
+                .arg(Arg::with_name("report-file")
+                    .long("report-file")
+                    .takes_value(true)
+                    .global(true)
+                    .help("File to write the report to (default shipcat-report.json or .xml)"))
                 .subcommand(SubCommand::with_name("reconcile")
                     .about("Reconcile shipcat custom resource definitions with local state")))
             .subcommand(SubCommand::with_name("helm")
@@ -217,7 +282,36 @@ fn main() {
                     .long("num-jobs")
                     .takes_value(true)
                     .help("Number of worker threads used"))
+                .arg(Arg::with_name("report")
+                    .long("report")
+                    .takes_value(true)
+                    .possible_values(&["json", "junit"])
+                    .global(true)
+                    .help("Write a report of every service's outcome"))
+                .arg(Arg::with_name("report-file")
+                    .long("report-file")
+                    .takes_value(true)
+                    .global(true)
+                    .help("File to write the report to (default shipcat-report.json or .xml)"))
                 .subcommand(SubCommand::with_name("reconcile")
+                    .arg(Arg::with_name("ordered")
+                        .long("ordered")
+                        .help("Reconcile in dependency order, waiting for dependencies to roll out"))
+                    .arg(Arg::with_name("max-failures")
+                        .long("max-failures")
+                        .takes_value(true)
+                        .validator(positive)
+                        .help("Stop starting new upgrades after this many failures"))
+                    .arg(Arg::with_name("wave-size")
+                        .long("wave-size")
+                        .takes_value(true)
+                        .validator(positive)
+                        .help("Most services to start upgrading at the same time"))
+                    .arg(Arg::with_name("pause-between-waves")
+                        .long("pause-between-waves")
+                        .takes_value(true)
+                        .validator(|v| v.parse::<u64>().map(void).map_err(|e| format!("'{}' is not a number of seconds: {}", v, e)))
+                        .help("Seconds to wait between waves"))
                     .about("Reconcile kubernetes region configs with local state"))
                 .subcommand(SubCommand::with_name("diff")
                     .about("Diff kubernetes region configs with local state"))))
@@ -256,6 +350,10 @@ fn main() {
                 .short("s")
                 .long("secrets")
                 .help("Use actual secrets from vault"))
+              .arg(Arg::with_name("explain")
+                .long("explain")
+                .conflicts_with("secrets")
+                .help("Show which manifest file each value came from"))
               .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service to generate values for"))
@@ -291,6 +389,23 @@ fn main() {
             .subcommand(SubCommand::with_name("verify")
                 .about("Verify the parsed config")))
 
+        // schema
+        .subcommand(SubCommand::with_name("schema")
+            .arg(Arg::with_name("kind")
+                .possible_values(shipcat::schema::SCHEMA_KINDS)
+                .default_value("manifest")
+                .help("Kind of file to generate the schema for"))
+            .about("Generate the JSON schema for manifests or the config"))
+
+        // migrate
+        .subcommand(SubCommand::with_name("migrate")
+            .arg(Arg::with_name("service")
+                .help("Service to migrate (defaults to all services)"))
+            .arg(Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Only report what would be changed"))
+            .about("Rewrite deprecated fields in manifests"))
+
         // products
         .subcommand(SubCommand::with_name("product")
             .setting(AppSettings::SubcommandRequiredElseHelp)
@@ -364,6 +479,32 @@ fn resolve_config(args: &ArgMatches, ct: ConfigType) -> Result<(Config, Region)>
 
 fn void<T>(_x: T) { () } // helper so that dispatch_commands can return Result<()>
 
+/// clap validator for counts that must be at least one
+fn positive(v: String) -> std::result::Result<(), String> {
+    match v.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".into()),
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("'{}' is not a positive number: {}", v, e)),
+    }
+}
+
+fn report_target(args: &ArgMatches) -> Option<shipcat::report::ReportTarget> {
+    args.value_of("report").map(|f| {
+        // possible_values guarantees a valid format
+        shipcat::report::ReportTarget::new(f.parse().unwrap(), args.value_of("report-file"))
+    })
+}
+
+/// Read a value piped to stdin, without its trailing newline
+///
+/// Keeps secrets out of shell history.
+fn read_stdin() -> Result<String> {
+    use std::io::Read;
+    let mut value = String::new();
+    std::io::stdin().read_to_string(&mut value)?;
+    Ok(value.trim_end_matches('\n').to_string())
+}
+
 /// Dispatch clap arguments to shipcat handlers
 ///
 /// A boring and somewhat error-prone "if-x-then-fnx dance". We are relying on types
@@ -446,6 +587,16 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
             return shipcat::product::show(product, &conf, location.unwrap());
         }*/
     }
+    else if let Some(a) = args.subcommand_matches("schema") {
+        return shipcat::schema::print(a.value_of("kind").unwrap());
+    }
+    else if let Some(a) = args.subcommand_matches("migrate") {
+        let dry = a.is_present("dry-run");
+        if let Some(svc) = a.value_of("service") {
+            return shipcat::migrate::service(svc, dry);
+        }
+        return shipcat::migrate::all(dry);
+    }
     else if let Some(a) = args.subcommand_matches("config") {
         if let Some(_) = a.subcommand_matches("crd") {
             let (conf, _region) = resolve_config(a, ConfigType::Base)?;
@@ -469,12 +620,41 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
     // helpers that can work without a kube region, but will shell out to kubectl if not passed
     // TODO: remove this
     else if let Some(a) = args.subcommand_matches("secret") {
+        if let Some(b) = a.subcommand_matches("list") {
+            let (conf, region) = resolve_config(b, ConfigType::Base)?;
+            return shipcat::secret::list(b.value_of("service").unwrap(), &conf, &region);
+        }
+        for cmd in &["get", "set", "rm"] {
+            if let Some(b) = a.subcommand_matches(cmd) {
+                let (conf, region) = resolve_config(b, ConfigType::Base)?;
+                let (svc, key) = (b.value_of("service").unwrap(), b.value_of("key").unwrap());
+                let force = b.is_present("force");
+                return match *cmd {
+                    "get" => shipcat::secret::get(svc, key, &conf, &region, force),
+                    "rm" => shipcat::secret::remove(svc, key, &conf, &region, force),
+                    _ => {
+                        let value = match b.value_of("value") {
+                            Some(v) => v.to_string(),
+                            None => read_stdin()?,
+                        };
+                        shipcat::secret::set(svc, key, &value, &conf, &region, force)
+                    }
+                };
+            }
+        }
         let rawconf = Config::read()?;
         if let Some(b) = a.subcommand_matches("verify-region") {
             let regions = b.values_of("regions").unwrap().map(String::from).collect::<Vec<_>>();
             // NB: this does a cheap verify of both Config and Manifest (vault list)
             return shipcat::validate::secret_presence(&rawconf, regions);
         }
+        if let Some(b) = a.subcommand_matches("audit") {
+            let regions = match b.values_of("regions") {
+                Some(rs) => rs.map(String::from).collect::<Vec<_>>(),
+                None => rawconf.list_regions(),
+            };
+            return shipcat::secret::audit(&rawconf, regions, b.is_present("json")).map(|_| ());
+        }
     }
 
     // ------------------------------------------------------------------------------
@@ -512,6 +692,9 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
         let ss = if a.is_present("secrets") { ConfigType::Filtered } else { ConfigType::Base };
         let (conf, region) = resolve_config(a, ss)?;
 
+        if a.is_present("explain") {
+            return shipcat::show::values_explain(&svc, &conf, &region);
+        }
         let mf = if a.is_present("secrets") {
             Manifest::base(&svc, &conf, &region)?.complete(&region)?
         } else {
@@ -614,6 +797,9 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
             else if b.is_present("no-wait") {
                 shipcat::helm::UpgradeMode::UpgradeNoWait
             }
+            else if b.is_present("canary") {
+                shipcat::helm::UpgradeMode::Canary
+            }
             else {
                 shipcat::helm::UpgradeMode::UpgradeWait
             }
@@ -642,8 +828,8 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
         if let Some(b) = a.subcommand_matches("crd") {
             let (conf, region) = resolve_config(args, ConfigType::Base)?;
             let jobs = b.value_of("num-jobs").unwrap_or("8").parse().unwrap();
-            if let Some(_) = b.subcommand_matches("reconcile") {
-                return shipcat::cluster::mass_crd(&conf, &region, jobs);
+            if let Some(c) = b.subcommand_matches("reconcile") {
+                return shipcat::cluster::mass_crd(&conf, &region, jobs, report_target(c));
             }
         }
         if let Some(b) = a.subcommand_matches("helm") {
@@ -652,11 +838,20 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
             assert!(conf.has_secrets()); // sanity on cluster disruptive commands
 
             let jobs = b.value_of("num-jobs").unwrap_or("8").parse().unwrap();
-            if let Some(_) = b.subcommand_matches("diff") {
-                return shipcat::cluster::helm_diff(&conf, &region, jobs);
+            if let Some(c) = b.subcommand_matches("diff") {
+                return shipcat::cluster::helm_diff(&conf, &region, jobs, report_target(c));
             }
-            else if let Some(_) = b.subcommand_matches("reconcile") {
-                return shipcat::cluster::helm_reconcile(&conf, &region, jobs);
+            else if let Some(c) = b.subcommand_matches("reconcile") {
+                let opts = shipcat::helm::ReconcileOpts {
+                    workers: jobs,
+                    ordered: c.is_present("ordered"),
+                    // validated by clap
+                    max_failures: c.value_of("max-failures").map(|n| n.parse().unwrap()),
+                    wave_size: c.value_of("wave-size").map(|n| n.parse().unwrap()),
+                    pause: c.value_of("pause-between-waves").unwrap_or("0").parse().unwrap(),
+                    report: report_target(c),
+                };
+                return shipcat::cluster::helm_reconcile(&conf, &region, &opts);
             }
         }
     }
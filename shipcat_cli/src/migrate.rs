@@ -0,0 +1,395 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+
+use shipcat_definitions::structs::{HealthCheck, VaultOpts};
+use super::{Result, Manifest};
+
+/// A yaml file edited line by line
+///
+/// Going through `serde_yaml` would drop comments and reorder keys,
+/// so migrations only ever touch the lines of the keys they rewrite.
+struct YamlLines {
+    lines: Vec<String>,
+    trailing_newline: bool,
+}
+
+impl YamlLines {
+    fn new(src: &str) -> Self {
+        YamlLines {
+            lines: src.lines().map(String::from).collect(),
+            trailing_newline: src.ends_with('\n'),
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut res = self.lines.join("\n");
+        if self.trailing_newline && !self.lines.is_empty() {
+            res.push('\n');
+        }
+        res
+    }
+
+    /// Line range `[start, end)` of a top level key and everything nested under it
+    fn block(&self, key: &str) -> Option<(usize, usize)> {
+        let prefix = format!("{}:", key);
+        let start = self.lines.iter().position(|l| l.starts_with(&prefix))?;
+        let mut end = start + 1;
+        for (i, l) in self.lines.iter().enumerate().skip(start + 1) {
+            if l.trim().is_empty() || l.starts_with('#') {
+                continue; // blank and comment lines only belong to the block if followed by more of it
+            }
+            if l.starts_with(' ') || l.starts_with('-') {
+                end = i + 1;
+            } else {
+                break;
+            }
+        }
+        Some((start, end))
+    }
+
+    /// Deserialize the value of a top level key
+    fn parse<T: DeserializeOwned>(&self, key: &str, (start, end): (usize, usize)) -> Result<T> {
+        let raw = self.lines[start..end].join("\n");
+        let mut map: BTreeMap<String, T> = serde_yaml::from_str(&raw)?;
+        match map.remove(key) {
+            Some(v) => Ok(v),
+            None => bail!("Failed to parse {} block", key),
+        }
+    }
+
+    /// Indentation unit used by a block, defaulting to two spaces
+    fn indent(&self, (start, end): (usize, usize)) -> String {
+        self.lines[start + 1..end].iter()
+            .find(|l| !l.trim().is_empty() && !l.starts_with('#'))
+            .map(|l| l.len() - l.trim_start().len())
+            .filter(|n| *n > 0)
+            .map(|n| " ".repeat(n))
+            .unwrap_or_else(|| "  ".into())
+    }
+
+    fn splice(&mut self, (start, end): (usize, usize), new: Vec<String>) {
+        self.lines.splice(start..end, new);
+    }
+}
+
+/// What is known about the service when migrating one of its files
+struct Context<'a> {
+    service: &'a str,
+    /// Whether the file is the main `shipcat.yml` rather than a region override
+    main: bool,
+    /// Whether the main `shipcat.yml` has a `readinessProbe`
+    readiness: bool,
+    /// Whether the main `shipcat.yml` has a `kong` block
+    kong: bool,
+    /// Service whose vault folder the main `shipcat.yml` borrows secrets from
+    vault: Option<String>,
+}
+
+/// Result of migrating a single file
+#[derive(Default)]
+struct Migration {
+    content: String,
+    /// Changes that were made
+    changes: Vec<String>,
+    /// Deprecated fields that were left alone and need a human
+    manual: Vec<String>,
+}
+
+/// Quote a scalar if it could be mistaken for something other than a plain string
+fn scalar(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || "/-_.".contains(c)) {
+        s.into()
+    } else {
+        format!("{:?}", s)
+    }
+}
+
+fn migrate_health(doc: &mut YamlLines, ctx: &Context, res: &mut Migration) -> Result<()> {
+    let blk = match doc.block("health") {
+        Some(b) => b,
+        None => return Ok(()),
+    };
+    let hc: HealthCheck = doc.parse("health", blk)?;
+    if ctx.readiness || doc.block("readinessProbe").is_some() {
+        doc.splice(blk, vec![]);
+        res.changes.push("removed `health` (superseded by `readinessProbe`)".into());
+    } else if hc.port.is_some() {
+        res.manual.push("`health.port` needs a named port in `ports` - convert `health` to `readinessProbe` by hand".into());
+    } else {
+        let i = doc.indent(blk);
+        doc.splice(blk, vec![
+            "readinessProbe:".into(),
+            format!("{}httpGet:", i),
+            format!("{}{}path: {}", i, i, scalar(&hc.uri)),
+            format!("{}{}port: http", i, i),
+            format!("{}initialDelaySeconds: {}", i, hc.wait),
+            format!("{}periodSeconds: 5", i),
+        ]);
+        res.changes.push("converted `health` to `readinessProbe`".into());
+    }
+    Ok(())
+}
+
+/// Point `KEY: IN_VAULT` lines at the same secret in another service's folder
+fn share_secrets(doc: &mut YamlLines, other: &str) -> usize {
+    let mut n = 0;
+    for l in &mut doc.lines {
+        let indent = l.len() - l.trim_start().len();
+        if indent == 0 {
+            continue; // secrets are always nested under env or secretFiles
+        }
+        let mut parts = l.trim_start().splitn(2, ':');
+        let key = parts.next().unwrap_or("").to_string();
+        let value = parts.next().unwrap_or("").trim();
+        let (value, comment) = match value.find(" #") {
+            Some(i) => (value[..i].trim(), value[i..].to_string()),
+            None => (value, "".into()),
+        };
+        let valid_key = !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || "_-.".contains(c));
+        if !valid_key || value.trim_matches(|c| c == '"' || c == '\'') != "IN_VAULT" {
+            continue;
+        }
+        let prefix = l[..indent].to_string();
+        *l = format!("{}{}: IN_VAULT:{}/{}{}", prefix, key, other, key, comment);
+        n += 1;
+    }
+    n
+}
+
+fn migrate_vault(doc: &mut YamlLines, ctx: &Context, res: &mut Migration) -> Result<()> {
+    if let Some(blk) = doc.block("vault") {
+        let vo: VaultOpts = doc.parse("vault", blk)?;
+        if vo.name == ctx.service && vo.region.is_none() {
+            doc.splice(blk, vec![]);
+            res.changes.push("removed redundant `vault` block".into());
+            return Ok(());
+        }
+        if vo.region.is_some() || ctx.vault.as_ref() != Some(&vo.name) {
+            res.manual.push(format!("`vault` reads secrets from {} - move them to {} in vault and remove the block",
+                vo.name, ctx.service));
+            return Ok(());
+        }
+        doc.splice(blk, vec![]);
+        res.changes.push(format!("removed `vault` block borrowing secrets from {}", vo.name));
+    }
+    // overrides use the folder of the main manifest as well
+    if let Some(other) = &ctx.vault {
+        let n = share_secrets(doc, other);
+        if n > 0 {
+            res.changes.push(format!("pointed {} `IN_VAULT` secrets at {}", n, other));
+        }
+    }
+    Ok(())
+}
+
+fn migrate_public(doc: &mut YamlLines, ctx: &Context, res: &mut Migration) -> Result<()> {
+    let blk = match doc.block("publiclyAccessible") {
+        Some(b) => b,
+        None => return Ok(()),
+    };
+    let public: bool = doc.parse("publiclyAccessible", blk)?;
+    if !public {
+        doc.splice(blk, vec![]);
+        res.changes.push("removed `publiclyAccessible: false`".into());
+        return Ok(());
+    }
+    if !ctx.main && doc.block("gate").is_none() {
+        // a new gate block would replace the whole gate of shipcat.yml
+        res.manual.push("`publiclyAccessible` in an override without a `gate` block - set `gate.public` by hand".into());
+        return Ok(());
+    }
+    if !ctx.kong {
+        res.manual.push("`publiclyAccessible` without `kong` in shipcat.yml - set `gate.public` where kong is configured".into());
+        return Ok(());
+    }
+    doc.splice(blk, vec![]);
+    match doc.block("gate") {
+        Some(gblk) => {
+            if doc.lines[gblk.0].trim_end() != "gate:" {
+                res.manual.push("inline `gate` block - set `gate.public: true` by hand".into());
+                doc.lines.insert(blk.0, "publiclyAccessible: true".into());
+                return Ok(());
+            }
+            let existing = (gblk.0 + 1..gblk.1).find(|i| doc.lines[*i].trim_start().starts_with("public:"));
+            match existing {
+                Some(i) => {
+                    let indent = doc.lines[i].len() - doc.lines[i].trim_start().len();
+                    doc.lines[i] = format!("{}public: true", " ".repeat(indent));
+                }
+                None => {
+                    let line = format!("{}public: true", doc.indent(gblk));
+                    doc.lines.insert(gblk.0 + 1, line);
+                }
+            }
+        }
+        None => {
+            doc.splice((blk.0, blk.0), vec!["gate:".into(), "  public: true".into()]);
+        }
+    }
+    res.changes.push("converted `publiclyAccessible` to `gate.public`".into());
+    Ok(())
+}
+
+/// Rewrite deprecated fields in the source of a manifest file
+fn rewrite(src: &str, ctx: &Context) -> Result<Migration> {
+    let mut doc = YamlLines::new(src);
+    let mut res = Migration::default();
+    migrate_health(&mut doc, ctx, &mut res)?;
+    migrate_vault(&mut doc, ctx, &mut res)?;
+    migrate_public(&mut doc, ctx, &mut res)?;
+    res.content = doc.render();
+    Ok(res)
+}
+
+/// The main manifest followed by the region overrides of a service
+fn service_files(svc: &str) -> Result<Vec<PathBuf>> {
+    let dir = Path::new(".").join("services").join(svc);
+    let main = dir.join("shipcat.yml");
+    if !main.is_file() {
+        bail!("Service {} does not exist in {}", svc, dir.display());
+    }
+    let mut overrides = vec![];
+    for entry in fs::read_dir(&dir)? {
+        let pth = entry?.path();
+        let stubs = pth.file_name().map_or(false, |f| f == "stubs.yml");
+        if pth != main && !stubs && pth.extension().map_or(false, |e| e == "yml") {
+            overrides.push(pth);
+        }
+    }
+    overrides.sort();
+    let mut files = vec![main];
+    files.extend(overrides);
+    Ok(files)
+}
+
+/// Migrate deprecated fields in all manifest files of a service
+///
+/// Files are rewritten in place unless `dry_run` is set.
+/// Anything that cannot be migrated safely is left alone and reported.
+pub fn service(svc: &str, dry_run: bool) -> Result<()> {
+    let files = service_files(svc)?;
+    let main = YamlLines::new(&fs::read_to_string(&files[0])?);
+    // secrets borrowed from another service can be referenced directly
+    let vault = match main.block("vault") {
+        Some(blk) => {
+            let vo: VaultOpts = main.parse("vault", blk)?;
+            Some(vo.name).filter(|n| n != svc && vo.region.is_none())
+        }
+        None => None,
+    };
+    for (i, pth) in files.iter().enumerate() {
+        let ctx = Context {
+            service: svc,
+            main: i == 0,
+            readiness: main.block("readinessProbe").is_some(),
+            kong: main.block("kong").is_some(),
+            vault: vault.clone(),
+        };
+        let src = fs::read_to_string(pth)?;
+        let res = rewrite(&src, &ctx)?;
+        for c in &res.changes {
+            info!("{}: {}", pth.display(), c);
+        }
+        for m in &res.manual {
+            warn!("{}: {}", pth.display(), m);
+        }
+        if !dry_run && res.content != src {
+            fs::write(pth, &res.content)?;
+        }
+    }
+    Ok(())
+}
+
+/// Migrate deprecated fields in every service
+pub fn all(dry_run: bool) -> Result<()> {
+    for svc in Manifest::all()? {
+        service(&svc, dry_run)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rewrite, Context};
+
+    fn ctx(main: bool, kong: bool) -> Context<'static> {
+        Context { service: "fake-ask", main, readiness: false, kong, vault: None }
+    }
+
+    #[test]
+    fn health_to_readiness() {
+        let src = "name: fake-ask\n# probe\nhealth:\n  uri: /health\n  wait: 20\n\nhttpPort: 8080\n";
+        let res = rewrite(src, &ctx(true, false)).unwrap();
+        assert_eq!(res.content, "name: fake-ask\n# probe\nreadinessProbe:\n  httpGet:\n    path: /health\n    port: http\n  initialDelaySeconds: 20\n  periodSeconds: 5\n\nhttpPort: 8080\n");
+        assert_eq!(res.changes.len(), 1);
+
+        let both = "health:\n  uri: /health\nreadinessProbe:\n  tcpSocket:\n    port: http\n";
+        let res = rewrite(both, &ctx(true, false)).unwrap();
+        assert_eq!(res.content, "readinessProbe:\n  tcpSocket:\n    port: http\n");
+
+        let commented = "health:\n  uri: /health\n# commented out\n#  port: 8081\n  wait: 20\n# next\nhttpPort: 8080\n";
+        let res = rewrite(commented, &ctx(true, false)).unwrap();
+        assert_eq!(res.content, "readinessProbe:\n  httpGet:\n    path: /health\n    port: http\n  initialDelaySeconds: 20\n  periodSeconds: 5\n# next\nhttpPort: 8080\n");
+
+        let port = "health:\n  uri: /health\n  port: 8081\n";
+        let res = rewrite(port, &ctx(true, false)).unwrap();
+        assert_eq!(res.content, port);
+        assert_eq!(res.manual.len(), 1);
+    }
+
+    #[test]
+    fn vault_removal() {
+        let src = "vault:\n  name: fake-ask\nregions:\n- dev-uk\n";
+        assert_eq!(rewrite(src, &ctx(true, false)).unwrap().content, "regions:\n- dev-uk\n");
+
+        let other = "vault:\n  name: fake-storage\n";
+        let res = rewrite(other, &ctx(true, false)).unwrap();
+        assert_eq!(res.content, other);
+        assert_eq!(res.manual.len(), 1);
+    }
+
+    #[test]
+    fn vault_to_shared_refs() {
+        let shared = |main| Context { vault: Some("fake-storage".into()), ..ctx(main, false) };
+        let src = "vault:\n  name: fake-storage\nenv:\n  DB_URL: IN_VAULT # db\n  PLAIN: foo\nsecretFiles:\n  fake-file: \"IN_VAULT\"\n";
+        let res = rewrite(src, &shared(true)).unwrap();
+        assert_eq!(res.content, "env:\n  DB_URL: IN_VAULT:fake-storage/DB_URL # db\n  PLAIN: foo\nsecretFiles:\n  fake-file: IN_VAULT:fake-storage/fake-file\n");
+        assert!(res.manual.is_empty());
+
+        // overrides borrowed from the same folder
+        let worker = "workers:\n- name: consumer\n  env:\n    QUEUE_KEY: IN_VAULT\n";
+        let res = rewrite(worker, &shared(false)).unwrap();
+        assert_eq!(res.content, "workers:\n- name: consumer\n  env:\n    QUEUE_KEY: IN_VAULT:fake-storage/QUEUE_KEY\n");
+
+        // other regions cannot be expressed as references
+        let regional = "vault:\n  name: fake-storage\n  region: prod-uk\nenv:\n  DB_URL: IN_VAULT\n";
+        let res = rewrite(regional, &shared(true)).unwrap();
+        assert_eq!(res.content, regional);
+        assert_eq!(res.manual.len(), 1);
+    }
+
+    #[test]
+    fn public_to_gate() {
+        let src = "publiclyAccessible: true\nkong:\n  uris: /ask\n";
+        let res = rewrite(src, &ctx(true, true)).unwrap();
+        assert_eq!(res.content, "gate:\n  public: true\nkong:\n  uris: /ask\n");
+
+        let gated = "publiclyAccessible: true\ngate:\n  public: false\n  websockets: true\n";
+        let res = rewrite(gated, &ctx(true, true)).unwrap();
+        assert_eq!(res.content, "gate:\n  public: true\n  websockets: true\n");
+
+        let nokong = "publiclyAccessible: true\n";
+        let res = rewrite(nokong, &ctx(true, false)).unwrap();
+        assert_eq!(res.content, nokong);
+        assert_eq!(res.manual.len(), 1);
+
+        // overrides only convert into their own gate block
+        let res = rewrite(nokong, &ctx(false, true)).unwrap();
+        assert_eq!(res.content, nokong);
+        assert_eq!(res.manual.len(), 1);
+        let res = rewrite(gated, &ctx(false, true)).unwrap();
+        assert_eq!(res.content, "gate:\n  public: true\n  websockets: true\n");
+    }
+}
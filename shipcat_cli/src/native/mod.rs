@@ -0,0 +1,510 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use serde_json::{json, Value};
+use shipcat_definitions::structs::{EnvVars, Port, VolumeMount};
+use shipcat_definitions::structs::rollingupdate::{RollingUpdate, AvailabilityPolicy};
+
+use super::{Result, Manifest};
+
+/// Typed kubernetes objects
+pub mod objects;
+use self::objects::*;
+
+/// Chart name that selects the native renderer
+pub const NATIVE_CHART: &str = "native";
+
+/// Whether a manifest is rendered natively rather than through helm
+pub fn is_native(mf: &Manifest) -> bool {
+    mf.chart.as_ref().map(|c| c == NATIVE_CHART).unwrap_or(false)
+}
+
+/// Render a completed manifest into kube objects
+///
+/// Produces the same objects as the `base` chart: a `Deployment` (plus one per worker),
+/// `Service`s for http ports, an autoscaler or a disruption budget, config maps,
+/// secrets, cron jobs, and the service account with its optional role.
+pub fn render(mf: &Manifest) -> Result<Vec<Object>> {
+    if let Some(s) = mf.sidecars.first() {
+        bail!("sidecar {} of {} needs a helm chart - the native renderer has no sidecar templates", s.name, mf.name);
+    }
+    let version = match &mf.version {
+        Some(v) => v,
+        None => bail!("{} needs a version to render", mf.name),
+    };
+    let image = format!("{}:{}", mf.image.clone().unwrap_or_default(), version);
+
+    let mut objs = vec![Object::ServiceAccount(service_account(mf))];
+    if !mf.rbac.is_empty() {
+        objs.push(Object::Role(role(mf)));
+        objs.push(Object::RoleBinding(role_binding(mf)));
+    }
+    if let Some(cm) = config_map(mf) {
+        objs.push(Object::ConfigMap(cm));
+    }
+    objs.extend(secrets(mf).into_iter().map(Object::Secret));
+
+    // main deployment
+    let mut container = Container {
+        name: mf.name.clone(),
+        image: image.clone(),
+        command: mf.command.clone(),
+        resources: mf.resources.clone(),
+        ports: container_ports(mf.httpPort, health_port(mf), &mf.ports)?,
+        env: env_vars(mf, &mf.env),
+        readinessProbe: to_value(&mf.readinessProbe)?.or_else(|| health_probe(mf)),
+        livenessProbe: to_value(&mf.livenessProbe)?,
+        lifecycle: to_value(&mf.lifecycle)?,
+        volumeMounts: volume_mounts(mf),
+        ..container_defaults()
+    };
+    if let Some(k) = &mf.kafka {
+        if k.mountPodIP {
+            container.env.push(EnvVar::field("HOST_NAME", "status.podIP"));
+        }
+    }
+    // blue/green colours are copies of a main deployment that runs nothing itself
+    let replicas = if mf.blueGreen.is_some() {
+        Some(0)
+    } else if mf.autoScaling.is_some() {
+        None
+    } else {
+        mf.replicaCount
+    };
+    objs.push(Object::Deployment(deployment(mf, &mf.name, replicas, container, mf.rollingUpdate.clone())?));
+    if let Some(s) = service(mf, &mf.name, mf.httpPort, health_port(mf), &mf.ports)? {
+        objs.push(Object::Service(s));
+    }
+    match &mf.autoScaling {
+        Some(hpa) => objs.push(Object::HorizontalPodAutoscaler(autoscaler(mf, &mf.name, hpa.clone()))),
+        None => {
+            if let Some(pdb) = disruption_budget(mf, mf.replicaCount.unwrap_or(1), &mf.rollingUpdate) {
+                objs.push(Object::PodDisruptionBudget(pdb));
+            }
+        }
+    }
+
+    for w in &mf.workers {
+        let mut env = if w.preserveEnv { mf.env.clone() } else { EnvVars::default() };
+        env.plain.extend(w.env.plain.clone());
+        env.secrets.extend(w.env.secrets.clone());
+        let container = Container {
+            name: w.name.clone(),
+            image: image.clone(),
+            command: w.command.clone(),
+            resources: Some(w.resources.clone()),
+            ports: container_ports(w.httpPort, None, &w.ports)?,
+            env: env_vars(mf, &env),
+            readinessProbe: to_value(&w.readinessProbe)?,
+            livenessProbe: to_value(&w.livenessProbe)?,
+            volumeMounts: volume_mounts(mf),
+            ..container_defaults()
+        };
+        let replicas = if w.autoScaling.is_some() { None } else { Some(w.replicaCount) };
+        objs.push(Object::Deployment(deployment(mf, &w.name, replicas, container, None)?));
+        if let Some(s) = service(mf, &w.name, w.httpPort, None, &w.ports)? {
+            objs.push(Object::Service(s));
+        }
+        if let Some(hpa) = &w.autoScaling {
+            objs.push(Object::HorizontalPodAutoscaler(autoscaler(mf, &w.name, hpa.clone())));
+        }
+    }
+
+    for c in &mf.cronJobs {
+        let mut env = if c.preserveEnv { mf.env.clone() } else { EnvVars::default() };
+        env.plain.extend(c.env.plain.clone());
+        env.secrets.extend(c.env.secrets.clone());
+        let cimage = match (&c.image, &c.version) {
+            (Some(i), Some(v)) => format!("{}:{}", i, v),
+            _ => image.clone(),
+        };
+        let container = Container {
+            name: c.name.clone(),
+            image: cimage,
+            args: c.command.clone(),
+            resources: c.resources.clone(),
+            env: env_vars(mf, &env),
+            volumeMounts: volume_mounts(mf),
+            ..container_defaults()
+        };
+        let mut template = pod_template(mf, &c.name, container)?;
+        template.spec.restartPolicy = Some("Never".into());
+        template.metadata = ObjectMeta::default();
+        objs.push(Object::CronJob(CronJob {
+            apiVersion: "batch/v1beta1".into(),
+            kind: "CronJob".into(),
+            metadata: metadata(mf, &c.name),
+            spec: CronJobSpec {
+                schedule: c.schedule.clone(),
+                concurrencyPolicy: "Forbid".into(),
+                jobTemplate: JobTemplateSpec {
+                    spec: JobSpec { activeDeadlineSeconds: c.timeout, template },
+                },
+            },
+        }));
+    }
+    Ok(objs)
+}
+
+/// Render a completed manifest into a multi document yaml string
+pub fn template(mf: &Manifest) -> Result<String> {
+    let mut docs = vec![];
+    for o in render(mf)? {
+        docs.push(serde_yaml::to_string(&o)?);
+    }
+    Ok(docs.join("\n"))
+}
+
+/// Read back the objects that `template` wrote to a file
+pub fn read(file: &str) -> Result<Vec<Value>> {
+    parse(&fs::read_to_string(file)?)
+}
+
+/// Objects of a multi document yaml string, like `template` or `helm template` output
+///
+/// Comment lines (like the `# Source:` lines of helm) and empty documents are skipped.
+pub fn parse(data: &str) -> Result<Vec<Value>> {
+    let mut docs = vec![String::new()];
+    for l in data.lines() {
+        if l.trim_end() == "---" {
+            docs.push(String::new());
+        } else if l.starts_with('#') {
+            continue;
+        } else if let Some(d) = docs.last_mut() {
+            d.push_str(l);
+            d.push('\n');
+        }
+    }
+    let mut res = vec![];
+    for d in docs.iter().filter(|d| !d.trim().is_empty()) {
+        let obj : Value = serde_yaml::from_str(d)?;
+        if !obj.is_null() {
+            res.push(obj);
+        }
+    }
+    Ok(res)
+}
+
+fn to_value<T: serde::Serialize>(x: &Option<T>) -> Result<Option<Value>> {
+    Ok(match x {
+        Some(v) => Some(serde_json::to_value(v)?),
+        None => None,
+    })
+}
+
+fn to_values<T: serde::Serialize>(xs: &[T]) -> Result<Vec<Value>> {
+    let mut res = vec![];
+    for x in xs {
+        res.push(serde_json::to_value(x)?);
+    }
+    Ok(res)
+}
+
+/// Labels on every object of a service
+///
+/// The same set as the `base` chart, with this renderer standing in for the chart
+/// (versioned as shipcat) and for tiller as the `heritage`.
+fn labels(mf: &Manifest) -> BTreeMap<String, String> {
+    let mut labels = mf.labels.clone();
+    labels.insert("app".into(), mf.name.clone());
+    labels.insert("type".into(), "service".into());
+    let mut chart = format!("{}-{}", mf.name, env!("CARGO_PKG_VERSION")).replace("+", "_");
+    chart.truncate(63);
+    labels.insert("chart".into(), chart.trim_end_matches('-').into());
+    labels.insert("release".into(), mf.name.clone());
+    labels.insert("heritage".into(), "shipcat".into());
+    labels
+}
+
+fn metadata(mf: &Manifest, name: &str) -> ObjectMeta {
+    ObjectMeta {
+        name: name.into(),
+        namespace: Some(mf.namespace.clone()),
+        labels: labels(mf),
+        ..Default::default()
+    }
+}
+
+/// Pod labels selected by deployments and services
+///
+/// Same as the `base` chart so services can switch charts without recreating deployments.
+fn selector(mf: &Manifest, app: &str) -> BTreeMap<String, String> {
+    let mut sel = BTreeMap::new();
+    sel.insert("app".to_string(), app.to_string());
+    sel.insert("release".to_string(), mf.name.clone());
+    sel
+}
+
+fn container_defaults() -> Container {
+    Container { imagePullPolicy: "IfNotPresent".into(), ..Default::default() }
+}
+
+/// Separate health port if it differs from the http port
+fn health_port(mf: &Manifest) -> Option<u32> {
+    mf.health.as_ref().and_then(|h| h.port).filter(|p| Some(*p) != mf.httpPort)
+}
+
+fn protocol(p: &Port) -> Result<String> {
+    Ok(serde_json::to_value(&p.protocol)?.as_str().unwrap_or("TCP").to_string())
+}
+
+fn container_ports(http: Option<u32>, health: Option<u32>, ports: &[Port]) -> Result<Vec<ContainerPort>> {
+    let mut res = vec![];
+    if let Some(p) = http {
+        res.push(ContainerPort { name: "http".into(), containerPort: p, protocol: "TCP".into() });
+    }
+    if let Some(p) = health {
+        res.push(ContainerPort { name: "health-http".into(), containerPort: p, protocol: "TCP".into() });
+    }
+    for p in ports {
+        res.push(ContainerPort { name: p.name.clone(), containerPort: p.port, protocol: protocol(p)? });
+    }
+    Ok(res)
+}
+
+/// Readiness probe from the `health` check when no explicit probe is set
+fn health_probe(mf: &Manifest) -> Option<Value> {
+    let health = mf.health.as_ref()?;
+    mf.httpPort?;
+    let port = if health_port(mf).is_some() { "health-http" } else { "http" };
+    Some(json!({
+        "httpGet": { "path": health.uri, "port": port },
+        "initialDelaySeconds": health.wait,
+        "periodSeconds": 5,
+    }))
+}
+
+/// Plain env vars, then secrets from the service's secret, then the injected ones
+fn env_vars(mf: &Manifest, env: &EnvVars) -> Vec<EnvVar> {
+    let secret = format!("{}-secrets", mf.name);
+    let mut res = env.plain.iter().map(|(k, v)| EnvVar::plain(k, v)).collect::<Vec<_>>();
+    res.extend(env.secrets.iter().map(|k| EnvVar::secret(k, &secret)));
+    res.push(EnvVar::plain("SERVICE_NAME", &mf.name));
+    res.push(EnvVar::plain("ENV_NAME", &mf.environment));
+    res.push(EnvVar::plain("REGION_NAME", &mf.region));
+    res.push(EnvVar::plain("SERVICE_VERSION", mf.version.as_ref().map(String::as_str).unwrap_or("")));
+    res
+}
+
+fn volume_mounts(mf: &Manifest) -> Vec<VolumeMount> {
+    let mut res = vec![];
+    if let Some(cfg) = &mf.configs {
+        for f in &cfg.files {
+            res.push(VolumeMount {
+                name: format!("{}-config-volume", mf.name),
+                mountPath: format!("{}{}", cfg.mount, f.dest),
+                subPath: Some(f.dest.clone()),
+                readOnly: false,
+            });
+        }
+    }
+    res.extend(mf.volumeMounts.iter().cloned());
+    res
+}
+
+/// Checksums of the config and secrets of a service, like the `base` chart annotates pods with
+///
+/// Changing either rolls the deployments, so pods never run with stale files or secrets.
+fn checksums(mf: &Manifest) -> Result<BTreeMap<String, String>> {
+    let config = match config_map(mf) {
+        Some(cm) => serde_yaml::to_string(&cm)?,
+        None => String::new(),
+    };
+    let mut secret = String::new();
+    for s in secrets(mf) {
+        secret.push_str(&serde_yaml::to_string(&s)?);
+    }
+    let mut res = BTreeMap::new();
+    res.insert("checksum/config".to_string(), sha256(&config));
+    res.insert("checksum/secrets".to_string(), sha256(&secret));
+    Ok(res)
+}
+
+fn sha256(data: &str) -> String {
+    openssl::sha::sha256(data.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn pod_template(mf: &Manifest, app: &str, container: Container) -> Result<PodTemplateSpec> {
+    let mut volumes = vec![];
+    if mf.configs.is_some() {
+        volumes.push(json!({
+            "name": format!("{}-config-volume", mf.name),
+            "configMap": { "name": format!("{}-config", mf.name) },
+        }));
+    }
+    volumes.extend(to_values(&mf.volumes)?);
+    Ok(PodTemplateSpec {
+        metadata: ObjectMeta { labels: selector(mf, app), annotations: checksums(mf)?, ..Default::default() },
+        spec: PodSpec {
+            serviceAccountName: mf.name.clone(),
+            containers: vec![container],
+            initContainers: to_values(&mf.initContainers)?,
+            tolerations: to_values(&mf.tolerations)?,
+            hostAliases: to_values(&mf.hostAliases)?,
+            volumes,
+            restartPolicy: None,
+        },
+    })
+}
+
+fn deployment(mf: &Manifest, name: &str, replicas: Option<u32>, container: Container, ru: Option<RollingUpdate>) -> Result<Deployment> {
+    // never take down the only replica
+    let strategy = ru.or_else(|| {
+        if replicas == Some(1) {
+            Some(RollingUpdate { maxUnavailable: Some(AvailabilityPolicy::Unsigned(0)), maxSurge: None })
+        } else {
+            None
+        }
+    }).map(|rollingUpdate| DeploymentStrategy { rollingUpdate });
+    Ok(Deployment {
+        apiVersion: "apps/v1".into(),
+        kind: "Deployment".into(),
+        metadata: metadata(mf, name),
+        spec: DeploymentSpec {
+            replicas,
+            revisionHistoryLimit: 20,
+            minReadySeconds: 10,
+            strategy,
+            selector: LabelSelector { matchLabels: selector(mf, name) },
+            template: pod_template(mf, name, container)?,
+        },
+    })
+}
+
+fn service(mf: &Manifest, name: &str, http: Option<u32>, health: Option<u32>, ports: &[Port]) -> Result<Option<Service>> {
+    let http = match http {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    let mut sports = vec![ServicePort { name: "http".into(), port: 80, targetPort: Some(http), protocol: "TCP".into() }];
+    if let Some(p) = health {
+        sports.push(ServicePort { name: "health".into(), port: p, targetPort: None, protocol: "TCP".into() });
+    }
+    for p in ports {
+        sports.push(ServicePort { name: p.name.clone(), port: p.port, targetPort: None, protocol: protocol(p)? });
+    }
+    let mut md = metadata(mf, name);
+    md.annotations = mf.serviceAnnotations.clone();
+    let mut sel = selector(mf, name);
+    if name == mf.name {
+        // canaries run under their own release label
+        sel.remove("release");
+    }
+    Ok(Some(Service {
+        apiVersion: "v1".into(),
+        kind: "Service".into(),
+        metadata: md,
+        spec: ServiceSpec { ports: sports, selector: sel },
+    }))
+}
+
+fn autoscaler(mf: &Manifest, name: &str, scaling: shipcat_definitions::structs::autoscaling::AutoScaling) -> HorizontalPodAutoscaler {
+    HorizontalPodAutoscaler {
+        apiVersion: "autoscaling/v2beta1".into(),
+        kind: "HorizontalPodAutoscaler".into(),
+        metadata: metadata(mf, name),
+        spec: HorizontalPodAutoscalerSpec {
+            scaleTargetRef: CrossVersionObjectReference {
+                apiVersion: "apps/v1".into(),
+                kind: "Deployment".into(),
+                name: name.into(),
+            },
+            scaling,
+        },
+    }
+}
+
+/// Limit voluntary evictions (like node drains) to what a rolling upgrade allows
+///
+/// Services with a single replica get none, as it would block drains entirely.
+fn disruption_budget(mf: &Manifest, replicas: u32, ru: &Option<RollingUpdate>) -> Option<PodDisruptionBudget> {
+    if replicas < 2 {
+        return None;
+    }
+    let maxUnavailable = ru.as_ref()
+        .and_then(|r| r.maxUnavailable.clone())
+        .unwrap_or(AvailabilityPolicy::Unsigned(1));
+    Some(PodDisruptionBudget {
+        apiVersion: "policy/v1beta1".into(),
+        kind: "PodDisruptionBudget".into(),
+        metadata: metadata(mf, &mf.name),
+        spec: PodDisruptionBudgetSpec {
+            maxUnavailable,
+            selector: LabelSelector { matchLabels: selector(mf, &mf.name) },
+        },
+    })
+}
+
+fn config_map(mf: &Manifest) -> Option<ConfigMap> {
+    let cfg = mf.configs.as_ref()?;
+    let data = cfg.files.iter()
+        .map(|f| (f.dest.clone(), f.value.clone().unwrap_or_default()))
+        .collect();
+    Some(ConfigMap {
+        apiVersion: "v1".into(),
+        kind: "ConfigMap".into(),
+        metadata: metadata(mf, &format!("{}-config", mf.name)),
+        data,
+    })
+}
+
+/// One secret per secret file, and one for all secret env vars
+fn secrets(mf: &Manifest) -> Vec<Secret> {
+    let mut res = vec![];
+    for (name, value) in &mf.secretFiles {
+        let mut data = BTreeMap::new();
+        data.insert("file".to_string(), value.clone()); // already base64 encoded
+        res.push(Secret {
+            apiVersion: "v1".into(),
+            kind: "Secret".into(),
+            metadata: metadata(mf, name),
+            secretType: "Opaque".into(),
+            data,
+            stringData: BTreeMap::new(),
+        });
+    }
+    let envs = mf.secrets.clone();
+    if !envs.is_empty() {
+        res.push(Secret {
+            apiVersion: "v1".into(),
+            kind: "Secret".into(),
+            metadata: metadata(mf, &format!("{}-secrets", mf.name)),
+            secretType: "Opaque".into(),
+            data: BTreeMap::new(),
+            stringData: envs,
+        });
+    }
+    res
+}
+
+fn service_account(mf: &Manifest) -> ServiceAccount {
+    ServiceAccount {
+        apiVersion: "v1".into(),
+        kind: "ServiceAccount".into(),
+        metadata: metadata(mf, &mf.name),
+        automountServiceAccountToken: !mf.rbac.is_empty(),
+    }
+}
+
+fn role(mf: &Manifest) -> Role {
+    Role {
+        apiVersion: "rbac.authorization.k8s.io/v1".into(),
+        kind: "Role".into(),
+        metadata: metadata(mf, &format!("{}-role", mf.name)),
+        rules: mf.rbac.clone(),
+    }
+}
+
+fn role_binding(mf: &Manifest) -> RoleBinding {
+    RoleBinding {
+        apiVersion: "rbac.authorization.k8s.io/v1".into(),
+        kind: "RoleBinding".into(),
+        metadata: metadata(mf, &format!("{}-binding", mf.name)),
+        subjects: vec![Subject { kind: "ServiceAccount".into(), name: mf.name.clone() }],
+        roleRef: RoleRef {
+            kind: "Role".into(),
+            name: format!("{}-role", mf.name),
+            apiGroup: "rbac.authorization.k8s.io".into(),
+        },
+    }
+}
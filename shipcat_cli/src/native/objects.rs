@@ -0,0 +1,348 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+use shipcat_definitions::structs::{Rbac, Resources, VolumeMount};
+use shipcat_definitions::structs::autoscaling::AutoScaling;
+use shipcat_definitions::structs::rollingupdate::{RollingUpdate, AvailabilityPolicy};
+
+/// Metadata common to all kube objects
+#[derive(Serialize, Clone, Default)]
+pub struct ObjectMeta {
+    /// Empty for pod templates
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct LabelSelector {
+    pub matchLabels: BTreeMap<String, String>,
+}
+
+// ----------------------------------------------------------------------------
+// pods
+
+#[derive(Serialize, Clone)]
+pub struct ContainerPort {
+    pub name: String,
+    pub containerPort: u32,
+    pub protocol: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct KeySelector {
+    pub name: String,
+    pub key: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct FieldSelector {
+    pub fieldPath: String,
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct EnvVarSource {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secretKeyRef: Option<KeySelector>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fieldRef: Option<FieldSelector>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct EnvVar {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valueFrom: Option<EnvVarSource>,
+}
+
+impl EnvVar {
+    pub fn plain(name: &str, value: &str) -> Self {
+        EnvVar { name: name.into(), value: Some(value.into()), valueFrom: None }
+    }
+    pub fn secret(name: &str, secret: &str) -> Self {
+        let selector = KeySelector { name: secret.into(), key: name.into() };
+        let source = EnvVarSource { secretKeyRef: Some(selector), ..Default::default() };
+        EnvVar { name: name.into(), value: None, valueFrom: Some(source) }
+    }
+    pub fn field(name: &str, path: &str) -> Self {
+        let source = EnvVarSource { fieldRef: Some(FieldSelector { fieldPath: path.into() }), ..Default::default() };
+        EnvVar { name: name.into(), value: None, valueFrom: Some(source) }
+    }
+}
+
+/// A container in a pod
+///
+/// Fields that manifests pass straight through are kept as json values.
+#[derive(Serialize, Clone, Default)]
+pub struct Container {
+    pub name: String,
+    pub image: String,
+    pub imagePullPolicy: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub command: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Resources<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<ContainerPort>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<EnvVar>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub readinessProbe: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub livenessProbe: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lifecycle: Option<Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub volumeMounts: Vec<VolumeMount>,
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct PodSpec {
+    pub serviceAccountName: String,
+    pub containers: Vec<Container>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub initContainers: Vec<Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tolerations: Vec<Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hostAliases: Vec<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restartPolicy: Option<String>,
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct PodTemplateSpec {
+    pub metadata: ObjectMeta,
+    pub spec: PodSpec,
+}
+
+// ----------------------------------------------------------------------------
+// workloads
+
+#[derive(Serialize, Clone)]
+pub struct DeploymentStrategy {
+    pub rollingUpdate: RollingUpdate,
+}
+
+#[derive(Serialize, Clone)]
+pub struct DeploymentSpec {
+    /// Left out when an autoscaler owns the replica count
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replicas: Option<u32>,
+    pub revisionHistoryLimit: u32,
+    pub minReadySeconds: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<DeploymentStrategy>,
+    pub selector: LabelSelector,
+    pub template: PodTemplateSpec,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Deployment {
+    pub apiVersion: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub spec: DeploymentSpec,
+}
+
+#[derive(Serialize, Clone)]
+pub struct JobSpec {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub activeDeadlineSeconds: Option<u32>,
+    pub template: PodTemplateSpec,
+}
+
+#[derive(Serialize, Clone)]
+pub struct JobTemplateSpec {
+    pub spec: JobSpec,
+}
+
+#[derive(Serialize, Clone)]
+pub struct CronJobSpec {
+    pub schedule: String,
+    pub concurrencyPolicy: String,
+    pub jobTemplate: JobTemplateSpec,
+}
+
+#[derive(Serialize, Clone)]
+pub struct CronJob {
+    pub apiVersion: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub spec: CronJobSpec,
+}
+
+// ----------------------------------------------------------------------------
+// networking and scaling
+
+#[derive(Serialize, Clone)]
+pub struct ServicePort {
+    pub name: String,
+    pub port: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub targetPort: Option<u32>,
+    pub protocol: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ServiceSpec {
+    pub ports: Vec<ServicePort>,
+    pub selector: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Service {
+    pub apiVersion: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub spec: ServiceSpec,
+}
+
+#[derive(Serialize, Clone)]
+pub struct CrossVersionObjectReference {
+    pub apiVersion: String,
+    pub kind: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct HorizontalPodAutoscalerSpec {
+    pub scaleTargetRef: CrossVersionObjectReference,
+    #[serde(flatten)]
+    pub scaling: AutoScaling,
+}
+
+#[derive(Serialize, Clone)]
+pub struct HorizontalPodAutoscaler {
+    pub apiVersion: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub spec: HorizontalPodAutoscalerSpec,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PodDisruptionBudgetSpec {
+    pub maxUnavailable: AvailabilityPolicy,
+    pub selector: LabelSelector,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PodDisruptionBudget {
+    pub apiVersion: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub spec: PodDisruptionBudgetSpec,
+}
+
+// ----------------------------------------------------------------------------
+// config and access
+
+#[derive(Serialize, Clone)]
+pub struct ConfigMap {
+    pub apiVersion: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub data: BTreeMap<String, String>,
+}
+
+/// A secret with base64 encoded `data`, or plain `stringData`
+#[derive(Serialize, Clone)]
+pub struct Secret {
+    pub apiVersion: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    #[serde(rename = "type")]
+    pub secretType: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub data: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub stringData: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ServiceAccount {
+    pub apiVersion: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub automountServiceAccountToken: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Role {
+    pub apiVersion: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub rules: Vec<Rbac>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Subject {
+    pub kind: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct RoleRef {
+    pub kind: String,
+    pub name: String,
+    pub apiGroup: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct RoleBinding {
+    pub apiVersion: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub subjects: Vec<Subject>,
+    pub roleRef: RoleRef,
+}
+
+/// Any object the native renderer creates
+#[derive(Serialize, Clone)]
+#[serde(untagged)]
+pub enum Object {
+    Deployment(Deployment),
+    Service(Service),
+    HorizontalPodAutoscaler(HorizontalPodAutoscaler),
+    PodDisruptionBudget(PodDisruptionBudget),
+    CronJob(CronJob),
+    ConfigMap(ConfigMap),
+    Secret(Secret),
+    ServiceAccount(ServiceAccount),
+    Role(Role),
+    RoleBinding(RoleBinding),
+}
+
+impl Object {
+    pub fn kind(&self) -> &str {
+        self.metadata_kind().0
+    }
+    pub fn name(&self) -> &str {
+        &self.metadata_kind().1.name
+    }
+    fn metadata_kind(&self) -> (&String, &ObjectMeta) {
+        match self {
+            Object::Deployment(o) => (&o.kind, &o.metadata),
+            Object::Service(o) => (&o.kind, &o.metadata),
+            Object::HorizontalPodAutoscaler(o) => (&o.kind, &o.metadata),
+            Object::PodDisruptionBudget(o) => (&o.kind, &o.metadata),
+            Object::CronJob(o) => (&o.kind, &o.metadata),
+            Object::ConfigMap(o) => (&o.kind, &o.metadata),
+            Object::Secret(o) => (&o.kind, &o.metadata),
+            Object::ServiceAccount(o) => (&o.kind, &o.metadata),
+            Object::Role(o) => (&o.kind, &o.metadata),
+            Object::RoleBinding(o) => (&o.kind, &o.metadata),
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{Utc, SecondsFormat};
+
+use super::{Result, Error};
+
+/// Formats a report can be written in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    Json,
+    /// JUnit XML for CI test report viewers
+    Junit,
+}
+
+impl FromStr for ReportFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(ReportFormat::Json),
+            "junit" => Ok(ReportFormat::Junit),
+            _ => bail!("Unknown report format {} - expected json or junit", s),
+        }
+    }
+}
+
+/// Where and how to write a report
+#[derive(Clone, Debug)]
+pub struct ReportTarget {
+    pub format: ReportFormat,
+    pub path: PathBuf,
+}
+
+impl ReportTarget {
+    /// Report to `path`, or to `shipcat-report.{json,xml}`
+    pub fn new(format: ReportFormat, path: Option<&str>) -> ReportTarget {
+        let path = match (path, format) {
+            (Some(p), _) => PathBuf::from(p),
+            (None, ReportFormat::Json) => PathBuf::from("shipcat-report.json"),
+            (None, ReportFormat::Junit) => PathBuf::from("shipcat-report.xml"),
+        };
+        ReportTarget { format, path }
+    }
+
+    /// Write the report, warning instead of failing
+    ///
+    /// The operation itself decides the exit code, not its report.
+    pub fn write(&self, report: &Report) {
+        let res = File::create(&self.path).map_err(Error::from).and_then(|mut f| {
+            let data = match self.format {
+                ReportFormat::Json => serde_json::to_string_pretty(report)?,
+                ReportFormat::Junit => report.junit(),
+            };
+            writeln!(f, "{}", data)?;
+            Ok(())
+        });
+        match res {
+            Ok(_) => info!("Wrote {} report to {}", report.operation, self.path.display()),
+            Err(e) => warn!("Failed to write report to {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// What happened to a service
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ServiceStatus {
+    /// Upgraded, installed or applied
+    Reconciled,
+    /// Up to date
+    Unchanged,
+    /// Differs from what is running (diff only)
+    Diffed,
+    /// Failed, or skipped because a dependency failed
+    Failed,
+    /// Failed in a way that does not fail the operation
+    Ignored,
+    /// Never started because the operation stopped early
+    NotStarted,
+}
+
+/// The outcome of one service in a mass operation
+#[derive(Serialize, Clone, Debug)]
+pub struct ServiceReport {
+    pub name: String,
+    pub status: ServiceStatus,
+    /// Upgrade mode used
+    pub mode: String,
+    /// Version running before
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oldVersion: Option<String>,
+    /// Version asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub newVersion: Option<String>,
+    /// Changed lines in the diff, as `+added -removed`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    /// Seconds the rollout was allowed to take
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub waitTime: Option<u32>,
+    /// Kind of error, e.g. `UpgradeTimeout`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errorKind: Option<String>,
+    /// Error message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Seconds spent on the service
+    pub duration: f64,
+}
+
+impl ServiceReport {
+    pub fn new(name: &str, status: ServiceStatus, mode: &str) -> ServiceReport {
+        ServiceReport {
+            name: name.into(),
+            status,
+            mode: mode.into(),
+            oldVersion: None,
+            newVersion: None,
+            diff: None,
+            waitTime: None,
+            errorKind: None,
+            error: None,
+            duration: 0.0,
+        }
+    }
+
+    /// Record an error and its kind
+    pub fn error(mut self, e: &Error) -> ServiceReport {
+        self.errorKind = Some(error_kind(e));
+        self.error = Some(e.to_string());
+        self
+    }
+
+    pub fn duration(mut self, d: Duration) -> ServiceReport {
+        self.duration = seconds(d);
+        self
+    }
+}
+
+/// The outcome of a mass operation across a region
+#[derive(Serialize, Clone, Debug)]
+pub struct Report {
+    /// Operation that ran, e.g. `helm reconcile`
+    pub operation: String,
+    pub region: String,
+    /// RFC 3339 time the report was made
+    pub timestamp: String,
+    /// Seconds the whole operation took
+    pub duration: f64,
+    pub services: Vec<ServiceReport>,
+}
+
+impl Report {
+    pub fn new(operation: &str, region: &str, duration: Duration, mut services: Vec<ServiceReport>) -> Report {
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        Report {
+            operation: operation.into(),
+            region: region.into(),
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            duration: seconds(duration),
+            services,
+        }
+    }
+
+    /// JUnit XML with a test case per service
+    pub fn junit(&self) -> String {
+        let count = |s: ServiceStatus| self.services.iter().filter(|r| r.status == s).count();
+        let suite = format!("{} {}", self.operation, self.region);
+        let mut xml = vec![
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string(),
+            "<testsuites>".into(),
+            format!(r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}" time="{:.3}" timestamp="{}">"#,
+                escape(&suite), self.services.len(), count(ServiceStatus::Failed),
+                count(ServiceStatus::Ignored) + count(ServiceStatus::NotStarted), self.duration, self.timestamp),
+        ];
+        for s in &self.services {
+            xml.push(format!(r#"    <testcase classname="{}" name="{}" time="{:.3}">"#, escape(&suite), escape(&s.name), s.duration));
+            let message = s.error.clone().unwrap_or_default();
+            match s.status {
+                ServiceStatus::Failed => xml.push(format!(r#"      <failure type="{}" message="{}"/>"#,
+                    escape(&s.errorKind.clone().unwrap_or_default()), escape(&message))),
+                ServiceStatus::Ignored => xml.push(format!(r#"      <skipped message="{}"/>"#, escape(&message))),
+                ServiceStatus::NotStarted => xml.push(r#"      <skipped message="not started"/>"#.into()),
+                ServiceStatus::Reconciled | ServiceStatus::Unchanged | ServiceStatus::Diffed => {},
+            }
+            let mut out = vec![format!("status: {:?}", s.status), format!("mode: {}", s.mode)];
+            if let Some(v) = &s.oldVersion {
+                out.push(format!("old version: {}", v));
+            }
+            if let Some(v) = &s.newVersion {
+                out.push(format!("new version: {}", v));
+            }
+            if let Some(d) = &s.diff {
+                out.push(format!("diff: {}", d));
+            }
+            if let Some(w) = s.waitTime {
+                out.push(format!("wait time: {}s", w));
+            }
+            xml.push(format!("      <system-out>{}</system-out>", escape(&out.join("\n"))));
+            xml.push("    </testcase>".into());
+        }
+        xml.push("  </testsuite>".into());
+        xml.push("</testsuites>".into());
+        xml.join("\n")
+    }
+}
+
+/// Changed lines of a diff, as `+added -removed`
+pub fn diff_summary(diff: &str) -> String {
+    let added = diff.lines().filter(|l| l.starts_with('+')).count();
+    let removed = diff.lines().filter(|l| l.starts_with('-')).count();
+    format!("+{} -{}", added, removed)
+}
+
+/// Name of the kind of an error, e.g. `UpgradeTimeout`
+pub fn error_kind(e: &Error) -> String {
+    let kind = format!("{:?}", e.kind());
+    kind.split(|c: char| !c.is_alphanumeric()).next().unwrap_or_default().to_string()
+}
+
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_millis()) / 1000.0
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,33 @@
+use schemars::{schema_for, schema::RootSchema};
+
+use super::{Result, Config, Manifest, Region};
+
+/// The kinds of files we can generate a schema for
+pub const SCHEMA_KINDS: &[&str] = &["manifest", "config", "region"];
+
+/// Generate the JSON schema for one of the `SCHEMA_KINDS`
+///
+/// Structs referenced by the top level object (i.e. everything in `structs`)
+/// end up in the `definitions` section, with doc comments as descriptions.
+pub fn generate(kind: &str) -> Result<RootSchema> {
+    let schema = match kind {
+        "manifest" => schema_for!(Manifest),
+        "config" => schema_for!(Config),
+        "region" => schema_for!(Region),
+        _ => bail!("Unknown schema kind {} - expected one of {:?}", kind, SCHEMA_KINDS),
+    };
+    Ok(schema)
+}
+
+/// Print the JSON schema for a kind
+///
+/// Can be used with editors or pre-commit hooks to validate `shipcat.yml` files:
+///
+/// ```sh
+/// shipcat schema manifest > shipcat.schema.json
+/// ```
+pub fn print(kind: &str) -> Result<()> {
+    let schema = generate(kind)?;
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
@@ -0,0 +1,278 @@
+/// This file contains the `shipcat secret` subcommands
+use std::collections::{BTreeMap, BTreeSet};
+
+use shipcat_definitions::SecretBackend;
+
+use super::{Config, Region};
+use super::{Result, Manifest};
+use crate::webhooks::{self, UpgradeState};
+
+/// Secrets of a service in one region
+pub struct ServiceSecrets {
+    pub region: String,
+    pub service: String,
+    /// Vault folder of the service
+    pub folder: String,
+    /// Secrets in the vault folder
+    pub found: BTreeSet<String>,
+    /// Secrets in the vault folder that the manifest references
+    pub referenced: BTreeSet<String>,
+}
+
+/// Problems found by an audit
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum FindingKind {
+    /// Referenced by the manifest but not in vault in any region
+    Missing,
+    /// In vault but not referenced by the manifest, so it can be deleted
+    Unused,
+    /// Referenced by the manifest but not in vault, while present for the same service in other regions
+    Inconsistent,
+}
+
+/// A secret that needs attention
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub region: String,
+    pub service: String,
+    /// Full vault path of the secret
+    pub path: String,
+    pub kind: FindingKind,
+    /// Regions where an inconsistent secret exists
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub presentIn: Vec<String>,
+}
+
+/// Names of the secrets in the service folder that a manifest references
+///
+/// Secrets referenced in other folders with `IN_VAULT:path` are left out.
+fn referenced(mf: &Manifest, folder: &str, reg: &Region) -> Result<BTreeSet<String>> {
+    let prefix = format!("{}/", folder);
+    let res = mf.vault_refs()?.into_iter()
+        .map(|(name, r)| r.locate(folder, &reg.vault, &name).0)
+        .filter(|pth| pth.starts_with(&prefix))
+        .map(|pth| pth[prefix.len()..].to_string())
+        .collect();
+    Ok(res)
+}
+
+/// Names of the secrets in a service folder
+///
+/// Services without any secrets have no folder in vault, which is not an error here.
+fn found_secrets(backend: &dyn SecretBackend, folder: &str) -> Result<BTreeSet<String>> {
+    Ok(backend.list_opt(folder)?.unwrap_or_default().into_iter().collect())
+}
+
+/// Find the secrets of every enabled service in a region
+pub fn collect(conf: &Config, reg: &Region) -> Result<Vec<ServiceSecrets>> {
+    let backend = reg.secret_backend()?;
+    let mut res = vec![];
+    for svc in Manifest::available(&reg.name)? {
+        let mf = Manifest::base(&svc, conf, reg)?;
+        let folder = mf.get_vault_path(&reg.vault);
+        let referenced = referenced(&mf, &folder, reg)?;
+        let found = found_secrets(&*backend, &folder)?;
+        debug!("Found secrets {:?} for {} in {}", found, svc, reg.name);
+        res.push(ServiceSecrets { region: reg.name.clone(), service: svc, folder, found, referenced });
+    }
+    Ok(res)
+}
+
+/// Compare found and referenced secrets within and across regions
+pub fn findings(secrets: &[ServiceSecrets]) -> Vec<Finding> {
+    // regions where each secret of a service exists
+    let mut present: BTreeMap<(&str, &str), Vec<String>> = BTreeMap::new();
+    for s in secrets {
+        for k in &s.found {
+            present.entry((s.service.as_str(), k.as_str())).or_default().push(s.region.clone());
+        }
+    }
+    let mut res = vec![];
+    for s in secrets {
+        let finding = |key: &str, kind, presentIn| Finding {
+            region: s.region.clone(),
+            service: s.service.clone(),
+            path: format!("{}/{}", s.folder, key),
+            kind, presentIn,
+        };
+        for k in s.referenced.difference(&s.found) {
+            match present.get(&(s.service.as_str(), k.as_str())) {
+                Some(regions) => res.push(finding(k, FindingKind::Inconsistent, regions.clone())),
+                None => res.push(finding(k, FindingKind::Missing, vec![])),
+            }
+        }
+        for k in s.found.difference(&s.referenced) {
+            res.push(finding(k, FindingKind::Unused, vec![]));
+        }
+    }
+    res.sort_by(|a, b| (&a.region, &a.service, a.kind, &a.path).cmp(&(&b.region, &b.service, b.kind, &b.path)));
+    res
+}
+
+/// Report missing, unused and inconsistent secrets across regions
+///
+/// Prints a table, or JSON if requested, and returns the findings.
+pub fn audit(conf: &Config, regions: Vec<String>, json: bool) -> Result<Vec<Finding>> {
+    let mut secrets = vec![];
+    for r in regions {
+        info!("auditing secrets in {}", r);
+        let reg = conf.get_region(&r)?;
+        secrets.extend(collect(conf, &reg)?);
+    }
+    let res = findings(&secrets);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&res)?);
+    } else {
+        print!("{}", table(&res));
+    }
+    Ok(res)
+}
+
+/// Changes made to a service's secrets
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretAction {
+    Set,
+    Delete,
+}
+
+/// Vault path of a secret in the service folder, and whether the manifest references it
+fn locate(svc: &str, key: &str, conf: &Config, reg: &Region) -> Result<(String, bool)> {
+    if key.is_empty() || key.contains('/') {
+        bail!("Invalid secret name '{}' - secrets are managed in the service folder only", key);
+    }
+    let mf = Manifest::base(svc, conf, reg)?;
+    let folder = mf.get_vault_path(&reg.vault);
+    let used = referenced(&mf, &folder, reg)?.contains(key);
+    Ok((format!("{}/{}", folder, key), used))
+}
+
+/// Vault path of a secret in the service folder
+///
+/// Refuses secrets the manifest does not reference as `IN_VAULT` unless forced,
+/// as these are usually typos or secrets in the wrong folder.
+pub fn secret_path(svc: &str, key: &str, conf: &Config, reg: &Region, force: bool) -> Result<String> {
+    let (pth, used) = locate(svc, key, conf, reg)?;
+    if !force && !used {
+        bail!("{} does not reference {} as IN_VAULT in {} - pass --force to use it anyway", svc, key, reg.name);
+    }
+    Ok(pth)
+}
+
+/// Vault path of a secret to delete from the service folder
+///
+/// The other way around from `secret_path`: unused secrets can go, as the audit suggests,
+/// but secrets the manifest still references are refused unless forced.
+pub fn removal_path(svc: &str, key: &str, conf: &Config, reg: &Region, force: bool) -> Result<String> {
+    let (pth, used) = locate(svc, key, conf, reg)?;
+    if !force && used {
+        bail!("{} still references {} as IN_VAULT in {} - pass --force to delete it anyway", svc, key, reg.name);
+    }
+    Ok(pth)
+}
+
+/// Print the value of a secret
+pub fn get(svc: &str, key: &str, conf: &Config, reg: &Region, force: bool) -> Result<()> {
+    let pth = secret_path(svc, key, conf, reg, force)?;
+    println!("{}", reg.secret_backend()?.read(&pth)?);
+    Ok(())
+}
+
+/// Print the secrets of a service, marking unused and missing ones
+pub fn list(svc: &str, conf: &Config, reg: &Region) -> Result<()> {
+    let mf = Manifest::base(svc, conf, reg)?;
+    let folder = mf.get_vault_path(&reg.vault);
+    let referenced = referenced(&mf, &folder, reg)?;
+    let found = found_secrets(&*reg.secret_backend()?, &folder)?;
+    for k in found.union(&referenced) {
+        match (found.contains(k), referenced.contains(k)) {
+            (true, true) => println!("{}", k),
+            (true, false) => println!("{} (unused)", k),
+            (false, _) => println!("{} (missing)", k),
+        }
+    }
+    Ok(())
+}
+
+/// Write the value of a secret
+pub fn set(svc: &str, key: &str, value: &str, conf: &Config, reg: &Region, force: bool) -> Result<()> {
+    let pth = secret_path(svc, key, conf, reg, force)?;
+    let res = reg.secret_backend()?.write(&pth, value);
+    notify(&res, SecretAction::Set, svc, &pth, reg);
+    res?;
+    info!("Wrote {} in {}", pth, reg.name);
+    Ok(())
+}
+
+/// Delete a secret
+pub fn remove(svc: &str, key: &str, conf: &Config, reg: &Region, force: bool) -> Result<()> {
+    let pth = removal_path(svc, key, conf, reg, force)?;
+    let res = reg.secret_backend()?.delete(&pth);
+    notify(&res, SecretAction::Delete, svc, &pth, reg);
+    res?;
+    info!("Deleted {} in {}", pth, reg.name);
+    Ok(())
+}
+
+/// Send an audit event for a change, never including the value
+fn notify<T>(res: &Result<T>, action: SecretAction, svc: &str, pth: &str, reg: &Region) {
+    let us = if res.is_ok() { UpgradeState::Completed } else { UpgradeState::Failed };
+    webhooks::secret_event(us, action, svc, pth, reg);
+}
+
+/// Render findings as an aligned table
+fn table(findings: &[Finding]) -> String {
+    let mut rows = vec![vec!["REGION".to_string(), "SERVICE".into(), "STATUS".into(), "PATH".into(), "PRESENT IN".into()]];
+    for f in findings {
+        let kind = serde_json::to_value(&f.kind).ok()
+            .and_then(|v| v.as_str().map(String::from))
+            .unwrap_or_default();
+        rows.push(vec![f.region.clone(), f.service.clone(), kind, f.path.clone(), f.presentIn.join(",")]);
+    }
+    let widths = (0..5).map(|i| rows.iter().map(|r| r[i].len()).max().unwrap_or(0)).collect::<Vec<_>>();
+    let mut out = String::new();
+    for r in rows {
+        let cells = r.iter().zip(&widths).map(|(c, w)| format!("{:w$}", c, w = w)).collect::<Vec<_>>();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{findings, table, ServiceSecrets, FindingKind};
+
+    fn secrets(region: &str, found: &[&str], referenced: &[&str]) -> ServiceSecrets {
+        ServiceSecrets {
+            region: region.into(),
+            service: "fake-ask".into(),
+            folder: format!("{}/fake-ask", region),
+            found: found.iter().map(|s| s.to_string()).collect(),
+            referenced: referenced.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn audit_findings() {
+        let res = findings(&[
+            secrets("dev-uk", &["DB_URL", "OLD_KEY", "API_KEY"], &["DB_URL", "API_KEY"]),
+            secrets("prod-uk", &["DB_URL"], &["DB_URL", "API_KEY", "NEW_KEY"]),
+            secrets("staging-uk", &["DB_URL", "API_KEY"], &["DB_URL", "API_KEY"]),
+        ]);
+        let summary = res.iter().map(|f| (f.path.as_str(), f.kind)).collect::<Vec<_>>();
+        // OLD_KEY is only unused in dev-uk, as no other region references it
+        assert_eq!(summary, vec![
+            ("dev-uk/fake-ask/OLD_KEY", FindingKind::Unused),
+            ("prod-uk/fake-ask/NEW_KEY", FindingKind::Missing),
+            ("prod-uk/fake-ask/API_KEY", FindingKind::Inconsistent),
+        ]);
+        assert!(res[1].presentIn.is_empty());
+        assert_eq!(res[2].presentIn, vec!["dev-uk", "staging-uk"]);
+
+        let out = table(&res);
+        assert_eq!(out.lines().next().unwrap(), "REGION   SERVICE   STATUS        PATH                      PRESENT IN");
+        assert_eq!(out.lines().nth(1).unwrap(), "dev-uk   fake-ask  unused        dev-uk/fake-ask/OLD_KEY");
+    }
+}
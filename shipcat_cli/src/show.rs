@@ -24,3 +24,72 @@ pub fn manifest_crd(svc: &str, conf: &Config, reg: &Region) -> Result<()> {
     println!("{}", serde_yaml::to_string(&crd)?);
     Ok(())
 }
+
+use std::collections::BTreeMap;
+use std::fs;
+use serde_yaml::Value;
+use shipcat_definitions::merge::{MergeStrategy, MANIFEST_MERGE_STRATEGIES};
+
+/// Whether a manifest field is merged key by key from override files
+fn deep_merged(key: &str) -> bool {
+    MANIFEST_MERGE_STRATEGIES.iter().any(|(k, s)| *k == key && *s == MergeStrategy::DeepMerge)
+}
+
+/// Where every value of a service's helm values came from
+///
+/// Returns value paths (`env.` keys are listed individually) along with the last
+/// manifest file that set them. Values not set in any manifest file are listed as `default`,
+/// as they are filled in from `shipcat.conf` defaults, the region, or the field's own default.
+pub fn explain(svc: &str, conf: &Config, reg: &Region) -> Result<Vec<(String, String)>> {
+    let mf = Manifest::base(svc, conf, reg)?;
+
+    // later layers win
+    let mut sources = BTreeMap::new();
+    for pth in Manifest::layers(svc, reg)? {
+        let data: BTreeMap<String, Value> = serde_yaml::from_str(&fs::read_to_string(&pth)?)?;
+        for (k, v) in data {
+            match v {
+                Value::Mapping(ref m) if deep_merged(&k) => {
+                    for sub in m.keys().filter_map(Value::as_str) {
+                        sources.insert(format!("{}.{}", k, sub), pth.display().to_string());
+                    }
+                }
+                _ => {
+                    sources.insert(k, pth.display().to_string());
+                }
+            }
+        }
+    }
+
+    let mut keys = vec![];
+    if let Value::Mapping(vals) = serde_yaml::to_value(&mf)? {
+        for (k, v) in vals {
+            let k = k.as_str().unwrap_or_default().to_string();
+            match v {
+                // env serializes with secrets split out, so use the merged plain values
+                _ if k == "env" => {
+                    keys.extend(mf.env.plain.keys().map(|e| format!("env.{}", e)));
+                }
+                Value::Mapping(ref m) if deep_merged(&k) => {
+                    keys.extend(m.keys().filter_map(Value::as_str).map(|s| format!("{}.{}", k, s)));
+                }
+                _ => keys.push(k),
+            }
+        }
+    }
+    let res = keys.into_iter().map(|k| {
+        let src = sources.get(&k).cloned().unwrap_or_else(|| "default".into());
+        (k, src)
+    }).collect();
+    Ok(res)
+}
+
+/// Print where every value of a service's helm values came from
+pub fn values_explain(svc: &str, conf: &Config, reg: &Region) -> Result<()> {
+    let res = explain(svc, conf, reg)?;
+    let width = res.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+    for (k, src) in res {
+        println!("{:width$}  {}", k, src, width = width);
+    }
+    Ok(())
+}
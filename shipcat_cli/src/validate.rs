@@ -34,7 +34,7 @@ pub fn secret_presence(conf: &Config, regions: Vec<String>) -> Result<()> {
         for svc in Manifest::available(&reg.name)? {
             let mf = Manifest::base(&svc, conf, &reg)?;
             debug!("validating secrets for {} in {}", svc, r);
-            mf.verify_secrets_exist(&reg.vault)?;
+            mf.verify_secrets_exist(&reg)?;
         }
     }
     Ok(())
@@ -5,6 +5,7 @@ use crate::{
     Result
 };
 use crate::helm::{UpgradeData, UpgradeMode};
+use crate::secret::SecretAction;
 use super::{Region, Webhook};
 
 /// The different states an upgrade can be in
@@ -23,6 +24,12 @@ pub enum UpgradeState {
     RolledBack,
     // Fail to revert
     RollbackFailed,
+    /// Canary deployed next to the old version
+    CanaryStarted,
+    /// Canary baked cleanly, upgrading everything
+    CanaryPromoted,
+    /// Canary misbehaved and was removed
+    CanaryAborted,
 }
 
 pub fn ensure_requirements(reg: &Region) -> Result<()> {
@@ -53,6 +60,25 @@ pub fn reconcile_event(us: UpgradeState, reg: &Region) {
     }
 }
 
+/// Throw events about secret changes to configured webhooks - warning on delivery errors
+///
+/// Http errors are NOT propagated from here
+pub fn secret_event(us: UpgradeState, action: SecretAction, svc: &str, path: &str, reg: &Region) {
+    if let Some(whs) = &reg.webhooks {
+        for wh in whs {
+            if let Ok(whc) = wh.get_configuration() {
+                if let Err(e) = match wh {
+                    Webhook::Audit(h) => {
+                        audit::audit_secret(&us, action, svc, path, &reg.name, &h, whc)
+                    }
+                } {
+                    warn!("Failed to notify about secret event: {}", e)
+                }
+            }
+        }
+    }
+}
+
 /// Throw events to configured webhooks - warning on delivery errors
 ///
 /// Http errors are NOT propagated from here
@@ -92,7 +118,10 @@ fn handle_upgrade_notifies(us: UpgradeState, ud: &UpgradeData, reg: &Region) {
     let code = if ud.diff.is_empty() { None } else { Some(ud.diff.clone()) };
     let (color, text) = match us {
         UpgradeState::Completed => ("good".into(), format!("{} `{}` in `{}`", ud.mode.action_verb(), ud.name, ud.region)),
-        UpgradeState::Failed => ("danger".into(), format!("failed to {} `{}` in `{}`", ud.mode, ud.name, ud.region)),
+        UpgradeState::Failed => match &ud.failure {
+            Some(f) => ("danger".into(), format!("failed to {} `{}` in `{}`: {}", ud.mode, ud.name, ud.region, f)),
+            None => ("danger".into(), format!("failed to {} `{}` in `{}`", ud.mode, ud.name, ud.region)),
+        },
         _ => ("good", format!("action state: {}", serde_json::to_string(&us).unwrap_or("unknown".into()))),
     };
 
@@ -169,3 +198,41 @@ pub fn upgrade_rollback_event(us: UpgradeState, ud: &UpgradeData, reg: &Region)
     // TODO: make a smarter loop over webhooks in here
     // TODO: first add grafana and slack to webhooks for region
 }
+
+/// Throw events about canary phases to configured webhooks - warning on delivery errors
+///
+/// Http errors are NOT propagated from here
+pub fn canary_event(us: UpgradeState, ud: &UpgradeData, reg: &Region) {
+    if let Some(whs) = &reg.webhooks {
+        for wh in whs {
+            if let Ok(whc) = wh.get_configuration() {
+                if let Err(e) = match wh {
+                    Webhook::Audit(h) => {
+                        audit::audit_deployment(&us, &ud, &h, whc)
+                    }
+                } {
+                    warn!("Failed to notify about canary event: {}", e)
+                }
+            }
+        }
+    }
+
+    let (color, text) = match us {
+        UpgradeState::CanaryStarted => (None, format!("started canary of `{}` in `{}`", ud.name, ud.region)),
+        UpgradeState::CanaryPromoted => (Some("good"), format!("promoting canary of `{}` in `{}`", ud.name, ud.region)),
+        UpgradeState::CanaryAborted => match &ud.failure {
+            Some(f) => (Some("danger"), format!("aborted canary of `{}` in `{}`: {}", ud.name, ud.region, f)),
+            None => (Some("danger"), format!("aborted canary of `{}` in `{}`", ud.name, ud.region)),
+        },
+        _ => return,
+    };
+    if let Err(e) = slack::send(slack::Message {
+        text,
+        color: color.map(String::from),
+        version: Some(ud.version.clone()),
+        metadata: ud.metadata.clone(),
+        ..Default::default()
+    }) {
+        warn!("Failed to notify about canary event: {}", e);
+    }
+}
@@ -10,12 +10,13 @@ use mockito;
 use shipcat;
 
 use crate::mockito::mock;
-// use mockito::Matcher;
+use crate::mockito::Matcher;
 
 use crate::shipcat::audit;
 use crate::shipcat::{AuditWebhook};
 use crate::shipcat::helm::direct::UpgradeData;
 use crate::shipcat::webhooks;
+use crate::shipcat::kube::{RolloutFailure, FailureReason};
 
 #[test]
 fn audit_does_audit_deployment() {
@@ -64,6 +65,39 @@ fn audit_does_audit_deployment() {
     mocked.assert();
 }
 
+#[test]
+fn audit_deployment_failure_reason() {
+    let mut whc: BTreeMap<String, String> = BTreeMap::default();
+    whc.insert("SHIPCAT_AUDIT_CONTEXT_ID".into(), "egcontextid".into());
+    whc.insert("SHIPCAT_AUDIT_REVISION".into(), "egrevision".into());
+
+    let audcfg = AuditWebhook{
+        url: Url::parse(&format!("{}/audit-failure", mockito::SERVER_URL)).unwrap(),
+        token: "1234auth".into(),
+    };
+    let us = webhooks::UpgradeState::Failed;
+    let ud = UpgradeData{
+        name: "svc".into(),
+        chart: "wtv".into(),
+        version: "v2".into(),
+        region: "r1".into(),
+        failure: Some(RolloutFailure {
+            reason: FailureReason::ImagePull,
+            object: "pod svc-abc".into(),
+            message: "svc: image not found".into(),
+        }),
+        ..Default::default()
+    };
+
+    let mocked = mock("POST", "/audit-failure")
+        .match_body(Matcher::Regex(r#""failure":\{"reason":"IMAGE_PULL","object":"pod svc-abc""#.into()))
+        .expect(1)
+        .create();
+
+    assert!(audit::audit_deployment(&us, &ud, &audcfg, whc).is_ok());
+    mocked.assert();
+}
+
 #[test]
 fn audit_reconciliation_has_type() {
     let mut whc: BTreeMap<String, String> = BTreeMap::default();
@@ -1,7 +1,8 @@
 mod common;
 use crate::common::setup;
-use shipcat_definitions::{Config, ConfigType};
-use shipcat::graph::{generate, nodeidx_from_name};
+use shipcat_definitions::{Config, ConfigType, Manifest};
+use shipcat_definitions::structs::Dependency;
+use shipcat::graph::{generate, nodeidx_from_name, waves};
 
 #[test]
 fn graph_generate() {
@@ -20,3 +21,36 @@ fn graph_generate() {
     println!("edge: {:?}", edge);
     assert_eq!(edge.intent, Some("testing graph module".into()));
 }
+
+fn service(name: &str, deps: &[&str]) -> Manifest {
+    let mut mf = Manifest::default();
+    mf.name = name.into();
+    mf.dependencies = deps.iter().map(|d| Dependency { name: d.to_string(), ..Default::default() }).collect();
+    mf
+}
+
+#[test]
+fn graph_waves() {
+    let svcs = vec![
+        service("web", &["api", "auth"]),
+        service("api", &["db", "external"]), // external is not reconciled
+        service("auth", &["db"]),
+        service("db", &[]),
+        service("cron", &[]),
+    ];
+    let ws = waves(&svcs).unwrap();
+    assert_eq!(ws, vec![vec!["db", "cron"], vec!["api", "auth"], vec!["web"]]);
+}
+
+#[test]
+fn graph_waves_cycle() {
+    let svcs = vec![
+        service("a", &["b"]),
+        service("b", &["c"]),
+        service("c", &["a"]),
+        service("d", &[]),
+    ];
+    let err = waves(&svcs).unwrap_err().to_string();
+    assert!(err.contains("a -> b -> c -> a") || err.contains("b -> c -> a -> b") || err.contains("c -> a -> b -> c"),
+        "unexpected cycle report: {}", err);
+}
@@ -1,7 +1,13 @@
 mod common;
 use crate::common::setup;
-use shipcat_definitions::{Manifest, Config, ConfigType};
-use shipcat::helm::values;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Mutex, MutexGuard};
+use shipcat_definitions::{Manifest, Config, ConfigType, HelmVersion};
+use shipcat::helm::{values, helpers, direct, UpgradeData, UpgradeMode};
 
 #[test]
 fn helm_values() {
@@ -16,3 +22,92 @@ fn helm_values() {
     // can verify output here matches what we want if we wanted to,
     // but type safety proves 99% of that anyway
 }
+
+/// Tests putting a fake helm on the PATH run one at a time
+static PATH_LOCK: Mutex<()> = Mutex::new(());
+
+/// A fake helm client that logs its arguments, in a directory of its own
+///
+/// Answers like Helm 3, but accepts Helm 2 flags too.
+fn fake_helm(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("shipcat-fake-helm-{}-{}", test, process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let script = r#"#!/bin/sh
+echo "$*" >> "$(dirname "$0")/calls.log"
+case "$*" in
+  "version --short --client") echo "v3.0.2+g19e47ee" ;;
+  *"get values fake-ask --output=json") echo '{"version":"1.6.0","replicaCount":1}' ;;
+  *"history fake-ask --output=json") echo '[{"revision":1,"updated":"2019-12-02T11:04:13Z","status":"superseded","chart":"base-0.1.0","app_version":"","description":"Install complete"},{"revision":2,"updated":"2019-12-03T09:00:00Z","status":"deployed","chart":"base-0.1.0","app_version":"","description":"Upgrade complete"}]' ;;
+  *" upgrade fake-ask "*) ;;
+  *"list --all --filter=^fake-ask$ --output=json") echo '[{"name":"fake-ask","namespace":"dev","revision":"2","updated":"2019-12-03 09:00:00 +0000 UTC","status":"deployed","chart":"base-0.1.0","app_version":""}]' ;;
+  *) echo "Error: unknown command" >&2; exit 1 ;;
+esac
+"#;
+    let helm = dir.join("helm");
+    fs::write(&helm, script).unwrap();
+    fs::set_permissions(&helm, fs::Permissions::from_mode(0o755)).unwrap();
+    let _ = fs::remove_file(dir.join("calls.log"));
+    dir
+}
+
+/// Find helm in `dir` before anywhere else on the PATH until the guard is dropped
+fn on_path(dir: &Path) -> MutexGuard<'static, ()> {
+    let guard = PATH_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = env::var_os("PATH").unwrap_or_default();
+    let dirs = vec![dir.to_path_buf()].into_iter().chain(env::split_paths(&path));
+    env::set_var("PATH", env::join_paths(dirs).unwrap());
+    guard
+}
+
+#[test]
+fn helm3_without_tiller() {
+    setup();
+    let dir = fake_helm("without-tiller");
+    let _path = on_path(&dir);
+    assert_eq!(helpers::detect_version().unwrap(), HelmVersion::V3);
+
+    let (_, mut reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    assert_eq!(reg.helmVersion, None); // detected
+    assert_eq!(helpers::helm_version(&reg).unwrap(), HelmVersion::V3);
+    reg.helmVersion = Some(HelmVersion::V2); // explicit setting wins
+    assert_eq!(helpers::helm_version(&reg).unwrap(), HelmVersion::V2);
+
+    let ver = helpers::infer_fallback_version("fake-ask", "dev", HelmVersion::V3).unwrap();
+    assert_eq!(ver, "1.6.0");
+    assert!(helpers::infer_fallback_version("fake-storage", "dev", HelmVersion::V3).is_err());
+
+    let revs = helpers::history("fake-ask", "dev", HelmVersion::V3).unwrap();
+    assert_eq!(revs.len(), 2);
+    assert_eq!(revs[1].status, "deployed");
+
+    let rel = helpers::release("fake-ask", "dev", HelmVersion::V3).unwrap().unwrap();
+    assert_eq!(rel.revision, 2);
+    assert!(!rel.is_failed_install());
+
+    let calls = fs::read_to_string(dir.join("calls.log")).unwrap();
+    assert!(calls.contains("--namespace=dev get values fake-ask --output=json"));
+    assert!(calls.contains("--namespace=dev history fake-ask --output=json"));
+    assert!(!calls.contains("tiller"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn helm2_recreate_pods() {
+    let dir = fake_helm("recreate-pods");
+    let _path = on_path(&dir);
+    let data = UpgradeData {
+        name: "fake-ask".into(),
+        chart: "base".into(),
+        helm: HelmVersion::V2,
+        version: "1.6.0".into(),
+        namespace: "dev".into(),
+        mode: UpgradeMode::UpgradeRecreateWait,
+        values: "fake-ask.helm.gen.yml".into(),
+        ..Default::default()
+    };
+    direct::upgrade(&data).unwrap();
+    let calls = fs::read_to_string(dir.join("calls.log")).unwrap();
+    assert!(calls.contains("--tiller-namespace=dev upgrade fake-ask charts/base"));
+    assert!(calls.contains("--recreate-pods"));
+    fs::remove_dir_all(&dir).unwrap();
+}
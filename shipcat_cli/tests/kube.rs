@@ -0,0 +1,702 @@
+mod common;
+use crate::common::setup;
+
+use std::env;
+use std::fs;
+use std::sync::{Once, ONCE_INIT};
+
+use mockito::{mock, Matcher};
+use serde_json::{json, Value};
+use shipcat_definitions::{Manifest, Config, ConfigType, HelmVersion};
+use chrono::Utc;
+use shipcat_definitions::structs::{Canary, BlueGreen};
+use shipcat::helm::{canary, bluegreen, direct, UpgradeData};
+use shipcat::kube::{self, Colour, FailureReason};
+use shipcat::kubeapi::Pod;
+
+static KUBECONFIG: Once = ONCE_INIT;
+
+/// Point the kubeconfig at the mock api server
+fn kubeconfig() {
+    KUBECONFIG.call_once(|| {
+        let pth = env::temp_dir().join("shipcat-kubeconfig-test.yml");
+        let kc = format!(r#"
+apiVersion: v1
+kind: Config
+current-context: dev-uk
+contexts:
+- name: dev-uk
+  context:
+    cluster: mock
+    user: ci
+    namespace: dev
+clusters:
+- name: mock
+  cluster:
+    server: {}
+users:
+- name: ci
+  user:
+    token: kubetoken
+"#, mockito::SERVER_URL);
+        fs::write(&pth, kc).unwrap();
+        env::set_var("KUBECONFIG", pth);
+    });
+}
+
+fn manifest(name: &str) -> Manifest {
+    let mut mf = Manifest::default();
+    mf.name = name.into();
+    mf.namespace = "dev".into();
+    mf
+}
+
+#[test]
+fn kube_current_context() {
+    kubeconfig();
+    assert_eq!(kube::current_context().unwrap(), "dev-uk");
+}
+
+#[test]
+fn kube_rollout_status() {
+    kubeconfig();
+    let deploys = mock("GET", "/apis/apps/v1/namespaces/dev/deployments?labelSelector=release%3Drolled-svc")
+        .match_header("authorization", "Bearer kubetoken")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "DeploymentList", "items": [{"metadata": {"name": "rolled-svc", "generation": 3},
+            "spec": {"replicas": 2, "template": {"spec": {"containers": [{"name": "rolled-svc", "image": "rolled-svc:1.2.0"}]}}},
+            "status": {"observedGeneration": 3, "replicas": 2, "updatedReplicas": 2, "readyReplicas": 2, "availableReplicas": 2}}]}"#)
+        .expect(1)
+        .create();
+    let sets = mock("GET", "/apis/apps/v1/namespaces/dev/statefulsets?labelSelector=release%3Drolled-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "StatefulSetList", "items": []}"#)
+        .expect(1)
+        .create();
+    assert!(kube::await_rollout_status(&manifest("rolled-svc")).unwrap().is_none());
+    deploys.assert();
+    sets.assert();
+
+    let deploy = mock("GET", "/apis/apps/v1/namespaces/dev/deployments/rolled-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"metadata": {"name": "rolled-svc", "generation": 3},
+            "spec": {"replicas": 2, "template": {"spec": {"containers": [{"name": "rolled-svc", "image": "rolled-svc:1.2.0"}]}}}}"#)
+        .expect(1)
+        .create();
+    assert_eq!(kube::running_version("rolled-svc", "dev").unwrap(), "1.2.0");
+    deploy.assert();
+
+    let _missing = mock("GET", "/apis/apps/v1/namespaces/dev/deployments/missing-svc")
+        .with_status(404)
+        .with_body(r#"{"kind": "Status", "message": "deployments.apps \"missing-svc\" not found", "code": 404}"#)
+        .create();
+    assert!(kube::running_version("missing-svc", "dev").is_err());
+}
+
+#[test]
+fn kube_rollout_status_workers() {
+    kubeconfig();
+    let mut mf = manifest("worked-svc");
+    for name in &["worked-svc-worker", "worked-svc-unlabelled"] {
+        mf.workers.push(serde_yaml::from_str(&format!(r#"
+name: {}
+replicaCount: 2
+resources: {{ requests: {{ cpu: 100m, memory: 100Mi }}, limits: {{ cpu: 200m, memory: 200Mi }} }}
+"#, name)).unwrap());
+    }
+    let _deploys = mock("GET", "/apis/apps/v1/namespaces/dev/deployments?labelSelector=release%3Dworked-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "DeploymentList", "items": [
+            {"metadata": {"name": "worked-svc", "generation": 2},
+             "spec": {"replicas": 2, "template": {"spec": {"containers": [{"name": "worked-svc"}]}}},
+             "status": {"observedGeneration": 2, "replicas": 2, "updatedReplicas": 2, "availableReplicas": 2}},
+            {"metadata": {"name": "worked-svc-worker", "generation": 2},
+             "spec": {"replicas": 2, "template": {"spec": {"containers": [{"name": "worked-svc-worker"}]}}},
+             "status": {"observedGeneration": 2, "replicas": 3, "updatedReplicas": 1, "availableReplicas": 2}}
+        ]}"#)
+        .create();
+    let _sets = mock("GET", "/apis/apps/v1/namespaces/dev/statefulsets?labelSelector=release%3Dworked-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "StatefulSetList", "items": [
+            {"metadata": {"name": "worked-svc-db", "generation": 1}, "spec": {"replicas": 1},
+             "status": {"observedGeneration": 1, "readyReplicas": 1, "currentRevision": "a", "updateRevision": "a"}}
+        ]}"#)
+        .create();
+    let unlabelled = mock("GET", "/apis/apps/v1/namespaces/dev/deployments/worked-svc-unlabelled")
+        .with_status(404)
+        .expect(1)
+        .create();
+
+    let statuses = kube::rollout_progress(&mf).unwrap();
+    unlabelled.assert();
+    let progress = statuses.iter()
+        .map(|r| (r.kind.as_str(), r.name.as_str(), r.ready, r.desired, r.rolledOut))
+        .collect::<Vec<_>>();
+    assert_eq!(progress, vec![
+        ("Deployment", "worked-svc", 2, 2, true),
+        ("Deployment", "worked-svc-unlabelled", 0, 0, false), // not created yet
+        ("Deployment", "worked-svc-worker", 1, 2, false), // broken worker
+        ("StatefulSet", "worked-svc-db", 1, 1, true),
+    ]);
+}
+
+#[test]
+fn kube_rollout_restart() {
+    kubeconfig();
+    let deploys = mock("GET", "/apis/apps/v1/namespaces/dev/deployments?labelSelector=release%3Drestarted-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "DeploymentList", "items": [
+            {"metadata": {"name": "restarted-svc"}, "spec": {"template": {"spec": {"containers": []}}}},
+            {"metadata": {"name": "restarted-worker"}, "spec": {"template": {"spec": {"containers": []}}}}]}"#)
+        .expect(1)
+        .create();
+    let patches = ["restarted-svc", "restarted-worker"].iter().map(|name| {
+        mock("PATCH", &*format!("/apis/apps/v1/namespaces/dev/deployments/{}", name))
+            .match_header("content-type", "application/merge-patch+json")
+            .match_body(Matcher::Regex(r#""kubectl.kubernetes.io/restartedAt":"20"#.into()))
+            .with_body("{}")
+            .expect(1)
+            .create()
+    }).collect::<Vec<_>>();
+    kube::rollout_restart("restarted-svc", "dev").unwrap();
+    deploys.assert();
+    for p in patches {
+        p.assert();
+    }
+}
+
+#[test]
+fn kube_pod_failure_reasons() {
+    let pod = |state: &str, restarts: u32| -> Pod {
+        serde_json::from_str(&format!(r#"{{"metadata": {{"name": "fake-pod"}}, "status": {{"phase": "Pending",
+            "containerStatuses": [{{"name": "fake", "restartCount": {}, "state": {}}}]}}}}"#, restarts, state)).unwrap()
+    };
+    let reason = |p: &Pod| kube::pod_failure(p).map(|(r, _)| r);
+    assert_eq!(reason(&pod(r#"{"waiting": {"reason": "ContainerCreating"}}"#, 0)), None);
+    assert_eq!(reason(&pod(r#"{"waiting": {"reason": "ImagePullBackOff"}}"#, 0)), Some(FailureReason::ImagePull));
+    assert_eq!(reason(&pod(r#"{"waiting": {"reason": "CreateContainerConfigError"}}"#, 0)), Some(FailureReason::ContainerConfig));
+    assert_eq!(reason(&pod(r#"{"waiting": {"reason": "CrashLoopBackOff"}}"#, 1)), Some(FailureReason::CrashLoop));
+    assert_eq!(reason(&pod(r#"{"running": {}}"#, 1)), None);
+    assert_eq!(reason(&pod(r#"{"running": {}}"#, 3)), Some(FailureReason::CrashLoop));
+
+    // crashes are left to the caller
+    let start = |p: &Pod| kube::pod_start_failure(p).map(|(r, _)| r);
+    assert_eq!(start(&pod(r#"{"waiting": {"reason": "ErrImagePull"}}"#, 0)), Some(FailureReason::ImagePull));
+    assert_eq!(start(&pod(r#"{"waiting": {"reason": "CrashLoopBackOff"}}"#, 5)), None);
+    assert_eq!(start(&pod(r#"{"running": {}}"#, 5)), None);
+}
+
+#[test]
+fn kube_rollout_failure_new_pods() {
+    kubeconfig();
+    let _deploys = mock("GET", "/apis/apps/v1/namespaces/dev/deployments?labelSelector=release%3Dcrashing-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "DeploymentList", "items": [
+            {"metadata": {"name": "crashing-svc", "generation": 3, "annotations": {"deployment.kubernetes.io/revision": "3"}},
+             "spec": {"replicas": 2, "template": {"spec": {"containers": [{"name": "crashing-svc"}]}}},
+             "status": {"observedGeneration": 3, "replicas": 3, "updatedReplicas": 1, "availableReplicas": 2}}
+        ]}"#)
+        .create();
+    let _sets = mock("GET", "/apis/apps/v1/namespaces/dev/statefulsets?labelSelector=release%3Dcrashing-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "StatefulSetList", "items": []}"#)
+        .create();
+    let _rs = mock("GET", "/apis/apps/v1/namespaces/dev/replicasets?labelSelector=release%3Dcrashing-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "ReplicaSetList", "items": [
+            {"metadata": {"name": "crashing-svc-old", "annotations": {"deployment.kubernetes.io/revision": "2"},
+                "ownerReferences": [{"kind": "Deployment", "name": "crashing-svc"}]}, "status": {"replicas": 2}},
+            {"metadata": {"name": "crashing-svc-new", "annotations": {"deployment.kubernetes.io/revision": "3"},
+                "ownerReferences": [{"kind": "Deployment", "name": "crashing-svc"}]}, "status": {"replicas": 1}}
+        ]}"#)
+        .create();
+    let _pods = mock("GET", "/api/v1/namespaces/dev/pods?labelSelector=release%3Dcrashing-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "PodList", "items": [
+            {"metadata": {"name": "crashing-svc-old-1", "ownerReferences": [{"kind": "ReplicaSet", "name": "crashing-svc-old"}]},
+             "status": {"phase": "Running", "containerStatuses": [{"name": "crashing-svc", "restartCount": 5,
+                "state": {"waiting": {"reason": "CrashLoopBackOff"}}}]}},
+            {"metadata": {"name": "crashing-svc-new-1", "ownerReferences": [{"kind": "ReplicaSet", "name": "crashing-svc-new"}]},
+             "status": {"phase": "Running", "containerStatuses": [{"name": "crashing-svc", "restartCount": 2,
+                "state": {"waiting": {"reason": "CrashLoopBackOff", "message": "back-off 20s"}}}]}}
+        ]}"#)
+        .create();
+    let events = mock("GET", "/api/v1/namespaces/dev/events?fieldSelector=involvedObject.name%3Dcrashing-svc-new-1")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "EventList", "items": [
+            {"type": "Warning", "reason": "Unhealthy", "message": "Liveness probe failed: HTTP probe failed with statuscode: 500", "count": 6}
+        ]}"#)
+        .expect(1)
+        .create();
+
+    // only the pod from the new replicaset counts
+    let failure = kube::rollout_failure(&manifest("crashing-svc")).unwrap().unwrap();
+    events.assert();
+    assert_eq!(failure.reason, FailureReason::FailedProbe);
+    assert_eq!(failure.object, "pod crashing-svc-new-1");
+    assert_eq!(failure.to_string(), "failed liveness probe in pod crashing-svc-new-1: crashing-svc: back-off 20s");
+}
+
+#[test]
+fn kube_debug_broken_pods() {
+    kubeconfig();
+    let pods = mock("GET", "/api/v1/namespaces/dev/pods?labelSelector=app%3Dbroken-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "PodList", "items": [
+            {"metadata": {"name": "broken-svc-1"}, "status": {"phase": "Running",
+                "containerStatuses": [{"name": "broken-svc", "ready": true, "restartCount": 0, "state": {"running": {}}}]}},
+            {"metadata": {"name": "broken-svc-2"}, "status": {"phase": "Running",
+                "containerStatuses": [{"name": "broken-svc", "ready": false, "restartCount": 4,
+                    "state": {"waiting": {"reason": "CrashLoopBackOff", "message": "back-off 40s"}}}]}}
+        ]}"#)
+        .expect(1)
+        .create();
+    let logs = mock("GET", "/api/v1/namespaces/dev/pods/broken-svc-2/log?container=broken-svc&tailLines=30")
+        .with_body("panic: no database\n")
+        .expect(1)
+        .create();
+    let events = mock("GET", "/api/v1/namespaces/dev/events?fieldSelector=involvedObject.name%3Dbroken-svc-2")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "EventList", "items": [
+            {"type": "Warning", "reason": "BackOff", "message": "Back-off restarting failed container", "count": 7}
+        ]}"#)
+        .expect(1)
+        .create();
+    let rs = mock("GET", "/apis/apps/v1/namespaces/dev/replicasets?labelSelector=app%3Dbroken-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "ReplicaSetList", "items": [
+            {"metadata": {"name": "broken-svc-abc", "creationTimestamp": "2019-01-10T10:00:00Z",
+                "ownerReferences": [{"kind": "Deployment", "name": "broken-svc"}]},
+             "status": {"replicas": 2, "availableReplicas": 1}}
+        ]}"#)
+        .expect(1)
+        .create();
+
+    kube::debug(&manifest("broken-svc")).unwrap();
+    pods.assert();
+    logs.assert();
+    events.assert();
+    rs.assert();
+}
+
+#[test]
+fn kube_crd_apply() {
+    setup();
+    kubeconfig();
+    let (conf, reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let mf = Manifest::base("fake-ask", &conf, &reg).unwrap();
+    let coll = "/apis/babylontech.co.uk/v1/namespaces/dev/shipcatmanifests";
+    // the plural comes from the installed definition
+    let _defs = mock("GET", "/apis/apiextensions.k8s.io/v1beta1/customresourcedefinitions")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "CustomResourceDefinitionList", "items": [
+            {"metadata": {"name": "shipcatconfigs.babylontech.co.uk"},
+             "spec": {"group": "babylontech.co.uk", "names": {"plural": "shipcatconfigs", "kind": "ShipcatConfig"}}},
+            {"metadata": {"name": "shipcatmanifests.babylontech.co.uk"},
+             "spec": {"group": "babylontech.co.uk", "names": {"plural": "shipcatmanifests", "kind": "ShipcatManifest"}}}
+        ]}"#)
+        .create();
+
+    // created when missing
+    let missing = mock("GET", &*format!("{}/fake-ask", coll)).with_status(404).create();
+    let create = mock("POST", coll).with_status(201).with_body("{}").expect(1).create();
+    kube::apply_crd("fake-ask", mf.clone(), "dev").unwrap();
+    create.assert();
+    drop(missing);
+
+    // merge patched when found, removing fields that are no longer set
+    let _found = mock("GET", &*format!("{}/fake-ask", coll))
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"metadata": {"name": "fake-ask", "resourceVersion": "1234"},
+            "spec": {"name": "fake-ask", "retiredField": "gone"}}"#)
+        .create();
+    let patch = mock("PATCH", &*format!("{}/fake-ask", coll))
+        .match_header("content-type", "application/merge-patch+json")
+        .match_body(Matcher::Regex(r#""retiredField":null"#.into()))
+        .with_body("{}")
+        .expect(1)
+        .create();
+    kube::apply_crd("fake-ask", mf, "dev").unwrap();
+    patch.assert();
+
+    // manifests for services that are gone get deleted
+    let _list = mock("GET", coll)
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"kind": "ShipcatManifestList", "items": [
+            {"metadata": {"name": "fake-ask"}}, {"metadata": {"name": "retired-svc"}}
+        ]}"#)
+        .create();
+    let delete = mock("DELETE", &*format!("{}/retired-svc", coll)).with_body("{}").expect(1).create();
+    let removed = kube::remove_redundant_manifests("dev", &["fake-ask".into(), "fake-storage".into()]).unwrap();
+    assert_eq!(removed, vec!["retired-svc".to_string()]);
+    delete.assert();
+}
+
+/// Mock what each kind shipcat renders has in a release, with `cronjobs` as the cron jobs
+fn mock_release(release: &str, cronjobs: &str) -> Vec<mockito::Mock> {
+    let kinds = [
+        "/api/v1/namespaces/dev/serviceaccounts",
+        "/apis/rbac.authorization.k8s.io/v1/namespaces/dev/roles",
+        "/apis/rbac.authorization.k8s.io/v1/namespaces/dev/rolebindings",
+        "/api/v1/namespaces/dev/configmaps",
+        "/api/v1/namespaces/dev/secrets",
+        "/apis/apps/v1/namespaces/dev/deployments",
+        "/api/v1/namespaces/dev/services",
+        "/apis/autoscaling/v2beta1/namespaces/dev/horizontalpodautoscalers",
+        "/apis/policy/v1beta1/namespaces/dev/poddisruptionbudgets",
+        "/apis/batch/v1beta1/namespaces/dev/cronjobs",
+    ];
+    kinds.iter().map(|coll| {
+        let items = if coll.ends_with("cronjobs") { cronjobs } else { "[]" };
+        mock("GET", &*format!("{}?labelSelector=release%3D{}", coll, release))
+            .with_header("content-type", "application/json")
+            .with_body(&format!(r#"{{"items": {}}}"#, items))
+            .expect(1)
+            .create()
+    }).collect()
+}
+
+/// Cron jobs in a release: one shipcat applied, and one it did not
+fn stale_cronjobs(release: &str) -> String {
+    format!(r#"[
+        {{"metadata": {{"name": "{0}-old-cron", "annotations": {{"shipcat.babylontech.co.uk/last-applied":
+            "{{\"apiVersion\":\"batch/v1beta1\",\"kind\":\"CronJob\",\"metadata\":{{\"name\":\"{0}-old-cron\"}}}}"}}}}}},
+        {{"metadata": {{"name": "{0}-manual-cron"}}}}
+    ]"#, release)
+}
+
+fn native_objects(name: &str, replicas: u32) -> Vec<Value> {
+    vec![
+        json!({"apiVersion": "v1", "kind": "ConfigMap", "metadata": {"name": format!("{}-config", name)}, "data": {"a": "b"}}),
+        json!({"apiVersion": "apps/v1", "kind": "Deployment", "metadata": {"name": name}, "spec": {"replicas": replicas}}),
+    ]
+}
+
+#[test]
+fn kube_native_apply() {
+    kubeconfig();
+    let lists = mock_release("applied-svc", &stale_cronjobs("applied-svc"));
+    let _missing = mock("GET", "/api/v1/namespaces/dev/configmaps/applied-svc-config").with_status(404).create();
+    let create = mock("POST", "/api/v1/namespaces/dev/configmaps")
+        .match_body(Matcher::Regex(r#""shipcat.babylontech.co.uk/last-applied":"#.into()))
+        .with_status(201)
+        .with_body("{}")
+        .expect(1)
+        .create();
+    let _live = mock("GET", "/apis/apps/v1/namespaces/dev/deployments/applied-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"metadata": {"name": "applied-svc", "annotations": {"shipcat.babylontech.co.uk/last-applied":
+            "{\"metadata\":{\"name\":\"applied-svc\"},\"spec\":{\"replicas\":1,\"paused\":true}}"}},
+            "spec": {"replicas": 1, "paused": true, "progressDeadlineSeconds": 600}}"#)
+        .create();
+    // fields shipcat applied before are removed, others are left alone
+    let patch = mock("PATCH", "/apis/apps/v1/namespaces/dev/deployments/applied-svc")
+        .match_header("content-type", "application/merge-patch+json")
+        .match_body(Matcher::Regex(r#""paused":null"#.into()))
+        .with_body("{}")
+        .expect(1)
+        .create();
+    let prune = mock("DELETE", "/apis/batch/v1beta1/namespaces/dev/cronjobs/applied-svc-old-cron")
+        .with_body("{}")
+        .expect(1)
+        .create();
+    kube::apply_objects(&native_objects("applied-svc", 2), "dev", "applied-svc").unwrap();
+    create.assert();
+    patch.assert();
+    prune.assert();
+    for l in lists {
+        l.assert();
+    }
+}
+
+#[test]
+fn kube_native_diff() {
+    kubeconfig();
+    let _lists = mock_release("diffed-svc", &stale_cronjobs("diffed-svc"));
+    let _missing = mock("GET", "/api/v1/namespaces/dev/configmaps/diffed-svc-config").with_status(404).create();
+    let _live = mock("GET", "/apis/apps/v1/namespaces/dev/deployments/diffed-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"metadata": {"name": "diffed-svc", "annotations": {"shipcat.babylontech.co.uk/last-applied":
+            "{\"apiVersion\":\"apps/v1\",\"kind\":\"Deployment\",\"metadata\":{\"name\":\"diffed-svc\"},\"spec\":{\"replicas\":1}}"}}}"#)
+        .create();
+    let diff = kube::diff_objects(&native_objects("diffed-svc", 2), "dev", "diffed-svc").unwrap();
+    let lines = diff.lines().collect::<Vec<_>>();
+    assert!(lines.contains(&"dev, diffed-svc-config, ConfigMap (v1) has been added:"));
+    assert!(lines.contains(&"dev, diffed-svc, Deployment (apps/v1) has changed:"));
+    assert!(lines.contains(&"-   replicas: 1"));
+    assert!(lines.contains(&"+   replicas: 2"));
+    assert!(lines.contains(&"  kind: Deployment")); // unchanged context
+    assert!(lines.contains(&"dev, diffed-svc-old-cron, CronJob (batch/v1beta1) has been removed:"));
+    assert!(!diff.contains("manual-cron"));
+}
+
+#[test]
+fn kube_native_rollout_undo() {
+    kubeconfig();
+    let mf = manifest("undone-svc");
+    let _deploy = mock("GET", "/apis/apps/v1/namespaces/dev/deployments/undone-svc")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"metadata": {"name": "undone-svc", "annotations": {"deployment.kubernetes.io/revision": "3"}},
+            "spec": {"template": {"metadata": {"labels": {"app": "undone-svc"}, "annotations": {"checksum/config": "new"}},
+                "spec": {"containers": [{"name": "undone-svc", "image": "undone-svc:3"}]}}}}"#)
+        .create();
+    let rs = |rev: u32| format!(r#"{{"metadata": {{"name": "undone-svc-{0}", "annotations": {{"deployment.kubernetes.io/revision": "{0}"}},
+            "ownerReferences": [{{"kind": "Deployment", "name": "undone-svc"}}]}},
+        "spec": {{"template": {{"metadata": {{"labels": {{"app": "undone-svc", "pod-template-hash": "abc{0}"}}}},
+            "spec": {{"containers": [{{"name": "undone-svc", "image": "undone-svc:{0}"}}]}}}}}}}}"#, rev);
+    let _sets = mock("GET", "/apis/apps/v1/namespaces/dev/replicasets?labelSelector=release%3Dundone-svc")
+        .with_header("content-type", "application/json")
+        .with_body(&format!(r#"{{"items": [{}, {}, {}]}}"#, rs(1), rs(3), rs(2)))
+        .create();
+    let patch = mock("PATCH", "/apis/apps/v1/namespaces/dev/deployments/undone-svc")
+        .match_body(Matcher::Regex(r#""annotations":null.*"image":"undone-svc:2""#.into()))
+        .with_body("{}")
+        .expect(1)
+        .create();
+    kube::rollout_undo(&mf).unwrap();
+    patch.assert();
+}
+
+fn canary_manifest(name: &str) -> Manifest {
+    let mut mf = manifest(name);
+    mf.image = Some(format!("quay.io/babylonhealth/{}", name));
+    mf.version = Some("1.1.0".into());
+    mf.replicaCount = Some(4);
+    mf.chart = Some("native".into());
+    mf.canary = Some(Canary { percentage: Some(25), bakeTime: Some(1), ..Default::default() });
+    mf
+}
+
+fn main_deployment(name: &str) -> String {
+    format!(r#"{{"metadata": {{"name": "{0}", "generation": 2, "resourceVersion": "42", "labels": {{"app": "{0}", "release": "{0}"}}}},
+        "spec": {{"replicas": 4, "selector": {{"matchLabels": {{"app": "{0}", "release": "{0}"}}}},
+            "template": {{"metadata": {{"labels": {{"app": "{0}", "release": "{0}"}}}},
+                "spec": {{"containers": [{{"name": "{0}", "image": "quay.io/babylonhealth/{0}:1.0.0"}}]}}}}}},
+        "status": {{"observedGeneration": 2, "replicas": 4, "updatedReplicas": 4, "availableReplicas": 4}}}}"#, name)
+}
+
+#[test]
+fn kube_canary_deployment() {
+    let mut mf = canary_manifest("fake-canary");
+    mf.env.plain.insert("NEW_SETTING".into(), "true".into());
+    let objs = direct::rendered(&mf, HelmVersion::V3).unwrap();
+    let canary = kube::canary_deployment(&objs, &mf, 1).unwrap();
+    assert_eq!(canary["metadata"]["name"], "fake-canary-canary");
+    assert_eq!(canary["spec"]["replicas"], 1);
+    assert_eq!(canary["spec"]["selector"]["matchLabels"]["track"], "canary");
+    // not claimed by the release or the main deployment's selector
+    assert_eq!(canary["metadata"]["labels"]["release"], "fake-canary-canary");
+    assert_eq!(canary["spec"]["selector"]["matchLabels"]["release"], "fake-canary-canary");
+    let template = &canary["spec"]["template"];
+    assert_eq!(template["metadata"]["labels"]["app"], "fake-canary"); // still behind the service
+    assert_eq!(template["metadata"]["labels"]["release"], "fake-canary-canary");
+    assert_eq!(template["metadata"]["labels"]["track"], "canary");
+    // the new manifest's pod spec, not the running one
+    let container = &template["spec"]["containers"][0];
+    assert_eq!(container["image"], "quay.io/babylonhealth/fake-canary:1.1.0");
+    assert!(container["env"].as_array().unwrap().iter().any(|e| e["name"] == "NEW_SETTING"));
+
+    // only the main deployment can be canaried
+    let workers = objs.into_iter().filter(|o| o["kind"] != "Deployment").collect::<Vec<_>>();
+    assert!(kube::canary_deployment(&workers, &mf, 1).is_err());
+}
+
+/// Mock a canary deployment for `name` whose pods report `restarts`
+fn mock_canary(name: &str, restarts: u32) -> Vec<mockito::Mock> {
+    let coll = "/apis/apps/v1/namespaces/dev/deployments";
+    let canary = format!(r#"{{"metadata": {{"name": "{0}-canary", "generation": 1}},
+        "spec": {{"replicas": 1, "template": {{"spec": {{"containers": [{{"name": "{0}"}}]}}}}}},
+        "status": {{"observedGeneration": 1, "replicas": 1, "updatedReplicas": 1, "availableReplicas": 1}}}}"#, name);
+    let pods = format!(r#"{{"kind": "PodList", "items": [{{"metadata": {{"name": "{0}-canary-1"}},
+        "status": {{"phase": "Running", "containerStatuses": [{{"name": "{0}", "ready": true, "restartCount": {1},
+            "state": {{"running": {{}}}}}}]}}}}]}}"#, name, restarts);
+    vec![
+        mock("GET", &*format!("{}/{}-canary", coll, name))
+            .with_header("content-type", "application/json")
+            .with_body(&canary)
+            .create(),
+        mock("GET", &*format!("/api/v1/namespaces/dev/pods?labelSelector=app%3D{}%2Ctrack%3Dcanary", name))
+            .with_header("content-type", "application/json")
+            .with_body(&pods)
+            .create(),
+    ]
+}
+
+#[test]
+fn kube_canary_bake() {
+    setup();
+    kubeconfig();
+    let (_, reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let coll = "/apis/apps/v1/namespaces/dev/deployments";
+
+    // a clean canary is promoted
+    let mf = canary_manifest("baked-svc");
+    let _mocks = mock_canary("baked-svc", 0);
+    let create = mock("POST", coll)
+        .match_body(Matcher::Regex(r#""name":"baked-svc-canary""#.into()))
+        .with_status(201)
+        .with_body("{}")
+        .expect(1)
+        .create();
+    let delete = mock("DELETE", &*format!("{}/baked-svc-canary?propagationPolicy=Background", coll))
+        .with_body("{}")
+        .expect(2) // stale canary before, and the canary itself after
+        .create();
+    let mut ud = UpgradeData { name: "baked-svc".into(), ..Default::default() };
+    canary::bake(&mut ud, &mf, &reg).unwrap();
+    assert!(ud.failure.is_none());
+    create.assert();
+    delete.assert();
+    drop(create);
+
+    // a restarting canary is aborted and removed
+    let mf = canary_manifest("restarting-svc");
+    let _mocks = mock_canary("restarting-svc", 1);
+    let _create = mock("POST", coll)
+        .match_body(Matcher::Regex(r#""name":"restarting-svc-canary""#.into()))
+        .with_status(201)
+        .with_body("{}")
+        .create();
+    let delete = mock("DELETE", &*format!("{}/restarting-svc-canary?propagationPolicy=Background", coll))
+        .with_body("{}")
+        .expect(2)
+        .create();
+    let mut ud = UpgradeData { name: "restarting-svc".into(), ..Default::default() };
+    assert!(canary::bake(&mut ud, &mf, &reg).is_err());
+    assert_eq!(ud.failure.unwrap().reason, FailureReason::CrashLoop);
+    delete.assert();
+
+    // restarts within maxRestarts are tolerated, even past the rollout restart limit
+    let mut mf = canary_manifest("tolerant-svc");
+    mf.canary = Some(Canary { maxRestarts: Some(5), bakeTime: Some(1), ..Default::default() });
+    let _mocks = mock_canary("tolerant-svc", 4);
+    let _create = mock("POST", coll)
+        .match_body(Matcher::Regex(r#""name":"tolerant-svc-canary""#.into()))
+        .with_status(201)
+        .with_body("{}")
+        .create();
+    let _delete = mock("DELETE", &*format!("{}/tolerant-svc-canary?propagationPolicy=Background", coll))
+        .with_body("{}")
+        .create();
+    let mut ud = UpgradeData { name: "tolerant-svc".into(), ..Default::default() };
+    canary::bake(&mut ud, &mf, &reg).unwrap();
+    assert!(ud.failure.is_none());
+}
+
+fn bluegreen_manifest(name: &str) -> Manifest {
+    let mut mf = manifest(name);
+    mf.version = Some("1.1.0".into());
+    mf.replicaCount = Some(4);
+    mf.blueGreen = Some(BlueGreen { rollbackWindow: Some(600) });
+    mf
+}
+
+/// Mock the `Service` of `name` selecting `active`, having switched from `previous` at `switched`
+fn mock_service(name: &str, active: &str, previous: &str, switched: &str) -> mockito::Mock {
+    mock("GET", &*format!("/api/v1/namespaces/dev/services/{}", name))
+        .with_header("content-type", "application/json")
+        .with_body(&format!(r#"{{"metadata": {{"name": "{0}", "resourceVersion": "7", "annotations": {{
+                "shipcat.babylontech.co.uk/previous-colour": "{2}", "shipcat.babylontech.co.uk/switched-at": "{3}"}}}},
+            "spec": {{"selector": {{"app": "{0}", "release": "{0}", "colour": "{1}"}}}}}}"#, name, active, previous, switched))
+        .create()
+}
+
+/// Mock a ready colour deployment for `name` with one healthy pod
+fn mock_colour(name: &str, colour: &str) -> Vec<mockito::Mock> {
+    let deploy = format!(r#"{{"metadata": {{"name": "{0}-{1}", "generation": 1}},
+        "spec": {{"replicas": 4, "template": {{"spec": {{"containers": [{{"name": "{0}"}}]}}}}}},
+        "status": {{"observedGeneration": 1, "replicas": 4, "updatedReplicas": 4, "availableReplicas": 4}}}}"#, name, colour);
+    let pods = format!(r#"{{"kind": "PodList", "items": [{{"metadata": {{"name": "{0}-{1}-1"}},
+        "status": {{"phase": "Running", "containerStatuses": [{{"name": "{0}", "ready": true, "restartCount": 0,
+            "state": {{"running": {{}}}}}}]}}}}]}}"#, name, colour);
+    vec![
+        mock("GET", &*format!("/apis/apps/v1/namespaces/dev/deployments/{}-{}", name, colour))
+            .with_header("content-type", "application/json")
+            .with_body(&deploy)
+            .create(),
+        mock("GET", &*format!("/api/v1/namespaces/dev/pods?labelSelector=app%3D{}%2Ccolour%3D{}", name, colour))
+            .with_header("content-type", "application/json")
+            .with_body(&pods)
+            .create(),
+    ]
+}
+
+#[test]
+fn kube_colour_deployment() {
+    let mf = bluegreen_manifest("fake-bluegreen");
+    let main : serde_json::Value = serde_json::from_str(&main_deployment("fake-bluegreen")).unwrap();
+    let green = kube::colour_deployment(&main, &mf, Colour::Blue.other(), 4).unwrap();
+    assert_eq!(green["metadata"]["name"], "fake-bluegreen-green");
+    assert!(green["metadata"]["labels"].get("release").is_none()); // not part of release rollouts
+    assert_eq!(green["spec"]["replicas"], 4);
+    assert_eq!(green["spec"]["selector"]["matchLabels"]["colour"], "green");
+    let template = &green["spec"]["template"];
+    assert_eq!(template["metadata"]["labels"]["release"], "fake-bluegreen");
+    assert_eq!(template["metadata"]["labels"]["colour"], "green");
+}
+
+#[test]
+fn kube_bluegreen_switch() {
+    kubeconfig();
+    let coll = "/apis/apps/v1/namespaces/dev/deployments";
+    let mf = bluegreen_manifest("switching-svc");
+    let _svc = mock_service("switching-svc", "blue", "green", "2019-01-01T00:00:00+00:00");
+    let _main = mock("GET", &*format!("{}/switching-svc", coll))
+        .with_header("content-type", "application/json")
+        .with_body(&main_deployment("switching-svc"))
+        .create();
+    let _colour = mock_colour("switching-svc", "green");
+    let delete = mock("DELETE", &*format!("{}/switching-svc-green?propagationPolicy=Background", coll))
+        .with_body("{}")
+        .expect(1) // the old green is replaced
+        .create();
+    let create = mock("POST", coll)
+        .match_body(Matcher::Regex(r#""name":"switching-svc-green""#.into()))
+        .with_status(201)
+        .with_body("{}")
+        .expect(1)
+        .create();
+    let switch = mock("PUT", "/api/v1/namespaces/dev/services/switching-svc")
+        .match_body(Matcher::Regex(r#"previous-colour":"blue".*"selector":\{[^}]*"colour":"green""#.into()))
+        .with_body("{}")
+        .expect(1)
+        .create();
+    assert!(bluegreen::switch(&mf).unwrap().is_none());
+    delete.assert();
+    create.assert();
+    switch.assert();
+}
+
+#[test]
+fn kube_bluegreen_rollback() {
+    setup();
+    kubeconfig();
+    let (_, reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+
+    // switches straight back within the window
+    let mf = bluegreen_manifest("flipping-svc");
+    let _svc = mock_service("flipping-svc", "green", "blue", &Utc::now().to_rfc3339());
+    let _colour = mock_colour("flipping-svc", "blue");
+    let switch = mock("PUT", "/api/v1/namespaces/dev/services/flipping-svc")
+        .match_body(Matcher::Regex(r#"previous-colour":"green".*"selector":\{[^}]*"colour":"blue""#.into()))
+        .with_body("{}")
+        .expect(1)
+        .create();
+    let ud = UpgradeData { name: "flipping-svc".into(), namespace: "dev".into(), ..Default::default() };
+    bluegreen::rollback(&reg, &ud, &mf).unwrap();
+    switch.assert();
+
+    // but not once the window passed, where the old colour gets scaled down instead
+    let mf = bluegreen_manifest("expired-svc");
+    let _svc = mock_service("expired-svc", "green", "blue", "2019-01-01T00:00:00+00:00");
+    let _colour = mock_colour("expired-svc", "blue");
+    let ud = UpgradeData { name: "expired-svc".into(), namespace: "dev".into(), ..Default::default() };
+    assert!(bluegreen::rollback(&reg, &ud, &mf).is_err());
+    let scale = mock("PUT", "/apis/apps/v1/namespaces/dev/deployments/expired-svc-blue")
+        .match_body(Matcher::Regex(r#""replicas":0"#.into()))
+        .with_body("{}")
+        .expect(1)
+        .create();
+    bluegreen::retire(&mf).unwrap();
+    scale.assert();
+}
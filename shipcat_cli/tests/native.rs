@@ -0,0 +1,150 @@
+mod common;
+use crate::common::setup;
+
+use shipcat_definitions::{Config, ConfigType, Manifest};
+use shipcat_definitions::structs::BlueGreen;
+use shipcat::native::{self, objects::Object};
+
+fn native_manifest() -> Manifest {
+    setup();
+    let (conf, reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let mut mf = Manifest::base("fake-storage", &conf, &reg).unwrap().stub(&reg).unwrap();
+    mf.chart = Some("native".into());
+    mf.version = Some("1.0.0".into());
+    mf.sidecars.clear(); // no sidecar templates natively
+    mf
+}
+
+#[test]
+fn native_render() {
+    let mut mf = native_manifest();
+    assert!(native::is_native(&mf));
+    mf.replicaCount = Some(3);
+    let objs = native::render(&mf).unwrap();
+
+    let kinds = objs.iter().map(|o| (o.kind(), o.name())).collect::<Vec<_>>();
+    assert!(kinds.contains(&("ServiceAccount", "fake-storage")));
+    assert!(kinds.contains(&("ConfigMap", "fake-storage-config")));
+    assert!(kinds.contains(&("PodDisruptionBudget", "fake-storage")));
+    assert!(!kinds.iter().any(|(k, _)| *k == "HorizontalPodAutoscaler"));
+
+    let deploy = objs.iter().filter_map(|o| match o {
+        Object::Deployment(d) => Some(d),
+        _ => None,
+    }).next().unwrap();
+    assert_eq!(deploy.spec.replicas, Some(3));
+    assert_eq!(deploy.spec.selector.matchLabels["app"], "fake-storage");
+    assert_eq!(deploy.spec.template.metadata.labels["app"], "fake-storage");
+    // same labels as the base chart
+    let labels = &deploy.metadata.labels;
+    assert_eq!(labels["app"], "fake-storage");
+    assert_eq!(labels["type"], "service");
+    assert_eq!(labels["release"], "fake-storage");
+    assert_eq!(labels["heritage"], "shipcat");
+    assert!(labels["chart"].starts_with("fake-storage-"));
+    let container = &deploy.spec.template.spec.containers[0];
+    assert_eq!(container.image, "nginx:1.0.0");
+    assert_eq!(container.command, vec!["./start-app.sh"]);
+    assert!(container.readinessProbe.is_some()); // from health
+
+    let svc = objs.iter().filter_map(|o| match o {
+        Object::Service(s) => Some(s),
+        _ => None,
+    }).next().unwrap();
+    assert_eq!(svc.spec.ports[0].port, 80);
+    assert_eq!(svc.spec.ports[0].targetPort, Some(3000));
+}
+
+#[test]
+fn native_render_checksums() {
+    let mut mf = native_manifest();
+    let checksums = |mf: &Manifest| match native::render(mf).unwrap().into_iter().find(|o| o.kind() == "Deployment") {
+        Some(Object::Deployment(d)) => d.spec.template.metadata.annotations,
+        _ => unreachable!(),
+    };
+    let before = checksums(&mf);
+    assert_eq!(before["checksum/config"].len(), 64); // sha256 like the base chart
+
+    // changed configs roll the pods, secrets are unaffected
+    mf.configs.as_mut().unwrap().files[0].value = Some("changed".into());
+    let after = checksums(&mf);
+    assert_ne!(before["checksum/config"], after["checksum/config"]);
+    assert_eq!(before["checksum/secrets"], after["checksum/secrets"]);
+}
+
+#[test]
+fn native_template_roundtrip() {
+    let mf = native_manifest();
+    let file = std::env::temp_dir().join(format!("shipcat-native-{}.yml", std::process::id()));
+    std::fs::write(&file, native::template(&mf).unwrap()).unwrap();
+    let objs = native::read(file.to_str().unwrap()).unwrap();
+    let rendered = native::render(&mf).unwrap();
+    assert_eq!(objs.len(), rendered.len());
+    for (o, r) in objs.iter().zip(rendered.iter()) {
+        assert_eq!(o["kind"], r.kind());
+        assert_eq!(o["metadata"]["name"], r.name());
+    }
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn native_render_single_replica() {
+    let mut mf = native_manifest();
+    mf.replicaCount = Some(1);
+    let objs = native::render(&mf).unwrap();
+    assert!(!objs.iter().any(|o| o.kind() == "PodDisruptionBudget"));
+    match objs.iter().find(|o| o.kind() == "Deployment").unwrap() {
+        Object::Deployment(d) => {
+            let ru = &d.spec.strategy.as_ref().unwrap().rollingUpdate;
+            assert_eq!(serde_json::to_value(&ru.maxUnavailable).unwrap(), 0);
+        },
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn native_render_bluegreen_template() {
+    let mut mf = native_manifest();
+    mf.replicaCount = Some(3);
+    mf.blueGreen = Some(BlueGreen::default());
+    let objs = native::render(&mf).unwrap();
+    match objs.iter().find(|o| o.kind() == "Deployment").unwrap() {
+        Object::Deployment(d) => assert_eq!(d.spec.replicas, Some(0)), // colours run the pods
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn native_render_rejects_sidecars() {
+    setup();
+    let (conf, reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let mut mf = Manifest::base("fake-storage", &conf, &reg).unwrap().stub(&reg).unwrap();
+    mf.version = Some("1.0.0".into());
+    assert!(native::render(&mf).is_err());
+    mf.sidecars.clear();
+    mf.version = None;
+    assert!(native::render(&mf).is_err()); // needs a version
+}
+
+#[test]
+fn native_parse_helm_template() {
+    let tpl = r#"---
+# Source: base/templates/serviceaccount.yaml
+apiVersion: v1
+kind: ServiceAccount
+metadata:
+  name: fake-ask
+---
+# Source: base/templates/empty.yaml
+---
+# Source: base/templates/deployment.yaml
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: fake-ask
+"#;
+    let objs = native::parse(tpl).unwrap();
+    assert_eq!(objs.len(), 2);
+    assert_eq!(objs[0]["kind"], "ServiceAccount");
+    assert_eq!(objs[1]["metadata"]["name"], "fake-ask");
+}
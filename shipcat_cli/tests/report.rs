@@ -0,0 +1,71 @@
+mod common;
+
+use std::time::Duration;
+
+use shipcat::{Error, ErrorKind};
+use shipcat::report::{self, Report, ServiceReport, ServiceStatus};
+
+fn report() -> Report {
+    let timeout : Error = ErrorKind::UpgradeTimeout("fake-ask".into(), 60).into();
+    let mut ask = ServiceReport::new("fake-ask", ServiceStatus::Failed, "UpgradeWaitMaybeRollback")
+        .error(&timeout)
+        .duration(Duration::from_millis(61500));
+    ask.newVersion = Some("1.2.3".into());
+    ask.waitTime = Some(60);
+    let mut storage = ServiceReport::new("fake-storage", ServiceStatus::Reconciled, "UpgradeWaitMaybeRollback")
+        .duration(Duration::from_secs(12));
+    storage.oldVersion = Some("0.1.0".into());
+    storage.newVersion = Some("0.2.0".into());
+    storage.diff = Some(report::diff_summary("-  image: <old>\n+  image: <new>\n+  foo: bar\n context"));
+    let late = ServiceReport::new("fake-late", ServiceStatus::NotStarted, "UpgradeWaitMaybeRollback");
+    Report::new("helm reconcile", "dev-uk", Duration::from_secs(75), vec![storage, late, ask])
+}
+
+#[test]
+fn report_summaries() {
+    assert_eq!(report::diff_summary("+a\n+b\n-c\n d"), "+2 -1");
+    assert_eq!(report::diff_summary(""), "+0 -0");
+    let e : Error = ErrorKind::DependencyFailed("a".into(), "b".into()).into();
+    assert_eq!(report::error_kind(&e), "DependencyFailed");
+    let e : Error = "plain".into();
+    assert_eq!(report::error_kind(&e), "Msg");
+}
+
+#[test]
+fn report_json() {
+    let r = report();
+    let names = r.services.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["fake-ask", "fake-late", "fake-storage"]); // sorted
+    let json = serde_json::to_value(&r).unwrap();
+    assert_eq!(json["operation"], "helm reconcile");
+    assert_eq!(json["duration"], 75.0);
+    let ask = &json["services"][0];
+    assert_eq!(ask["status"], "FAILED");
+    assert_eq!(ask["errorKind"], "UpgradeTimeout");
+    assert_eq!(ask["duration"], 61.5);
+    assert!(ask.get("oldVersion").is_none());
+    let storage = &json["services"][2];
+    assert_eq!(storage["status"], "RECONCILED");
+    assert_eq!(storage["diff"], "+2 -1");
+    assert_eq!(json["services"][1]["status"], "NOT_STARTED");
+}
+
+#[test]
+fn report_junit() {
+    let xml = report().junit();
+    print!("{}", xml);
+    assert!(xml.contains(r#"tests="3" failures="1" skipped="1""#));
+    assert!(xml.contains(r#"<failure type="UpgradeTimeout" message="fake-ask upgrade timed out"#));
+    assert!(xml.contains(r#"<skipped message="not started"/>"#));
+    assert!(xml.contains("diff: +2 -1"));
+    assert_eq!(xml.matches("<testcase ").count(), 3);
+    assert_eq!(xml.matches("</testcase>").count(), 3);
+}
+
+#[test]
+fn report_junit_escaping() {
+    let e : Error = ErrorKind::UpgradeRolloutFailure("fake-ask".into(), "pod <x> & \"y\"".into()).into();
+    let svc = ServiceReport::new("fake-ask", ServiceStatus::Ignored, "UpgradeInstall").error(&e);
+    let xml = Report::new("helm reconcile", "dev-uk", Duration::from_secs(1), vec![svc]).junit();
+    assert!(xml.contains(r#"<skipped message="fake-ask upgrade aborted: pod &lt;x&gt; &amp; &quot;y&quot;"/>"#));
+}
@@ -0,0 +1,30 @@
+use shipcat::schema;
+
+#[test]
+fn schema_manifest() {
+    let raw = schema::generate("manifest").unwrap();
+    let s = serde_json::to_value(&raw).unwrap();
+
+    // deny_unknown_fields propagates
+    assert_eq!(s["additionalProperties"], false);
+    // doc comments become descriptions
+    assert!(s["properties"]["name"]["description"].as_str().unwrap().starts_with("Name of the service"));
+    // structs end up as definitions
+    assert!(s["definitions"]["Kong"].is_object());
+    assert!(s["definitions"]["Resources_for_String"].is_object());
+    // env is a plain map in shipcat.yml
+    assert_eq!(s["definitions"]["EnvVars"]["type"], "object");
+    assert_eq!(s["definitions"]["EnvVars"]["additionalProperties"]["type"], "string");
+}
+
+#[test]
+fn schema_config() {
+    let conf = serde_json::to_value(&schema::generate("config").unwrap()).unwrap();
+    assert_eq!(conf["properties"]["version"]["type"], "string");
+    assert!(conf["definitions"]["Region"].is_object());
+
+    let reg = serde_json::to_value(&schema::generate("region").unwrap()).unwrap();
+    assert!(reg["properties"]["vault"].is_object());
+
+    assert!(schema::generate("shipcat").is_err());
+}
@@ -0,0 +1,121 @@
+mod common;
+use crate::common::setup;
+
+use std::env;
+use mockito::mock;
+
+use shipcat_definitions::{Config, ConfigType, Manifest, SecretBackendConfig, SecretBackend};
+use shipcat::secret::{collect, findings, secret_path, removal_path, FindingKind};
+
+#[test]
+fn local_secret_backend() {
+    setup();
+    let (conf, mut reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    reg.secretBackend = SecretBackendConfig::Local { path: "secrets/dev-uk.yml".into(), encrypted: false };
+
+    let backend = reg.secret_backend().unwrap();
+    assert_eq!(backend.read_key("dev-uk/shared/postgres", "password").unwrap(), "hunter22");
+    assert!(backend.exists("dev-uk/test-shipcat/fake-file").unwrap());
+
+    // completes without a vault
+    let base = Manifest::base("fake-ask", &conf, &reg).unwrap();
+    assert!(base.verify_secrets_exist(&reg).is_ok());
+    let mf = base.complete(&reg).unwrap();
+    assert_eq!(mf.secrets["FAKE_SECRET"], "hello-local");
+    assert_eq!(mf.secrets["FAKE_NUMBER"], "-3");
+
+    reg.secretBackend = SecretBackendConfig::Local { path: "secrets/missing.yml".into(), encrypted: false };
+    assert!(reg.secret_backend().is_err());
+}
+
+#[test]
+fn stubbed_secrets() {
+    setup();
+    let (conf, reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let mut base = Manifest::base("fake-ask", &conf, &reg).unwrap();
+    base.secretFiles.insert("fake-file".into(), "IN_VAULT".into());
+    base.env.plain.insert("DB_PASSWORD".into(), "IN_VAULT:shared/postgres#password".into());
+    let mf = base.stub(&reg).unwrap();
+
+    assert_eq!(mf.secrets["FAKE_SECRET"], "STUB_FAKE_SECRET");
+    assert_eq!(mf.secrets["DB_PASSWORD"], "STUB_DB_PASSWORD");
+    assert_eq!(mf.secrets["FAKE_NUMBER"], "42"); // from stubs.yml
+    assert_eq!(mf.secrets["CLIENT_SECRET"], "FAKEASKSECRET"); // templated from the config
+    assert_eq!(mf.secretFiles["fake-file"], "U1RVQl9mYWtlLWZpbGU="); // base64 of STUB_fake-file
+}
+
+#[test]
+fn secret_audit() {
+    setup();
+    let (conf, mut reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    reg.secretBackend = SecretBackendConfig::Local { path: "secrets/dev-uk.yml".into(), encrypted: false };
+
+    let secrets = collect(&conf, &reg).unwrap();
+    let ask = secrets.iter().find(|s| s.service == "fake-ask").unwrap();
+    assert_eq!(ask.folder, "dev-uk/test-shipcat");
+    assert_eq!(ask.referenced.iter().collect::<Vec<_>>(), vec!["FAKE_NUMBER", "FAKE_SECRET"]);
+
+    let res = findings(&secrets);
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].path, "dev-uk/test-shipcat/fake-file");
+    assert_eq!(res[0].kind, FindingKind::Unused);
+}
+
+#[test]
+fn vault_secret_audit() {
+    setup();
+    let (conf, mut reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    reg.secretBackend = SecretBackendConfig::Vault;
+    reg.vault.url = mockito::SERVER_URL.into();
+    env::set_var("VAULT_TOKEN", "s.audittoken");
+
+    let ask = mock("GET", "/v1/secret/dev-uk/test-shipcat?list=true")
+        .match_header("X-Vault-Token", "s.audittoken")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"keys": ["FAKE_NUMBER", "FAKE_SECRET", "fake-file"]}, "lease_duration": 0}"#)
+        .expect(1)
+        .create();
+    // services without secrets have no folder
+    let storage = mock("GET", "/v1/secret/dev-uk/fake-storage?list=true")
+        .with_status(404)
+        .with_body(r#"{"errors": []}"#)
+        .expect(1)
+        .create();
+
+    let secrets = collect(&conf, &reg).unwrap();
+    let empty = secrets.iter().find(|s| s.service == "fake-storage").unwrap();
+    assert!(empty.found.is_empty());
+
+    let res = findings(&secrets);
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].path, "dev-uk/test-shipcat/fake-file");
+    assert_eq!(res[0].kind, FindingKind::Unused);
+
+    ask.assert();
+    storage.assert();
+}
+
+#[test]
+fn service_secret_paths() {
+    setup();
+    let (conf, mut reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let pth = secret_path("fake-ask", "FAKE_SECRET", &conf, &reg, false).unwrap();
+    assert_eq!(pth, "dev-uk/test-shipcat/FAKE_SECRET"); // folder from the vault block
+
+    // only referenced secrets unless forced
+    assert!(secret_path("fake-ask", "FAKE_SECERT", &conf, &reg, false).is_err());
+    assert!(secret_path("fake-ask", "fake-file", &conf, &reg, true).is_ok());
+    assert!(secret_path("fake-ask", "../other/KEY", &conf, &reg, true).is_err());
+
+    // only unused secrets can be deleted unless forced
+    assert!(removal_path("fake-ask", "OLD_KEY", &conf, &reg, false).is_ok());
+    assert!(removal_path("fake-ask", "FAKE_SECRET", &conf, &reg, false).is_err());
+    assert!(removal_path("fake-ask", "FAKE_SECRET", &conf, &reg, true).is_ok());
+    assert!(removal_path("fake-ask", "../other/KEY", &conf, &reg, true).is_err());
+
+    // local secrets are read only
+    reg.secretBackend = SecretBackendConfig::Local { path: "secrets/dev-uk.yml".into(), encrypted: false };
+    let backend = reg.secret_backend().unwrap();
+    assert!(backend.write(&pth, "new").is_err());
+    assert!(backend.delete(&pth).is_err());
+}
@@ -13,3 +13,29 @@ fn validate_test() {
     let res2 = validate(vec!["fake-storage".into(), "fake-ask".into()], &conf, &reg, false);
     assert!(res2.is_ok())
 }
+
+use shipcat_definitions::Manifest;
+use shipcat_definitions::diagnostics::{Code, Severity};
+
+#[test]
+fn validate_collects_all_errors() {
+    setup();
+    let (conf, reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let mut mf = Manifest::base("fake-ask", &conf, &reg).unwrap().stub(&reg).unwrap();
+    mf.name = "Fake_Ask".into();
+    mf.replicaCount = Some(0);
+    mf.workers[0].resources.limits.cpu = "40".into();
+
+    let diags = mf.diagnose(&conf, &reg);
+    let errs = diags.errors();
+    let paths = errs.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+    assert!(paths.contains(&"name"));
+    assert!(paths.contains(&"replicaCount"));
+    assert!(paths.contains(&"workers[0].resources.limits.cpu"));
+    let cpu = errs.iter().find(|e| e.path == "workers[0].resources.limits.cpu").unwrap();
+    assert_eq!(cpu.code, Code::OutOfRange);
+    assert_eq!(cpu.severity, Severity::Error);
+
+    let err = mf.verify(&conf, &reg).unwrap_err().to_string();
+    assert!(err.contains("error[E006] workers[0].resources.limits.cpu"));
+}
@@ -0,0 +1,43 @@
+mod common;
+use crate::common::setup;
+
+use std::path::PathBuf;
+use shipcat_definitions::{Config, ConfigType, Manifest};
+use shipcat::show::explain;
+
+#[test]
+fn environment_overrides() {
+    setup();
+    let (conf, reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    assert_eq!(reg.environment, "dev");
+    let layers = Manifest::layers("fake-storage", &reg).unwrap();
+    let names = layers.iter().map(|p| p.file_name().unwrap().to_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["web.yml", "shipcat.yml", "dev.yml", "dev-uk.yml"]);
+
+    let mf = Manifest::base("fake-storage", &conf, &reg).unwrap();
+    assert_eq!(mf.replicaCount, Some(1)); // environment overrides shipcat.yml
+    assert_eq!(mf.env.plain["LOG_LEVEL"], "debug"); // environment adds
+    assert_eq!(mf.env.plain["RAILS_ENV"], "development"); // region overrides environment
+    assert_eq!(mf.env.plain["INSTANCE_TYPE"], "web"); // shipcat.yml kept
+    assert_eq!(mf.env.plain["WEB_CONCURRENCY"], "2"); // from the base
+    assert_eq!(mf.httpPort, Some(3000)); // shipcat.yml overrides the base
+}
+
+#[test]
+fn values_explain() {
+    setup();
+    let (conf, reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let res = explain("fake-storage", &conf, &reg).unwrap();
+    let source = |key: &str| {
+        let (_, src) = res.iter().find(|(k, _)| k == key).unwrap();
+        PathBuf::from(src).file_name().unwrap().to_str().unwrap().to_string()
+    };
+    assert_eq!(source("image"), "shipcat.yml");
+    assert_eq!(source("replicaCount"), "dev.yml");
+    assert_eq!(source("env.LOG_LEVEL"), "dev.yml");
+    assert_eq!(source("env.RAILS_ENV"), "dev-uk.yml");
+    assert_eq!(source("env.INSTANCE_TYPE"), "shipcat.yml");
+    assert_eq!(source("env.WEB_CONCURRENCY"), "web.yml");
+    assert_eq!(source("httpPort"), "shipcat.yml");
+    assert_eq!(source("chart"), "default");
+}
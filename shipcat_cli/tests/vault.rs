@@ -0,0 +1,398 @@
+mod common;
+
+use std::{env, fs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use mockito::{mock, Matcher};
+use shipcat_definitions::{Manifest, Vault, VaultConfig, VaultAuth, KvVersion, SecretBackend};
+use shipcat_definitions::structs::Worker;
+
+fn vault_config(kv: KvVersion) -> VaultConfig {
+    VaultConfig {
+        url: mockito::SERVER_URL.into(),
+        folder: "dev-uk".into(),
+        kvVersion: kv,
+        auth: VaultAuth::Token,
+    }
+}
+
+/// A manifest with a secret env var and a secret file in vault
+fn secret_manifest(name: &str) -> Manifest {
+    let mut mf = Manifest::default();
+    mf.name = name.into();
+    mf.env.plain.insert("FAKE_SECRET".into(), "IN_VAULT".into());
+    mf.secretFiles.insert("fake-file".into(), "IN_VAULT".into());
+    mf
+}
+
+#[test]
+fn vault_kv1() {
+    let vc = vault_config(KvVersion::V1);
+    let v = Vault::with_token(&vc, "s.kv1token").unwrap();
+
+    let secret = mock("GET", "/v1/secret/dev-uk/kv1-svc/FAKE_SECRET")
+        .match_header("X-Vault-Token", "s.kv1token")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"value": "hello"}, "lease_duration": 2764800, "renewable": false}"#)
+        .expect(1)
+        .create();
+    let number = mock("GET", "/v1/secret/dev-uk/kv1-svc/FAKE_NUMBER")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"value": -2}, "lease_duration": 2764800, "renewable": false}"#)
+        .create();
+    let list = mock("GET", "/v1/secret/dev-uk/kv1-svc?list=true")
+        .match_header("X-Vault-Token", "s.kv1token")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"keys": ["FAKE_NUMBER", "FAKE_SECRET", "fake-file", "nested/"]}, "lease_duration": 0}"#)
+        .expect(2)
+        .create();
+
+    assert_eq!(v.read("dev-uk/kv1-svc/FAKE_SECRET").unwrap(), "hello");
+    assert_eq!(v.read("dev-uk/kv1-svc/FAKE_NUMBER").unwrap(), "-2");
+    let keys = v.list("dev-uk/kv1-svc").unwrap();
+    assert_eq!(keys, vec!["FAKE_NUMBER", "FAKE_SECRET", "fake-file"]); // no sub folders
+    assert!(secret_manifest("kv1-svc").verify_secrets_with(&v, &vc).is_ok());
+
+    // vault has no empty folders, so missing ones are an error
+    let missing = mock("GET", "/v1/secret/dev-uk/kv1-missing?list=true")
+        .with_status(404)
+        .with_body(r#"{"errors": []}"#)
+        .expect(1)
+        .create();
+    assert!(v.list("dev-uk/kv1-missing").is_err());
+
+    secret.assert();
+    number.assert();
+    list.assert();
+    missing.assert();
+}
+
+#[test]
+fn vault_kv2() {
+    let vc = vault_config(KvVersion::V2);
+    let v = Vault::with_token(&vc, "s.kv2token").unwrap();
+
+    let secret = mock("GET", "/v1/secret/data/dev-uk/kv2-svc/FAKE_SECRET")
+        .match_header("X-Vault-Token", "s.kv2token")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{
+            "data": {
+                "data": {"value": "hello"},
+                "metadata": {"created_time": "2018-03-22T02:24:06.945319214Z", "deletion_time": "", "destroyed": false, "version": 2}
+            },
+            "lease_duration": 0,
+            "renewable": false
+        }"#)
+        .expect(1)
+        .create();
+    let list = mock("GET", "/v1/secret/metadata/dev-uk/kv2-svc?list=true")
+        .match_header("X-Vault-Token", "s.kv2token")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"keys": ["FAKE_SECRET", "nested/"]}, "lease_duration": 0}"#)
+        .expect(2)
+        .create();
+
+    assert_eq!(v.read("dev-uk/kv2-svc/FAKE_SECRET").unwrap(), "hello");
+    assert_eq!(v.list("dev-uk/kv2-svc").unwrap(), vec!["FAKE_SECRET"]);
+    // fake-file is missing
+    let err = secret_manifest("kv2-svc").verify_secrets_with(&v, &vc).unwrap_err();
+    assert!(err.to_string().contains("fake-file"));
+
+    secret.assert();
+    list.assert();
+}
+
+/// A worker with a single env var
+fn worker(key: &str, value: &str) -> Worker {
+    let yaml = format!("name: consumer
+replicaCount: 1
+resources:
+  requests: {{cpu: 100m, memory: 100Mi}}
+  limits: {{cpu: 100m, memory: 100Mi}}
+env:
+  {}: {}
+", key, value);
+    serde_yaml::from_str(&yaml).unwrap()
+}
+
+#[test]
+fn vault_shared_references() {
+    let vc = vault_config(KvVersion::V1);
+    let v = Vault::with_token(&vc, "s.sharedtoken").unwrap();
+    let mut mf = secret_manifest("shared-svc");
+    mf.env.plain.insert("POSTGRES_PASSWORD".into(), "IN_VAULT:shared/postgres#password".into());
+    mf.env.plain.insert("POSTGRES_USER".into(), "IN_VAULT:shared/postgres#username".into());
+    mf.workers.push(worker("POSTGRES_PASSWORD", "IN_VAULT:shared/postgres#password"));
+
+    let own = mock("GET", "/v1/secret/dev-uk/shared-svc?list=true")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"keys": ["FAKE_SECRET", "fake-file"]}, "lease_duration": 0}"#)
+        .expect(1)
+        .create();
+    let shared = mock("GET", "/v1/secret/dev-uk/shared?list=true")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"keys": ["postgres", "redis"]}, "lease_duration": 0}"#)
+        .expect(1)
+        .create();
+    assert!(mf.verify_secrets_with(&v, &vc).is_ok());
+    own.assert();
+    shared.assert();
+
+    let postgres = mock("GET", "/v1/secret/dev-uk/shared/postgres")
+        .match_header("X-Vault-Token", "s.sharedtoken")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"password": "hunter22", "username": "shared"}, "lease_duration": 0}"#)
+        .expect(1) // both keys from one read
+        .create();
+    let secret = mock("GET", "/v1/secret/dev-uk/shared-svc/FAKE_SECRET")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"value": "hello"}, "lease_duration": 0}"#)
+        .create();
+    let file = mock("GET", "/v1/secret/dev-uk/shared-svc/fake-file")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"value": "aGVsbG8gd29ybGQ="}, "lease_duration": 0}"#)
+        .create();
+    mf.secrets(&v, &vc).unwrap();
+    assert_eq!(mf.secrets["POSTGRES_PASSWORD"], "hunter22");
+    assert_eq!(mf.secrets["POSTGRES_USER"], "shared");
+    assert_eq!(mf.secrets["FAKE_SECRET"], "hello");
+    assert_eq!(mf.secretFiles["fake-file"], "aGVsbG8gd29ybGQ=");
+    assert!(mf.workers[0].env.secrets.contains("POSTGRES_PASSWORD"));
+    assert_eq!(mf.get_secrets(), vec!["aGVsbG8gd29ybGQ=", "hello", "hunter22", "shared"]);
+    postgres.assert();
+    secret.assert();
+    file.assert();
+
+    // missing keys are reported when reading (from the cache)
+    let mut missing = Manifest::default();
+    missing.name = "shared-svc".into();
+    missing.env.plain.insert("POSTGRES_HOST".into(), "IN_VAULT:shared/postgres#host".into());
+    let err = missing.secrets(&v, &vc).unwrap_err();
+    assert!(err.iter().any(|e| e.to_string() == "secret 'secret/dev-uk/shared/postgres' does not have the 'host' key"));
+}
+
+#[test]
+fn vault_conflicting_references() {
+    let vc = vault_config(KvVersion::V1);
+    let v = Vault::with_token(&vc, "s.token").unwrap();
+    let mut mf = Manifest::default();
+    mf.name = "conflict-svc".into();
+    mf.env.plain.insert("DB_PASSWORD".into(), "IN_VAULT:shared/postgres#password".into());
+    mf.workers.push(worker("DB_PASSWORD", "IN_VAULT:shared/mysql#password"));
+    let err = mf.secrets(&v, &vc).unwrap_err();
+    assert!(err.to_string().contains("DB_PASSWORD"));
+}
+
+#[test]
+fn vault_secret_cache() {
+    let vc = vault_config(KvVersion::V1);
+    let forever = mock("GET", "/v1/secret/dev-uk/cache-svc/FOREVER")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"value": "forever"}, "lease_duration": 0}"#)
+        .expect(1)
+        .create();
+    let leased = mock("GET", "/v1/secret/dev-uk/cache-svc/LEASED")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"value": "leased"}, "lease_duration": 1}"#)
+        .expect(2)
+        .create();
+    let many = mock("GET", Matcher::Regex(r"^/v1/secret/dev-uk/cache-svc/MANY_\d+$".into()))
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"value": "many", "other": "key"}, "lease_duration": 0}"#)
+        .expect(20)
+        .create();
+
+    // separate clients share the cache
+    for _ in 0..3 {
+        let v = Vault::with_token(&vc, "s.cachetoken").unwrap();
+        assert_eq!(v.read("dev-uk/cache-svc/FOREVER").unwrap(), "forever");
+    }
+    // but only with the same token
+    let other = mock("GET", "/v1/secret/dev-uk/cache-svc/OTHER")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"value": "other"}, "lease_duration": 0}"#)
+        .expect(2)
+        .create();
+    for token in &["s.cachetoken", "s.othertoken", "s.othertoken"] {
+        let v = Vault::with_token(&vc, *token).unwrap();
+        assert_eq!(v.read("dev-uk/cache-svc/OTHER").unwrap(), "other");
+    }
+    let elapsed = Arc::new(AtomicUsize::new(0));
+    let clock = elapsed.clone();
+    let start = Instant::now();
+    let v = Vault::with_token(&vc, "s.cachetoken").unwrap()
+        .with_clock(move || start + Duration::from_secs(clock.load(Ordering::SeqCst) as u64));
+    assert_eq!(v.read("dev-uk/cache-svc/LEASED").unwrap(), "leased");
+    assert_eq!(v.read("dev-uk/cache-svc/LEASED").unwrap(), "leased");
+    elapsed.store(2, Ordering::SeqCst); // lease ran out
+    assert_eq!(v.read("dev-uk/cache-svc/LEASED").unwrap(), "leased");
+
+    // fetched concurrently, one request per secret
+    let mut reqs = (0..20).map(|i| (format!("dev-uk/cache-svc/MANY_{}", i), "value".to_string())).collect::<Vec<_>>();
+    reqs.push(("dev-uk/cache-svc/MANY_3".into(), "other".into()));
+    let values = v.read_keys(&reqs).unwrap();
+    assert_eq!(values.len(), 21);
+    assert_eq!(values[19], "many");
+    assert_eq!(values[20], "key");
+
+    forever.assert();
+    other.assert();
+    leased.assert();
+    many.assert();
+}
+
+#[test]
+fn vault_write_and_delete() {
+    let v1 = Vault::with_token(&vault_config(KvVersion::V1), "s.writetoken").unwrap();
+    let read = mock("GET", "/v1/secret/dev-uk/write-svc/API_KEY")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"value": "old"}, "lease_duration": 0}"#)
+        .expect(2)
+        .create();
+    let write = mock("POST", "/v1/secret/dev-uk/write-svc/API_KEY")
+        .match_header("X-Vault-Token", "s.writetoken")
+        .match_body(r#"{"value":"new"}"#)
+        .with_status(204)
+        .create();
+    let delete = mock("DELETE", "/v1/secret/dev-uk/write-svc/API_KEY")
+        .match_header("X-Vault-Token", "s.writetoken")
+        .with_status(204)
+        .create();
+
+    assert_eq!(v1.read("dev-uk/write-svc/API_KEY").unwrap(), "old");
+    v1.write("dev-uk/write-svc/API_KEY", "new").unwrap();
+    v1.read("dev-uk/write-svc/API_KEY").unwrap(); // cache dropped after writing
+    v1.delete("dev-uk/write-svc/API_KEY").unwrap();
+
+    // kv2 nests the data, and deletes all versions through the metadata
+    let v2 = Vault::with_token(&vault_config(KvVersion::V2), "s.writetoken").unwrap();
+    let write2 = mock("POST", "/v1/secret/data/dev-uk/write-svc/API_KEY")
+        .match_body(r#"{"data":{"value":"new"}}"#)
+        .with_status(200)
+        .with_body("{}")
+        .create();
+    let delete2 = mock("DELETE", "/v1/secret/metadata/dev-uk/write-svc/API_KEY")
+        .with_status(204)
+        .create();
+    v2.write("dev-uk/write-svc/API_KEY", "new").unwrap();
+    v2.delete("dev-uk/write-svc/API_KEY").unwrap();
+
+    let denied = mock("POST", "/v1/secret/dev-uk/write-svc/DENIED")
+        .with_status(403)
+        .with_body(r#"{"errors": ["permission denied"]}"#)
+        .create();
+    assert!(v1.write("dev-uk/write-svc/DENIED", "new").is_err());
+
+    for m in &[read, write, delete, write2, delete2, denied] {
+        m.assert();
+    }
+}
+
+#[test]
+fn vault_kv_version_mismatch() {
+    // a kv2 engine configured as kv1 only has secrets under data/
+    let v = Vault::with_token(&vault_config(KvVersion::V1), "s.token").unwrap();
+    let missing = mock("GET", "/v1/secret/dev-uk/mismatch-svc/FAKE_SECRET")
+        .with_status(404)
+        .with_body(r#"{"errors": []}"#)
+        .create();
+    assert!(v.read("dev-uk/mismatch-svc/FAKE_SECRET").is_err());
+    missing.assert();
+}
+
+fn login_config(auth: VaultAuth) -> VaultConfig {
+    VaultConfig { auth, ..vault_config(KvVersion::V1) }
+}
+
+#[test]
+fn vault_approle_login_is_cached() {
+    env::set_var("VAULT_ROLE_ID", "role-123");
+    env::set_var("VAULT_SECRET_ID", "secret-456");
+    let vc = login_config(VaultAuth::AppRole { mount: "approle-cached".into() });
+
+    let login = mock("POST", "/v1/auth/approle-cached/login")
+        .match_body(Matcher::Regex(r#""role_id":"role-123""#.into()))
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"auth": {"client_token": "s.approle", "lease_duration": 3600, "renewable": true}}"#)
+        .expect(1)
+        .create();
+    let secret = mock("GET", Matcher::Regex(r"^/v1/secret/dev-uk/approle-svc/FAKE_\w+$".into()))
+        .match_header("X-Vault-Token", "s.approle")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"value": "hello"}, "lease_duration": 0}"#)
+        .expect(2)
+        .create();
+
+    // separate clients share the token
+    for key in &["FAKE_SECRET", "FAKE_NUMBER"] {
+        let v = Vault::regional(&vc).unwrap();
+        assert_eq!(v.read(&format!("dev-uk/approle-svc/{}", key)).unwrap(), "hello");
+    }
+    login.assert();
+    secret.assert();
+}
+
+#[test]
+fn vault_kubernetes_login_renews() {
+    let jwt = env::temp_dir().join("shipcat-test-sa-token");
+    fs::write(&jwt, "eyJhbGciOiJSUzI1NiJ9.fake\n").unwrap();
+    let vc = login_config(VaultAuth::Kubernetes {
+        role: "shipcat".into(),
+        mount: "kubernetes-renew".into(),
+        jwtPath: jwt.display().to_string(),
+    });
+
+    let login = mock("POST", "/v1/auth/kubernetes-renew/login")
+        .match_body(Matcher::Regex(r#""jwt":"eyJhbGciOiJSUzI1NiJ9.fake""#.into()))
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"auth": {"client_token": "s.k8s", "lease_duration": 90, "renewable": true}}"#)
+        .expect(1)
+        .create();
+    let renew = mock("POST", "/v1/auth/token/renew-self")
+        .match_header("X-Vault-Token", "s.k8s")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"auth": {"client_token": "s.k8s", "lease_duration": 3600, "renewable": true}}"#)
+        .expect(1)
+        .create();
+    let secret = mock("GET", Matcher::Regex(r"^/v1/secret/dev-uk/k8s-svc/SECRET_\d$".into()))
+        .match_header("X-Vault-Token", "s.k8s")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": {"value": "hello"}, "lease_duration": 0}"#)
+        .expect(3)
+        .create();
+
+    // different secrets each time; the secrets themselves are cached
+    let elapsed = Arc::new(AtomicUsize::new(0));
+    let clock = elapsed.clone();
+    let start = Instant::now();
+    let v = Vault::regional(&vc).unwrap()
+        .with_clock(move || start + Duration::from_secs(clock.load(Ordering::SeqCst) as u64));
+    assert_eq!(v.read("dev-uk/k8s-svc/SECRET_1").unwrap(), "hello");
+    // less than a third of the lease left
+    elapsed.store(61, Ordering::SeqCst);
+    assert_eq!(v.read("dev-uk/k8s-svc/SECRET_2").unwrap(), "hello");
+    // renewed lease is long enough
+    assert_eq!(v.read("dev-uk/k8s-svc/SECRET_3").unwrap(), "hello");
+
+    login.assert();
+    renew.assert();
+    secret.assert();
+}
+
+#[test]
+fn vault_login_failure() {
+    env::set_var("VAULT_ROLE_ID", "role-123");
+    env::set_var("VAULT_SECRET_ID", "secret-456");
+    let vc = login_config(VaultAuth::AppRole { mount: "approle-denied".into() });
+    let login = mock("POST", "/v1/auth/approle-denied/login")
+        .with_status(400)
+        .with_body(r#"{"errors": ["invalid secret id"]}"#)
+        .create();
+
+    let v = Vault::regional(&vc).unwrap();
+    let err = v.read("dev-uk/denied-svc/FAKE_SECRET").unwrap_err();
+    assert!(err.iter().any(|e| e.to_string() == "vault approle login failed"));
+    login.assert();
+}
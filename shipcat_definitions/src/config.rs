@@ -12,13 +12,14 @@ use crate::structs::SlackChannel;
 #[allow(unused_imports)]
 use super::{Result, Error};
 use super::structs::{Contact};
+use super::Diagnostics;
 use crate::states::ConfigType;
 use crate::region::Region;
 
 // ----------------------------------------------------------------------------------
 
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ManifestDefaults {
     /// Image prefix string
@@ -43,7 +44,7 @@ impl Default for ManifestDefaults {
 
 
 /// Kubernetes cluster information
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Cluster {
     /// Name of the cluster
@@ -54,7 +55,7 @@ pub struct Cluster {
     pub regions: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct Team {
     /// Team name
     pub name: String,
@@ -69,7 +70,7 @@ pub struct Team {
     pub notifications: Option<SlackChannel>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Location {
     /// Location name
@@ -82,7 +83,7 @@ pub struct Location {
     pub local_region: String,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct GithubParameters {
     /// Location name
@@ -90,7 +91,7 @@ pub struct GithubParameters {
 }
 
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct SlackParameters {
     /// Location name
@@ -102,7 +103,7 @@ pub struct SlackParameters {
 
 
 /// Main manifest, serializable from shipcat.yml
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     /// Global defaults for the manifests
@@ -136,6 +137,7 @@ pub struct Config {
     pub teams: Vec<Team>,
 
     /// Shipcat version pin
+    #[schemars(with = "String")]
     pub version: Version,
 
     // Internal state of the config
@@ -208,7 +210,11 @@ impl Config {
         }
         for t in &self.teams {
             for o in &t.owners {
-                o.verify()?; // not very strict
+                let mut d = Diagnostics::new();
+                o.verify(&mut d); // not very strict
+                if let Some(e) = d.errors().first() {
+                    bail!("Invalid owner {} for team {}: {}", o.name, t.name, e.message);
+                }
                 // verify optionals filled in for owners:
                 if o.github.is_none() {
                     bail!("Every owner must have a github id attached");
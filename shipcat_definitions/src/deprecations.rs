@@ -0,0 +1,56 @@
+use super::Manifest;
+
+/// A deprecated manifest field
+///
+/// `shipcat validate` warns about every registered deprecation in use,
+/// and `shipcat migrate` rewrites the ones it knows how to.
+pub struct Deprecation {
+    /// Manifest key that is deprecated
+    pub field: &'static str,
+    /// What to use instead
+    pub replacement: &'static str,
+    /// Shipcat version where the field will be removed
+    pub removal: &'static str,
+    /// Whether `shipcat migrate` can rewrite this automatically in most cases
+    pub migratable: bool,
+    /// Whether a completed manifest uses the field
+    pub used: fn(&Manifest) -> bool,
+}
+
+/// All current deprecations
+pub const DEPRECATIONS: &[Deprecation] = &[
+    Deprecation {
+        field: "health",
+        replacement: "readinessProbe",
+        removal: "0.90.0",
+        migratable: true,
+        used: |mf| mf.health.is_some(),
+    },
+    Deprecation {
+        field: "vault",
+        replacement: "`IN_VAULT:service/KEY` references",
+        removal: "0.90.0",
+        migratable: true,
+        used: |mf| mf.vault.is_some(),
+    },
+    Deprecation {
+        field: "publiclyAccessible",
+        replacement: "gate.public",
+        removal: "0.90.0",
+        migratable: true,
+        used: |mf| mf.publiclyAccessible.is_some(),
+    },
+];
+
+impl Deprecation {
+    /// Warning message for a service using this field
+    pub fn message(&self, svc: &str) -> String {
+        let fix = if self.migratable {
+            format!(" (run `shipcat migrate {}`)", svc)
+        } else {
+            "".into()
+        };
+        format!("`{}` is deprecated and will be removed in shipcat {} - use {} instead{}",
+            self.field, self.removal, self.replacement, fix)
+    }
+}
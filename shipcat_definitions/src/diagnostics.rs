@@ -0,0 +1,334 @@
+use serde::ser::{Serialize, Serializer};
+use std::fmt;
+use std::path::Path;
+
+use super::Result;
+
+/// How serious a diagnostic is
+///
+/// Errors fail validation, warnings are only reported.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Stable codes for classes of validation problems
+///
+/// These are shown to users and matched against in CI, so codes must never
+/// be renumbered or reused. Add new variants at the end of their block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Code {
+    // ------------------------------------------------------------------------
+    // Errors
+    // ------------------------------------------------------------------------
+
+    /// Service is not configured for the region being validated
+    UnsupportedRegion,
+    /// Name does not follow naming conventions
+    InvalidName,
+    /// A mandatory field is not set
+    MissingField,
+    /// Fields that cannot be used together are both set
+    ConflictingFields,
+    /// A value is not in the accepted format
+    InvalidValue,
+    /// A numeric value or size is outside the accepted range
+    OutOfRange,
+    /// A value references a team, service or file that does not exist
+    UnknownReference,
+    /// A property that `implicits` should have set is missing (shipcat bug)
+    MissingImplicit,
+
+    // ------------------------------------------------------------------------
+    // Warnings
+    // ------------------------------------------------------------------------
+
+    /// A recommended field is not set
+    MissingRecommended,
+    /// An experimental feature is used
+    Experimental,
+    /// Personally identifiable information is stored without encryption
+    UnencryptedPii,
+    /// Validation is limited because the service runs outside kubernetes
+    ExternalService,
+    /// A deprecated field is used
+    Deprecated,
+}
+
+impl Code {
+    /// The stable identifier for this code
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::UnsupportedRegion => "E001",
+            Code::InvalidName => "E002",
+            Code::MissingField => "E003",
+            Code::ConflictingFields => "E004",
+            Code::InvalidValue => "E005",
+            Code::OutOfRange => "E006",
+            Code::UnknownReference => "E007",
+            Code::MissingImplicit => "E008",
+
+            Code::MissingRecommended => "W001",
+            Code::Experimental => "W002",
+            Code::UnencryptedPii => "W003",
+            Code::ExternalService => "W004",
+            Code::Deprecated => "W005",
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Serialize for Code {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// A single validation problem
+#[derive(Serialize, Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    /// Path to the offending field, e.g. `workers[1].resources.limits.cpu`
+    ///
+    /// Empty if the problem concerns the manifest as a whole.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+        } else {
+            write!(f, "{}[{}] {}: {}", self.severity, self.code, self.path, self.message)
+        }
+    }
+}
+
+/// A collector of diagnostics
+///
+/// Verifiers push errors and warnings into this rather than bailing,
+/// so that every problem in a manifest is reported in one go.
+/// The collector keeps track of where in the manifest it currently is:
+///
+/// ```rust,ignore
+/// d.each("workers", &self.workers, |d, w| w.verify(d));
+/// // inside Worker::verify
+/// d.nested("resources", |d| self.resources.verify(d));
+/// // inside Resources::verify
+/// d.error(Code::OutOfRange, "limits.cpu", "CPU limit set to more than 36 cores");
+/// // => error[E006] workers[1].resources.limits.cpu: CPU limit set to more than 36 cores
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics {
+    scope: Vec<String>,
+    entries: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Diagnostics::default()
+    }
+
+    /// Run verifications under a sub-path
+    pub fn nested<S, F>(&mut self, field: S, f: F)
+    where
+        S: Into<String>,
+        F: FnOnce(&mut Diagnostics),
+    {
+        self.scope.push(field.into());
+        f(self);
+        self.scope.pop();
+    }
+
+    /// Run verifications for every element of a list field
+    pub fn each<T, F>(&mut self, field: &str, xs: &[T], mut f: F)
+    where
+        F: FnMut(&mut Diagnostics, &T),
+    {
+        self.nested(field, |d| {
+            for (i, x) in xs.iter().enumerate() {
+                d.nested(format!("[{}]", i), |d| f(d, x));
+            }
+        });
+    }
+
+    /// Record an error for a field relative to the current scope
+    ///
+    /// Pass an empty field to refer to the current scope itself.
+    pub fn error<S: Into<String>>(&mut self, code: Code, field: &str, msg: S) {
+        self.push(Severity::Error, code, field, msg.into())
+    }
+
+    /// Record a warning for a field relative to the current scope
+    pub fn warn<S: Into<String>>(&mut self, code: Code, field: &str, msg: S) {
+        self.push(Severity::Warning, code, field, msg.into())
+    }
+
+    /// Record a failed `Result` from a helper as an error
+    pub fn check<T>(&mut self, code: Code, field: &str, res: Result<T>) -> Option<T> {
+        match res {
+            Ok(x) => Some(x),
+            Err(e) => {
+                self.error(code, field, e.to_string());
+                None
+            }
+        }
+    }
+
+    fn push(&mut self, severity: Severity, code: Code, field: &str, message: String) {
+        let path = join_path(self.scope.iter().map(String::as_str).chain(Some(field)));
+        self.entries.push(Diagnostic { severity, code, path, message });
+    }
+
+    /// All diagnostics in the order they were found
+    pub fn entries(&self) -> &[Diagnostic] {
+        &self.entries
+    }
+
+    pub fn errors(&self) -> Vec<&Diagnostic> {
+        self.entries.iter().filter(|d| d.severity == Severity::Error).collect()
+    }
+
+    pub fn warnings(&self) -> Vec<&Diagnostic> {
+        self.entries.iter().filter(|d| d.severity == Severity::Warning).collect()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.entries.iter().any(|d| d.severity == Severity::Error)
+    }
+
+    /// Log warnings and convert to an error if anything failed
+    pub fn into_result(self, svc: &str) -> Result<()> {
+        for w in self.warnings() {
+            warn!("{}: {}", svc, w);
+        }
+        if self.has_errors() {
+            let errs = self.entries.into_iter().filter(|d| d.severity == Severity::Error).collect();
+            bail!(super::ErrorKind::ManifestDiagnostics(svc.into(), errs));
+        }
+        Ok(())
+    }
+}
+
+/// Join path segments, attaching index segments like `[1]` without a dot
+fn join_path<'a, I: Iterator<Item = &'a str>>(segments: I) -> String {
+    let mut res = String::new();
+    for s in segments.filter(|s| !s.is_empty()) {
+        if !res.is_empty() && !s.starts_with('[') {
+            res.push('.');
+        }
+        res.push_str(s);
+    }
+    res
+}
+
+/// A position in a manifest file along with the offending line
+#[derive(Serialize, Clone, Debug)]
+pub struct SourceLocation {
+    pub file: String,
+    /// One-based line number
+    pub line: usize,
+    /// One-based column number
+    pub column: usize,
+    /// The source line at `line`
+    pub snippet: String,
+}
+
+impl SourceLocation {
+    pub fn new(file: &Path, src: &str, line: usize, column: usize) -> Self {
+        let snippet = src.lines().nth(line.saturating_sub(1)).unwrap_or("").to_string();
+        SourceLocation { file: file.display().to_string(), line, column, snippet }
+    }
+
+    /// Locate a top level key in a yaml file
+    ///
+    /// Good enough for the keys that are checked during merges, which are all top level.
+    pub fn find_key(file: &Path, src: &str, key: &str) -> Option<Self> {
+        let prefix = format!("{}:", key);
+        src.lines()
+            .position(|l| l.starts_with(&prefix))
+            .map(|i| SourceLocation::new(file, src, i + 1, 1))
+    }
+}
+
+/// Renders as a rustc style snippet:
+///
+/// ```text
+///  --> services/fake-ask/dev-uk.yml:3:1
+///   |
+/// 3 | kong:
+///   | ^
+/// ```
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lineno = self.line.to_string();
+        let pad = " ".repeat(lineno.len());
+        writeln!(f, "{} --> {}:{}:{}", pad, self.file, self.line, self.column)?;
+        writeln!(f, "{} |", pad)?;
+        writeln!(f, "{} | {}", lineno, self.snippet)?;
+        write!(f, "{} | {}^", pad, " ".repeat(self.column.saturating_sub(1)))
+    }
+}
+
+/// Render a list of diagnostics one per line
+pub fn render(diags: &[Diagnostic]) -> String {
+    diags.iter().map(|d| format!("  {}", d)).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Code, Diagnostics, Severity, SourceLocation};
+    use std::path::Path;
+
+    #[test]
+    fn paths_are_nested() {
+        let mut d = Diagnostics::new();
+        d.error(Code::InvalidName, "name", "bad name");
+        d.each("workers", &[1, 2], |d, x| {
+            d.nested("resources", |d| {
+                if *x == 2 {
+                    d.error(Code::OutOfRange, "limits.cpu", "too many cores");
+                }
+            });
+        });
+        d.warn(Code::MissingRecommended, "", "no health check");
+
+        let paths = d.entries().iter().map(|e| e.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["name", "workers[1].resources.limits.cpu", ""]);
+        assert_eq!(d.errors().len(), 2);
+        assert_eq!(d.warnings()[0].severity, Severity::Warning);
+        assert_eq!(d.errors()[1].to_string(), "error[E006] workers[1].resources.limits.cpu: too many cores");
+        assert!(d.into_result("fake-ask").is_err());
+    }
+
+    #[test]
+    fn source_snippets() {
+        let src = "name: fake-ask\nkong:\n  uris: /ask\n";
+        let pth = Path::new("services/fake-ask/shipcat.yml");
+        let loc = SourceLocation::find_key(pth, src, "kong").unwrap();
+        assert_eq!((loc.line, loc.column), (2, 1));
+        assert_eq!(loc.to_string(), "  --> services/fake-ask/shipcat.yml:2:1\n  |\n2 | kong:\n  | ^");
+        assert!(SourceLocation::find_key(pth, src, "uris").is_none()); // not top level
+
+        let nested = SourceLocation::new(pth, src, 3, 3);
+        assert_eq!(nested.snippet, "  uris: /ask");
+    }
+}
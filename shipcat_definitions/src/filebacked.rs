@@ -1,10 +1,12 @@
+use std::collections::BTreeMap;
 use std::io::prelude::*;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
 use super::{Config, Region, Manifest};
-use super::Result;
+use super::{Result, Error, ErrorKind};
+use crate::diagnostics::SourceLocation;
 use crate::states::{ManifestType};
 
 /// Private helpers for a filebacked Manifest Backend
@@ -16,36 +18,55 @@ impl Manifest {
         if !mpath.exists() {
             bail!("Manifest file {} does not exist", mpath.display())
         }
-        let mut f = File::open(&mpath)?;
-        let mut data = String::new();
-        f.read_to_string(&mut data)?;
-        Ok(serde_yaml::from_str(&data)?)
+        let data = read_file(&mpath)?;
+        let mf = parse_manifest(&mpath, &data)?;
+        let chain = base_chain(&mf, load_base)?;
+        Ok(extend_bases(mf, chain))
     }
 
 
+    /// Override files merged on top of shipcat.yml, in merge order
+    ///
+    /// The environment file (e.g. `dev.yml`) is shared by every region in that environment,
+    /// and the region file (e.g. `dev-uk.yml`) applies to a single region.
+    fn override_files(service: &str, region: &Region) -> Vec<PathBuf> {
+        let svcdir = Path::new(".").join("services").join(service);
+        let mut names = vec![&region.environment, &region.name];
+        names.dedup();
+        names.into_iter()
+            .filter(|n| !n.is_empty())
+            .map(|n| svcdir.join(format!("{}.yml", n)))
+            .filter(|p| p.is_file())
+            .collect()
+    }
+
     /// Fill in env overrides and apply merge rules
     fn merge_and_fill_defaults(&mut self, conf: &Config, region: &Region) -> Result<()> {
-        // merge service specific env overrides if they exists
-        let envlocals = Path::new(".")
-            .join("services")
-            .join(&self.name)
-            .join(format!("{}.yml", region.name));
-        if envlocals.is_file() {
-            debug!("Merging environment locals from {}", envlocals.display());
-            if !envlocals.exists() {
-                bail!("Defaults file {} does not exist", envlocals.display())
-            }
-            let mut f = File::open(&envlocals)?;
-            let mut data = String::new();
-            f.read_to_string(&mut data)?;
+        let mainpth = Path::new(".").join("services").join(&self.name).join("shipcat.yml");
+        // files merged so far, for locating conflicts
+        let mut merged = vec![(mainpth.clone(), read_file(&mainpth).unwrap_or_default())];
+        let main_kong = self.kong.is_some();
+        // merge environment and region specific overrides if they exist
+        let overrides = Manifest::override_files(&self.name, region);
+        for pth in &overrides {
+            debug!("Merging overrides from {}", pth.display());
+            let data = read_file(pth)?;
             if data.is_empty() {
-                bail!("Environment override file {} is empty", envlocals.display());
+                bail!("Override file {} is empty", pth.display());
             }
             // Because Manifest has most things implementing Default via serde
             // we can put this straight into a Manifest struct
-            let other: Manifest = serde_yaml::from_str(&data)?;
-
-            self.merge(other)?;
+            let other = parse_manifest(pth, &data)?;
+            if main_kong && other.kong.is_some() {
+                // Must override Kong per environment or region (overwrite full struct)
+                let mains = Manifest::layers(&self.name, region)?.into_iter()
+                    .filter(|p| !overrides.contains(p))
+                    .map(|p| read_file(&p).map(|d| (p, d)))
+                    .collect::<Result<Vec<_>>>()?;
+                bail!(kong_conflict(&mains, pth, &data));
+            }
+            merged.push((pth.clone(), data));
+            self.merge(other).map_err(|e| locate_conflict(e, &merged))?;
         }
         self.add_config_defaults(&conf)?;
         self.add_region_implicits(region)?;
@@ -53,6 +74,100 @@ impl Manifest {
     }
 }
 
+fn read_file(pth: &Path) -> Result<String> {
+    let mut f = File::open(pth)?;
+    let mut data = String::new();
+    f.read_to_string(&mut data)?;
+    Ok(data)
+}
+
+/// Deserialize a manifest file, pointing to the failing line in errors
+fn parse_manifest(pth: &Path, data: &str) -> Result<Manifest> {
+    serde_yaml::from_str(data).map_err(|e| {
+        let loc = e.location().map(|l| SourceLocation::new(pth, data, l.line(), l.column()));
+        ErrorKind::InvalidManifestFile(pth.display().to_string(), e.to_string(), loc).into()
+    })
+}
+
+/// Read a base manifest from the bases folder
+fn load_base(name: &str) -> Result<(PathBuf, String)> {
+    let pth = Path::new(".").join("bases").join(format!("{}.yml", name));
+    if !pth.is_file() {
+        bail!("Base manifest {} does not exist", pth.display())
+    }
+    let data = read_file(&pth)?;
+    Ok((pth, data))
+}
+
+/// Resolve the chain of bases a manifest extends, nearest base first
+///
+/// Fails on cycles, and on bases setting properties that belong to a single service.
+fn base_chain<F>(mf: &Manifest, load: F) -> Result<Vec<(PathBuf, Manifest)>>
+where
+    F: Fn(&str) -> Result<(PathBuf, String)>,
+{
+    let mut seen = vec![mf.name.clone()];
+    let mut chain = vec![];
+    let mut next = mf.extends.clone();
+    while let Some(name) = next {
+        if seen[1..].contains(&name) {
+            seen.push(name);
+            bail!("Cyclic extends: {}", seen.join(" -> "));
+        }
+        let (pth, data) = load(&name)?;
+        let base = parse_manifest(&pth, &data)?;
+        for &(key, set) in &[("name", !base.name.is_empty()), ("metadata", base.metadata.is_some())] {
+            if set {
+                let loc = SourceLocation::find_key(&pth, &data, key);
+                let msg = format!("Base manifests cannot set {}", key);
+                bail!(ErrorKind::InvalidManifestFile(pth.display().to_string(), msg, loc));
+            }
+        }
+        next = base.extends.clone();
+        seen.push(name);
+        chain.push((pth, base));
+    }
+    Ok(chain)
+}
+
+/// Merge a manifest on top of the chain of bases it extends
+fn extend_bases(mf: Manifest, chain: Vec<(PathBuf, Manifest)>) -> Manifest {
+    // start from the base at the bottom of the chain
+    let mut layers = chain.into_iter().rev().map(|(_, base)| base);
+    match layers.next() {
+        None => mf,
+        Some(mut res) => {
+            for base in layers {
+                res.merge_extension(base);
+            }
+            res.merge_extension(mf);
+            res
+        }
+    }
+}
+
+/// Conflict of kong in an override file with kong in the main manifest or its bases
+fn kong_conflict(mains: &[(PathBuf, String)], pth: &Path, data: &str) -> Error {
+    let mut locs = mains.iter()
+        .filter_map(|(p, d)| SourceLocation::find_key(p, d, "kong"))
+        .collect::<Vec<_>>();
+    let main = locs.first().map(|l| l.file.clone()).unwrap_or_else(|| "shipcat.yml".into());
+    let msg = format!("Cannot have kong in both {} and {}", main, pth.display());
+    locs.extend(SourceLocation::find_key(pth, data, "kong"));
+    ErrorKind::MergeConflict("kong".into(), msg, locs).into()
+}
+
+/// Point merge conflicts at the key in every merged file that sets it
+fn locate_conflict(e: Error, merged: &[(PathBuf, String)]) -> Error {
+    if let ErrorKind::MergeConflict(key, msg, _) = e.kind() {
+        let locs = merged.iter()
+            .filter_map(|(pth, data)| SourceLocation::find_key(pth, data, key))
+            .collect();
+        return ErrorKind::MergeConflict(key.clone(), msg.clone(), locs).into();
+    }
+    e
+}
+
 fn walk_services() -> Vec<String> {
     let svcsdir = Path::new(".").join("services");
     let mut res : Vec<_> = WalkDir::new(&svcsdir)
@@ -81,7 +196,7 @@ impl Manifest {
         let mut xs = vec![];
         for svc in walk_services() {
             let mf = Manifest::blank(&svc)?;
-            if mf.regions.contains(&region.to_string()) && !mf.disabled && !mf.external {
+            if mf.regions.contains(&region.to_string()) && !mf.disabled.unwrap_or(false) && !mf.external.unwrap_or(false) {
                 xs.push(svc);
             }
         }
@@ -101,6 +216,20 @@ impl Manifest {
         Ok(mf)
     }
 
+    /// Files a manifest is built from in a region, in merge order
+    ///
+    /// Starts with any bases the main shipcat.yml extends, followed by shipcat.yml itself,
+    /// then any environment and region overrides.
+    pub fn layers(service: &str, region: &Region) -> Result<Vec<PathBuf>> {
+        let mainpth = Path::new(".").join("services").join(service).join("shipcat.yml");
+        let mf = parse_manifest(&mainpth, &read_file(&mainpth)?)?;
+        let chain = base_chain(&mf, load_base)?;
+        let mut res = chain.into_iter().rev().map(|(pth, _)| pth).collect::<Vec<_>>();
+        res.push(mainpth);
+        res.extend(Manifest::override_files(service, region));
+        Ok(res)
+    }
+
     /// Return all services found in the manifests services folder
     pub fn all() -> Result<Vec<String>> {
         Ok(walk_services())
@@ -116,11 +245,29 @@ impl Manifest {
         }
         let mf = Manifest::read_from(&pth)?;
         if mf.name != service {
-            bail!("Service name must equal the folder name");
+            let mpath = pth.join("shipcat.yml");
+            let loc = SourceLocation::find_key(&mpath, &read_file(&mpath)?, "name");
+            let msg = "Service name must equal the folder name".into();
+            bail!(ErrorKind::InvalidManifestFile(mpath.display().to_string(), msg, loc));
         }
         Ok(mf)
     }
 
+    /// Stub values for secrets from the optional `stubs.yml` of a service
+    ///
+    /// A flat map from env var or secret file names to the values used in stubbed manifests.
+    pub(crate) fn read_stubs(service: &str) -> Result<BTreeMap<String, String>> {
+        let pth = Path::new(".").join("services").join(service).join("stubs.yml");
+        if !pth.is_file() {
+            return Ok(BTreeMap::new());
+        }
+        let data = read_file(&pth)?;
+        serde_yaml::from_str(&data).map_err(|e| {
+            let loc = e.location().map(|l| SourceLocation::new(&pth, &data, l.line(), l.column()));
+            ErrorKind::InvalidManifestFile(pth.display().to_string(), e.to_string(), loc).into()
+        })
+    }
+
     /// Create a simple manifest that has enough for most reducers
     pub fn simple(service: &str, conf: &Config, reg: &Region) -> Result<Manifest> {
         let mut mf = Manifest::blank(service)?;
@@ -130,3 +277,120 @@ impl Manifest {
         Ok(mf)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use super::{parse_manifest, locate_conflict, kong_conflict, base_chain, extend_bases};
+    use crate::ErrorKind;
+
+    #[test]
+    fn parse_errors_are_located() {
+        let pth = Path::new("services/fake-ask/dev-uk.yml");
+        let err = parse_manifest(pth, "replicaCount: 2\nnotAField: true\n").err().unwrap();
+        match err.kind() {
+            ErrorKind::InvalidManifestFile(file, _, Some(loc)) => {
+                assert_eq!(file, "services/fake-ask/dev-uk.yml");
+                assert_eq!(loc.line, 2);
+                assert_eq!(loc.snippet, "notAField: true");
+            }
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn merge_conflicts_are_located() {
+        let mainpth = PathBuf::from("services/fake-ask/shipcat.yml");
+        let maindata = "name: fake-ask\nregions:\n- dev-uk\n";
+        let pth = PathBuf::from("services/fake-ask/dev.yml");
+        let data = "replicaCount: 2\n\nregions:\n- dev-uk\n";
+        let mut main = parse_manifest(&mainpth, maindata).unwrap();
+        let other = parse_manifest(&pth, data).unwrap();
+        let merged = vec![(mainpth, maindata.to_string()), (pth, data.to_string())];
+        let err = locate_conflict(main.merge(other).err().unwrap(), &merged);
+        match err.kind() {
+            ErrorKind::MergeConflict(key, _, locs) => {
+                assert_eq!(key, "regions");
+                assert_eq!(locs.len(), 2);
+                assert_eq!(locs[0].line, 2);
+                assert_eq!(locs[1].line, 3);
+                assert_eq!(locs[1].file, "services/fake-ask/dev.yml");
+            }
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn kong_conflicts_name_both_files() {
+        let mains = vec![
+            (PathBuf::from("bases/web.yml"), "kong:\n  uris: /web\n".to_string()),
+            (PathBuf::from("services/fake-ask/shipcat.yml"), "name: fake-ask\n".to_string()),
+        ];
+        let pth = Path::new("services/fake-ask/dev-uk.yml");
+        let err = kong_conflict(&mains, pth, "replicaCount: 2\nkong:\n  uris: /ask\n");
+        assert_eq!(err.to_string().lines().next().unwrap(),
+            "Cannot have kong in both bases/web.yml and services/fake-ask/dev-uk.yml");
+        match err.kind() {
+            ErrorKind::MergeConflict(_, _, locs) => assert_eq!(locs.len(), 2),
+            _ => panic!("unexpected error {}", err),
+        }
+
+        // environment and region files both setting kong are merged instead
+        let env = parse_manifest(Path::new("services/fake-ask/dev.yml"), "kong:\n  uris: /ask\n").unwrap();
+        let reg = parse_manifest(pth, "kong:\n  strip_uri: false\n").unwrap();
+        let mut mf = parse_manifest(&mains[1].0, &mains[1].1).unwrap();
+        mf.merge(env).unwrap();
+        mf.merge(reg).unwrap();
+        let kong = mf.kong.unwrap();
+        assert_eq!(kong.uris, Some("/ask".into()));
+        assert_eq!(kong.strip_uri, Some(false));
+    }
+
+    fn bases(name: &str) -> crate::Result<(PathBuf, String)> {
+        let data = match name {
+            "web" => "extends: probes\nreplicaCount: 2\nenv:\n  A: web\n  B: web\n",
+            "probes" => "readinessProbe:\n  httpGet:\n    path: /health\n    port: http\nenv:\n  A: probes\n",
+            "loop" => "extends: loop-back\n",
+            "loop-back" => "extends: loop\n",
+            "named" => "name: fake-ask\nreplicaCount: 1\n",
+            _ => bail!("Base manifest {} does not exist", name),
+        };
+        Ok((PathBuf::from(format!("bases/{}.yml", name)), data.into()))
+    }
+
+    #[test]
+    fn bases_are_extended() {
+        let pth = PathBuf::from("services/fake-ask/shipcat.yml");
+        let mf = parse_manifest(&pth, "name: fake-ask\nextends: web\nenv:\n  B: svc\n").unwrap();
+        let chain = base_chain(&mf, bases).unwrap();
+        let names = chain.iter().map(|(p, _)| p.display().to_string()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["bases/web.yml", "bases/probes.yml"]);
+
+        let mf = extend_bases(mf, chain);
+        assert_eq!(mf.name, "fake-ask");
+        assert_eq!(mf.replicaCount, Some(2));
+        assert!(mf.readinessProbe.is_some());
+        assert_eq!(mf.env.plain["A"], "web");
+        assert_eq!(mf.env.plain["B"], "svc");
+    }
+
+    #[test]
+    fn invalid_bases_are_rejected() {
+        let pth = PathBuf::from("services/fake-ask/shipcat.yml");
+        let cyclic = parse_manifest(&pth, "name: fake-ask\nextends: loop\n").unwrap();
+        let err = base_chain(&cyclic, bases).err().unwrap();
+        assert_eq!(err.to_string(), "Cyclic extends: fake-ask -> loop -> loop-back -> loop");
+
+        let named = parse_manifest(&pth, "name: fake-ask\nextends: named\n").unwrap();
+        match base_chain(&named, bases).err().unwrap().kind() {
+            ErrorKind::InvalidManifestFile(file, _, Some(loc)) => {
+                assert_eq!(file, "bases/named.yml");
+                assert_eq!(loc.line, 1);
+            }
+            e => panic!("unexpected error {}", e),
+        }
+
+        let missing = parse_manifest(&pth, "name: fake-ask\nextends: nope\n").unwrap();
+        assert!(base_chain(&missing, bases).is_err());
+    }
+}
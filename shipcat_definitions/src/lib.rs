@@ -5,6 +5,8 @@
 
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate log;
+#[macro_use] extern crate schemars;
+#[macro_use] extern crate lazy_static;
 
 /// The backing for manifests must come from the filesystem or the CRD
 /// This assert enforce that users of this library choses a feature.
@@ -41,6 +43,10 @@ error_chain! {
             description("VAULT_TOKEN not specified")
             display("VAULT_TOKEN not specified")
         }
+        VaultLoginFailure(method: String) {
+            description("vault login failed")
+            display("vault {} login failed", &method)
+        }
         UnexpectedHttpStatus(status: reqwest::StatusCode) {
             description("unexpected HTTP status")
             display("unexpected HTTP status: {}", &status)
@@ -61,9 +67,21 @@ error_chain! {
             description("manifest does not validate")
             display("manifest for {} does not validate", &svc)
         }
-        InvalidSecretForm(key: String) {
+        InvalidManifestFile(file: String, msg: String, loc: Option<crate::diagnostics::SourceLocation>) {
+            description("manifest file could not be parsed")
+            display("{}: {}{}", &file, &msg, loc.as_ref().map(|l| format!("\n{}", l)).unwrap_or_default())
+        }
+        MergeConflict(key: String, msg: String, locs: Vec<crate::diagnostics::SourceLocation>) {
+            description("manifest override conflicts with main manifest")
+            display("{}{}", &msg, locs.iter().map(|l| format!("\n{}", l)).collect::<String>())
+        }
+        ManifestDiagnostics(svc: String, diags: Vec<crate::diagnostics::Diagnostic>) {
+            description("manifest does not validate")
+            display("manifest for {} does not validate:\n{}", &svc, crate::diagnostics::render(diags))
+        }
+        InvalidSecretForm(path: String, key: String) {
             description("secret is of incorrect form")
-            display("secret '{}' not have the 'value' key", &key)
+            display("secret '{}' does not have the '{}' key", &path, &key)
         }
         SecretNotAccessible(key: String) {
             description("secret could not be reached or accessed")
@@ -74,12 +92,19 @@ error_chain! {
 
 /// Config with regional data
 pub mod region;
-pub use crate::region::{Region, VaultConfig, VersionScheme, KongConfig};
+pub use crate::region::{Region, VaultConfig, VaultAuth, KvVersion, HelmVersion, SecretBackendConfig, VersionScheme, KongConfig};
 /// Master config with cross-region data
 pub mod config;
 pub use crate::config::{Config, Cluster, Team, ManifestDefaults};
 
 
+/// Validation diagnostics with field paths and stable codes
+pub mod diagnostics;
+pub use crate::diagnostics::Diagnostics;
+
+/// Registry of deprecated manifest fields
+pub mod deprecations;
+
 /// Structs for the manifest
 pub mod structs;
 
@@ -98,8 +123,8 @@ pub use crate::states::{ConfigType};
 #[cfg(feature = "filesystem")]
 mod filebacked;
 
-// Merge behaviour for manifests
-mod merge;
+/// Merge behaviour for manifests and region overrides
+pub mod merge;
 
 /// Computational helpers
 pub mod math;
@@ -114,4 +139,8 @@ pub mod template;
 
 /// A Hashicorp Vault HTTP client using `reqwest`
 pub mod vault;
-pub use crate::vault::Vault;
+pub use crate::vault::{Vault, SecretRef};
+
+/// Secret backends that manifests are completed from
+pub mod secrets;
+pub use crate::secrets::{SecretBackend, LocalSecrets};
@@ -1,4 +1,5 @@
-use crate::vault::Vault;
+use crate::vault::SecretRef;
+use crate::secrets::SecretBackend;
 use std::collections::{BTreeMap, BTreeSet};
 use regex::Regex;
 
@@ -6,6 +7,8 @@ use crate::config::{Config};
 use crate::region::{VaultConfig, Region};
 use crate::states::ManifestType;
 use super::Result;
+use super::diagnostics::{Diagnostics, Code};
+use super::deprecations::DEPRECATIONS;
 
 // All structs come from the structs directory
 use super::structs::{
@@ -19,6 +22,8 @@ use super::structs::{
     {CronJob, Sidecar, EnvVars},
     {Gate, Kafka, Kong, Rbac},
     RollingUpdate,
+    Canary,
+    BlueGreen,
     autoscaling::AutoScaling,
     tolerations::Tolerations,
     LifeCycle,
@@ -29,7 +34,7 @@ use super::structs::{
 };
 
 /// Main manifest, serializable from shipcat.yml or the shipcat CRD.
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Manifest {
     // ------------------------------------------------------------------------
@@ -54,15 +59,28 @@ pub struct Manifest {
     #[serde(default)]
     pub name: String,
 
+    /// Base manifest to extend
+    ///
+    /// Loads `bases/{extends}.yml` next to the `services` folder and merges this
+    /// manifest on top of it, using the same rules as region overrides.
+    /// Bases can extend other bases, but cannot set `name` or `metadata`.
+    ///
+    /// ```yaml
+    /// extends: python-web
+    /// ```
+    #[serde(default, skip_serializing)]
+    pub extends: Option<String>,
+
     /// Whether the service should be public
     ///
     /// This is a special flag not exposed to the charts at the moment.
+    /// DEPRECATED. Should use `gate.public`.
     ///
     /// ```yaml
     /// publiclyAccessible: true
     /// ```
     #[serde(default, skip_serializing)]
-    pub publiclyAccessible: bool,
+    pub publiclyAccessible: Option<bool>,
 
     /// Service is external
     ///
@@ -72,7 +90,7 @@ pub struct Manifest {
     /// external: true
     /// ```
     #[serde(default, skip_serializing)]
-    pub external: bool,
+    pub external: Option<bool>,
 
     /// Service is disabled
     ///
@@ -82,7 +100,7 @@ pub struct Manifest {
     /// disabled: true
     /// ```
     #[serde(default, skip_serializing)]
-    pub disabled: bool,
+    pub disabled: Option<bool>,
 
     /// Regions to deploy this service to.
     ///
@@ -127,6 +145,8 @@ pub struct Manifest {
     /// ```yaml
     /// chart: custom
     /// ```
+    ///
+    /// The special `native` chart renders the kube objects in shipcat without helm.
     #[serde(default)]
     pub chart: Option<String>,
 
@@ -241,6 +261,7 @@ pub struct Manifest {
     ///
     /// These have a few special convenience behaviours:
     /// "IN_VAULT" values is replaced with value from vault/secret/folder/service/KEY
+    /// "IN_VAULT:path#key" values is replaced with the `key` of vault/secret/folder/path
     /// One off `tera` templates are calculated with a limited template context
     ///
     /// IN_VAULT secrets will all be put in a single kubernetes `Secret` object.
@@ -254,6 +275,9 @@ pub struct Manifest {
     ///   # vault lookup:
     ///   DATABASE_URL: IN_VAULT
     ///
+    ///   # shared vault lookup:
+    ///   POSTGRES_PASSWORD: IN_VAULT:shared/postgres#password
+    ///
     ///   # templated evars:
     ///   INTERNAL_AUTH_URL: "{{ base_urls.services }}/auth/internal"
     ///   AUTH_ID: "{{ kong.consumers['webapp'].oauth_client_id }}"
@@ -275,6 +299,7 @@ pub struct Manifest {
     ///
     /// These have the same special "IN_VAULT" behavior as `Manifest::env`:
     /// "IN_VAULT" values is replaced with value from vault/secret/folder/service/key
+    /// "IN_VAULT:path#key" values is replaced with the `key` of vault/secret/folder/path
     ///
     /// Note the lowercase restriction on keys.
     /// All `secretFiles` are expected to be base64 in vault, and are placed into a
@@ -284,6 +309,7 @@ pub struct Manifest {
     /// secretFiles:
     ///   webapp-ssl-keystore: IN_VAULT
     ///   webapp-ssl-truststore: IN_VAULT
+    ///   shared-ca-bundle: IN_VAULT:shared/ca#bundle
     /// ```
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub secretFiles: BTreeMap<String, String>,
@@ -473,6 +499,32 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub rollingUpdate: Option<RollingUpdate>,
 
+    /// Canary upgrade parameters
+    ///
+    /// How `shipcat helm upgrade --canary` runs the new version next to the old one
+    /// before promoting it. Region overrides are merged field by field.
+    ///
+    /// ```yaml
+    /// canary:
+    ///   percentage: 25
+    ///   bakeTime: 600
+    ///   maxRestarts: 1
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary: Option<Canary>,
+
+    /// Blue/green deployment parameters
+    ///
+    /// Upgrades bring up a full new colour next to the old one, and switch the `Service`
+    /// over to it once it is ready, instead of rolling the pods. Cannot be autoscaled.
+    ///
+    /// ```yaml
+    /// blueGreen:
+    ///   rollbackWindow: 7200
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blueGreen: Option<BlueGreen>,
+
     /// `HorizontalPodAutoScaler` parameters for kubernetes
     ///
     /// Passed all parameters directly onto the `spec` of a kube HPA.
@@ -768,155 +820,181 @@ impl Manifest {
 
     /// Verify assumptions about manifest
     ///
-    /// Assumes the manifest has been populated with `implicits`
+    /// Assumes the manifest has been populated with `implicits`.
+    /// Logs all warnings, and fails with every error found if any.
     pub fn verify(&self, conf: &Config, region: &Region) -> Result<()> {
+        self.diagnose(conf, region).into_result(&self.name)
+    }
+
+    /// Run all manifest verifications and collect the diagnostics
+    ///
+    /// Every check runs regardless of earlier failures, so that all problems
+    /// can be reported in one go with a path to the offending field.
+    pub fn diagnose(&self, conf: &Config, region: &Region) -> Diagnostics {
+        let mut d = Diagnostics::new();
+        self.verify_into(&mut d, conf, region);
+        d
+    }
+
+    fn verify_into(&self, d: &mut Diagnostics, conf: &Config, region: &Region) {
         assert!(self.region != ""); // needs to have been set by implicits!
         if !self.regions.contains(&self.region.to_string()) {
-            bail!("Unsupported region {} for service {}", self.region, self.name);
+            d.error(Code::UnsupportedRegion, "regions",
+                format!("Unsupported region {} for service {}", self.region, self.name));
         }
         // limit to 50 characters, alphanumeric, dashes for sanity.
         // 63 is kube dns limit (13 char suffix buffer)
         let re = Regex::new(r"^[0-9a-z\-]{1,50}$").unwrap();
         if !re.is_match(&self.name) {
-            bail!("Please use a short, lower case service names with dashes");
+            d.error(Code::InvalidName, "name", "Please use a short, lower case service names with dashes");
         }
         if self.name.ends_with('-') || self.name.starts_with('-') {
-            bail!("Please use dashes to separate words only");
+            d.error(Code::InvalidName, "name", "Please use dashes to separate words only");
         }
 
         if let Some(ref dh) = self.dataHandling {
-            dh.verify()?
+            d.nested("dataHandling", |d| dh.verify(d));
         } // TODO: mandatory for later environments!
 
         if let Some(ref md) = self.metadata {
-            md.verify(&conf.teams)?;
+            d.nested("metadata", |d| md.verify(d, &conf.teams));
         } else {
-            bail!("Missing metadata for {}", self.name);
+            d.error(Code::MissingField, "metadata", format!("Missing metadata for {}", self.name));
         }
 
-        if self.external {
-            warn!("Ignoring most validation for kube-external service {}", self.name);
-            return Ok(());
+        for dep in DEPRECATIONS {
+            if (dep.used)(self) {
+                d.warn(Code::Deprecated, dep.field, dep.message(&self.name));
+            }
+        }
+
+        if self.external.unwrap_or(false) {
+            d.warn(Code::ExternalService, "external",
+                format!("Ignoring most validation for kube-external service {}", self.name));
+            return;
         }
 
         if let Some(v) = &self.version {
-            region.versioningScheme.verify(v)?;
+            d.check(Code::InvalidValue, "version", region.versioningScheme.verify(v));
         }
 
         // TODO [DIP-499]: Separate gate/kong params + adjust the checks
         if let Some(g) = &self.gate {
             if self.kong.is_none() {
-                bail!("Can't have a `gate` configuration without a `kong` one");
+                d.error(Code::MissingField, "kong", "Can't have a `gate` configuration without a `kong` one");
             }
-            if g.public != self.publiclyAccessible {
-                bail!("[Migration plan] `publiclyAccessible` and `gate.public` must be equal");
+            // publiclyAccessible is deprecated, so only an explicit disagreement is an error
+            if self.publiclyAccessible.unwrap_or(false) && !g.public {
+                d.error(Code::ConflictingFields, "gate.public",
+                    "[Migration plan] `publiclyAccessible` requires `gate.public: true`");
             }
         }
 
-        // run the `Verify` trait on all imported structs
+        // run the verifiers on all imported structs
         // mandatory structs first
         if let Some(ref r) = self.resources {
-            r.verify()?;
+            d.nested("resources", |d| r.verify(d));
         } else {
-            bail!("Resources is mandatory");
+            d.error(Code::MissingField, "resources", "Resources is mandatory");
         }
 
         // optional/vectorised entries
-        for d in &self.dependencies {
-            d.verify()?;
-        }
-        for ha in &self.hostAliases {
-            ha.verify()?;
-        }
-        for tl in &self.tolerations {
-            tl.verify()?;
-        }
-        for ic in &self.initContainers {
-            ic.verify()?;
-        }
-        for wrk in &self.workers {
-            wrk.verify()?;
-        }
-        for s in &self.sidecars {
-            s.verify()?;
-        }
-        for c in &self.cronJobs {
-            c.verify()?;
-        }
-        for p in &self.ports {
-            p.verify()?;
-        }
-        for r in &self.rbac {
-            r.verify()?;
-        }
-        for pv in &self.persistentVolumes {
-            pv.verify()?;
-        }
+        d.each("dependencies", &self.dependencies, |d, x| x.verify(d));
+        d.each("hostAliases", &self.hostAliases, |d, x| x.verify(d));
+        d.each("tolerations", &self.tolerations, |d, x| x.verify(d));
+        d.each("initContainers", &self.initContainers, |d, x| x.verify(d));
+        d.each("workers", &self.workers, |d, x| x.verify(d));
+        d.each("sidecars", &self.sidecars, |d, x| x.verify(d));
+        d.each("cronJobs", &self.cronJobs, |d, x| x.verify(d));
+        d.each("ports", &self.ports, |d, x| x.verify(d));
+        d.each("rbac", &self.rbac, |d, x| x.verify(d));
+        d.each("persistentVolumes", &self.persistentVolumes, |d, x| x.verify(d));
         if let Some(ref cmap) = self.configs {
-            cmap.verify()?;
+            d.nested("configs", |d| cmap.verify(d));
         }
         // misc minor properties
-        if self.replicaCount.unwrap() == 0 {
-            bail!("Need replicaCount to be at least 1");
+        let replicas = self.replicaCount.unwrap();
+        if replicas == 0 {
+            d.error(Code::OutOfRange, "replicaCount", "Need replicaCount to be at least 1");
         }
         if let Some(ref ru) = &self.rollingUpdate {
-            ru.verify(self.replicaCount.unwrap())?;
+            d.nested("rollingUpdate", |d| ru.verify(d, replicas));
+        }
+        if let Some(ref c) = &self.canary {
+            d.nested("canary", |d| c.verify(d));
+        }
+        if let Some(ref bg) = &self.blueGreen {
+            d.nested("blueGreen", |d| bg.verify(d));
+            if self.autoScaling.is_some() {
+                d.error(Code::ConflictingFields, "blueGreen", "blueGreen services cannot use autoScaling");
+            }
+            if self.httpPort.is_none() {
+                d.error(Code::MissingField, "httpPort", "blueGreen services need an httpPort to switch traffic with");
+            }
         }
 
-        self.env.verify()?;
+        d.nested("env", |d| self.env.verify(d));
+        d.nested("secretFiles", |d| {
+            for (k, v) in &self.secretFiles {
+                if let Some(Err(e)) = SecretRef::parse(v) {
+                    d.error(Code::InvalidValue, k, e.to_string());
+                }
+            }
+        });
 
         // internal errors - implicits set these!
         if self.image.is_none() {
-            bail!("Image should be set at this point")
+            d.error(Code::MissingImplicit, "image", "Image should be set at this point")
         }
         if self.imageSize.is_none() {
-            bail!("imageSize must be set at this point");
+            d.error(Code::MissingImplicit, "imageSize", "imageSize must be set at this point");
         }
         if self.chart.is_none() {
-            bail!("chart must be set at this point");
+            d.error(Code::MissingImplicit, "chart", "chart must be set at this point");
         }
         if self.namespace == "" {
-            bail!("namespace must be set at this point");
+            d.error(Code::MissingImplicit, "namespace", "namespace must be set at this point");
         }
         if self.regions.is_empty() {
-            bail!("No regions specified for {}", self.name);
+            d.error(Code::MissingField, "regions", format!("No regions specified for {}", self.name));
         }
         if self.environment == "" {
-            bail!("Service {} ended up with an empty environment", self.name);
+            d.error(Code::MissingImplicit, "environment",
+                format!("Service {} ended up with an empty environment", self.name));
         }
         if self.namespace == "" {
-            bail!("Service {} ended up with an empty namespace", self.name);
+            d.error(Code::MissingImplicit, "namespace",
+                format!("Service {} ended up with an empty namespace", self.name));
         }
 
         // health check
         // every service that exposes http MUST have a health check
         if self.httpPort.is_some() && (self.health.is_none() && self.readinessProbe.is_none()) {
-            bail!("{} has an httpPort but no health check", self.name)
+            d.error(Code::MissingField, "readinessProbe", format!("{} has an httpPort but no health check", self.name))
         }
 
         // add some warnigs about missing health checks and ports regardless
         // TODO: make both mandatory once we have sidecars supported
         if self.httpPort.is_none() {
-            warn!("{} exposes no http port", self.name);
+            d.warn(Code::MissingRecommended, "httpPort", format!("{} exposes no http port", self.name));
         }
         if self.health.is_none() && self.readinessProbe.is_none() {
-            warn!("{} does not set a health check", self.name)
+            d.warn(Code::MissingRecommended, "readinessProbe", format!("{} does not set a health check", self.name))
         }
 
         if !self.serviceAnnotations.is_empty() {
-            warn!("serviceAnnotation is an experimental/temporary feature")
+            d.warn(Code::Experimental, "serviceAnnotations", "serviceAnnotation is an experimental/temporary feature")
         }
         if let Some(db) = &self.database {
-            db.verify()?;
+            d.nested("database", |d| db.verify(d));
         }
         if let Some(redis) = &self.redis {
-            redis.verify()?;
+            d.nested("redis", |d| redis.verify(d));
         }
-
-        Ok(())
     }
 
-    fn get_vault_path(&self, vc: &VaultConfig) -> String {
+    /// Vault folder holding the service's own secrets
+    pub fn get_vault_path(&self, vc: &VaultConfig) -> String {
         // some services use keys from other services
         let (svc, reg) = if let Some(ref vopts) = self.vault {
             (vopts.name.clone(), vopts.region.clone().unwrap_or_else(|| vc.folder.clone()))
@@ -942,19 +1020,21 @@ impl Manifest {
         envs
     }
 
-    /// Populate placeholder fields with secrets from vault
+    /// Move secret env vars out of the plain env vars and collect vault references
     ///
-    /// This will use the HTTP api of Vault using the configuration parameters
-    /// in the `Config`.
-    pub fn secrets(&mut self, client: &Vault, vc: &VaultConfig) -> Result<()> {
-        let pth = self.get_vault_path(vc);
-        debug!("Injecting secrets from vault {} ({:?})", pth, client.mode());
-
-        let mut vault_secrets = BTreeSet::new();
+    /// Templated secrets are moved straight into `Manifest::secrets`.
+    /// Returns the vault references of env vars and secret files, by name.
+    fn collect_secrets(&mut self) -> Result<(BTreeMap<String, SecretRef>, BTreeMap<String, SecretRef>)> {
+        let mut vault_secrets = BTreeMap::new();
         let mut template_secrets = BTreeMap::new();
         for e in &mut self.get_env_vars() {
-            for k in e.vault_secrets() {
-                vault_secrets.insert(k.to_string());
+            for (k, r) in e.vault_secrets()? {
+                // all containers share a single kubernetes `Secret`
+                if let Some(original) = vault_secrets.insert(k.to_string(), r.clone()) {
+                    if original != r {
+                        bail!("Secret {} can not reference different vault secrets in different containers", k);
+                    }
+                }
             }
             for (k, v) in e.template_secrets() {
                 let original = template_secrets.insert(k.to_string(), v.to_string());
@@ -964,25 +1044,42 @@ impl Manifest {
             }
         }
 
-        let template_keys = template_secrets.keys().map(|x| x.to_string()).collect();
-        if let Some(k) = vault_secrets.intersection(&template_keys).next() {
+        if let Some(k) = vault_secrets.keys().find(|k| template_secrets.contains_key(*k)) {
             bail!("Secret {} can not be both templated and fetched from vault", k);
         }
+        self.secrets.append(&mut template_secrets);
 
-        // Lookup values for each secret in vault.
-        for k in vault_secrets {
-            let vkey = format!("{}/{}", pth, k);
-            self.secrets.insert(k.to_string(), client.read(&vkey)?);
+        // secret files reference vault the same way
+        let mut vault_files = BTreeMap::new();
+        for (k, v) in &self.secretFiles {
+            if let Some(r) = SecretRef::parse(v) {
+                vault_files.insert(k.to_string(), r?);
+            }
         }
+        Ok((vault_secrets, vault_files))
+    }
 
-        self.secrets.append(&mut template_secrets);
+    /// Populate placeholder fields with secrets from a secret backend
+    ///
+    /// Paths are resolved using the vault configuration parameters in the `Config`.
+    pub fn secrets(&mut self, client: &dyn SecretBackend, vc: &VaultConfig) -> Result<()> {
+        let pth = self.get_vault_path(vc);
+        debug!("Injecting secrets from {}", pth);
+        let (vault_secrets, vault_files) = self.collect_secrets()?;
 
-        // do the same for secret secrets
-        for (k, v) in &mut self.secretFiles {
-            if v == "IN_VAULT" {
-                let vkey = format!("{}/{}", pth, k);
-                *v = client.read(&vkey)?;
-            }
+        // Lookup values for all secrets in one go so the backend can fetch them concurrently.
+        let reqs = vault_secrets.iter().chain(vault_files.iter())
+            .map(|(k, r)| r.locate(&pth, vc, k))
+            .collect::<Vec<_>>();
+        let mut values = client.read_keys(&reqs)?.into_iter();
+        for (k, v) in vault_secrets.keys().zip(&mut values) {
+            self.secrets.insert(k.to_string(), v);
+        }
+        for (k, v) in vault_files.keys().zip(&mut values) {
+            self.secretFiles.insert(k.to_string(), v);
+        }
+
+        for (k, v) in &self.secretFiles {
             // sanity check; secretFiles are assumed base64 verify we can decode
             if base64::decode(v).is_err() {
                 bail!("Secret {} is not base64 encoded", k);
@@ -991,47 +1088,87 @@ impl Manifest {
         Ok(())
     }
 
+    /// Populate placeholder fields with recognisable stub values
+    ///
+    /// Env vars get `STUB_<KEY>` and secret files get a base64 encoded `STUB_<name>`,
+    /// unless `stubs` has a value for the env var or secret file.
+    /// Stubs for secret files are given in plain text, and encoded here.
+    pub fn stub_secrets(&mut self, stubs: &BTreeMap<String, String>) -> Result<()> {
+        let (vault_secrets, vault_files) = self.collect_secrets()?;
+        let stub = |k: &str| stubs.get(k).cloned().unwrap_or_else(|| format!("STUB_{}", k));
+        for k in vault_secrets.keys() {
+            self.secrets.insert(k.to_string(), stub(k));
+        }
+        for k in vault_files.keys() {
+            self.secretFiles.insert(k.to_string(), base64::encode(&stub(k)));
+        }
+        Ok(())
+    }
+
     /// Get a list of raw secrets (without associated keys)
     ///
+    /// Includes resolved secret files. Vault secrets shared by several env vars are listed once.
     /// Useful for obfuscation mechanisms so it knows what to obfuscate.
     pub fn get_secrets(&self) -> Vec<String> {
-        self.secrets.values().cloned().collect()
+        let mut res = self.secrets.values()
+            .chain(self.secretFiles.values())
+            .filter(|v| SecretRef::parse(v).is_none()) // not resolved yet
+            .cloned()
+            .collect::<Vec<_>>();
+        res.sort();
+        res.dedup();
+        res
     }
 
-    pub fn verify_secrets_exist(&self, vc: &VaultConfig) -> Result<()> {
-        // what are we requesting
-        // TODO: Use envvars directly
-        let keys = self
-            .env
-            .plain
-            .clone()
-            .into_iter()
-            .filter(|(_, v)| v == "IN_VAULT")
-            .map(|(k, _)| k)
-            .collect::<Vec<_>>();
-        let files = self.secretFiles.clone().into_iter()
-            .filter(|(_,v)| v == "IN_VAULT")
-            .map(|(k, _)| k)
-            .collect::<Vec<_>>();
-        if keys.is_empty() && files.is_empty() {
-            return Ok(()); // no point trying to cross reference
+    /// Vault references in env vars of all containers and in secret files
+    ///
+    /// Returned with the name of the env var or secret file referencing them.
+    pub fn vault_refs(&self) -> Result<Vec<(String, SecretRef)>> {
+        let envs = Some(&self.env).into_iter()
+            .chain(self.sidecars.iter().map(|s| &s.env))
+            .chain(self.workers.iter().map(|w| &w.env))
+            .chain(self.cronJobs.iter().map(|c| &c.env));
+        let mut refs = vec![];
+        for e in envs {
+            refs.extend(e.vault_refs()?);
+        }
+        for (k, v) in &self.secretFiles {
+            if let Some(r) = SecretRef::parse(v) {
+                refs.push((k.to_string(), r?));
+            }
         }
+        Ok(refs)
+    }
 
-        // what we have
-        let v = Vault::regional(vc)?; // only listing anyway
-        let secpth = self.get_vault_path(vc);
-        let found = v.list(&secpth)?; // can fail if folder is empty
-        debug!("Found secrets {:?} for {}", found, self.name);
+    pub fn verify_secrets_exist(&self, reg: &Region) -> Result<()> {
+        let v = reg.secret_backend()?; // only listing anyway
+        self.verify_secrets_with(&*v, &reg.vault)
+    }
 
-        // compare
-        for k in keys {
-            if !found.contains(&k) {
-                bail!("Secret {} not found in vault {} for {}", k, secpth, self.name);
-            }
+    /// Verify secrets exist using a specific secret backend
+    ///
+    /// Only lists folders, so keys of shared secrets are not checked until they are read.
+    pub fn verify_secrets_with(&self, v: &dyn SecretBackend, vc: &VaultConfig) -> Result<()> {
+        // what are we requesting, grouped by the folder to list
+        let secpth = self.get_vault_path(vc);
+        let mut wanted: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for (name, r) in self.vault_refs()? {
+            let (path, _) = r.locate(&secpth, vc, &name);
+            // locate always joins a folder with a secret name
+            let i = path.rfind('/').unwrap();
+            wanted.entry(path[..i].to_string()).or_default().insert(path[i+1..].to_string());
         }
-        for k in files {
-            if !found.contains(&k) {
-                bail!("Secret file {} not found in vault {} for {}", k, secpth, self.name);
+
+        for (folder, secrets) in wanted {
+            // what we have
+            let found = v.list(&folder)?;
+            debug!("Found secrets {:?} in {} for {}", found, folder, self.name);
+
+            // compare
+            for s in secrets {
+                if !found.contains(&s) {
+                    bail!("Secret {} not found in vault {} for {}", s, folder, self.name);
+                }
             }
         }
         Ok(())
@@ -1,4 +1,4 @@
-use super::structs::Resources;
+use super::structs::{Resources, Worker};
 use super::structs::rollingupdate::{RollingUpdate};
 use super::{Result, Manifest};
 
@@ -21,13 +21,8 @@ impl Manifest {
 
     /// Estimate how many iterations needed in a kube rolling upgrade
     ///
-    /// Used to `estimate_wait_time` for a rollout.
-    fn estimate_rollout_iterations(&self) -> u32 {
-        let rcount = if let Some(ref hpa) = self.autoScaling {
-            hpa.minReplicas
-        } else {
-            self.replicaCount.unwrap() // verify ensures we have one of these
-        };
+    /// Used to `estimate_wait_time` for a rollout of `rcount` replicas.
+    fn estimate_rollout_iterations(&self, rcount: u32) -> u32 {
         if let Some(ru) = self.rollingUpdate.clone() {
             ru.rollout_iterations(rcount)
         } else {
@@ -39,12 +34,35 @@ impl Manifest {
     ///
     /// Was used by helm, now used by the internal upgrade wait time.
     pub fn estimate_wait_time(&self) -> u32 {
+        let rcount = if let Some(ref hpa) = self.autoScaling {
+            Some(hpa.minReplicas)
+        } else {
+            self.replicaCount
+        };
+        self.estimate_replica_wait_time(rcount)
+    }
+
+    /// Estimate how long to wait for a kube rolling upgrade of a worker
+    ///
+    /// Workers share the image and health of the main deployment,
+    /// but roll out their own number of replicas.
+    pub fn estimate_worker_wait_time(&self, w: &Worker) -> u32 {
+        let rcount = if let Some(ref hpa) = w.autoScaling {
+            hpa.minReplicas
+        } else {
+            w.replicaCount
+        };
+        self.estimate_replica_wait_time(Some(rcount))
+    }
+
+    fn estimate_replica_wait_time(&self, rcount: Option<u32>) -> u32 {
         // TODO: handle install case elsewhere..
         if let Some(size) = self.imageSize {
             // 512 default => extra 90s wait, then 90s per half gig...
             // TODO: smoothen..
             let pulltimeestimate = std::cmp::max(60, ((size as f64 * 90.0) / 512.0) as u32);
-            let rollout_iterations = self.estimate_rollout_iterations();
+            let rcount = rcount.unwrap(); // verify ensures we have one of these
+            let rollout_iterations = self.estimate_rollout_iterations(rcount);
             //println!("estimating wait for {} cycle rollout: size={} (est={})", rollout_iterations, size, pulltimeestimate);
 
             // how long each iteration needs to wait due to readinessProbe params.
@@ -112,7 +130,7 @@ impl Manifest {
 
 #[cfg(test)]
 mod tests {
-    use crate::structs::HealthCheck;
+    use crate::structs::{HealthCheck, Worker};
     use super::{Manifest};
 
     #[test]
@@ -153,4 +171,25 @@ mod tests {
         assert_eq!(mf.estimate_wait_time(), 990); // lots of leeway here just in case
 
     }
+
+    #[test]
+    fn mf_worker_wait_time_check() {
+        let mut mf = Manifest::default();
+        mf.imageSize = Some(512);
+        mf.health = Some(HealthCheck {
+            uri: "/".into(),
+            wait: 60,
+            ..Default::default()
+        });
+        mf.replicaCount = Some(2);
+        let mut w : Worker = serde_yaml::from_str(r#"
+name: fake-worker
+replicaCount: 4
+resources: { requests: { cpu: 100m, memory: 100Mi }, limits: { cpu: 200m, memory: 200Mi } }
+"#).unwrap();
+        assert_eq!(mf.estimate_wait_time(), 180); // one cycle for the main deployment
+        assert_eq!(mf.estimate_worker_wait_time(&w), 360); // (60*1.5 + 90s)*2
+        w.replicaCount = 1;
+        assert_eq!(mf.estimate_worker_wait_time(&w), 180);
+    }
 }
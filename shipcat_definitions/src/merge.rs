@@ -1,7 +1,251 @@
 // This file describes how manifests and environment manifest overrides are merged.
 
+use std::collections::BTreeMap;
+
 use super::{Config, Region};
-use super::{Manifest, Result};
+use super::{Manifest, Result, ErrorKind};
+use super::states::ManifestType;
+use super::structs::{EnvVars, Kong, Canary, Authentication};
+
+/// How a field in a region override file is merged into the main manifest
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// The override value replaces the main value if set
+    Replace,
+    /// Maps are merged by key, structs field by field
+    DeepMerge,
+    /// The override list is appended to the main list
+    Append,
+    /// The field can only be set in the main manifest
+    Forbidden,
+}
+
+/// A value that may or may not have been set in a manifest file
+///
+/// Files are deserialized with serde defaults, so a value counts as set
+/// when it differs from what a missing key deserializes to.
+pub trait Mergeable {
+    fn is_set(&self) -> bool;
+}
+
+impl<T> Mergeable for Option<T> {
+    fn is_set(&self) -> bool { self.is_some() }
+}
+impl<T> Mergeable for Vec<T> {
+    fn is_set(&self) -> bool { !self.is_empty() }
+}
+impl<K, V> Mergeable for BTreeMap<K, V> {
+    fn is_set(&self) -> bool { !self.is_empty() }
+}
+impl Mergeable for String {
+    fn is_set(&self) -> bool { !self.is_empty() }
+}
+impl Mergeable for EnvVars {
+    fn is_set(&self) -> bool { !self.plain.is_empty() }
+}
+impl Mergeable for ManifestType {
+    fn is_set(&self) -> bool { *self != ManifestType::default() }
+}
+impl Mergeable for Authentication {
+    fn is_set(&self) -> bool { *self != Authentication::default() }
+}
+
+/// A value that can be merged more than one level deep
+pub trait DeepMerge {
+    fn deep_merge(&mut self, other: Self);
+}
+
+impl<K: Ord, V> DeepMerge for BTreeMap<K, V> {
+    fn deep_merge(&mut self, other: Self) {
+        self.extend(other)
+    }
+}
+impl DeepMerge for EnvVars {
+    fn deep_merge(&mut self, other: Self) {
+        self.plain.extend(other.plain)
+    }
+}
+impl DeepMerge for Kong {
+    fn deep_merge(&mut self, other: Self) {
+        self.merge(other)
+    }
+}
+impl DeepMerge for Canary {
+    fn deep_merge(&mut self, other: Self) {
+        // Canary declares no forbidden fields
+        let _ = self.merge_fields(other, true);
+    }
+}
+impl<T: DeepMerge> DeepMerge for Option<T> {
+    fn deep_merge(&mut self, other: Self) {
+        if let Some(y) = other {
+            if let Some(x) = self {
+                x.deep_merge(y)
+            } else {
+                *self = Some(y)
+            }
+        }
+    }
+}
+
+/// Declare the merge strategy of every field of a struct
+///
+/// Generates a `$table` of field names and strategies, and a private `merge_fields`
+/// method applying them. When `overriding` is false, `Forbidden` fields replace
+/// rather than fail. The override is destructured without `..`,
+/// so adding a field without declaring its strategy fails to compile.
+///
+/// ```rust,ignore
+/// merge_strategies!(Kong, KONG_MERGE_STRATEGIES, {
+///     uris: Replace,
+///     add_headers: DeepMerge,
+/// });
+/// ```
+macro_rules! merge_strategies {
+    ($ty:ident, $table:ident, { $($field:ident: $strategy:ident,)* }) => {
+        /// Merge strategy of every field, in declaration order
+        pub const $table: &[(&str, $crate::merge::MergeStrategy)] = &[
+            $((stringify!($field), $crate::merge::MergeStrategy::$strategy),)*
+        ];
+
+        impl $ty {
+            /// Merge an override into self according to the declared strategies
+            ///
+            /// Fails with the name of a field that was set against a `Forbidden` strategy.
+            #[allow(unused_imports, unused_variables)]
+            fn merge_fields(&mut self, other: $ty, overriding: bool) -> ::std::result::Result<(), &'static str> {
+                use $crate::merge::{Mergeable, DeepMerge};
+                let $ty { $($field),* } = other;
+                $(merge_field!($strategy, self.$field, $field, stringify!($field), overriding);)*
+                Ok(())
+            }
+        }
+    }
+}
+
+macro_rules! merge_field {
+    (Replace, $main:expr, $over:ident, $name:expr, $overriding:ident) => {
+        if $over.is_set() {
+            $main = $over;
+        }
+    };
+    (DeepMerge, $main:expr, $over:ident, $name:expr, $overriding:ident) => {
+        $main.deep_merge($over);
+    };
+    (Append, $main:expr, $over:ident, $name:expr, $overriding:ident) => {
+        $main.extend($over);
+    };
+    (Forbidden, $main:expr, $over:ident, $name:expr, $overriding:ident) => {
+        if $over.is_set() {
+            if $overriding {
+                return Err($name);
+            }
+            $main = $over;
+        }
+    };
+}
+
+merge_strategies!(Manifest, MANIFEST_MERGE_STRATEGIES, {
+    // service type properties
+    name: Forbidden,
+    extends: Forbidden,
+    publiclyAccessible: Replace,
+    external: Replace,
+    disabled: Replace,
+    regions: Forbidden,
+    metadata: Forbidden,
+
+    chart: Replace,
+    image: Replace,
+    imageSize: Replace,
+    version: Replace,
+    command: Replace,
+    dataHandling: Replace,
+    language: Replace,
+    resources: Replace,
+    replicaCount: Replace,
+    env: DeepMerge,
+    secretFiles: DeepMerge,
+    configs: Replace,
+    vault: Replace,
+    httpPort: Replace,
+    ports: Replace,
+    externalPort: Replace,
+    health: Replace,
+    dependencies: Replace,
+    workers: Replace,
+    sidecars: Replace,
+    readinessProbe: Replace,
+    livenessProbe: Replace,
+    lifecycle: Replace,
+    rollingUpdate: Replace,
+    canary: DeepMerge,
+    blueGreen: Replace,
+    autoScaling: Replace,
+    tolerations: Replace,
+    hostAliases: Replace,
+    initContainers: Replace,
+    volumes: Replace,
+    volumeMounts: Replace,
+    persistentVolumes: Replace,
+    cronJobs: Replace,
+    serviceAnnotations: DeepMerge,
+    labels: Replace,
+    kong: DeepMerge,
+    gate: Replace,
+    hosts: Replace,
+    kafka: Replace,
+    sourceRanges: Replace,
+    rbac: Replace,
+    database: Replace,
+    redis: Replace,
+
+    // set internally, never read from files
+    region: Forbidden,
+    environment: Forbidden,
+    namespace: Forbidden,
+    secrets: Forbidden,
+    kind: Forbidden,
+});
+
+merge_strategies!(Kong, KONG_MERGE_STRATEGIES, {
+    name: Replace,
+    upstream_url: Replace,
+    unauthenticated: Replace,
+    internal: Replace,
+    publiclyAccessible: Replace,
+    cookie_auth: Replace,
+    cookie_auth_csrf: Replace,
+    uris: Replace,
+    hosts: Replace,
+    host: Replace,
+    auth: Replace,
+    strip_uri: Replace,
+    preserve_host: Replace,
+    cors: Replace,
+    additional_internal_ips: Replace,
+    babylon_auth_header: Replace,
+    oauth2_anonymous: Replace,
+    oauth2_extension_plugin: Replace,
+    upstream_connect_timeout: Replace,
+    upstream_send_timeout: Replace,
+    upstream_read_timeout: Replace,
+    add_headers: DeepMerge,
+});
+
+merge_strategies!(Canary, CANARY_MERGE_STRATEGIES, {
+    percentage: Replace,
+    bakeTime: Replace,
+    maxRestarts: Replace,
+});
+
+impl Kong {
+    /// Merge in fields from an override, if they're set
+    pub fn merge(&mut self, other: Kong) {
+        // Kong declares no forbidden fields
+        let _ = self.merge_fields(other, true);
+    }
+}
 
 impl Manifest {
     /// Add implicit defaults to self after merging in region overrides
@@ -81,158 +325,169 @@ impl Manifest {
 
     /// Merge defaults from partial override file
     ///
-    /// Copies keys from environment files into the current manifest struct by default.
-    /// One special cases are merged carefully:
-    /// - env dict (merged by key)
+    /// Every field is merged according to its `MergeStrategy` in `MANIFEST_MERGE_STRATEGIES`.
+    /// Fields set with a `Forbidden` strategy are reported as conflicts.
     pub fn merge(&mut self, mf: Manifest) -> Result<()> {
-        if mf.name != "" {
-            bail!("Cannot override service names in other environments");
-        }
-        // sanity asserts
-        if self.kong.is_some() && mf.kong.is_some() {
-            // Must override Kong per environment (overwrite full struct)
-            bail!("Cannot have kong in main shipcat.yml and environment override files");
+        if let Err(key) = self.merge_fields(mf, true) {
+            // key is passed on so callers can locate the conflict
+            let msg = format!("{} can only be defined in the main shipcat.yml", key);
+            bail!(ErrorKind::MergeConflict(key.into(), msg, vec![]));
         }
-        if !mf.regions.is_empty() {
-            // these cannot be overridden - it's a service type property
-            bail!("Regions must only be defined in the main shipcat.yml file");
-        }
-        if mf.metadata.is_some() {
-            bail!("metadata can only live in the main shipcat.yml")
-        }
-        //if self.version.is_some() {
-        //    debug!("{} locks versions across all environments in shipcat.yml", self.name);
-        //}
+        Ok(())
+    }
 
-        // start merging:
+    /// Merge a manifest on top of a base that it extends
+    ///
+    /// Uses the same strategies as region overrides, except that fields forbidden
+    /// in overrides belong to the extending manifest and are taken from it as is.
+    pub(crate) fn merge_extension(&mut self, mf: Manifest) {
+        // cannot fail when not overriding
+        let _ = self.merge_fields(mf, false);
+    }
+}
 
-        // merge maps by appending to keys found in shipcat.yml
-        for (k, v) in mf.env.plain {
-            self.env.plain.insert(k, v);
-        }
-        for (k,v) in mf.secretFiles {
-            self.secretFiles.insert(k, v);
-        }
-        for (k,v) in mf.serviceAnnotations {
-            self.serviceAnnotations.insert(k, v);
-        }
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use schemars::{schema_for, JsonSchema};
+    use super::{MergeStrategy, MANIFEST_MERGE_STRATEGIES, KONG_MERGE_STRATEGIES, CANARY_MERGE_STRATEGIES};
+    use crate::structs::{Canary, Gate, Kong};
+    use crate::{Manifest, ErrorKind};
 
-        // optional values that are replaced if present in override
-        if mf.chart.is_some() {
-            self.chart = mf.chart;
-        }
-        if mf.image.is_some() {
-            self.image = mf.image;
-        }
-        if mf.imageSize.is_some() {
-            self.imageSize = mf.imageSize;
-        }
-        if mf.version.is_some() {
-            self.version = mf.version;
-        }
-        if mf.dataHandling.is_some() {
-            self.dataHandling = mf.dataHandling;
-        }
-        if mf.language.is_some() {
-            self.language = mf.language;
-        }
-        if mf.replicaCount.is_some() {
-            self.replicaCount = mf.replicaCount;
-        }
-        if mf.configs.is_some() {
-            self.configs = mf.configs;
-        }
-        if mf.resources.is_some(){
-            self.resources = mf.resources;
-        }
-        if mf.kafka.is_some() {
-            self.kafka = mf.kafka;
-        }
-        if mf.kong.is_some() {
-            self.kong = mf.kong;
-        }
-        if mf.database.is_some() {
-            self.database = mf.database;
-        }
-        if mf.redis.is_some() {
-            self.redis = mf.redis;
-        }
-        if mf.vault.is_some() {
-            self.vault = mf.vault;
-        }
-        if mf.rollingUpdate.is_some() {
-            self.rollingUpdate = mf.rollingUpdate;
-        }
-        if mf.autoScaling.is_some() {
-            self.autoScaling = mf.autoScaling;
-        }
-        if mf.health.is_some() {
-            self.health = mf.health;
-        }
-        if mf.readinessProbe.is_some() {
-            self.readinessProbe = mf.readinessProbe;
-        }
-        if mf.livenessProbe.is_some() {
-            self.livenessProbe = mf.livenessProbe;
-        }
-        if mf.httpPort.is_some() {
-            self.httpPort = mf.httpPort;
-        }
-        if mf.externalPort.is_some() {
-            self.externalPort = mf.externalPort;
-        }
-        if mf.lifecycle.is_some() {
-            self.lifecycle = mf.lifecycle;
-        }
+    /// Serialized fields of a struct that have no declared merge strategy
+    fn undeclared<T: JsonSchema>(table: &[(&str, MergeStrategy)]) -> Vec<String> {
+        let declared = table.iter().map(|(k, _)| *k).collect::<BTreeSet<_>>();
+        assert_eq!(declared.len(), table.len(), "duplicate merge strategies");
+        let schema = schema_for!(T);
+        schema.schema.object.unwrap().properties.keys()
+            .filter(|k| !declared.contains(k.as_str()))
+            .cloned()
+            .collect()
+    }
 
-        // vectors are replaced if they are non-empty in override
-        if !mf.command.is_empty() {
-            self.command = mf.command;
-        }
-        if !mf.hosts.is_empty() {
-            self.hosts = mf.hosts;
-        }
-        if !mf.hostAliases.is_empty() {
-            self.hostAliases = mf.hostAliases;
-        }
-        if !mf.tolerations.is_empty() {
-            self.tolerations = mf.tolerations;
-        }
-        if !mf.volumeMounts.is_empty() {
-            self.volumeMounts = mf.volumeMounts;
-        }
-        if !mf.initContainers.is_empty() {
-            self.initContainers = mf.initContainers;
-        }
-        if !mf.volumes.is_empty() {
-            self.volumes = mf.volumes;
-        }
-        if !mf.persistentVolumes.is_empty() {
-            self.persistentVolumes = mf.persistentVolumes;
-        }
-        if !mf.cronJobs.is_empty() {
-            self.cronJobs = mf.cronJobs;
-        }
-        if !mf.dependencies.is_empty() {
-            self.dependencies = mf.dependencies;
-        }
-        if !mf.sidecars.is_empty() {
-            self.sidecars = mf.sidecars;
-        }
-        if !mf.workers.is_empty() {
-            self.workers = mf.workers;
-        }
-        if !mf.labels.is_empty() {
-            self.labels = mf.labels;
-        }
-        if !mf.sourceRanges.is_empty() {
-            self.sourceRanges = mf.sourceRanges;
-        }
+    #[test]
+    fn every_field_has_a_strategy() {
+        let missing = undeclared::<Manifest>(MANIFEST_MERGE_STRATEGIES);
+        assert!(missing.is_empty(), "Manifest fields without a merge strategy: {:?}", missing);
+        let missing = undeclared::<Kong>(KONG_MERGE_STRATEGIES);
+        assert!(missing.is_empty(), "Kong fields without a merge strategy: {:?}", missing);
+        let missing = undeclared::<Canary>(CANARY_MERGE_STRATEGIES);
+        assert!(missing.is_empty(), "Canary fields without a merge strategy: {:?}", missing);
+    }
+
+    #[test]
+    fn manifest_strategies() {
+        let mut main = Manifest::default();
+        main.env.plain.insert("A".into(), "main".into());
+        main.env.plain.insert("B".into(), "main".into());
+        main.chart = Some("base".into());
+        main.replicaCount = Some(2);
+
+        let mut over = Manifest::default();
+        over.env.plain.insert("B".into(), "override".into());
+        over.replicaCount = Some(3);
+        over.gate = Some(Gate::default());
+        main.merge(over).unwrap();
 
-        if !mf.ports.is_empty() {
-            self.ports = mf.ports;
+        assert_eq!(main.env.plain["A"], "main");
+        assert_eq!(main.env.plain["B"], "override");
+        assert_eq!(main.chart, Some("base".into()));
+        assert_eq!(main.replicaCount, Some(3));
+        assert!(main.gate.is_some());
+
+        let mut over = Manifest::default();
+        over.regions = vec!["dev-uk".into()];
+        match main.merge(over).unwrap_err().kind() {
+            ErrorKind::MergeConflict(key, _, _) => assert_eq!(key, "regions"),
+            e => panic!("unexpected error {}", e),
         }
+    }
 
-        Ok(())
+    #[test]
+    fn kong_strategies() {
+        let mut main = Kong::default();
+        main.uris = Some("/ask".into());
+        main.add_headers = Some(vec![("X-Main".into(), "1".into())].into_iter().collect());
+
+        let mut over = Kong::default();
+        over.upstream_read_timeout = Some(1000);
+        over.add_headers = Some(vec![("X-Region".into(), "2".into())].into_iter().collect());
+        main.merge(over);
+
+        assert_eq!(main.uris, Some("/ask".into()));
+        assert_eq!(main.upstream_read_timeout, Some(1000));
+        assert_eq!(main.add_headers.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn flags_can_be_overridden() {
+        let mut main = Manifest::default();
+        main.disabled = Some(true);
+        main.external = Some(true);
+        main.publiclyAccessible = Some(true);
+        main.kong = Some(Kong {
+            uris: Some("/ask".into()),
+            strip_uri: Some(true),
+            internal: Some(true),
+            cookie_auth: Some(true),
+            ..Default::default()
+        });
+
+        let mut over = Manifest::default();
+        over.disabled = Some(false);
+        over.external = Some(false);
+        over.publiclyAccessible = Some(false);
+        over.kong = Some(Kong { internal: Some(false), cookie_auth: Some(false), ..Default::default() });
+        main.merge(over).unwrap();
+        assert_eq!(main.disabled, Some(false));
+        assert_eq!(main.external, Some(false));
+        assert_eq!(main.publiclyAccessible, Some(false));
+
+        // kong is merged field by field when extending
+        let mut ext = Manifest::default();
+        ext.kong = Some(Kong { strip_uri: Some(false), ..Default::default() });
+        main.merge_extension(ext);
+        let kong = main.kong.unwrap();
+        assert_eq!(kong.uris, Some("/ask".into()));
+        assert_eq!(kong.strip_uri, Some(false));
+        assert_eq!(kong.preserve_host, None);
+        // true flags can be turned off again
+        assert_eq!(kong.internal, Some(false));
+        assert_eq!(kong.cookie_auth, Some(false));
+    }
+
+    #[test]
+    fn canary_strategies() {
+        let mut main = Manifest::default();
+        main.canary = Some(Canary { percentage: Some(20), bakeTime: Some(120), ..Default::default() });
+
+        let mut over = Manifest::default();
+        over.canary = Some(Canary { bakeTime: Some(900), ..Default::default() });
+        main.merge(over).unwrap();
+
+        let canary = main.canary.unwrap();
+        assert_eq!(canary.percentage, Some(20));
+        assert_eq!(canary.bakeTime, Some(900));
+        assert_eq!(canary.maxRestarts, None);
+    }
+
+    struct Lists {
+        appended: Vec<u32>,
+        replaced: Vec<u32>,
+    }
+    merge_strategies!(Lists, LISTS_MERGE_STRATEGIES, {
+        appended: Append,
+        replaced: Replace,
+    });
+
+    #[test]
+    fn list_strategies() {
+        let mut main = Lists { appended: vec![1], replaced: vec![1] };
+        main.merge_fields(Lists { appended: vec![2], replaced: vec![2] }, true).unwrap();
+        assert_eq!(main.appended, vec![1, 2]);
+        assert_eq!(main.replaced, vec![2]);
+        main.merge_fields(Lists { appended: vec![], replaced: vec![] }, true).unwrap();
+        assert_eq!(main.replaced, vec![2]);
+        assert_eq!(LISTS_MERGE_STRATEGIES[0], ("appended", MergeStrategy::Append));
     }
 }
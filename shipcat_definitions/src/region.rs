@@ -7,7 +7,7 @@ use semver::Version;
 use url::Url;
 use uuid::Uuid;
 
-use super::Vault;
+use super::{Vault, SecretBackend, LocalSecrets};
 #[allow(unused_imports)]
 use super::{Result, Error, ErrorKind};
 use super::ConfigType;
@@ -16,7 +16,7 @@ use super::ConfigType;
 ///
 /// This is valdiated strictly using `shipcat validate` when versions are found in manifests.
 /// Otherwise, it's validated on upgrade time (via `shipcat apply`) when it's passed.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub enum VersionScheme {
     /// Version must be valid semver (no leading v)
     ///
@@ -56,7 +56,7 @@ impl VersionScheme {
 }
 
 /// Vault configuration for a region
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[cfg_attr(test, derive(Default))]
 #[serde(deny_unknown_fields)]
 pub struct VaultConfig {
@@ -66,6 +66,122 @@ pub struct VaultConfig {
     ///
     /// Typically, the name of the region to disambiguate.
     pub folder: String,
+    /// Version of the KV secrets engine mounted at secret/
+    #[serde(default)]
+    pub kvVersion: KvVersion,
+    /// How to obtain a vault token
+    #[serde(default)]
+    pub auth: VaultAuth,
+}
+
+/// How shipcat obtains a vault token in a region
+///
+/// Tokens from logins are cached for the duration of a run, and renewed
+/// when their lease runs low.
+///
+/// ```yaml
+/// auth:
+///   method: kubernetes
+///   role: shipcat
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub enum VaultAuth {
+    /// A token from `VAULT_TOKEN` or `~/.vault-token`
+    Token,
+    /// AppRole login using the `VAULT_ROLE_ID` and `VAULT_SECRET_ID` evars
+    AppRole {
+        /// Mount path of the approle auth backend
+        #[serde(default = "approle_mount_default")]
+        mount: String,
+    },
+    /// Kubernetes login using the service account token of the pod
+    Kubernetes {
+        /// Vault role bound to the service account
+        role: String,
+        /// Mount path of the kubernetes auth backend
+        #[serde(default = "kubernetes_mount_default")]
+        mount: String,
+        /// Path to the service account token
+        #[serde(default = "kubernetes_jwt_path_default")]
+        jwtPath: String,
+    },
+}
+
+impl Default for VaultAuth {
+    fn default() -> Self {
+        VaultAuth::Token
+    }
+}
+
+fn approle_mount_default() -> String { "approle".into() }
+fn kubernetes_mount_default() -> String { "kubernetes".into() }
+fn kubernetes_jwt_path_default() -> String { "/var/run/secrets/kubernetes.io/serviceaccount/token".into() }
+
+/// Version of a Vault KV secrets engine
+///
+/// Version 2 engines keep secrets under `data/` and list them under `metadata/`,
+/// and nest the secret values one level deeper in responses.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+pub enum KvVersion {
+    #[serde(rename = "v1")]
+    V1,
+    #[serde(rename = "v2")]
+    V2,
+}
+
+impl Default for KvVersion {
+    fn default() -> Self {
+        KvVersion::V1
+    }
+}
+
+/// Where a region reads secrets from
+///
+/// Regions without access to vault can use a local secrets file:
+///
+/// ```yaml
+/// secretBackend:
+///   kind: local
+///   path: secrets/dev-uk.yml
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SecretBackendConfig {
+    /// The vault in the region's `vault` config
+    Vault,
+    /// A yaml file of secrets keyed by vault path
+    Local {
+        /// Path to the file, relative to the manifests repo
+        path: String,
+        /// Whether the file needs decrypting with `sops`
+        #[serde(default)]
+        encrypted: bool,
+    },
+}
+
+impl Default for SecretBackendConfig {
+    fn default() -> Self {
+        SecretBackendConfig::Vault
+    }
+}
+
+/// Major version of helm used in a region
+///
+/// Helm 2 talks to a tiller in the region's namespace, while Helm 3 has no tiller
+/// and keeps releases in the namespace itself.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+pub enum HelmVersion {
+    #[serde(rename = "v2")]
+    V2,
+    #[serde(rename = "v3")]
+    V3,
+}
+
+impl Default for HelmVersion {
+    fn default() -> Self {
+        HelmVersion::V2
+    }
 }
 
 //#[derive(Serialize, Deserialize, Clone, Default)]
@@ -78,7 +194,7 @@ pub struct VaultConfig {
 //}
 
 /// Kafka configuration for a region
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct KafkaConfig {
     /// Broker urls in "hostname:port" format.
@@ -88,7 +204,7 @@ pub struct KafkaConfig {
 }
 
 /// Webhook types that shipcat might trigger after actions
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(tag = "name", deny_unknown_fields, rename_all = "snake_case")]
 pub enum Webhook {
     /// Audit webhook details
@@ -96,18 +212,19 @@ pub enum Webhook {
 }
 
 /// Where / how to send audited events
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct AuditWebhook {
     /// Endpoint
     #[serde(with = "url_serde")]
+    #[schemars(with = "String")]
     pub url: Url,
     /// Credential
     pub token: String,
 }
 
 /// Configure how CRs will be deployed on a region
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct CRSettings {
     #[serde(rename = "config")]
@@ -117,7 +234,7 @@ pub struct CRSettings {
 // ----------------------------------------------------------------------------------
 
 /// Kong configuration for a region
-#[derive(Serialize, Deserialize, Clone, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)] // TODO: better Default impl
 #[serde(deny_unknown_fields)]
 pub struct KongConfig {
     /// Base URL to use (e.g. uk.dev.babylontech.co.uk)
@@ -142,7 +259,7 @@ pub struct KongConfig {
 }
 
 /// StatusCake configuration for a region
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct StatuscakeConfig {
     /// Contact Group that will be used if tests go down
@@ -154,7 +271,7 @@ pub struct StatuscakeConfig {
 }
 
 /// Logz.io configuration for a region
-#[derive(Serialize, Deserialize, Clone, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)] // TODO: better Default impl
 #[serde(deny_unknown_fields)]
 pub struct LogzIoConfig {
     /// Base URL to use (e.g. https://app-eu.logz.io/#/dashboard/kibana/dashboard)
@@ -164,7 +281,7 @@ pub struct LogzIoConfig {
 }
 
 /// Grafana details for a region
-#[derive(Serialize, Deserialize, Clone, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)] // TODO: better Default impl
 #[serde(deny_unknown_fields)]
 pub struct GrafanaConfig {
     /// Base URL to use (e.g. https://dev-grafana.ops.babylontech.co.uk)
@@ -174,20 +291,20 @@ pub struct GrafanaConfig {
 }
 
 /// Sentry details for a region
-#[derive(Serialize, Deserialize, Clone, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)] // TODO: better Default impl
 #[serde(deny_unknown_fields)]
 pub struct SentryConfig {
     /// Base URL to use (e.g. https://dev-uk-sentry.ops.babylontech.co.uk)
     pub url: String,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct KongAnonymousConsumers {
     pub anonymous: BTreeMap<String, String>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct KongOauthConsumer {
     pub oauth_client_id: String,
@@ -195,7 +312,7 @@ pub struct KongOauthConsumer {
     pub username: String
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct KongJwtConsumer {
     pub issuer: String,
@@ -203,15 +320,26 @@ pub struct KongJwtConsumer {
 }
 
 impl KongConfig {
-    fn secrets(&mut self, vault: &Vault, region: &str) -> Result<()> {
-        for (svc, data) in &mut self.consumers {
+    fn secrets(&mut self, vault: &dyn SecretBackend, region: &str) -> Result<()> {
+        // read all consumer credentials in one go so they can be fetched concurrently
+        let mut reqs = vec![];
+        for (svc, data) in &self.consumers {
             if data.oauth_client_id == "IN_VAULT" {
-                let vkey = format!("{}/kong/consumers/{}_oauth_client_id", region, svc);
-                data.oauth_client_id = vault.read(&vkey)?;
+                reqs.push(format!("{}/kong/consumers/{}_oauth_client_id", region, svc));
             }
             if data.oauth_client_secret == "IN_VAULT" {
-                let vkey = format!("{}/kong/consumers/{}_oauth_client_secret", region, svc);
-                data.oauth_client_secret = vault.read(&vkey)?;
+                reqs.push(format!("{}/kong/consumers/{}_oauth_client_secret", region, svc));
+            }
+        }
+        let reqs = reqs.into_iter().map(|p| (p, "value".to_string())).collect::<Vec<_>>();
+        let mut values = vault.read_keys(&reqs)?.into_iter();
+        // same order as the requests
+        for data in self.consumers.values_mut() {
+            if data.oauth_client_id == "IN_VAULT" {
+                data.oauth_client_id = values.next().unwrap();
+            }
+            if data.oauth_client_secret == "IN_VAULT" {
+                data.oauth_client_secret = values.next().unwrap();
             }
         }
         if self.oauth_provision_key == "IN_VAULT" {
@@ -220,7 +348,7 @@ impl KongConfig {
         }
         Ok(())
     }
-    fn verify_secrets_exist(&self, vault: &Vault, region: &str) -> Result<()> {
+    fn verify_secrets_exist(&self, vault: &dyn SecretBackend, region: &str) -> Result<()> {
         let mut expected = vec![];
         for (svc, data) in &self.consumers {
             if data.oauth_client_id == "IN_VAULT" {
@@ -245,7 +373,7 @@ impl KongConfig {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct KongTcpLogConfig {
     pub enabled: bool,
@@ -260,7 +388,7 @@ impl KongConfig {
 }
 
 impl Webhook {
-    fn secrets(&mut self, vault: &Vault, region: &str) -> Result<()> {
+    fn secrets(&mut self, vault: &dyn SecretBackend, region: &str) -> Result<()> {
         match self {
             Webhook::Audit(h) => {
                 if h.token == "IN_VAULT" {
@@ -272,7 +400,7 @@ impl Webhook {
         Ok(())
     }
 
-    fn verify_secrets_exist(&self, vault: &Vault, region: &str) -> Result<()> {
+    fn verify_secrets_exist(&self, vault: &dyn SecretBackend, region: &str) -> Result<()> {
         match self {
             Webhook::Audit(_h) => {
                 let vkey = format!("{}/shipcat/WEBHOOK_AUDIT_TOKEN", region);
@@ -377,7 +505,7 @@ mod test_webhooks {
 ///
 /// Either it's a pure kubernetes context with a namespace and a cluster,
 /// or it's an abstract concept with many associated real kubernetes contexts.
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[cfg_attr(test, derive(Default))]
 #[serde(deny_unknown_fields)]
 pub struct Region {
@@ -411,6 +539,12 @@ pub struct Region {
     pub kafka: KafkaConfig,
     /// Vault configuration for the region
     pub vault: VaultConfig,
+    /// Where secrets are read from
+    #[serde(default)]
+    pub secretBackend: SecretBackendConfig,
+    /// Helm version to use, detected from `helm version` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub helmVersion: Option<HelmVersion>,
     /// Logz.io configuration for the region
     pub logzio: Option<LogzIoConfig>,
     /// Grafana details for the region
@@ -427,13 +561,21 @@ pub struct Region {
 }
 
 impl Region {
+    /// Secret backend configured for this region
+    pub fn secret_backend(&self) -> Result<Box<dyn SecretBackend>> {
+        match &self.secretBackend {
+            SecretBackendConfig::Vault => Ok(Box::new(Vault::regional(&self.vault)?)),
+            SecretBackendConfig::Local { path, encrypted } => Ok(Box::new(LocalSecrets::new(path, *encrypted)?)),
+        }
+    }
+
     // Internal secret populator for Config::new
     pub fn secrets(&mut self) -> Result<()> {
-        let v = Vault::regional(&self.vault)?;
-        self.kong.secrets(&v, &self.name)?;
+        let v = self.secret_backend()?;
+        self.kong.secrets(&*v, &self.name)?;
         if let Some(ref mut whs) = &mut self.webhooks {
             for wh in whs.iter_mut() {
-                wh.secrets(&v, &self.name)?;
+                wh.secrets(&*v, &self.name)?;
             }
         }
         Ok(())
@@ -441,12 +583,12 @@ impl Region {
 
     // Entry point for region verifier
     pub fn verify_secrets_exist(&self) -> Result<()> {
-        let v = Vault::regional(&self.vault)?;
+        let v = self.secret_backend()?;
         debug!("Validating kong secrets for {}", self.name);
-        self.kong.verify_secrets_exist(&v, &self.name)?;
+        self.kong.verify_secrets_exist(&*v, &self.name)?;
         if let Some(whs) = &self.webhooks {
             for wh in whs.iter() {
-                wh.verify_secrets_exist(&v, &self.name)?;
+                wh.verify_secrets_exist(&*v, &self.name)?;
             }
         }
         Ok(())
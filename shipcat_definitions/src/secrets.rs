@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use super::{Result, ErrorKind, ResultExt};
+use crate::vault::SecretValue;
+
+/// A store of secrets that manifests and regions are completed from
+///
+/// Paths are full paths under `secret/`, e.g. `dev-uk/fake-ask/FAKE_SECRET`.
+pub trait SecretBackend {
+    /// Read a key of the secret at a path
+    fn read_key(&self, path: &str, key: &str) -> Result<String>;
+
+    /// Read the `value` key that plain `IN_VAULT` secrets use
+    fn read(&self, path: &str) -> Result<String> {
+        self.read_key(path, "value")
+    }
+
+    /// Read several `(path, key)` pairs, returning values in the same order
+    ///
+    /// Backends over a network should fetch these concurrently.
+    fn read_keys(&self, reqs: &[(String, String)]) -> Result<Vec<String>> {
+        reqs.iter().map(|(p, k)| self.read_key(p, k)).collect()
+    }
+
+    /// Names of the secrets in a folder, excluding sub folders
+    fn list(&self, folder: &str) -> Result<Vec<String>>;
+
+    /// Names of the secrets in a folder, or `None` when the folder does not exist
+    ///
+    /// Backends without folders of their own find every folder.
+    fn list_opt(&self, folder: &str) -> Result<Option<Vec<String>>> {
+        self.list(folder).map(Some)
+    }
+
+    /// Whether a secret exists
+    fn exists(&self, path: &str) -> Result<bool> {
+        let (folder, name) = match path.rfind('/') {
+            Some(i) => (&path[..i], &path[i+1..]),
+            None => ("", path),
+        };
+        Ok(self.list(folder)?.iter().any(|s| s == name))
+    }
+
+    /// Write the `value` key of a secret, replacing any other keys
+    ///
+    /// Read only backends refuse.
+    fn write(&self, path: &str, _value: &str) -> Result<()> {
+        bail!("Can not write {} - the secret backend is read only", path)
+    }
+
+    /// Delete a secret
+    fn delete(&self, path: &str) -> Result<()> {
+        bail!("Can not delete {} - the secret backend is read only", path)
+    }
+}
+
+/// A secret in a local secrets file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LocalSecret {
+    /// Shorthand for a secret with only a `value` key
+    Value(SecretValue),
+    Keys(BTreeMap<String, SecretValue>),
+}
+
+/// Secrets read from a yaml file instead of vault
+///
+/// For offline development and clusters without access to vault.
+/// Keys are full vault paths, and values are either the `value` of the secret,
+/// or a map of all its keys:
+///
+/// ```yaml
+/// dev-uk/fake-ask/FAKE_SECRET: hello
+/// dev-uk/shared/postgres:
+///   username: shared
+///   password: hunter2
+/// ```
+pub struct LocalSecrets {
+    /// File the secrets were read from
+    file: String,
+    secrets: Arc<BTreeMap<String, LocalSecret>>,
+}
+
+lazy_static! {
+    /// Secrets files read during this run, keyed by their full path
+    ///
+    /// Every manifest asks for a secret backend, so files are only read
+    /// (and decrypted) the first time.
+    static ref LOADED: Mutex<BTreeMap<String, Arc<BTreeMap<String, LocalSecret>>>> = Mutex::new(BTreeMap::new());
+}
+
+impl LocalSecrets {
+    /// Read a plain or `sops` encrypted secrets file
+    pub fn new(file: &str, encrypted: bool) -> Result<LocalSecrets> {
+        let key = fs::canonicalize(file).map(|p| p.display().to_string()).unwrap_or_else(|_| file.into());
+        // held while reading so concurrent callers wait for the same decryption
+        let mut loaded = LOADED.lock().unwrap();
+        if let Some(secrets) = loaded.get(&key) {
+            return Ok(LocalSecrets { file: file.into(), secrets: secrets.clone() });
+        }
+        let data = if encrypted {
+            decrypt(file)?
+        } else {
+            fs::read_to_string(file).chain_err(|| format!("Failed to read secrets file {}", file))?
+        };
+        let secrets: BTreeMap<String, LocalSecret> = serde_yaml::from_str(&data)
+            .chain_err(|| format!("Failed to parse secrets file {}", file))?;
+        let secrets = Arc::new(secrets);
+        loaded.insert(key, secrets.clone());
+        Ok(LocalSecrets { file: file.into(), secrets })
+    }
+}
+
+/// Decrypt a file with `sops`, which picks up keys the same way as the `sops` CLI
+fn decrypt(file: &str) -> Result<String> {
+    let out = Command::new("sops").args(&["--decrypt", file]).output()
+        .chain_err(|| format!("Failed to run sops to decrypt {}", file))?;
+    if !out.status.success() {
+        bail!("Failed to decrypt {}: {}", file, String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+impl SecretBackend for LocalSecrets {
+    fn read_key(&self, path: &str, key: &str) -> Result<String> {
+        let secret = self.secrets.get(path)
+            .ok_or_else(|| ErrorKind::SecretNotAccessible(format!("{} in {}", path, self.file)))?;
+        let value = match (secret, key) {
+            (LocalSecret::Value(v), "value") => Some(v),
+            (LocalSecret::Value(_), _) => None,
+            (LocalSecret::Keys(keys), k) => keys.get(k),
+        };
+        value
+            .map(|v| v.clone().into())
+            .ok_or_else(|| ErrorKind::InvalidSecretForm(path.into(), key.into()).into())
+    }
+
+    fn list(&self, folder: &str) -> Result<Vec<String>> {
+        let prefix = format!("{}/", folder);
+        let res = self.secrets.keys()
+            .filter(|k| k.starts_with(&prefix))
+            .map(|k| k[prefix.len()..].to_string())
+            .filter(|k| !k.contains('/')) // skip sub folders
+            .collect();
+        Ok(res)
+    }
+
+    fn exists(&self, path: &str) -> Result<bool> {
+        Ok(self.secrets.contains_key(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LocalSecrets, SecretBackend};
+    use std::{env, fs, process};
+    use std::sync::Arc;
+
+    #[test]
+    fn local_secrets() {
+        let secrets = LocalSecrets {
+            file: "secrets.yml".into(),
+            secrets: Arc::new(serde_yaml::from_str("
+dev-uk/fake-ask/FAKE_SECRET: hello
+dev-uk/fake-ask/FAKE_NUMBER: -2
+dev-uk/fake-ask/nested/KEY: skipped
+dev-uk/shared/postgres:
+  username: shared
+  password: hunter2
+").unwrap()),
+        };
+        assert_eq!(secrets.read("dev-uk/fake-ask/FAKE_SECRET").unwrap(), "hello");
+        assert_eq!(secrets.read("dev-uk/fake-ask/FAKE_NUMBER").unwrap(), "-2");
+        assert_eq!(secrets.read_key("dev-uk/shared/postgres", "password").unwrap(), "hunter2");
+        assert!(secrets.read("dev-uk/shared/postgres").is_err()); // no value key
+        assert!(secrets.read_key("dev-uk/fake-ask/FAKE_SECRET", "other").is_err());
+        assert!(secrets.read("dev-uk/fake-ask/MISSING").is_err());
+
+        assert_eq!(secrets.list("dev-uk/fake-ask").unwrap(), vec!["FAKE_NUMBER", "FAKE_SECRET"]);
+        assert!(secrets.exists("dev-uk/shared/postgres").unwrap());
+        assert!(!secrets.exists("dev-uk/shared").unwrap());
+    }
+
+    #[test]
+    fn local_secrets_read_once() {
+        let file = env::temp_dir().join(format!("shipcat-local-secrets-{}.yml", process::id()));
+        let file = file.to_str().unwrap();
+        fs::write(file, "dev-uk/fake-ask/FAKE_SECRET: hello\n").unwrap();
+        assert_eq!(LocalSecrets::new(file, false).unwrap().read("dev-uk/fake-ask/FAKE_SECRET").unwrap(), "hello");
+        // later backends reuse what was read
+        fs::write(file, "dev-uk/fake-ask/FAKE_SECRET: changed\n").unwrap();
+        assert_eq!(LocalSecrets::new(file, false).unwrap().read("dev-uk/fake-ask/FAKE_SECRET").unwrap(), "hello");
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -1,11 +1,13 @@
+#[cfg(not(feature = "filesystem"))]
+use std::collections::BTreeMap;
+
 use super::{Result, Manifest, Region};
-use super::vault::Vault;
 
 
 /// Various states a manifest can exist in depending on resolution.
 ///
 /// This only matters within shipcat and is used to optimize speed of accessors.
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, JsonSchema)]
 pub enum ManifestType {
     /// A completed manifest
     ///
@@ -82,17 +84,24 @@ impl Manifest {
     /// Upgrade a `Base` manifest to either a Complete or a Stubbed one
     fn upgrade(mut self, reg: &Region, kind: ManifestType) -> Result<Self> {
         assert_eq!(self.kind, ManifestType::Base); // sanity
-        let v = match kind {
-            ManifestType::Completed => Vault::regional(&reg.vault)?,
-            ManifestType::Stubbed => Vault::mocked(&reg.vault)?,
-            _ => bail!("Can only upgrade a Base manifest to Completed or Stubbed"),
-        };
+        if kind != ManifestType::Completed && kind != ManifestType::Stubbed {
+            bail!("Can only upgrade a Base manifest to Completed or Stubbed");
+        }
         // replace one-off templates in evar strings with values
         // note that this happens before secrets because:
         // secrets may be injected at this step from the Region
         self.template_evars(reg)?;
         // secrets before configs (.j2 template files use raw secret values)
-        self.secrets(&v, &reg.vault)?;
+        if kind == ManifestType::Completed {
+            let v = reg.secret_backend()?;
+            self.secrets(&*v, &reg.vault)?;
+        } else {
+            #[cfg(feature = "filesystem")]
+            let stubs = Manifest::read_stubs(&self.name)?;
+            #[cfg(not(feature = "filesystem"))]
+            let stubs = BTreeMap::new();
+            self.stub_secrets(&stubs)?;
+        }
 
         // templates last
         self.template_configs(reg)?;
@@ -115,7 +124,7 @@ impl Manifest {
 /// Various states a Config can exist in depending on resolution.
 ///
 /// Within shipcat, this is used to optimize speed of accessors.
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, JsonSchema)]
 pub enum ConfigType {
     /// A filtered config for a specific region, with resolved secrets
     Filtered,
@@ -1,10 +1,10 @@
 // AutoScaling types roughly as defined in kubernetes source
 // https://github.com/kubernetes/kubernetes/blob/master/pkg/apis/autoscaling/types.go
 
-use super::{Result};
+use super::{Diagnostics, Code};
 
 /// Configuration parameters for HorizontalPodAutoScaler
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct AutoScaling {
     pub minReplicas: u32,
     pub maxReplicas: u32,
@@ -17,7 +17,7 @@ pub struct AutoScaling {
 ///
 /// The content name (for adjacency) is dynamic - so need wrapper structs..
 /// The name of the wrapper is tagged correctly via serde under a `type` key
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(tag = "type")]
 pub enum ScalingMetric {
     Resource(ScalingMetricResourceWrapper),
@@ -26,13 +26,13 @@ pub enum ScalingMetric {
 }
 
 // dumb adjacency wrappers to get the adjacency content
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct ScalingMetricResourceWrapper { resource: ScalingMetricResource }
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct ScalingMetricPodWrapper { pods: ScalingMetricPod }
 
 /// Native resource scaling via kube
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct ScalingMetricResource {
     name: ScalingMetricResourceType,
     /// The target value of the average of the resource metric across relevant pods,
@@ -44,7 +44,7 @@ pub struct ScalingMetricResource {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     targetAverageValue: Option<String>,
 }
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub enum ScalingMetricResourceType {
     #[serde(rename = "cpu")]
     CPU,
@@ -53,7 +53,7 @@ pub enum ScalingMetricResourceType {
 }
 
 /// Scaling Metrics from prometheus
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct ScalingMetricPod {
     /// Promethus metric name
     pub metricName: String,
@@ -61,14 +61,14 @@ pub struct ScalingMetricPod {
 }
 
 impl AutoScaling {
-     pub fn verify(&self) -> Result<()> {
+     pub fn verify(&self, d: &mut Diagnostics) {
         if self.minReplicas == 0 {
-            bail!("minReplicas must be at least 1");
+            d.error(Code::OutOfRange, "minReplicas", "minReplicas must be at least 1");
         }
         if self.minReplicas > self.maxReplicas {
-            bail!("maxReplicas must be > minReplicas");
+            d.error(Code::OutOfRange, "maxReplicas", "maxReplicas must be > minReplicas");
         }
-        for m in &self.metrics {
+        d.each("metrics", &self.metrics, |d, m| {
             match m {
                 ScalingMetric::Resource(r) => {
                     // need at least one of the values
@@ -76,17 +76,21 @@ impl AutoScaling {
                     // if this is the case should disallow both to be set..
                     match r.resource.name {
                         ScalingMetricResourceType::CPU => {
-                            assert!(r.resource.targetAverageUtilization.is_some());
+                            if r.resource.targetAverageUtilization.is_none() {
+                                d.error(Code::MissingField, "resource.targetAverageUtilization",
+                                    "cpu scaling metrics need a targetAverageUtilization");
+                            }
                         },
                         ScalingMetricResourceType::Memory => {
-                            assert!(r.resource.targetAverageValue.is_some());
+                            if r.resource.targetAverageValue.is_none() {
+                                d.error(Code::MissingField, "resource.targetAverageValue",
+                                    "memory scaling metrics need a targetAverageValue");
+                            }
                         }
                     }
                 },
                 ScalingMetric::Pods(_p) => {} // no validation here
             }
-        }
-
-        Ok(())
+        });
      }
 }
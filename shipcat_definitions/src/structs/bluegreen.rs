@@ -0,0 +1,31 @@
+use super::{Diagnostics, Code};
+
+/// Blue/green deployment configuration
+///
+/// Services with this set never run mixed versions behind their `Service`.
+/// Upgrades bring up every replica of the new version in an idle `{name}-blue` or `{name}-green`
+/// Deployment, wait for it to become ready, and then switch the `Service` selector over to it.
+/// The previous colour keeps running so that `shipcat helm rollback` can switch straight back.
+///
+/// The release's own Deployment is kept at zero replicas as the template for the colours.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BlueGreen {
+    /// Seconds after a switch during which a rollback can switch back (default 3600)
+    ///
+    /// The previous colour is scaled down by the first successful switch after this window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollbackWindow: Option<u32>,
+}
+
+impl BlueGreen {
+    pub fn rollback_window(&self) -> u32 {
+        self.rollbackWindow.unwrap_or(3600)
+    }
+
+    pub fn verify(&self, d: &mut Diagnostics) {
+        if self.rollback_window() == 0 {
+            d.error(Code::OutOfRange, "rollbackWindow", "blueGreen rollbackWindow must be positive");
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use super::{Diagnostics, Code};
+
+/// Canary upgrade configuration
+///
+/// Used by `shipcat helm upgrade --canary`, which runs the new version in a separate
+/// `{name}-canary` Deployment next to the old one, holds it for a bake period,
+/// and then promotes it to a full upgrade, or removes it again if it misbehaved.
+///
+/// Every field can be overridden per region.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Canary {
+    /// Percentage of the service's replicas to run as canaries (default 10)
+    ///
+    /// Always rounds up to at least one replica.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percentage: Option<u32>,
+
+    /// Seconds to watch the canary once it is ready before promoting it (default 300)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bakeTime: Option<u32>,
+
+    /// Container restarts across all canary pods tolerated during the bake (default 0)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxRestarts: Option<u32>,
+}
+
+impl Canary {
+    pub fn percentage(&self) -> u32 {
+        self.percentage.unwrap_or(10)
+    }
+    pub fn bake_time(&self) -> u32 {
+        self.bakeTime.unwrap_or(300)
+    }
+    pub fn max_restarts(&self) -> u32 {
+        self.maxRestarts.unwrap_or(0)
+    }
+
+    /// Number of canary replicas for a service running `replicas` replicas
+    pub fn replicas(&self, replicas: u32) -> u32 {
+        let share = (f64::from(replicas) * f64::from(self.percentage()) / 100.0).ceil() as u32;
+        std::cmp::max(share, 1)
+    }
+
+    pub fn verify(&self, d: &mut Diagnostics) {
+        let perc = self.percentage();
+        if perc == 0 || perc >= 100 {
+            d.error(Code::OutOfRange, "percentage", format!("canary percentage must be between 1 and 99, got {}", perc));
+        }
+        if self.bake_time() == 0 {
+            d.error(Code::OutOfRange, "bakeTime", "canary bakeTime must be positive");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Canary;
+
+    #[test]
+    fn canary_replicas() {
+        let mut c = Canary::default();
+        assert_eq!(c.replicas(2), 1); // always at least one
+        assert_eq!(c.replicas(30), 3);
+        c.percentage = Some(25);
+        assert_eq!(c.replicas(6), 2);
+        assert_eq!(c.bake_time(), 300);
+    }
+}
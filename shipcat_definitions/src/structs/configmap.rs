@@ -1,4 +1,4 @@
-use super::Result;
+use super::{Diagnostics, Code};
 
 /// ConfigMap
 ///
@@ -6,7 +6,7 @@ use super::Result;
 /// Deals with automatic mounting into the pods.
 ///
 /// Only one of these is supported.
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigMap {
     /// Container-local directory path where configs are available
@@ -18,7 +18,7 @@ pub struct ConfigMap {
 /// ConfigMapped File
 ///
 /// Files that are mounted under the parent `mount` path.
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigMappedFile {
     /// Name of file to template (from service repo paths)
@@ -34,24 +34,23 @@ pub struct ConfigMappedFile {
 
 
 impl ConfigMap {
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         // mount paths can't be empty string
         if self.mount == "" || self.mount.starts_with('~') {
-            bail!("Invalid mountpath '{}'", self.mount)
+            d.error(Code::InvalidValue, "mount", format!("Invalid mountpath '{}'", self.mount))
         }
         // and must end in a slash to have a standard
-        if !self.mount.ends_with('/') {
-            bail!("Mount path '{}' must end with a slash", self.mount);
+        else if !self.mount.ends_with('/') {
+            d.error(Code::InvalidValue, "mount", format!("Mount path '{}' must end with a slash", self.mount));
         }
-        for f in &self.files {
+        d.each("files", &self.files, |d, f| {
             if !f.name.ends_with(".j2") {
-                bail!("Only supporting templated config files atm")
+                d.error(Code::InvalidValue, "name", "Only supporting templated config files atm")
             }
             if f.dest == "" {
-                bail!("Empty mount destination for {}", f.name);
+                d.error(Code::MissingField, "dest", format!("Empty mount destination for {}", f.name));
             }
-        }
+        });
         // TODO: verify file exists? done later anyway
-        Ok(())
     }
 }
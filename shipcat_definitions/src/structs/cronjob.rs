@@ -2,9 +2,9 @@ use regex::Regex;
 
 use crate::structs::resources::Resources;
 use super::EnvVars;
-use super::Result;
+use super::{Diagnostics, Code};
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct CronJobVolumeClaim {
     /// The cron job name
@@ -12,7 +12,7 @@ pub struct CronJobVolumeClaim {
     pub mountPath: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct CronJob {
     /// The cron job name
@@ -62,24 +62,22 @@ pub struct CronJob {
 
 
 impl CronJob {
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         let re = Regex::new(r"^[0-9a-z\-]{1,50}$").unwrap();
         if !re.is_match(&self.name) {
-            bail!("Please use a short, lower case cron job names with dashes");
+            d.error(Code::InvalidName, "name", "Please use a short, lower case cron job names with dashes");
         }
         // TODO: version verify
-        self.env.verify()?;
+        d.nested("env", |d| self.env.verify(d));
 
         if let Some(ref r) = &self.resources {
-            r.verify()?;
+            d.nested("resources", |d| r.verify(d));
         }
         if self.image.is_some() && self.version.is_none() {
-            bail!("Cannot specify image without specifying version in CronJob")
+            d.error(Code::MissingField, "version", "Cannot specify image without specifying version in CronJob")
         }
         if self.version.is_some() && self.image.is_none() {
-            bail!("Cannot specify the version without specifying an image in CronJob")
+            d.error(Code::MissingField, "image", "Cannot specify the version without specifying an image in CronJob")
         }
-
-        Ok(())
     }
 }
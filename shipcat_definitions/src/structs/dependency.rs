@@ -1,10 +1,10 @@
 use std::path::Path;
-use super::Result;
+use super::{Diagnostics, Code};
 
 /// Supported dependency protocols
 ///
 /// Forces lowercase values of this enum to be used
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum DependencyProtocol {
     /// HTTP REST dependency
@@ -25,7 +25,7 @@ impl Default for DependencyProtocol {
 }
 
 /// Dependency of a service
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Dependency {
     /// Name of service relied upon (used to goto dependent manifest)
@@ -46,17 +46,18 @@ fn default_api_version() -> String { "v1".into() }
 
 
 impl Dependency {
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         // self.name must exist in services/
         let dpth = Path::new(".").join("services").join(self.name.clone());
         if !dpth.is_dir() {
-            bail!("Service {} does not exist in services/", self.name);
+            d.error(Code::UnknownReference, "name", format!("Service {} does not exist in services/", self.name));
         }
         if self.api != "" {
             let vstr = self.api.chars().skip_while(|ch| *ch == 'v').collect::<String>();
-            let ver : usize = vstr.parse()?;
-            trace!("Parsed api version of dependency {} as {}", self.name.clone(), ver);
+            let parsed = vstr.parse::<usize>().map_err(Into::into);
+            if let Some(ver) = d.check(Code::InvalidValue, "api", parsed) {
+                trace!("Parsed api version of dependency {} as {}", self.name.clone(), ver);
+            }
         }
-        Ok(())
     }
 }
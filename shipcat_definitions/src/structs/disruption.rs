@@ -7,10 +7,10 @@
 /// This work is left here in case it becomes useful.
 /// Users may wish to look at rollingupdate.rs instead, which has a useful alternative.
 
-use super::{Result};
+use super::{Diagnostics, Code};
 
 // Untagged enum to get around the weird validation
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(untagged)]
 pub enum AvailabilityPolicy {
     Percentage(String),
@@ -19,25 +19,28 @@ pub enum AvailabilityPolicy {
 // Kube has a weird hybrid type for this intstr.IntOrString: IntVal | StrVal
 // if it's a string, then '[0-9]+%!' has to parse
 impl AvailabilityPolicy {
-    fn verify(&self, name: &str, maxNumber: u32) -> Result<()> {
+    fn verify(&self, d: &mut Diagnostics, name: &str, maxNumber: u32) {
         match self {
             AvailabilityPolicy::Unsigned(ref n) => {
                 if n > &maxNumber {
-                    bail!("Cannot have {} set higher than replicaCount {}", name, maxNumber);
+                    d.error(Code::OutOfRange, name,
+                        format!("Cannot have {} set higher than replicaCount {}", name, maxNumber));
                 }
             },
             AvailabilityPolicy::Percentage(s) => {
                 if !s.ends_with('%') {
-                    bail!("{} must end with a '%' sign", name);
+                    d.error(Code::InvalidValue, name, format!("{} must end with a '%' sign", name));
+                    return;
                 }
                 let digits = s.chars().take_while(|ch| *ch != '%').collect::<String>();
-                let res : u32 = digits.parse()?;
-                if res > 100 {
-                    bail!("Percentage value for {} cannot exceed 100", name);
+                let parsed = digits.parse::<u32>().map_err(Into::into);
+                if let Some(res) = d.check(Code::InvalidValue, name, parsed) {
+                    if res > 100 {
+                        d.error(Code::OutOfRange, name, format!("Percentage value for {} cannot exceed 100", name));
+                    }
                 }
             }
         }
-        Ok(())
     }
 }
 
@@ -46,7 +49,7 @@ impl AvailabilityPolicy {
 /// Users need to set exactly one of these to pass validation.
 /// The values are "how many replicas" when integer values are used,
 /// and "what percentage of total replicas" when a % is added to the string.
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct DisruptionBudget {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub minAvailable: Option<AvailabilityPolicy>,
@@ -55,19 +58,18 @@ pub struct DisruptionBudget {
 }
 
 impl DisruptionBudget {
-     pub fn verify(&self, replicas: u32) -> Result<()> {
+     pub fn verify(&self, d: &mut Diagnostics, replicas: u32) {
         if self.minAvailable.is_none() && self.maxUnavailable.is_none() {
-            bail!("Need to set one of minAvailable or maxUnavailable in disruptionBudget");
+            d.error(Code::MissingField, "", "Need to set one of minAvailable or maxUnavailable in disruptionBudget");
         }
         if self.minAvailable.is_some() && self.maxUnavailable.is_some() {
-            bail!("Cannot set both minAvailable and maxUnavailable in disruptionBudget");
+            d.error(Code::ConflictingFields, "", "Cannot set both minAvailable and maxUnavailable in disruptionBudget");
         }
         if let Some(ref ma) = &self.minAvailable {
-            ma.verify("minAvailable", replicas)?;
+            ma.verify(d, "minAvailable", replicas);
         }
         if let Some(ref mu) = &self.maxUnavailable {
-            mu.verify("maxUnavailable", replicas)?;
+            mu.verify(d, "maxUnavailable", replicas);
         }
-        Ok(())
      }
 }
@@ -2,14 +2,14 @@
 //! Supports redis with cluster mode disabled (single shard - up to 5 read replicas)
 //! https://docs.aws.amazon.com/AmazonElastiCache/latest/red-ug/Replication.Redis-RedisCluster.html
 
-use super::Result;
+use super::{Diagnostics, Code};
 use super::Metadata;
 
 /// ElastiCache Node Types
 ///
 /// Subset of the official [AWS ElastiCache node type list](https://aws.amazon.com/elasticache/pricing/).
 /// Only current generation (m5 + r5) + along with cheap t2 nodes
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, JsonSchema)]
 pub enum NodeType {
     // Cheap t2 nodes
     #[serde(rename = "cache.t2.micro")]
@@ -47,7 +47,7 @@ pub enum NodeType {
 /// - [aws elasticache cluster replication](https://docs.aws.amazon.com/AmazonElastiCache/latest/red-ug/Replication.Redis-RedisCluster.html)
 /// - [aws elasticache cluster replication groups](https://docs.aws.amazon.com/AmazonElastiCache/latest/red-ug/Replication.CreatingReplGroup.ExistingCluster.html)
 /// - [terraform aws_elasticache_replication_group](https://www.terraform.io/docs/providers/aws/r/elasticache_replication_group.html)
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct ElastiCache {
     /// Name of service (filled from manifest name)
     #[serde(skip_deserializing)]
@@ -72,15 +72,14 @@ pub struct ElastiCache {
 }
 
 impl ElastiCache {
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         let num = self.nodes.unwrap(); // must exist by implicits
         if num < 1 {
-            bail!("Need at least 1 node (cluster includes the master)")
+            d.error(Code::OutOfRange, "nodes", "Need at least 1 node (cluster includes the master)")
         }
         if num > 6 {
-            bail!("Need less than 6 nodes (non-cluster mode has max 5 read replicas)")
+            d.error(Code::OutOfRange, "nodes", "Need less than 6 nodes (non-cluster mode has max 5 read replicas)")
         }
-        Ok(())
     }
 
     pub fn implicits(&mut self, svc: &str, md: &Metadata) {
@@ -1,4 +1,5 @@
-use super::Result;
+use super::{Diagnostics, Code, Result};
+use crate::vault::SecretRef;
 use std::collections::{BTreeMap, BTreeSet};
 use std::mem;
 
@@ -6,6 +7,7 @@ use std::mem;
 ///
 /// These have a few special convenience behaviours:
 /// "IN_VAULT" values is replaced with value from vault/secret/folder/service/KEY
+/// "IN_VAULT:path#key" values is replaced with the `key` of vault/secret/folder/path
 /// One off `tera` templates are calculated with a limited template context
 ///
 /// IN_VAULT secrets will all be put in a single kubernetes `Secret` object.
@@ -19,6 +21,9 @@ use std::mem;
 ///   # vault lookup:
 ///   DATABASE_URL: IN_VAULT
 ///
+///   # shared vault lookup:
+///   POSTGRES_PASSWORD: IN_VAULT:shared/postgres#password
+///
 ///   # templated evars:
 ///   INTERNAL_AUTH_URL: "{{ base_urls.services }}/auth/internal"
 ///   AUTH_ID: "{{ kong.consumers['webapp'].oauth_client_id }}"
@@ -33,7 +38,7 @@ use std::mem;
 ///
 /// The `as_secret` destinction only serves to put `AUTH_SECRET` into `Manifest::secrets`.
 #[derive(Serialize, Clone, Default)]
-#[cfg_attr(feature = "crd", derive(Deserialize))]
+#[cfg_attr(feature = "crd", derive(Deserialize, JsonSchema))]
 pub struct EnvVars {
     /// Plain text (non-secret) environment variables
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
@@ -48,10 +53,6 @@ pub struct EnvVars {
 
 
 impl EnvVars {
-    fn is_vault_secret(value: &String) -> bool {
-        value == "IN_VAULT"
-    }
-
     fn template_secret_value(value: &String) -> Option<String> {
         let prefix = "SHIPCAT_SECRET::";
         if value.starts_with(prefix) {
@@ -61,29 +62,38 @@ impl EnvVars {
         }
     }
 
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         for k in self.plain.keys() {
             if k != &k.to_uppercase()  {
-                bail!("Env vars need to be uppercase, found: {}", k);
+                d.error(Code::InvalidName, k, format!("Env vars need to be uppercase, found: {}", k));
+            }
+        }
+        for (k, v) in &self.plain {
+            if let Some(Err(e)) = SecretRef::parse(v) {
+                d.error(Code::InvalidValue, k, e.to_string());
             }
         }
-        Ok(())
     }
 
-    // Remove variables with a value "IN_VAULT", mark them as a secret and return them.
-    pub fn vault_secrets(&mut self) -> BTreeSet<String> {
-        let mut plain = BTreeMap::new();
-        let mut vs = BTreeSet::new();
-        for (k, v) in self.plain.iter() {
-            if EnvVars::is_vault_secret(&v) {
-                vs.insert(k.to_string());
-                self.secrets.insert(k.to_string());
-            } else {
-                plain.insert(k.to_string(), v.to_string());
+    /// Vault references in the plain variables
+    pub fn vault_refs(&self) -> Result<BTreeMap<String, SecretRef>> {
+        let mut refs = BTreeMap::new();
+        for (k, v) in &self.plain {
+            if let Some(r) = SecretRef::parse(v) {
+                refs.insert(k.to_string(), r?);
             }
         }
-        mem::replace(&mut self.plain, plain);
-        vs
+        Ok(refs)
+    }
+
+    // Remove variables referencing vault, mark them as a secret and return their references.
+    pub fn vault_secrets(&mut self) -> Result<BTreeMap<String, SecretRef>> {
+        let vs = self.vault_refs()?;
+        for k in vs.keys() {
+            self.plain.remove(k);
+            self.secrets.insert(k.to_string());
+        }
+        Ok(vs)
     }
 
     // Remove secrets generated from templates from the plain variables, mark them as a secret and return them.
@@ -121,3 +131,18 @@ impl<'de> Deserialize<'de> for EnvVars {
         Ok(EnvVars { plain, secrets })
     }
 }
+
+#[cfg(feature = "filesystem")]
+use schemars::{JsonSchema, gen::SchemaGenerator, schema::Schema};
+
+/// Schema matches the custom `Deserialize` above; a plain map of strings
+#[cfg(feature = "filesystem")]
+impl JsonSchema for EnvVars {
+    fn schema_name() -> String {
+        "EnvVars".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        <BTreeMap<String, String>>::json_schema(gen)
+    }
+}
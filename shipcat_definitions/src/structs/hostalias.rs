@@ -1,9 +1,9 @@
 use regex::Regex;
-use super::Result;
+use super::{Diagnostics, Code};
 
 // HostAlias support for all pods regardless of network configuration.
 
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
 pub struct HostAlias {
     /// ip address string
     pub ip: String,
@@ -13,22 +13,22 @@ pub struct HostAlias {
 
 impl HostAlias {
     /// Verify syntax
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         // Commonly accepted hostname regex from https://stackoverflow.com/questions/106179/regular-expression-to-match-dns-hostname-or-ip-address
         let ip_re = Regex::new(r"^(([0-9]|[1-9][0-9]|1[0-9]{2}|2[0-4][0-9]|25[0-5])\.){3}([0-9]|[1-9][0-9]|1[0-9]{2}|2[0-4][0-9]|25[0-5])$").unwrap();
         if self.ip == "" || !ip_re.is_match(&self.ip){
-            bail!("The ip address for the host alias is incorrect");
+            d.error(Code::InvalidValue, "ip", "The ip address for the host alias is incorrect");
         }
         if self.hostnames.is_empty() {
-            bail!("At least one hostname must be specified for the host alias");
+            d.error(Code::MissingField, "hostnames", "At least one hostname must be specified for the host alias");
         }
-        for hostname in &self.hostnames {
+        for (i, hostname) in self.hostnames.iter().enumerate() {
             // Commonly accepted ip address regex from https://stackoverflow.com/questions/106179/regular-expression-to-match-dns-hostname-or-ip-address
             let host_re = Regex::new(r"^(([a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9\-]*[a-zA-Z0-9])\.)*([A-Za-z0-9]|[A-Za-z0-9][A-Za-z0-9\-]*[A-Za-z0-9])$").unwrap();
             if !host_re.is_match(&hostname) {
-                bail!("The hostname {} is incorrect for {}", hostname, self.ip);
+                d.error(Code::InvalidValue, &format!("hostnames[{}]", i),
+                    format!("The hostname {} is incorrect for {}", hostname, self.ip));
             }
         }
-        Ok(())
     }
 }
@@ -1,7 +1,7 @@
 use regex::Regex;
-use super::Result;
+use super::{Diagnostics, Code};
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 pub struct InitContainer {
     pub name: String,
     pub image: String,
@@ -10,14 +10,15 @@ pub struct InitContainer {
 
 impl InitContainer {
     /// Verify syntax
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         let re = Regex::new(r"(?:[a-z]+/)?([a-z]+)(?::[0-9]+)?").unwrap();
         if !re.is_match(&self.image) {
-            bail!("The init container {} does not seem to match a valid image registry", self.name);
+            d.error(Code::InvalidValue, "image",
+                format!("The init container {} does not seem to match a valid image registry", self.name));
         }
         if self.command.is_empty() {
-            bail!("A command must be specified for the init container {}", self.name);
+            d.error(Code::MissingField, "command",
+                format!("A command must be specified for the init container {}", self.name));
         }
-        Ok(())
     }
 }
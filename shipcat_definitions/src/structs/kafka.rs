@@ -1,7 +1,7 @@
 use crate::region::{Region};
 
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct Kafka {
     #[serde(default)]
     pub mountPodIP: bool,
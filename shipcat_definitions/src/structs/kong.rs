@@ -1,9 +1,8 @@
-use super::{Result, Region};
-use std::ops::Not;
+use super::{Diagnostics, Code, Region};
 use std::collections::BTreeMap;
 
 /// Kong setup for a service
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Kong {
     /// Auto-populated name of service
@@ -24,23 +23,23 @@ pub struct Kong {
 
     /// Whether the oauth2 plugin will be applied or not to this api
     #[serde(default, skip_serializing)]
-    pub unauthenticated: bool,
+    pub unauthenticated: Option<bool>,
 
     /// Whether or not to apply the ip whitelisting (?)
-    #[serde(default, skip_serializing_if = "Not::not")]
-    pub internal: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub internal: Option<bool>,
 
     /// Marker for gate to let external traffic in or not
-    #[serde(default, skip_serializing_if = "Not::not")]
-    pub publiclyAccessible: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publiclyAccessible: Option<bool>,
 
     /// Whether to allow cookie based authentication for front-end applications
-    #[serde(default, skip_serializing_if = "Not::not")]
-    pub cookie_auth: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookie_auth: Option<bool>,
 
     /// Whether or not to CSRF for cookie auths (mattmalones plugin)
-    #[serde(default, skip_serializing_if = "Not::not")]
-    pub cookie_auth_csrf: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookie_auth_csrf: Option<bool>,
 
 
     /// Simple path based routing
@@ -72,8 +71,10 @@ pub struct Kong {
     ///
     /// false => application has to listen on the `uris` parameter (e.g. /raftcat)
     /// true => application has to listen on `/`, but use prefix agnostic urls everywhere.
+    ///
+    /// Defaults to false.
     #[serde(default)]
-    pub strip_uri: bool,
+    pub strip_uri: Option<bool>,
 
     /// Preserves host headers to backend service
     ///
@@ -82,8 +83,8 @@ pub struct Kong {
     /// meaning the upstream Host header will be extracted from the configured upstream_url.
     ///
     /// Shipcat assumes a default of true, as the normal use case is to have this enabled.
-    #[serde(default = "preserve_host_default")]
-    pub preserve_host: bool,
+    #[serde(default)]
+    pub preserve_host: Option<bool>,
 
     /// Configuration parameters for Cross Origin Resource Sharing plugin
     ///
@@ -147,10 +148,13 @@ pub struct Kong {
 impl Kong {
     pub fn implicits(&mut self, svc: String, reg: Region, tophosts: Vec<String>) {
         self.name = svc;
-        if self.unauthenticated {
+        if self.unauthenticated.unwrap_or(false) {
             self.auth = Authentication::None;
         }
         // Generate upstream_url for an in-kubernetes service
+        // unset flags get their defaults once overrides are merged
+        self.strip_uri.get_or_insert(false);
+        self.preserve_host.get_or_insert(true);
         if self.upstream_url.is_empty() {
           self.upstream_url = format!("http://{}.{}.svc.cluster.local", self.name, reg.namespace);
         }
@@ -164,22 +168,10 @@ impl Kong {
             self.hosts = Some(tophosts.join(","));
         }
     }
-
-    /// Merge in fields from an override, if they're set
-    pub fn merge(&mut self, other: Kong) {
-        if let Some(cors) = other.cors {
-            self.cors = Some(cors.clone());
-        }
-        if ! other.additional_internal_ips.is_empty() {
-            self.additional_internal_ips = other.additional_internal_ips.clone();
-        }
-    }
 }
 
-fn preserve_host_default() -> bool { true }
-
 /// Cors plugin data
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Cors {
     pub credentials: bool,
@@ -193,7 +185,7 @@ pub struct Cors {
 }
 
 /// Babylon Auth Header plugin data
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct BabylonAuthHeader {
     pub auth_service: String,
@@ -203,34 +195,36 @@ pub struct BabylonAuthHeader {
 }
 
 impl Kong {
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         if self.uris.is_none() && self.host.is_none() {
-            bail!("One of `uris` or `host` needs to be defined for Kong");
+            d.error(Code::MissingField, "uris", "One of `uris` or `host` needs to be defined for Kong");
         }
         if self.uris.is_some() && self.host.is_some() {
-            bail!("Only one of `uris` or `host` needs to be defined for Kong");
+            d.error(Code::ConflictingFields, "host", "Only one of `uris` or `host` needs to be defined for Kong");
         }
         match self.auth {
             Authentication::OAuth2 => {},
             Authentication::Jwt => {
                 if let Some(true) = self.oauth2_extension_plugin {
-                    bail!("`oauth2_extension_plugin` not supported when Kong `auth` is `jwt`");
+                    d.error(Code::ConflictingFields, "oauth2_extension_plugin",
+                        "`oauth2_extension_plugin` not supported when Kong `auth` is `jwt`");
                 }
             }
             Authentication::None => {
                 if let Some(_) = self.oauth2_anonymous {
-                    bail!("`oauth2_anonymous` not supported when Kong `auth` is `none`");
+                    d.error(Code::ConflictingFields, "oauth2_anonymous",
+                        "`oauth2_anonymous` not supported when Kong `auth` is `none`");
                 }
                 if let Some(true) = self.oauth2_extension_plugin {
-                    bail!("`oauth2_extension_plugin` not supported when Kong `auth` is `none`");
+                    d.error(Code::ConflictingFields, "oauth2_extension_plugin",
+                        "`oauth2_extension_plugin` not supported when Kong `auth` is `none`");
                 }
             }
         }
-        Ok(())
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub enum Authentication {
     None,
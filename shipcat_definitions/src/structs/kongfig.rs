@@ -395,11 +395,11 @@ pub fn kongfig_apis(from: BTreeMap<String, Kong>, config: KongConfig) -> Vec<Api
         }
 
         // If enabled: JsonCookies and JsonCookiesCsrf
-        if v.cookie_auth {
+        if v.cookie_auth.unwrap_or(false) {
             plugins.push(ApiPlugin::JsonCookiesToHeaders(PluginBase::default()));
         }
 
-        if v.cookie_auth_csrf {
+        if v.cookie_auth_csrf.unwrap_or(false) {
             plugins.push(ApiPlugin::JsonCookiesCsrf(PluginBase::default()));
         }
 
@@ -411,7 +411,7 @@ pub fn kongfig_apis(from: BTreeMap<String, Kong>, config: KongConfig) -> Vec<Api
                 hosts: v.hosts.map(splitter),
                 uris: v.uris.map(|s| vec![s]),
                 preserve_host: true,
-                strip_uri: v.strip_uri,
+                strip_uri: v.strip_uri.unwrap_or(false),
                 upstream_connect_timeout: v.upstream_connect_timeout.unwrap_or(30000),
                 upstream_read_timeout: v.upstream_read_timeout.unwrap_or(30000),
                 upstream_send_timeout: v.upstream_send_timeout.unwrap_or(30000),
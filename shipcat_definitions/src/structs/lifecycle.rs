@@ -1,9 +1,9 @@
-use super::Result;
+use super::{Diagnostics, Code};
 
 /// A straight port of Kubernetes Container Lifecycle Events
 ///
 /// From https://kubernetes.io/docs/tasks/configure-pod-container/attach-handler-lifecycle-event/
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct LifeCycle {
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -12,13 +12,13 @@ pub struct LifeCycle {
     pub preStop: Option<LifeCycleHandler>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct LifeCycleHandler {
    pub exec: ExecAction,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ExecAction {
     command: Vec<String>,
@@ -27,28 +27,26 @@ pub struct ExecAction {
 // TODO: support HttpGetAction + TcpSocketAction
 
 impl LifeCycle {
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         if self.postStart.is_none() && self.preStop.is_none() {
-            bail!("Need to set one of postStart or preStop in lifecycle");
+            d.error(Code::MissingField, "", "Need to set one of postStart or preStop in lifecycle");
         }
         if self.postStart.is_some() && self.preStop.is_some() {
-            bail!("Cannot set both postStart and preStop in lifecycle");
+            d.error(Code::ConflictingFields, "", "Cannot set both postStart and preStop in lifecycle");
         }
         if let Some(ref start) = self.postStart {
-            start.verify()?;
+            d.nested("postStart", |d| start.verify(d));
         }
         if let Some(ref stop) = self.preStop {
-            stop.verify()?;
+            d.nested("preStop", |d| stop.verify(d));
         }
-        Ok(())
     }
 }
 
 impl LifeCycleHandler {
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         if self.exec.command.is_empty() {
-            bail!("Cannot have empty lifecycle exec commands");
+            d.error(Code::MissingField, "exec.command", "Cannot have empty lifecycle exec commands");
         }
-        Ok(())
     }
 }
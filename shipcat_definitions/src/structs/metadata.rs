@@ -1,11 +1,11 @@
 use regex::Regex;
 use std::ops::{Deref, DerefMut};
 
-use super::Result;
+use super::{Result, Diagnostics, Code};
 use crate::config::{Team, SlackParameters};
 
 /// Contact data
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct Contact {
     /// Free text name
     pub name: String,
@@ -19,41 +19,39 @@ pub struct Contact {
     pub github: Option<String>,
 }
 impl Contact {
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         if self.name.is_empty() {
-            bail!("Contact name cannot be empty")
+            d.error(Code::MissingField, "name", "Contact name cannot be empty")
         }
         if !self.slack.starts_with('@') {
-            bail!("Contact slack handle needs to start with the slack guid '@U...' - got {}", self.slack)
+            d.error(Code::InvalidValue, "slack",
+                format!("Contact slack handle needs to start with the slack guid '@U...' - got {}", self.slack))
         }
         if self.slack.contains('|') {
-            bail!("Contact slack user id invalid - got {}", self.slack)
+            d.error(Code::InvalidValue, "slack", format!("Contact slack user id invalid - got {}", self.slack))
         }
         if let Some(ref gh) = &self.github {
             if gh.starts_with('@') || gh.contains('/') {
-               bail!("github id must be the raw username only - got {}", gh)
+               d.error(Code::InvalidValue, "github", format!("github id must be the raw username only - got {}", gh))
             }
             // TODO: check members of org!
         }
-        Ok(())
     }
 }
 
 /// Slack channel verifier
-#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug, JsonSchema)]
 pub struct SlackChannel(String);
 impl SlackChannel {
     pub fn new(chan: &str) -> Self {
         SlackChannel(chan.into())
     }
 
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         let channelre = Regex::new(r"^#[a-z0-9._-]+$").unwrap();
         if !channelre.is_match(&self.0) {
-            bail!("channel is invalid: {}", self.0)
+            d.error(Code::InvalidValue, "", format!("channel is invalid: {}", self.0))
         }
-
-        Ok(())
     }
 
     pub fn link(&self, params: &SlackParameters) -> String {
@@ -76,7 +74,7 @@ impl DerefMut for SlackChannel {
 }
 
 /// Metadata for a service
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[cfg_attr(test, derive(Default))]
 #[serde(deny_unknown_fields)]
 pub struct Metadata {
@@ -122,34 +120,34 @@ impl Metadata {
 }
 
 impl Metadata {
-    pub fn verify(&self, teams: &[Team]) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics, teams: &[Team]) {
         let ts = teams.to_vec().into_iter().map(|t| t.name).collect::<Vec<_>>();
         if !ts.contains(&self.team) {
-            bail!("Illegal team name {} not found in the config", self.team);
-        }
-        for cc in &self.contacts {
-            cc.verify()?;
+            d.error(Code::UnknownReference, "team", format!("Illegal team name {} not found in the config", self.team));
         }
+        d.each("contacts", &self.contacts, |d, cc| cc.verify(d));
         let re = Regex::new(r"[a-z0-9\-\.\{\}]").unwrap();
+        let sanityre = Regex::new(r"\{\{.?version.?\}\}").unwrap();
         if !re.is_match(&self.gitTagTemplate) {
-            bail!("gitTagTemplate {} is of invalid format", self.gitTagTemplate);
+            d.error(Code::InvalidValue, "gitTagTemplate",
+                format!("gitTagTemplate {} is of invalid format", self.gitTagTemplate));
         }
-        let sanityre = Regex::new(r"\{\{.?version.?\}\}").unwrap();
-        if !sanityre.is_match(&self.gitTagTemplate) {
-            bail!("gitTagTemplate {} does not dereference {{ version }}", self.gitTagTemplate);
+        else if !sanityre.is_match(&self.gitTagTemplate) {
+            d.error(Code::InvalidValue, "gitTagTemplate",
+                format!("gitTagTemplate {} does not dereference {{ version }}", self.gitTagTemplate));
         }
         if let Some(channel) = &self.support {
-            channel.verify()?;
+            d.nested("support", |d| channel.verify(d));
         }
         if let Some(channel) = &self.notifications {
-            channel.verify()?;
+            d.nested("notifications", |d| channel.verify(d));
         }
         if let Some(runbook) = &self.runbook {
             if !runbook.ends_with(".md") && !runbook.ends_with(".rt") {
-                bail!("Runbook must be in markdown or restructured text in the service repo");
+                d.error(Code::InvalidValue, "runbook",
+                    "Runbook must be in markdown or restructured text in the service repo");
             }
         }
-        Ok(())
     }
 }
 
@@ -158,6 +156,7 @@ mod tests {
     use super::Metadata;
     use super::SlackChannel;
     use super::default_format_string;
+    use super::Diagnostics;
 
     #[test]
     fn version_tpl() {
@@ -180,16 +179,18 @@ mod tests {
     #[test]
     fn valid_slack_channel() {
         let sc = SlackChannel::new("#dev-platform");
-        let valid = sc.verify();
-        println!("{:?}", valid);
-        assert!(valid.is_ok());
+        let mut d = Diagnostics::new();
+        sc.verify(&mut d);
+        println!("{:?}", d);
+        assert!(!d.has_errors());
     }
 
     #[test]
     fn invalid_slack_channel() {
         let sc = SlackChannel::new("# iaminvalidåß∂ƒ••");
-        let valid = sc.verify();
-        println!("{:?}", valid);
-        assert!(valid.is_err());
+        let mut d = Diagnostics::new();
+        sc.verify(&mut d);
+        println!("{:?}", d);
+        assert!(d.has_errors());
     }
 }
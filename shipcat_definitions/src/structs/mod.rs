@@ -2,6 +2,8 @@
 
 /// Allow normal error handling from structs
 pub use super::Result;
+/// Verifiers collect problems into diagnostics
+pub use super::diagnostics::{Diagnostics, Code};
 /// Verify trait gets the Region and Team
 pub use super::{Region, Team};
 /// Verify traits sometimes need to cross reference stuff from other manifests
@@ -64,6 +66,12 @@ pub use self::probes::Probe;
 /// Kubernetes rolling-update settings
 pub mod rollingupdate;
 pub use self::rollingupdate::RollingUpdate;
+/// Canary upgrade settings
+pub mod canary;
+pub use self::canary::Canary;
+/// Blue/green deployment settings
+pub mod bluegreen;
+pub use self::bluegreen::BlueGreen;
 /// Kubernetes horizontal pod autoscaler
 pub mod autoscaling;
 /// Kuberneter tolerations
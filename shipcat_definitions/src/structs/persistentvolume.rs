@@ -1,7 +1,7 @@
-use super::Result;
+use super::{Diagnostics, Code};
 use super::resources::parse_memory;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct PersistentVolume {
     pub name: String,
     pub claim: String,
@@ -11,11 +11,11 @@ pub struct PersistentVolume {
 }
 
 impl PersistentVolume {
-    pub fn verify(&self) -> Result<()> {
-        let size = parse_memory(&self.size)?;
-        if size > 100.0*1024.0*1024.0*1024.0 {
-            bail!("Memory size set to more than 100 GB of persistent memory")
+    pub fn verify(&self, d: &mut Diagnostics) {
+        if let Some(size) = d.check(Code::InvalidValue, "size", parse_memory(&self.size)) {
+            if size > 100.0*1024.0*1024.0*1024.0 {
+                d.error(Code::OutOfRange, "size", "Memory size set to more than 100 GB of persistent memory")
+            }
         }
-        Ok(())
     }
 }
@@ -1,6 +1,6 @@
-use super::Result;
+use super::{Diagnostics, Code};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum PortProtocol {
     Tcp,
@@ -13,7 +13,7 @@ impl Default for PortProtocol {
 }
 
 /// Port to open on a container
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct Port {
     /// Name of the port
     pub name: String,
@@ -25,8 +25,9 @@ pub struct Port {
 }
 
 impl Port {
-    pub fn verify(&self) -> Result<()> {
-        assert_ne!(self.port, 80, "Port should not be 80");
-        Ok(())
+    pub fn verify(&self, d: &mut Diagnostics) {
+        if self.port == 80 {
+            d.error(Code::InvalidValue, "port", "Port should not be 80");
+        }
     }
 }
@@ -1,7 +1,7 @@
-use super::Result;
+use super::{Diagnostics, Code};
 
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct HttpGet {
     /// Uri path to GET (i.e. / or /health)
@@ -15,7 +15,7 @@ pub struct HttpGet {
 }
 fn http_get_default_port() -> String { "http".into() }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct HttpHeader {
     pub name: String,
@@ -23,7 +23,7 @@ pub struct HttpHeader {
 }
 
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Exec {
     /// Command to execute in the container
@@ -31,14 +31,14 @@ pub struct Exec {
 }
 
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct TcpSocket {
     pub port: String,
 }
 
 /// Liveness or readiness Probe
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Probe {
     /// Http Get probe
@@ -69,13 +69,12 @@ fn period_seconds_default() -> u32 { 5 }
 
 
 impl Probe {
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         if self.httpGet.is_some() && (self.exec.is_some() || self.tcpSocket.is_some()) {
-            bail!("Probe needs to have at most one of 'httpGet' or 'exec'");
+            d.error(Code::ConflictingFields, "httpGet", "Probe needs to have at most one of 'httpGet' or 'exec'");
         }
         if self.httpGet.is_none() && self.exec.is_none() && self.tcpSocket.is_none() {
-            bail!("Probe needs to define one of 'httpGet', 'exec', 'tcpSocket");
+            d.error(Code::MissingField, "", "Probe needs to define one of 'httpGet', 'exec', 'tcpSocket");
         }
-        Ok(())
     }
 }
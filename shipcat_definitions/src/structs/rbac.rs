@@ -1,10 +1,10 @@
-use super::Result;
+use super::{Diagnostics, Code};
 
 /// RBAC (Role-Based Access Control)
 ///
 /// Designed for services which requires escalated privileges
 /// Used to generate roles and role bindings in kubernetes
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Rbac {
     /// API groups containing resources (defined below)
@@ -15,7 +15,7 @@ pub struct Rbac {
     pub verbs: Vec<AllowedVerbs>
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum AllowedApiGroups {
     #[serde(rename = "")]
@@ -26,7 +26,7 @@ pub enum AllowedApiGroups {
     Babylontech,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum AllowedResources {
     Deployments,
@@ -52,7 +52,7 @@ pub enum AllowedResources {
 
 /// We don't allow eg Delete or other operations for security reasons (least privilege).
 /// More operations can be added if required but due diligence would be sane.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum AllowedVerbs {
     List,
@@ -61,17 +61,15 @@ pub enum AllowedVerbs {
 }
 
 impl Rbac {
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         if self.apiGroups.is_empty() {
-            bail!("RBAC needs to have at least one item in apiGroups");
+            d.error(Code::MissingField, "apiGroups", "RBAC needs to have at least one item in apiGroups");
         }
         if self.resources.is_empty() {
-            bail!("RBAC needs to have at least one item in resources");
+            d.error(Code::MissingField, "resources", "RBAC needs to have at least one item in resources");
         }
         if self.verbs.is_empty() {
-            bail!("RBAC needs to have at least one item in verbs");
+            d.error(Code::MissingField, "verbs", "RBAC needs to have at least one item in verbs");
         }
-
-        Ok(())
     }
 }
@@ -1,10 +1,10 @@
-use super::Result;
+use super::{Diagnostics, Code};
 use super::Metadata;
 
 /// Supported RDS engines
 ///
 /// Subset of the official [AWS RDS database engines](https://aws.amazon.com/rds/).
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum RdsEngine {
     Postgres,
@@ -15,7 +15,7 @@ pub enum RdsEngine {
 ///
 /// Subset of the official [AWS RDS instance type list](https://aws.amazon.com/rds/instance-types/).
 /// Current gen (m5 + t3) along with older m4 + t2.
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, JsonSchema)]
 pub enum InstanceClass {
     // Burstable T2 instances for compat
     #[serde(rename = "db.t2.micro")]
@@ -61,7 +61,7 @@ pub enum InstanceClass {
 /// Simplified input for configuring a database for your service.
 /// Based loosely on the inputs from
 /// [terraform aws_db_instance](https://www.terraform.io/docs/providers/aws/r/db_instance.html).
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct Rds {
     /// Name of service (filled from manifest name)
     #[serde(skip_deserializing)]
@@ -93,12 +93,12 @@ pub struct Rds {
 }
 
 impl Rds {
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         if self.size > 20_000 { // gp2 limits for rds
-            bail!("Cannot allocate RDS databases larger than than 20 TB")
+            d.error(Code::OutOfRange, "size", "Cannot allocate RDS databases larger than than 20 TB")
         }
         if self.size < 20 {
-            bail!("Minimum allocatable RDS database is 20 GB") // rds limit
+            d.error(Code::OutOfRange, "size", "Minimum allocatable RDS database is 20 GB") // rds limit
         }
         let _ev = &self.version;
         match self.engine {
@@ -106,8 +106,6 @@ impl Rds {
             RdsEngine::Postgres => {} // maybe check if ev starts with 9, then at least 9.6
             RdsEngine::Mysql => {} // maybe check that ev is 5.7* or 8.* something
         }
-
-        Ok(())
     }
 
     pub fn implicits(&mut self, svc: &str, md: &Metadata) {
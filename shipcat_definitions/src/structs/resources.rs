@@ -1,5 +1,5 @@
 use std::ops::{Add, AddAssign, Mul};
-use super::Result;
+use super::{Result, Diagnostics, Code};
 
 // Kubernetes resouce structs
 //
@@ -10,7 +10,7 @@ use super::Result;
 // implemented to be a bit more useful, as well as some to convert between them.
 
 /// Kubernetes resource requests
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ResourceRequest<T> {
     /// CPU request string
@@ -21,7 +21,7 @@ pub struct ResourceRequest<T> {
 }
 
 /// Kubernetes resource limits
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ResourceLimit<T> {
     /// CPU limit string
@@ -34,7 +34,7 @@ pub struct ResourceLimit<T> {
 /// Kubernetes resources
 ///
 /// This can be inlined straight into a container spec at the moment
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Resources<T> {
     /// Resource requests for k8s
@@ -118,33 +118,37 @@ impl Resources<f64> {
 
 impl Resources<String> {
     // TODO: look at config for limits?
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, d: &mut Diagnostics) {
         // (We can unwrap all the values as we assume implicit called!)
-        let n = self.normalised()?;
-        let req = &n.requests;
-        let lim = &n.limits;
+        let req_cpu = d.check(Code::InvalidValue, "requests.cpu", parse_cpu(&self.requests.cpu));
+        let req_mem = d.check(Code::InvalidValue, "requests.memory", parse_memory(&self.requests.memory));
+        let lim_cpu = d.check(Code::InvalidValue, "limits.cpu", parse_cpu(&self.limits.cpu));
+        let lim_mem = d.check(Code::InvalidValue, "limits.memory", parse_memory(&self.limits.memory));
 
         // 1.1 limits >= requests
-        if req.cpu > lim.cpu {
-            bail!("Requested more CPU than what was limited");
+        if let (Some(req), Some(lim)) = (req_cpu, lim_cpu) {
+            if req > lim {
+                d.error(Code::OutOfRange, "requests.cpu", "Requested more CPU than what was limited");
+            }
         }
-        if req.memory > lim.memory {
-            bail!("Requested more memory than what was limited");
+        if let (Some(req), Some(lim)) = (req_mem, lim_mem) {
+            if req > lim {
+                d.error(Code::OutOfRange, "requests.memory", "Requested more memory than what was limited");
+            }
         }
         // 1.2 sanity numbers (based on c5.9xlarge)
-        if req.cpu > 36.0 {
-            bail!("Requested more than 36 cores");
+        if req_cpu.map_or(false, |c| c > 36.0) {
+            d.error(Code::OutOfRange, "requests.cpu", "Requested more than 36 cores");
         }
-        if req.memory > 72.0*1024.0*1024.0*1024.0 {
-            bail!("Requested more than 72 GB of memory");
+        if req_mem.map_or(false, |m| m > 72.0*1024.0*1024.0*1024.0) {
+            d.error(Code::OutOfRange, "requests.memory", "Requested more than 72 GB of memory");
         }
-        if lim.cpu > 36.0 {
-            bail!("CPU limit set to more than 36 cores");
+        if lim_cpu.map_or(false, |c| c > 36.0) {
+            d.error(Code::OutOfRange, "limits.cpu", "CPU limit set to more than 36 cores");
         }
-        if lim.memory > 72.0*1024.0*1024.0*1024.0 {
-            bail!("Memory limit set to more than 72 GB of memory");
+        if lim_mem.map_or(false, |m| m > 72.0*1024.0*1024.0*1024.0) {
+            d.error(Code::OutOfRange, "limits.memory", "Memory limit set to more than 72 GB of memory");
         }
-        Ok(())
     }
 }
 
@@ -1,7 +1,7 @@
-use super::{Result};
+use super::{Diagnostics, Code};
 
 // Untagged enum to get around the weird validation
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(untagged)]
 pub enum AvailabilityPolicy {
     Percentage(String),
@@ -10,25 +10,28 @@ pub enum AvailabilityPolicy {
 // Kube has a weird hybrid type for this intstr.IntOrString: IntVal | StrVal
 // if it's a string, then '[0-9]+%!' has to parse
 impl AvailabilityPolicy {
-    fn verify(&self, name: &str, maxNumber: u32) -> Result<()> {
+    fn verify(&self, d: &mut Diagnostics, name: &str, maxNumber: u32) {
         match self {
             AvailabilityPolicy::Unsigned(ref n) => {
                 if n > &maxNumber {
-                    bail!("Cannot have {} set higher than replicaCount {}", name, maxNumber);
+                    d.error(Code::OutOfRange, name,
+                        format!("Cannot have {} set higher than replicaCount {}", name, maxNumber));
                 }
             },
             AvailabilityPolicy::Percentage(s) => {
                 if !s.ends_with('%') {
-                    bail!("{} must end with a '%' sign", name);
+                    d.error(Code::InvalidValue, name, format!("{} must end with a '%' sign", name));
+                    return;
                 }
                 let digits = s.chars().take_while(|ch| *ch != '%').collect::<String>();
-                let res : u32 = digits.parse()?;
-                if res > 100 {
-                    bail!("Percentage value for {} cannot exceed 100", name);
+                let parsed = digits.parse::<u32>().map_err(Into::into);
+                if let Some(res) = d.check(Code::InvalidValue, name, parsed) {
+                    if res > 100 {
+                        d.error(Code::OutOfRange, name, format!("Percentage value for {} cannot exceed 100", name));
+                    }
                 }
             }
         }
-        Ok(())
     }
 
     /// FIgure out how many the availability policy refers to
@@ -49,7 +52,7 @@ impl AvailabilityPolicy {
 }
 
 /// Configuration parameters for Deployment.spec.strategy.rollingUpdate
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct RollingUpdate {
     /// How many replicas or percentage of replicas that can be down during rolling-update
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -74,17 +77,16 @@ impl Default for RollingUpdate {
 
 
 impl RollingUpdate {
-     pub fn verify(&self, replicas: u32) -> Result<()> {
+     pub fn verify(&self, d: &mut Diagnostics, replicas: u32) {
         if self.maxUnavailable.is_none() && self.maxSurge.is_none() {
-            bail!("Need to set one of maxUnavailable or maxSurge in rollingUpdate");
+            d.error(Code::MissingField, "", "Need to set one of maxUnavailable or maxSurge in rollingUpdate");
         }
         if let Some(ref ma) = &self.maxUnavailable {
-            ma.verify("maxUnavailable", replicas)?;
+            ma.verify(d, "maxUnavailable", replicas);
         }
         if let Some(ref mu) = &self.maxSurge {
-            mu.verify("maxSurge", replicas)?;
+            mu.verify(d, "maxSurge", replicas);
         }
-        Ok(())
      }
 }
 
@@ -1,10 +1,10 @@
 use std::path::Path;
-use super::Result;
+use super::{Diagnostics, Code};
 
 /// What sensitive data is managed and how
 ///
 /// See https://engineering.ops.babylontech.co.uk/docs/principles-security/
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct DataHandling {
     /// Where and how data is stored
@@ -24,7 +24,7 @@ impl DataHandling {
 }
 
 /// Data storage information and encryption information
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct DataStore {
     /// Storage type (one of "MySQL", "DynamoDB", "S3", "File", "Kafka")
@@ -83,7 +83,7 @@ impl DataStore {
 ///
 /// This is to indicate the canonical data type, not the actual field names.
 /// TODO: into Config!
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub enum DataFieldType {
     FullName,
     HomeAddress,
@@ -136,7 +136,7 @@ impl DataFieldType {
 
 
 /// Data storage information and encryption information
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct DataField {
     /// Canonical name of the data field
@@ -158,7 +158,7 @@ pub struct DataField {
 }
 
 /// Data storage information and encryption information
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct DataProcess {
     /// Canonical field name
@@ -168,27 +168,27 @@ pub struct DataProcess {
 }
 
 impl DataHandling {
-    pub fn verify(&self) -> Result<()> {
-        for s in &self.stores {
-            for f in &s.fields {
+    pub fn verify(&self, d: &mut Diagnostics) {
+        d.each("stores", &self.stores, |d, s| {
+            d.each("fields", &s.fields, |d, f| {
                 let enc = f.encrypted.unwrap(); // filled by implicits
                 // can't block on this yet - so just warn a lot
                 if f.name.is_spii() && !enc {
-                    warn!("{} stores SPII ({:?}) without encryption", s.backend, f.name)
+                    d.warn(Code::UnencryptedPii, "encrypted",
+                        format!("{} stores SPII ({:?}) without encryption", s.backend, f.name))
                 }
                 // weaker warning
                 else if f.name.is_pii() && !enc {
-                    warn!("{} stores PII ({:?}) without encryption", s.backend, f.name)
+                    d.warn(Code::UnencryptedPii, "encrypted",
+                        format!("{} stores PII ({:?}) without encryption", s.backend, f.name))
                 }
-
-            }
-        }
-        for p in &self.processes {
+            });
+        });
+        d.each("processes", &self.processes, |d, p| {
             let sourcepth = Path::new(".").join("services").join(&p.source);
             if !sourcepth.is_dir() {
-                bail!("Service {} does not exist in services/", p.source);
+                d.error(Code::UnknownReference, "source", format!("Service {} does not exist in services/", p.source));
             }
-        }
-        Ok(())
+        });
     }
 }
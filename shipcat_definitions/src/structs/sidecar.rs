@@ -1,8 +1,8 @@
 use super::{Resources};
 use super::env::EnvVars;
-use super::{Result};
+use super::{Diagnostics};
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields, rename_all = "lowercase")]
 pub struct Sidecar {
   pub name: String,
@@ -18,8 +18,7 @@ pub struct Sidecar {
 }
 
 impl Sidecar {
-    pub fn verify(&self) -> Result<()> {
-      self.env.verify()?;
-      Ok(())
+    pub fn verify(&self, d: &mut Diagnostics) {
+      d.nested("env", |d| self.env.verify(d));
     }
 }
@@ -1,14 +1,14 @@
-use super::{Result};
+use super::{Diagnostics, Code};
 
 /// Operator for a toleraton
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub enum Operator {
     Exists,
     Equal,
 }
 
 /// Effect of a toleration
-#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
 pub enum Effect {
     NoSchedule,
     NoExecute,
@@ -21,7 +21,7 @@ impl Default for Effect {
 }
 
 /// Kubernetes Tolerations parameters for a service
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct Tolerations {
     /// What key does the toleration apply to?
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -41,18 +41,22 @@ pub struct Tolerations {
 
 
 impl Tolerations {
-     pub fn verify(&self) -> Result<()> {
+     pub fn verify(&self, d: &mut Diagnostics) {
         match self.operator {
             Operator::Exists => {
-                assert!(self.value.is_none(), "must set tolerations.value when operator is Exists")
+                if self.value.is_some() {
+                    d.error(Code::ConflictingFields, "value", "cannot set tolerations.value when operator is Exists")
+                }
             },
             Operator::Equal => {
-                assert!(self.value.is_some(), "cannot set tolerations.value when operator is Equal")
+                if self.value.is_none() {
+                    d.error(Code::MissingField, "value", "must set tolerations.value when operator is Equal")
+                }
             }
         }
         if self.effect != Effect::NoExecute && self.tolerationSeconds.is_some() {
-            bail!("cannot set tolerations.tolerationSeconds unless effect is NoExecute");
+            d.error(Code::ConflictingFields, "tolerationSeconds",
+                "cannot set tolerations.tolerationSeconds unless effect is NoExecute");
         }
-        Ok(())
      }
 }
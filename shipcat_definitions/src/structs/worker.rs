@@ -1,14 +1,14 @@
 use super::{Resources, Probe, Port, EnvVars};
 use super::autoscaling::AutoScaling;
 
-use super::Result;
+use super::{Diagnostics};
 
 
 /// Worker for a service
 ///
 /// Essentially a side-car like object that can scale resources separately to the main pods.
 /// Useful for services that have one single side service that polls or does some work.
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Worker {
     /// Name of the worker
@@ -58,23 +58,20 @@ pub struct Worker {
 }
 
 impl Worker {
-    pub fn verify(&self) -> Result<()> {
-        self.env.verify()?;
+    pub fn verify(&self, d: &mut Diagnostics) {
+        d.nested("env", |d| self.env.verify(d));
         if let Some(hpa) = &self.autoScaling {
-            hpa.verify()?;
+            d.nested("autoScaling", |d| hpa.verify(d));
         }
-        for p in &self.ports {
-            p.verify()?;
-        }
-        self.resources.verify()?;
+        d.each("ports", &self.ports, |d, p| p.verify(d));
+        d.nested("resources", |d| self.resources.verify(d));
         if let Some(rp) = &self.readinessProbe {
-            rp.verify()?;
+            d.nested("readinessProbe", |d| rp.verify(d));
         }
         if let Some(lp) = &self.livenessProbe {
-            lp.verify()?;
+            d.nested("livenessProbe", |d| lp.verify(d));
         }
 
         // maybe the http ports shouldn't overlap? might not matter.
-        Ok(())
     }
 }
@@ -1,9 +1,16 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::collections::hash_map::DefaultHasher;
 use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
 use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use super::{Result, ErrorKind, ResultExt, Error};
-use crate::region::{VaultConfig};
+use crate::region::{VaultConfig, VaultAuth, KvVersion};
+use crate::secrets::SecretBackend;
 
 fn default_addr() -> Result<String> {
     env::var("VAULT_ADDR").map_err(|_| ErrorKind::MissingVaultAddr.into())
@@ -46,7 +53,7 @@ fn default_token() -> Result<String> {
 /// Use untagged feature to have serde autodetect the type, and implement string coerce.
 #[derive(Debug, Deserialize, Clone)]
 #[serde(untagged)]
-enum SecretValue {
+pub(crate) enum SecretValue {
     S(String),
     I(i64),
 }
@@ -70,97 +77,366 @@ struct Secret {
     lease_duration: u64,
 }
 
+/// Secret data retrieved from a KV version 2 engine
+///
+/// The key-value pairs are nested under `data.data`, next to `data.metadata`.
+#[derive(Debug, Deserialize)]
+struct SecretV2 {
+    data: SecretV2Data,
+    lease_duration: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SecretV2Data {
+    data: BTreeMap<String, SecretValue>,
+}
+
+impl From<SecretV2> for Secret {
+    fn from(s: SecretV2) -> Secret {
+        Secret { data: s.data.data, lease_duration: s.lease_duration }
+    }
+}
+
 /// List data retrieved from Vault when listing available secrets
 #[derive(Debug, Deserialize)]
 struct ListSecrets {
     data: BTreeMap<String, Vec<String>>
 }
 
+/// Token data returned from vault logins and renewals
+#[derive(Debug, Deserialize)]
+struct AuthResponse {
+    auth: AuthData,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthData {
+    client_token: String,
+    /// Seconds until the token expires, zero if it never does
+    lease_duration: u64,
+    renewable: bool,
+}
+
+/// A token obtained by logging in
+#[derive(Clone, Debug)]
+struct Lease {
+    token: String,
+    issued: Instant,
+    /// Zero for tokens that never expire
+    ttl: Duration,
+    renewable: bool,
+}
+
+impl Lease {
+    fn new(a: AuthData, issued: Instant) -> Lease {
+        Lease {
+            token: a.client_token,
+            issued,
+            ttl: Duration::from_secs(a.lease_duration),
+            renewable: a.renewable,
+        }
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        self.ttl != Duration::from_secs(0) && now >= self.issued + self.ttl
+    }
+
+    /// Whether less than a third of the lease remains
+    fn expiring(&self, now: Instant) -> bool {
+        self.ttl != Duration::from_secs(0) && now + self.ttl / 3 >= self.issued + self.ttl
+    }
+}
+
+/// A secret read earlier in the run
+struct CachedSecret {
+    data: BTreeMap<String, SecretValue>,
+    fetched: Instant,
+    /// Zero for secrets without a lease
+    ttl: Duration,
+}
+
+impl CachedSecret {
+    fn expired(&self, now: Instant) -> bool {
+        self.ttl != Duration::from_secs(0) && now >= self.fetched + self.ttl
+    }
+}
+
+/// Number of secrets fetched at the same time
+const FETCH_CONCURRENCY: usize = 8;
+
+lazy_static! {
+    /// Tokens from logins, shared by all clients using the same vault and auth method
+    ///
+    /// This avoids a login per manifest in long running reconciles.
+    /// Each vault and auth method has its own lock, held while logging in,
+    /// so concurrent clients wait for the same login rather than all logging in.
+    static ref LEASES: Mutex<BTreeMap<String, Arc<Mutex<Option<Lease>>>>> = Mutex::new(BTreeMap::new());
+
+    /// Secrets read during this run, keyed by a hash of the vault address and credentials, and path
+    ///
+    /// Entries are kept until their lease runs out, so secrets shared by many
+    /// services (or read again for every manifest in a reconcile) are fetched once.
+    static ref SECRETS: Mutex<BTreeMap<String, CachedSecret>> = Mutex::new(BTreeMap::new());
+}
+
+/// How a client authenticates its requests
+#[derive(Clone)]
+enum Credentials {
+    /// A token managed outside shipcat
+    Static(String),
+    /// A token obtained (and renewed) by logging in
+    Login(VaultAuth),
+}
+
+/// A reference to a vault secret in a manifest value
+///
+/// A plain `IN_VAULT` refers to the `value` key of the secret named after the
+/// env var (or secret file) in the service's own folder.
+/// `IN_VAULT:shared/postgres#password` refers to the `password` key of the
+/// `shared/postgres` secret in the region's vault folder, so services can share secrets.
+/// The key defaults to `value` when left out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecretRef {
+    /// Path relative to the region's vault folder, `None` for the service's own folder
+    pub path: Option<String>,
+    /// Key to read from the secret
+    pub key: String,
+}
+
+impl SecretRef {
+    /// Parse a manifest value
+    ///
+    /// Returns `None` for values that do not reference vault at all.
+    pub fn parse(value: &str) -> Option<Result<SecretRef>> {
+        let prefix = "IN_VAULT";
+        if !value.starts_with(prefix) {
+            return None;
+        }
+        let rest = &value[prefix.len()..];
+        if rest.is_empty() {
+            return Some(Ok(SecretRef { path: None, key: "value".into() }));
+        }
+        if !rest.starts_with(':') {
+            return None;
+        }
+        Some(SecretRef::parse_explicit(value, &rest[1..]))
+    }
+
+    fn parse_explicit(value: &str, reference: &str) -> Result<SecretRef> {
+        let (path, key) = match reference.find('#') {
+            Some(i) => (&reference[..i], &reference[i+1..]),
+            None => (reference, "value"),
+        };
+        if path.is_empty() || key.is_empty() || key.contains('#') {
+            bail!("Invalid vault reference '{}' - expected IN_VAULT:path#key", value);
+        }
+        if path.split('/').any(|s| s.is_empty() || s == "." || s == "..") {
+            bail!("Invalid vault reference '{}' - path must be relative to the vault folder", value);
+        }
+        Ok(SecretRef { path: Some(path.into()), key: key.into() })
+    }
+
+    /// Vault path and key of the secret referenced by an env var or secret file `name`
+    ///
+    /// `svcpath` is the folder holding the service's own secrets.
+    pub fn locate(&self, svcpath: &str, vc: &VaultConfig, name: &str) -> (String, String) {
+        let path = match &self.path {
+            Some(p) => format!("{}/{}", vc.folder, p),
+            None => format!("{}/{}", svcpath, name),
+        };
+        (path, self.key.clone())
+    }
+}
+
+/// Source of the current time for lease and cache expiry
+type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
 /// Vault client with cached data
+#[derive(Clone)]
 pub struct Vault {
     /// Our HTTP client.  This can be configured to mock out the network.
     client: reqwest::Client,
     /// The address of our Vault server.
     addr: reqwest::Url,
-    /// The token which we'll use to access Vault.
-    token: String,
-    /// Vault operation mode
-    mode: Mode,
-}
-
-/// Vault usage mode
-#[derive(PartialEq, Debug, Clone)]
-pub enum Mode {
-    /// Normal HTTP calls to vault returing actual secret
-    Standard,
-    /// Not using HTTP calls, just returning dummy data
-    Mocked,
+    /// How we obtain the token used to access Vault.
+    creds: Credentials,
+    /// Hash of the address and credentials, keying the shared caches
+    id: u64,
+    /// Version of the KV engine mounted at secret/
+    kv: KvVersion,
+    /// Time used to expire leases and cached secrets
+    clock: Clock,
 }
 
 impl Vault {
     /// Initialize using the same evars or token files that the `vault` CLI uses
     pub fn from_evars() -> Result<Vault> {
-        Vault::new(reqwest::Client::new(), &default_addr()?, default_token()?, Mode::Standard)
+        let creds = Credentials::Static(default_token()?);
+        Vault::new(reqwest::Client::new(), &default_addr()?, creds, KvVersion::V1)
     }
 
-    /// Initialize using VAULT_TOKEN evar + addr in shipcat.conf
+    /// Initialize using the auth method and addr in shipcat.conf
+    ///
+    /// The `token` auth method uses the VAULT_TOKEN evar or `~/.vault-token`.
     pub fn regional(vc: &VaultConfig) -> Result<Vault> {
-        Vault::new(reqwest::Client::new(), &vc.url, default_token()?, Mode::Standard)
+        Vault::new(reqwest::Client::new(), &vc.url, Vault::credentials(vc)?, vc.kvVersion)
     }
 
-    /// Initialize using dummy values and return garbage
-    pub fn mocked(vc: &VaultConfig) -> Result<Vault> {
-        Vault::new(reqwest::Client::new(), &vc.url, default_token()?, Mode::Mocked)
+    /// Initialize using an explicit token + addr in shipcat.conf
+    pub fn with_token<S: Into<String>>(vc: &VaultConfig, token: S) -> Result<Vault> {
+        let creds = Credentials::Static(token.into());
+        Vault::new(reqwest::Client::new(), &vc.url, creds, vc.kvVersion)
     }
 
-    fn new<U, S>(client: reqwest::Client, addr: U, token: S, mode: Mode) -> Result<Vault>
-        where U: reqwest::IntoUrl,
-              S: Into<String>
+    fn credentials(vc: &VaultConfig) -> Result<Credentials> {
+        match vc.auth {
+            VaultAuth::Token => Ok(Credentials::Static(default_token()?)),
+            ref auth => Ok(Credentials::Login(auth.clone())),
+        }
+    }
+
+    fn new<U>(client: reqwest::Client, addr: U, creds: Credentials, kv: KvVersion) -> Result<Vault>
+        where U: reqwest::IntoUrl
     {
         let addr = addr.into_url()?;
-        Ok(Vault { client, addr, mode, token: token.into() })
+        let mut h = DefaultHasher::new();
+        addr.as_str().hash(&mut h);
+        match &creds {
+            Credentials::Static(token) => token.hash(&mut h),
+            Credentials::Login(auth) => format!("{:?}", auth).hash(&mut h),
+        }
+        let id = h.finish();
+        Ok(Vault { client, addr, kv, creds, id, clock: Arc::new(Instant::now) })
     }
 
-    pub fn mode(&self) -> Mode {
-        self.mode.clone()
+    /// Use a different clock to expire leases and cached secrets
+    ///
+    /// Lets tests move past a lease without waiting for it.
+    pub fn with_clock<F>(mut self, clock: F) -> Vault
+        where F: Fn() -> Instant + Send + Sync + 'static
+    {
+        self.clock = Arc::new(clock);
+        self
     }
 
-    // The actual HTTP GET logic
-    fn get_secret(&self, path: &str) -> Result<Secret> {
-        let url = self.addr.join(&format!("v1/{}", path))?;
-        debug!("GET {}", url);
+    fn now(&self) -> Instant {
+        (self.clock)()
+    }
 
-        let mkerr = || ErrorKind::Url(url.clone());
-        let mut res = self.client.get(url.clone())
-            .header("X-Vault-Token", self.token.clone())
-            .send()
-            .chain_err(&mkerr)?;
+    /// API path of a secret for the KV engine version
+    fn data_path(&self, key: &str) -> String {
+        match self.kv {
+            KvVersion::V1 => format!("secret/{}", key),
+            KvVersion::V2 => format!("secret/data/{}", key),
+        }
+    }
 
-        // Generate informative errors for HTTP failures, because these can
-        // be caused by everything from bad URLs to overly restrictive vault policies
+    /// API path of a folder to list for the KV engine version
+    fn list_path(&self, folder: &str) -> String {
+        match self.kv {
+            KvVersion::V1 => format!("secret/{}", folder),
+            KvVersion::V2 => format!("secret/metadata/{}", folder),
+        }
+    }
+
+    /// Token for the next request, logging in or renewing as needed
+    fn token(&self) -> Result<String> {
+        let auth = match &self.creds {
+            Credentials::Static(token) => return Ok(token.clone()),
+            Credentials::Login(auth) => auth,
+        };
+        let key = format!("{:016x}", self.id);
+        // the global lock is released before any request is made
+        let slot = LEASES.lock().unwrap().entry(key).or_insert_with(Default::default).clone();
+        let mut lease = slot.lock().unwrap();
+        let now = self.now();
+        let fresh = match &*lease {
+            Some(l) if !l.expiring(now) => return Ok(l.token.clone()),
+            Some(l) if l.renewable && !l.expired(now) => {
+                self.renew(&l.token).or_else(|e| {
+                    warn!("Failed to renew vault token, logging in again: {}", e);
+                    self.login(auth)
+                })?
+            }
+            _ => self.login(auth)?,
+        };
+        let token = fresh.token.clone();
+        *lease = Some(fresh);
+        Ok(token)
+    }
+
+    /// Log in with a non-token auth method
+    fn login(&self, auth: &VaultAuth) -> Result<Lease> {
+        let mut body = BTreeMap::new();
+        let (method, mount) = match auth {
+            VaultAuth::Token => bail!("token auth does not log in"),
+            VaultAuth::AppRole { mount } => {
+                let role_id = env::var("VAULT_ROLE_ID").chain_err(|| "VAULT_ROLE_ID not specified")?;
+                let secret_id = env::var("VAULT_SECRET_ID").chain_err(|| "VAULT_SECRET_ID not specified")?;
+                body.insert("role_id", role_id);
+                body.insert("secret_id", secret_id);
+                ("approle", mount)
+            }
+            VaultAuth::Kubernetes { role, mount, jwtPath } => {
+                let jwt = fs::read_to_string(jwtPath)
+                    .chain_err(|| format!("Failed to read service account token from {}", jwtPath))?;
+                body.insert("role", role.clone());
+                body.insert("jwt", jwt.trim().to_string());
+                ("kubernetes", mount)
+            }
+        };
+        let url = self.addr.join(&format!("v1/auth/{}/login", mount))?;
+        debug!("POST {}", url);
+        let res = self.post_auth(url, None, &body).chain_err(|| ErrorKind::VaultLoginFailure(method.into()))?;
+        Ok(res)
+    }
+
+    /// Extend the lease of a token we logged in with
+    fn renew(&self, token: &str) -> Result<Lease> {
+        let url = self.addr.join("v1/auth/token/renew-self")?;
+        debug!("POST {}", url);
+        self.post_auth(url, Some(token), &BTreeMap::new())
+    }
+
+    fn post_auth(&self, url: reqwest::Url, token: Option<&str>, body: &BTreeMap<&str, String>) -> Result<Lease> {
+        let mkerr = || ErrorKind::Url(url.clone());
+        let mut req = self.client.post(url.clone()).json(body);
+        if let Some(t) = token {
+            req = req.header("X-Vault-Token", t);
+        }
+        let mut res = req.send().chain_err(&mkerr)?;
         if !res.status().is_success() {
             let status = res.status().to_owned();
             let err: Error = ErrorKind::UnexpectedHttpStatus(status).into();
             return Err(err).chain_err(&mkerr);
         }
-
-        let mut body = String::new();
-        res.read_to_string(&mut body)?;
-        Ok(serde_json::from_str(&body)?)
+        let mut text = String::new();
+        res.read_to_string(&mut text)?;
+        let auth: AuthResponse = serde_json::from_str(&text)?;
+        Ok(Lease::new(auth.auth, self.now()))
     }
 
-    /// List secrets
-    ///
-    /// Does a HTTP LIST on the folder a service is in and returns the keys
-    pub fn list(&self, path: &str) -> Result<Vec<String>> {
-        let url = self.addr.join(&format!("v1/secret/{}?list=true", path))?;
-        debug!("LIST {}", url);
+    // The actual HTTP GET logic
+    fn get(&self, url: reqwest::Url) -> Result<String> {
+        match self.get_opt(url.clone())? {
+            Some(body) => Ok(body),
+            None => {
+                let err: Error = ErrorKind::UnexpectedHttpStatus(reqwest::StatusCode::NOT_FOUND).into();
+                Err(err).chain_err(|| ErrorKind::Url(url))
+            }
+        }
+    }
 
+    /// HTTP GET that returns `None` when vault has nothing at the url
+    fn get_opt(&self, url: reqwest::Url) -> Result<Option<String>> {
         let mkerr = || ErrorKind::Url(url.clone());
         let mut res = self.client.get(url.clone())
-            .header("X-Vault-Token", self.token.clone())
+            .header("X-Vault-Token", self.token()?)
             .send()
             .chain_err(&mkerr)?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
 
         // Generate informative errors for HTTP failures, because these can
         // be caused by everything from bad URLs to overly restrictive vault policies
@@ -172,44 +448,206 @@ impl Vault {
 
         let mut body = String::new();
         res.read_to_string(&mut body)?;
-        let lsec : ListSecrets = serde_json::from_str(&body)?;
-        if !lsec.data.contains_key("keys") {
-            bail!("secret list {} does not contain keys list from vault api!?: {}", url, body);
+        Ok(Some(body))
+    }
+
+    /// Send a request that changes vault, checking its status
+    fn send(&self, req: reqwest::RequestBuilder, url: reqwest::Url) -> Result<()> {
+        let mkerr = || ErrorKind::Url(url.clone());
+        let res = req.header("X-Vault-Token", self.token()?).send().chain_err(&mkerr)?;
+        if !res.status().is_success() {
+            let status = res.status().to_owned();
+            let err: Error = ErrorKind::UnexpectedHttpStatus(status).into();
+            return Err(err).chain_err(&mkerr);
         }
-        let res = lsec.data["keys"].iter()
-            .filter(|e| !e.ends_with('/')) // skip sub folders
-            .map(|e| e.to_string())
-            .collect::<Vec<String>>();
-        Ok(res)
+        Ok(())
     }
 
+    /// Cache key of a secret
+    ///
+    /// Includes who is asking, as clients with other tokens or auth methods
+    /// may not be allowed to read what another client cached.
+    /// Credentials are only kept as a hash.
+    fn cache_key(&self, pth: &str) -> String {
+        format!("{:016x} {}", self.id, pth)
+    }
+
+    /// Drop a secret from the cache after changing it
+    fn forget(&self, pth: &str) {
+        SECRETS.lock().unwrap().remove(&self.cache_key(pth));
+    }
 
-    /// Read secret from a Vault via an authenticated HTTP GET (or memory cache)
-    pub fn read(&self, key: &str) -> Result<String> {
-        let pth = format!("secret/{}", key);
-        if self.mode == Mode::Mocked {
-            // arbitrary base64 encoded value so it's compatible with everything
-            return Ok("aGVsbG8gd29ybGQ=".into());
+    /// Data of a secret, from the cache if its lease is still valid
+    fn fetch(&self, pth: &str) -> Result<BTreeMap<String, SecretValue>> {
+        let key = self.cache_key(pth);
+        if let Some(c) = SECRETS.lock().unwrap().get(&key) {
+            if !c.expired(self.now()) {
+                return Ok(c.data.clone());
+            }
         }
+        // not holding the lock while fetching so other secrets can be fetched meanwhile
+        let secret = self.get_secret(pth).chain_err(|| ErrorKind::SecretNotAccessible(pth.into()))?;
+        let cached = CachedSecret {
+            data: secret.data.clone(),
+            fetched: self.now(),
+            ttl: Duration::from_secs(secret.lease_duration),
+        };
+        SECRETS.lock().unwrap().insert(key, cached);
+        Ok(secret.data)
+    }
 
-        let secret = self.get_secret(&pth).chain_err(|| ErrorKind::SecretNotAccessible(pth.clone()))?;
+    fn get_secret(&self, path: &str) -> Result<Secret> {
+        let url = self.addr.join(&format!("v1/{}", path))?;
+        debug!("GET {}", url);
+        let body = self.get(url)?;
+        let secret = match self.kv {
+            KvVersion::V1 => serde_json::from_str(&body)?,
+            KvVersion::V2 => serde_json::from_str::<SecretV2>(&body)?.into(),
+        };
+        Ok(secret)
+    }
+}
 
-        // NB: Currently assume each path in vault has a single `value`
-        // Read the value key (which should exist)
-        secret.data
-            .get("value")
-            .ok_or_else(|| { ErrorKind::InvalidSecretForm(pth).into() })
+impl SecretBackend for Vault {
+    /// Read a key of a secret via an authenticated HTTP GET
+    fn read_key(&self, path: &str, key: &str) -> Result<String> {
+        let pth = self.data_path(path);
+        self.fetch(&pth)?
+            .get(key)
+            .ok_or_else(|| { ErrorKind::InvalidSecretForm(pth, key.into()).into() })
             .map(|v| {
                 v.clone().into()
             })
     }
+
+    /// Read several keys, fetching each secret once and up to 8 secrets at a time
+    fn read_keys(&self, reqs: &[(String, String)]) -> Result<Vec<String>> {
+        let paths = reqs.iter()
+            .map(|(p, _)| self.data_path(p))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        for batch in paths.chunks(FETCH_CONCURRENCY) {
+            let handles = batch.iter().map(|pth| {
+                let (v, pth) = (self.clone(), pth.clone());
+                thread::spawn(move || v.fetch(&pth).map(|_| ()))
+            }).collect::<Vec<_>>();
+            for h in handles {
+                h.join().map_err(|_| Error::from("secret fetching thread panicked"))??;
+            }
+        }
+        // everything is cached now
+        reqs.iter().map(|(p, k)| self.read_key(p, k)).collect()
+    }
+
+    /// Write a secret via an authenticated HTTP POST
+    fn write(&self, path: &str, value: &str) -> Result<()> {
+        let pth = self.data_path(path);
+        let mut data = BTreeMap::new();
+        data.insert("value".to_string(), value.to_string());
+        let body = match self.kv {
+            KvVersion::V1 => serde_json::to_value(&data)?,
+            KvVersion::V2 => {
+                let mut nested = BTreeMap::new();
+                nested.insert("data".to_string(), data);
+                serde_json::to_value(&nested)?
+            }
+        };
+        let url = self.addr.join(&format!("v1/{}", pth))?;
+        debug!("POST {}", url);
+        self.send(self.client.post(url.clone()).json(&body), url)?;
+        self.forget(&pth);
+        Ok(())
+    }
+
+    /// Delete a secret via an authenticated HTTP DELETE
+    ///
+    /// On KV version 2 this deletes every version of the secret.
+    fn delete(&self, path: &str) -> Result<()> {
+        // metadata path for v2 so no versions are left behind
+        let url = self.addr.join(&format!("v1/{}", self.list_path(path)))?;
+        debug!("DELETE {}", url);
+        self.send(self.client.delete(url.clone()), url)?;
+        self.forget(&self.data_path(path));
+        Ok(())
+    }
+
+    /// List secrets
+    ///
+    /// Does a HTTP LIST on the folder a service is in and returns the keys
+    fn list(&self, path: &str) -> Result<Vec<String>> {
+        match self.list_opt(path)? {
+            Some(keys) => Ok(keys),
+            None => {
+                let url = self.addr.join(&format!("v1/{}?list=true", self.list_path(path)))?;
+                let err: Error = ErrorKind::UnexpectedHttpStatus(reqwest::StatusCode::NOT_FOUND).into();
+                Err(err).chain_err(|| ErrorKind::Url(url))
+            }
+        }
+    }
+
+    /// List secrets, with `None` for a folder vault has nothing in
+    fn list_opt(&self, path: &str) -> Result<Option<Vec<String>>> {
+        let url = self.addr.join(&format!("v1/{}?list=true", self.list_path(path)))?;
+        debug!("LIST {}", url);
+        let body = match self.get_opt(url.clone())? {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+
+        // same response shape for both engine versions
+        let lsec : ListSecrets = serde_json::from_str(&body)?;
+        if !lsec.data.contains_key("keys") {
+            bail!("secret list {} does not contain keys list from vault api!?: {}", url, body);
+        }
+        let res = lsec.data["keys"].iter()
+            .filter(|e| !e.ends_with('/')) // skip sub folders
+            .map(|e| e.to_string())
+            .collect::<Vec<String>>();
+        Ok(Some(res))
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use super::Vault;
+    use super::{Vault, Lease, SecretRef};
+    use crate::secrets::SecretBackend;
     use base64;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn lease_renewal_window() {
+        let now = Instant::now();
+        let secs = Duration::from_secs;
+        let lease = Lease { token: "s.token".into(), issued: now, ttl: secs(90), renewable: true };
+        assert!(!lease.expiring(now + secs(59)));
+        assert!(lease.expiring(now + secs(60))); // a third left
+        assert!(!lease.expired(now + secs(89)));
+        assert!(lease.expired(now + secs(90)));
+
+        let forever = Lease { ttl: secs(0), ..lease };
+        assert!(!forever.expiring(now + secs(100_000)));
+        assert!(!forever.expired(now + secs(100_000)));
+    }
+
+    #[test]
+    fn secret_references() {
+        let implicit = SecretRef::parse("IN_VAULT").unwrap().unwrap();
+        assert_eq!(implicit, SecretRef { path: None, key: "value".into() });
+        let shared = SecretRef::parse("IN_VAULT:shared/postgres#password").unwrap().unwrap();
+        assert_eq!(shared.path, Some("shared/postgres".into()));
+        assert_eq!(shared.key, "password");
+        let nokey = SecretRef::parse("IN_VAULT:shared/redis").unwrap().unwrap();
+        assert_eq!(nokey.key, "value");
+
+        assert!(SecretRef::parse("plaintext").is_none());
+        assert!(SecretRef::parse("IN_VAULTED").is_none());
+        for bad in &["IN_VAULT:", "IN_VAULT:#key", "IN_VAULT:shared/postgres#", "IN_VAULT:/abs#key",
+                     "IN_VAULT:shared/#key", "IN_VAULT:../other#key", "IN_VAULT:a#b#c"] {
+            assert!(SecretRef::parse(bad).unwrap().is_err(), "{} should be invalid", bad);
+        }
+    }
 
     #[test]
     fn get_dev_secret() {